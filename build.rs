@@ -0,0 +1,46 @@
+//! Records the git commit qvm is built from, for `qvm --version` and `qvm version`
+
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    let version = env!("CARGO_PKG_VERSION");
+    let root = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").expect("set by cargo"));
+    let git = root.join(".git");
+    // Source tarballs and vendored builds have no git checkout of their own (and one
+    // unpacked inside another repository must not take that repository's commit): the
+    // version stands alone
+    if !git.exists() {
+        println!("cargo:rerun-if-changed=build.rs");
+        println!("cargo:rustc-env=QVM_VERSION={}", version);
+        return;
+    }
+
+    // Rebuild when HEAD moves to another branch or its branch gets a new commit
+    let head = git.join("HEAD");
+    if head.is_file() {
+        println!("cargo:rerun-if-changed={}", head.display());
+    }
+    if let Some(branch) = std::fs::read_to_string(&head)
+        .ok()
+        .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+    {
+        println!("cargo:rerun-if-changed={}", git.join(branch).display());
+    }
+    let hash = Command::new("git")
+        .arg("-C")
+        .arg(&root)
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+        .filter(|hash| !hash.is_empty());
+    match hash {
+        Some(hash) => {
+            println!("cargo:rustc-env=QVM_GIT_HASH={}", hash);
+            println!("cargo:rustc-env=QVM_VERSION={} ({})", version, hash);
+        }
+        None => println!("cargo:rustc-env=QVM_VERSION={}", version),
+    }
+}
@@ -1,17 +1,44 @@
 //! CLI command definitions
 
+use crate::cli::output::OutputFormat;
 use clap::{Parser, Subcommand};
 use clap_complete::Shell;
 use std::path::PathBuf;
 
 /// QVM CLI (Rust)
 #[derive(Parser, Debug)]
-#[command(name = "qvm", about = "QEMU VM manager in Rust")]
+#[command(name = "qvm", about = "QEMU VM manager in Rust", version = crate::cli::version::VERSION)]
 pub struct Cli {
+    /// Directory holding the VMs (default: ~/qvm)
+    #[arg(long, global = true, env = "QVM_HOME")]
+    pub qvm_home: Option<PathBuf>,
+
+    /// qemu-system-* binary to run instead of searching Nix paths and PATH
+    #[arg(long, global = true, env = "QVM_QEMU_BIN")]
+    pub qemu_bin: Option<PathBuf>,
+
+    /// Show what qvm is doing (-vv for more detail)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only print errors (no warnings or notes)
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Answer yes to confirmation prompts; unlike delete --force, what is deleted is still listed
+    #[arg(short, long, visible_alias = "assume-yes", global = true)]
+    pub yes: bool,
+
+    /// Result format on stdout for create, delete, list, status, start and stop
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
     #[command(subcommand)]
     pub cmd: Cmd,
 }
 
+// Parsed once per invocation; boxing the create flags would only obscure them
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 pub enum Cmd {
     /// Create a new VM (writes vm.json; can create qcow2 disk)
@@ -42,10 +69,30 @@ pub enum Cmd {
         #[arg(long)]
         threads: Option<u32>,
 
+        /// Accelerator (default: detected; kvm on Linux, hvf on macOS, else tcg)
+        #[arg(long, value_parser = ["kvm","hvf","tcg"])]
+        accel: Option<String>,
+
+        /// qemu machine type and options (default: virt,gic-version=3 on aarch64, q35 on x86_64)
+        #[arg(long)]
+        machine: Option<String>,
+
         /// Memory (MB)
         #[arg(long, default_value_t = 4096)]
         mem: u32,
 
+        /// What the guest clock starts from: utc, or localtime for Windows guests
+        #[arg(long, value_parser = ["utc","localtime"], default_value = "utc")]
+        rtc: String,
+
+        /// Allocate all guest RAM when the VM starts instead of on first touch
+        #[arg(long)]
+        mem_prealloc: bool,
+
+        /// Back guest RAM with huge pages from this hugetlbfs mount (Linux only)
+        #[arg(long, num_args = 0..=1, default_missing_value = "/dev/hugepages", value_name = "MOUNT")]
+        hugepages: Option<PathBuf>,
+
         /// Network mode (vmnet-shared|vmnet-bridged|user)
         #[arg(long, default_value = "vmnet-shared")]
         net_mode: String,
@@ -54,24 +101,74 @@ pub enum Cmd {
         #[arg(long, default_value = "en0")]
         bridge_if: String,
 
+        /// MAC address of the first guest NIC (default: generated per --mac-strategy)
+        #[arg(long)]
+        mac: Option<String>,
+        /// Generate the MAC's last three octets at random, or hash them from the VM name
+        #[arg(long, value_parser = ["random","deterministic"], default_value = "random", conflicts_with = "mac")]
+        mac_strategy: String,
+        /// First three octets (OUI) of the generated MAC
+        #[arg(long, default_value = "52:54:00", conflicts_with = "mac")]
+        mac_prefix: String,
+
+        /// Default login for `qvm ssh`
+        #[arg(long)]
+        ssh_user: Option<String>,
+
+        /// Forward a free host port (from 2222) to the guest's SSH port (needs --net-mode user)
+        #[arg(long)]
+        ssh_forward: bool,
+        /// Host port of the SSH forward (implies --ssh-forward)
+        #[arg(long)]
+        ssh_port: Option<u16>,
+
+        /// Free-form notes shown by `qvm status`
+        #[arg(long, default_value = "")]
+        description: String,
+
+        /// Label for grouping VMs (repeatable; filter with `qvm list --tag`)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+
         /// Display mode (cocoa|vnc|spice|headless)
         #[arg(long, default_value = "cocoa")]
         display_mode: String,
 
         // Disk options
-        /// Disk path (qcow2). If relative, it's under the VM root.
+        /// Disk image or block device path. If relative, it's under the VM root.
         #[arg(long)]
         disk: Option<PathBuf>,
 
-        /// Create qcow2 disk if absent (e.g., 64G, 100G)
+        /// Create the disk image if absent (e.g., 64G, 100G)
         #[arg(long)]
         disk_size: Option<String>,
 
+        /// Boot disk format (raw for block devices or plain image files)
+        #[arg(long, value_parser = ["qcow2","raw"], default_value = "qcow2")]
+        disk_format: String,
+
+        /// Boot disk host cache mode (default: qemu's, writeback)
+        #[arg(long, value_parser = ["none","writeback","writethrough"])]
+        disk_cache: Option<String>,
+
+        /// Boot disk async I/O engine (default: qemu's, threads; native needs --disk-cache none)
+        #[arg(long, value_parser = ["threads","native","io_uring"])]
+        disk_aio: Option<String>,
+
+        /// Attach the boot disk read-only
+        #[arg(long)]
+        disk_readonly: bool,
+
+        /// Create the boot disk as a qcow2 overlay of this golden image (shared, never written)
+        #[arg(long)]
+        backing: Option<PathBuf>,
+
         // VNC
         #[arg(long, default_value = "127.0.0.1")]
         vnc_host: String,
-        #[arg(long, default_value_t = 1)]
-        vnc_display: u8,
+        /// VNC display number (default: the lowest one free on this host and among VMs)
+        #[arg(long)]
+        vnc_display: Option<u8>,
         #[arg(long)]
         vnc_sock: Option<PathBuf>,
         /// Use VNC UNIX socket (boolean flag; default false)
@@ -81,8 +178,9 @@ pub enum Cmd {
         // SPICE
         #[arg(long, default_value = "127.0.0.1")]
         spice_addr: String,
-        #[arg(long, default_value_t = 5930)]
-        spice_port: u16,
+        /// SPICE port (default: the lowest one from 5930 free on this host and among VMs)
+        #[arg(long)]
+        spice_port: Option<u16>,
         #[arg(long)]
         spice_sock: Option<PathBuf>,
         /// Use SPICE UNIX socket (boolean flag; default false)
@@ -90,11 +188,73 @@ pub enum Cmd {
         spice_unix: bool,
         #[arg(long, default_value_t = true)]
         spice_disable_ticketing: bool,
+
+        /// Attach an emulated TPM 2.0 (requires swtpm)
+        #[arg(long)]
+        tpm: bool,
+
+        /// Host audio backend for a guest sound card (none = no sound card)
+        #[arg(long, value_parser = ["none","coreaudio","pa","pipewire","alsa","sdl"], default_value = "none")]
+        audio: String,
+        /// Guest sound card model
+        #[arg(long, value_parser = ["intel-hda","usb-audio"], default_value = "intel-hda")]
+        audio_model: String,
+
+        /// UEFI code file instead of the detected one (relative: under the VM root)
+        #[arg(long, requires = "firmware_vars")]
+        firmware_code: Option<PathBuf>,
+        /// UEFI vars template to go with --firmware-code (relative: under the VM root)
+        #[arg(long, requires = "firmware_code")]
+        firmware_vars: Option<PathBuf>,
+        /// Use a secure boot firmware build (fails if none is installed)
+        #[arg(long)]
+        secure_boot: bool,
+        /// Keep running this VM with the qemu binary picked now (saved as paths.qemu_bin)
+        #[arg(long)]
+        pin_qemu: bool,
+        /// Extra argument appended verbatim to the qemu command line (repeatable; unvalidated)
+        #[arg(long = "extra-arg", allow_hyphen_values = true)]
+        extra_args: Vec<String>,
+
+        /// Boot this kernel directly instead of through the disk's bootloader
+        #[arg(long)]
+        kernel: Option<PathBuf>,
+        /// Initial ramdisk for --kernel
+        #[arg(long, requires = "kernel")]
+        initrd: Option<PathBuf>,
+        /// Kernel command line for --kernel (e.g. "console=ttyAMA0 root=/dev/vda2")
+        #[arg(long, requires = "kernel")]
+        append: Option<String>,
+        /// Firmware boot order as qemu drive letters (c = disk, d = CD-ROM, n-p = the first three NICs)
+        #[arg(long)]
+        boot_order: Option<String>,
+        /// Attach this install ISO and boot from it first, then from the disk; it stays
+        /// attached across reboots until 'qvm set-iso NAME --remove'
+        #[arg(long, value_name = "ISO")]
+        from_iso: Option<PathBuf>,
+
+        /// Print the vm.json that would be written, without creating anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Start the VM right after creating it
+        #[arg(long, conflicts_with = "dry_run")]
+        start: bool,
+        /// Run this script once the VM is created (with QVM_NAME, QVM_ROOT, QVM_DISK set)
+        #[arg(long, value_name = "SCRIPT", conflicts_with = "dry_run")]
+        on_create: Option<PathBuf>,
+
+        /// Take defaults from $QVM_HOME/profiles/<PROFILE>.json (flags still win)
+        #[arg(long)]
+        profile: Option<String>,
     },
 
     /// Start a VM (optionally override display, attach ISO, pick console, daemonize)
     Start {
-        name: String,
+        #[arg(required_unless_present = "all")]
+        name: Option<String>,
+        /// Start every VM with autostart enabled ('qvm enable'), in the background
+        #[arg(long, conflicts_with_all = ["name", "iso", "console"])]
+        all: bool,
         #[arg(long)]
         iso: Option<PathBuf>,
         #[arg(long, value_parser = ["cocoa","vnc","spice","headless"])]
@@ -103,14 +263,336 @@ pub enum Cmd {
         console: String,
         #[arg(long)]
         daemon: bool,
+        /// With --daemon or --all: block until the guest's SSH port answers (needs a user-mode SSH forward)
+        #[arg(long)]
+        wait_for_ssh: bool,
+        /// Seconds --wait-for-ssh waits before stopping the VM again and failing
+        #[arg(long, default_value_t = 300, requires = "wait_for_ssh")]
+        ssh_timeout: u64,
+        /// Start even if the host does not have the VM's memory available
+        #[arg(long)]
+        force: bool,
     },
 
-    /// Stop a VM (reads vm.pid and kills)
-    Stop { name: String },
+    /// Have 'qvm start --all' start this VM
+    Enable { name: String },
 
-    /// Delete a VM and its associated files
-    Delete {
+    /// Stop 'qvm start --all' from starting this VM
+    Disable { name: String },
+
+    /// Stop a VM (reads vm.pid, sends SIGTERM, escalates to SIGKILL)
+    Stop {
+        name: String,
+        /// Seconds to wait for the guest to shut down before terminating qemu
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+        /// Skip the guest shutdown and terminate qemu at once (like pulling the plug)
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Restart a VM: stop it (if running) and start it again
+    Restart {
+        name: String,
+        /// Seconds to wait for the guest to shut down before terminating qemu
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+        #[arg(long)]
+        daemon: bool,
+        /// Start even if the host does not have the VM's memory available
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// List all VMs with their state, architecture, CPUs, memory, display and tags
+    List {
+        /// Only VMs carrying this tag (repeat to require several)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+
+    /// Show whether a VM is running, with PID, uptime, vCPUs, memory, disk and display endpoint
+    Status {
+        name: String,
+        /// Print machine-readable JSON instead of text (same as --output json)
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show the running VMs with their qemu's CPU use, resident memory, uptime and display
+    Ps {
+        /// Refresh until interrupted instead of printing one snapshot
+        #[arg(long)]
+        watch: bool,
+        /// Seconds between refreshes with --watch
+        #[arg(long, default_value_t = 2, requires = "watch")]
+        interval: u64,
+    },
+
+    /// SSH into a running VM through its user-mode forward to guest port 22
+    Ssh {
+        name: String,
+        /// Login name (default: network.ssh_user in vm.json, else ssh's default)
+        #[arg(long)]
+        user: Option<String>,
+        /// Remote command and arguments, after --
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+
+    /// Print the resolved qvm home directory
+    Home,
+
+    /// Print the absolute path of a VM's vm.json (or its directory, or a disk image)
+    ConfigPath {
+        name: String,
+        /// Print the VM directory instead
+        #[arg(long, conflicts_with = "disk")]
+        root: bool,
+        /// Print the image of disk N instead (0 = boot disk)
+        #[arg(long, num_args = 0..=1, default_missing_value = "0", value_name = "N")]
+        disk: Option<usize>,
+    },
+
+    /// Show a VM's configuration, or the qemu command line `start` would run
+    Info {
+        name: String,
+        /// Print the full qemu-system-* invocation instead (dry run)
+        #[arg(long)]
+        command: bool,
+    },
+
+    /// Show which qemu binary and UEFI firmware a VM resolves to, and every path searched
+    Which {
+        name: String,
+    },
+
+    /// Check everything a VM needs to start (values, files, host support) and list every problem
+    Validate { name: String },
+
+    /// Change one vm.json field by its dotted key, e.g. `qvm set my-vm hardware.mem_mb 8192`
+    Set {
+        name: String,
+        /// Dotted path into vm.json (array elements by index: paths.disks.0.format)
+        key: String,
+        /// New value, read as the field's type; JSON for lists and objects, null to unset
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+    },
+
+    /// Rename a stopped VM
+    Rename { old: String, new: String },
+
+    /// Attach an additional disk to a stopped VM (creates it with qemu-img unless --existing)
+    AddDisk {
+        name: String,
+        /// Size of the new image (e.g., 50G)
+        #[arg(long, required_unless_present = "existing", conflicts_with = "existing")]
+        size: Option<String>,
+        /// Image format
+        #[arg(long, value_parser = ["qcow2","raw"], default_value = "qcow2")]
+        format: String,
+        /// Bus the disk is attached to
+        #[arg(long, value_parser = ["virtio","nvme","ide"], default_value = "virtio")]
+        interface: String,
+        /// File name of the new image under the VM root (default: diskN.<format>)
+        #[arg(long, conflicts_with = "existing")]
+        file: Option<PathBuf>,
+        /// Attach an already-created image instead of creating one
+        #[arg(long)]
+        existing: Option<PathBuf>,
+        /// Host cache mode (default: qemu's, writeback)
+        #[arg(long, value_parser = ["none","writeback","writethrough"])]
+        cache: Option<String>,
+        /// Async I/O engine (default: qemu's, threads; native needs --cache none)
+        #[arg(long, value_parser = ["threads","native","io_uring"])]
+        aio: Option<String>,
+        /// Attach the disk read-only
+        #[arg(long)]
+        readonly: bool,
+    },
+
+    /// Check the disks of a stopped VM for corruption and leaked clusters (qemu-img check)
+    Check {
+        name: String,
+        /// Repair what the check finds (qemu-img check -r all)
+        #[arg(long)]
+        repair: bool,
+    },
+
+    /// Grow a disk of a stopped VM with qemu-img resize (shrinking needs --shrink --force)
+    ResizeDisk {
+        name: String,
+        /// New size (e.g., 100G)
+        size: String,
+        /// Index of the disk in vm.json (0 = boot disk)
+        #[arg(long, default_value_t = 0)]
+        disk: usize,
+        /// Allow a size smaller than the current one (with --force)
+        #[arg(long)]
+        shrink: bool,
+        /// Confirm a destructive shrink
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Shrink the qcow2 disks of a stopped VM to the data they hold (qemu-img convert)
+    Compact {
+        name: String,
+        /// Only this disk, by index in vm.json (0 = boot disk)
+        #[arg(long)]
+        disk: Option<usize>,
+    },
+
+    /// Build a cloud-init NoCloud seed ISO and attach it on every start
+    CloudInit {
+        name: String,
+        /// cloud-init user-data file
+        #[arg(long, required_unless_present = "remove")]
+        user_data: Option<PathBuf>,
+        /// cloud-init meta-data file (generated from name/UUID if omitted)
+        #[arg(long)]
+        meta_data: Option<PathBuf>,
+        /// cloud-init network-config file
+        #[arg(long)]
+        network_config: Option<PathBuf>,
+        /// Detach and delete the current seed ISO
+        #[arg(long, conflicts_with_all = ["user_data", "meta_data", "network_config"])]
+        remove: bool,
+    },
+
+    /// Persist an ISO attached (and booted first) on every start
+    SetIso {
+        name: String,
+        /// ISO path; relative paths resolve under the VM root
+        #[arg(required_unless_present = "remove")]
+        iso: Option<PathBuf>,
+        /// Detach the persisted ISO
+        #[arg(long, conflicts_with = "iso")]
+        remove: bool,
+    },
+
+    /// Save a running VM's display as PPM (or PNG when the file name ends in .png)
+    Screenshot { name: String, out: PathBuf },
+
+    /// Send key combos to a running VM's keyboard, e.g. `qvm sendkey my-vm ctrl-alt-delete` or `... a b ret`
+    Sendkey {
+        name: String,
+        /// Combos pressed one after another; keys within a combo are joined by '-'
+        #[arg(required = true)]
+        keys: Vec<String>,
+        /// How long each combo is held down, in milliseconds
+        #[arg(long, default_value_t = 100)]
+        hold_ms: u32,
+    },
+
+    /// Pause a running VM (QMP stop)
+    Pause { name: String },
+
+    /// Resume a paused VM (QMP cont)
+    Resume { name: String },
+
+    /// Manage internal qcow2 snapshots
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotCmd,
+    },
+
+    /// Print (or write) a libvirt domain XML for the VM, for virsh define / virt-manager
+    ExportLibvirt {
         name: String,
+        /// Write the XML to this file instead of stdout
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Back up a VM's disks and vm.json into a timestamped directory (works while running)
+    Backup {
+        name: String,
+        /// Directory holding the backups and their manifest (backups.json)
+        dest: PathBuf,
+    },
+
+    /// Bundle a stopped VM into a tar archive
+    Export {
+        name: String,
+        /// Archive to write (e.g., my-vm.tar)
+        file: PathBuf,
+    },
+
+    /// Create a VM from a complete vm.json written elsewhere ('-' reads it from stdin)
+    ImportConfig {
+        /// vm.json to read, or '-' for stdin
+        file: PathBuf,
+        /// Name the VM this instead of its meta.name
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// Restore a VM from an archive made by `qvm export`
+    Import {
+        /// Archive to read
+        file: PathBuf,
+        /// Import under a different name
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// Manage host directories shared into the guest (virtiofs or 9p)
+    Share {
+        #[command(subcommand)]
+        action: ShareCmd,
+    },
+
+    /// Manage host→guest port forwards (user-mode networking)
+    Forward {
+        #[command(subcommand)]
+        action: ForwardCmd,
+    },
+
+    /// Manage guest network interfaces
+    Nic {
+        #[command(subcommand)]
+        action: NicCmd,
+    },
+
+    /// Manage USB devices (emulated tablet/keyboard, host passthrough)
+    Usb {
+        #[command(subcommand)]
+        action: UsbCmd,
+    },
+
+    /// Attach to the serial console of a running VM (Ctrl-] to detach)
+    Console { name: String },
+
+    /// Attach to the HMP monitor of a running VM (`info registers`, `sendkey`, ...; Ctrl-] to detach)
+    Monitor { name: String },
+
+    /// Print the qemu output of a VM started with --daemon
+    Logs {
+        name: String,
+        /// Keep printing new output as it is written
+        #[arg(long, short)]
+        follow: bool,
+        /// Only show the last N lines
+        #[arg(long, short = 'n')]
+        lines: Option<usize>,
+    },
+
+    /// Send a raw QMP command to a running VM and print the response
+    Qmp {
+        name: String,
+        /// Command as JSON, e.g. '{"execute":"query-status"}'
+        command: String,
+    },
+
+    /// Delete VMs and their associated files (names, shell-style patterns such as 'test-*', or --all)
+    Delete {
+        #[arg(required_unless_present = "all")]
+        names: Vec<String>,
+        /// Delete every VM
+        #[arg(long, conflicts_with = "names")]
+        all: bool,
         /// Skip confirmation prompt
         #[arg(long, short)]
         force: bool,
@@ -143,6 +625,49 @@ pub enum Cmd {
         spice_sock: Option<PathBuf>,
         #[arg(long)]
         spice_disable_ticketing: Option<bool>,
+
+        // TLS (VNC or SPICE, whichever `mode` is)
+        /// Encrypt the display with ca-cert.pem, server-cert.pem and server-key.pem from DIR
+        #[arg(long, value_name = "DIR", conflicts_with = "no_tls")]
+        tls_cert_dir: Option<PathBuf>,
+        /// Require clients to present a certificate signed by the CA (VNC only)
+        #[arg(long, requires = "tls_cert_dir")]
+        tls_verify_peer: bool,
+        /// Serve the display without TLS again
+        #[arg(long)]
+        no_tls: bool,
+    },
+
+    /// Change a VM's sound card (applies on next start)
+    SetAudio {
+        name: String,
+        /// Host audio backend (none removes the sound card)
+        #[arg(value_parser = ["none","coreaudio","pa","pipewire","alsa","sdl"])]
+        backend: String,
+        /// Guest sound card model (default: keep the current one)
+        #[arg(long, value_parser = ["intel-hda","usb-audio"])]
+        model: Option<String>,
+    },
+
+    /// Set the VNC/SPICE display password (prompted for unless --env is given)
+    SetPassword {
+        name: String,
+        /// Read the password from this environment variable at every start instead of storing it
+        #[arg(long, value_name = "VAR")]
+        env: Option<String>,
+        /// Remove the password
+        #[arg(long, conflicts_with = "env")]
+        clear: bool,
+    },
+
+    /// Check that qemu, firmware and helper tools are installed (exits 1 on failure)
+    Doctor,
+
+    /// Show the qvm version (and with --full the qemu, firmware and accelerator it finds)
+    Version {
+        /// Also report the qemu binaries and firmware for each arch and the accelerator
+        #[arg(long)]
+        full: bool,
     },
 
     /// Generate shell completions
@@ -151,9 +676,105 @@ pub enum Cmd {
         shell: Shell,
     },
 
+    /// Print a systemd user unit (Linux) or launchd agent (macOS) that starts the VM at
+    /// login and stops it on shutdown; --install writes it where the service manager looks
+    InstallService {
+        name: String,
+        /// Write the definition instead of printing it
+        #[arg(long)]
+        install: bool,
+        /// Service manager to generate for (default: this host's)
+        #[arg(long, value_parser = ["systemd", "launchd"])]
+        kind: Option<String>,
+    },
+
     /// Install Fish shell completions automatically
     InstallFish,
 
     /// Generate man page
     ManPage,
-}
\ No newline at end of file
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SnapshotCmd {
+    /// Take a snapshot (savevm when running, qemu-img when stopped)
+    Create { vm: String, tag: String },
+    /// List snapshots
+    List { vm: String },
+    /// Revert to a snapshot (loadvm when running, qemu-img when stopped)
+    Restore { vm: String, tag: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ShareCmd {
+    /// Share a host directory with the guest
+    Add {
+        vm: String,
+        path: PathBuf,
+        /// Mount tag used inside the guest (default: the directory name)
+        #[arg(long)]
+        tag: Option<String>,
+        /// Export the directory read-only
+        #[arg(long)]
+        readonly: bool,
+    },
+    /// Stop sharing a directory
+    Remove { vm: String, tag: String },
+    /// List shared directories
+    List { vm: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ForwardCmd {
+    /// Forward a host port to a guest port
+    Add {
+        vm: String,
+        host_port: u16,
+        guest_port: u16,
+        #[arg(long, value_parser = ["tcp","udp"], default_value = "tcp")]
+        protocol: String,
+        /// NIC to forward to, by index (default: the first user-mode NIC)
+        #[arg(long)]
+        nic: Option<usize>,
+    },
+    /// Remove the forward listening on a host port
+    Remove {
+        vm: String,
+        host_port: u16,
+        #[arg(long, value_parser = ["tcp","udp"], default_value = "tcp")]
+        protocol: String,
+    },
+    /// List port forwards
+    List { vm: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum NicCmd {
+    /// Add a NIC; it is attached on the next start
+    Add {
+        vm: String,
+        /// Network mode (vmnet-shared|vmnet-bridged|user)
+        #[arg(long, value_parser = ["vmnet-shared","vmnet-bridged","user"], default_value = "user")]
+        mode: String,
+        /// Bridge interface (when vmnet-bridged)
+        #[arg(long, default_value = "en0")]
+        bridge_if: String,
+        /// MAC address (default: random 52:54:00:xx:xx:xx)
+        #[arg(long)]
+        mac: Option<String>,
+    },
+    /// Remove the NIC at an index (see 'list'), with its port forwards
+    Remove { vm: String, index: usize },
+    /// List NICs
+    List { vm: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum UsbCmd {
+    /// Attach a device: tablet, keyboard, or a host device as vendor:product (e.g. 046d:c52b)
+    Add { vm: String, device: String },
+    /// Detach a device (same spec as add)
+    Remove { vm: String, device: String },
+    /// List USB devices
+    List { vm: String },
+}
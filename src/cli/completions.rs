@@ -1,11 +1,11 @@
 //! Shell completion utilities
 
 use crate::cli::commands::Cli;
-use crate::Result;
-use anyhow::anyhow;
+use crate::{QvmError, Result};
 use clap::{CommandFactory};
 use clap_complete::{generate, Generator, Shell};
 use std::fs::{self, File};
+use std::io::Write;
 
 /// Generate shell completions
 pub fn print_completions<G: Generator>(gen: G, cmd: &mut clap::Command) {
@@ -15,7 +15,7 @@ pub fn print_completions<G: Generator>(gen: G, cmd: &mut clap::Command) {
 /// Install Fish completions automatically
 pub fn install_fish_completions() -> Result<()> {
     let home_dir = dirs::home_dir()
-        .ok_or_else(|| anyhow!("Could not find home directory"))?;
+        .ok_or(QvmError::HomeNotFound)?;
 
     let fish_dir = home_dir
         .join(".config")
@@ -41,6 +41,6 @@ pub fn generate_man_page() -> Result<()> {
     let mut buffer: Vec<u8> = Vec::new();
     man.render(&mut buffer)?;
 
-    print!("{}", String::from_utf8(buffer)?);
+    std::io::stdout().write_all(&buffer)?;
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,160 @@
+//! `qvm doctor`: environment diagnostics
+
+use crate::utils::paths::qvm_home;
+use crate::utils::system::{detect_accel, pick_qemu_bin, qemu_version};
+use crate::vm::cloud_init::find_iso_tool;
+use crate::vm::firmware::locate_firmware_from_qemu;
+use crate::vm::share::find_virtiofsd;
+use std::ffi::CString;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    /// Optional component missing; some features are unavailable
+    Warn,
+    /// Critical component missing; VMs cannot be created or started
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        })
+    }
+}
+
+/// One line of the doctor report
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: Status, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Run every environment check. Missing pieces for the host's own arch are failures;
+/// for the other arch, and for optional helpers, they are warnings.
+pub fn run_checks() -> Vec<Check> {
+    let mut checks = Vec::new();
+    let host_arch = std::env::consts::ARCH;
+
+    for arch in ["aarch64", "x86_64"] {
+        let missing = if arch == host_arch { Status::Fail } else { Status::Warn };
+        match pick_qemu_bin(arch) {
+            Ok(bin) => {
+                checks.push(match qemu_version(&bin) {
+                    Ok(version) => Check::new(
+                        format!("qemu-system-{}", arch),
+                        Status::Pass,
+                        format!("{} (QEMU {})", bin.display(), version),
+                    ),
+                    Err(e) => Check::new(format!("qemu-system-{}", arch), Status::Warn, e.to_string()),
+                });
+                checks.push(match locate_firmware_from_qemu(&bin, arch, false) {
+                    Ok((code, _)) => Check::new(
+                        format!("UEFI firmware ({})", arch),
+                        Status::Pass,
+                        code.display().to_string(),
+                    ),
+                    Err(e) => Check::new(format!("UEFI firmware ({})", arch), missing, e.to_string()),
+                });
+            }
+            Err(e) => checks.push(Check::new(format!("qemu-system-{}", arch), missing, e.to_string())),
+        }
+    }
+
+    checks.push(tool_check("qemu-img", Status::Fail, "needed to create disks and snapshots"));
+
+    let accel = detect_accel(host_arch);
+    checks.push(if accel == "tcg" {
+        Check::new(
+            "accelerator",
+            Status::Warn,
+            "none available; guests will run under slow TCG emulation",
+        )
+    } else {
+        Check::new("accelerator", Status::Pass, accel)
+    });
+
+    checks.push(home_check());
+
+    checks.push(tool_check("swtpm", Status::Warn, "needed for --tpm"));
+    checks.push(match find_virtiofsd() {
+        Some(path) => Check::new("virtiofsd", Status::Pass, path.display().to_string()),
+        None => Check::new("virtiofsd", Status::Warn, "not found; shares fall back to 9p"),
+    });
+    checks.push(match find_iso_tool() {
+        Ok((path, _)) => Check::new("ISO tool", Status::Pass, path.display().to_string()),
+        Err(e) => Check::new("ISO tool", Status::Warn, e.to_string()),
+    });
+
+    checks
+}
+
+/// True when no check failed
+pub fn all_passed(checks: &[Check]) -> bool {
+    checks.iter().all(|c| c.status != Status::Fail)
+}
+
+/// Print the report as aligned `STATUS name  detail` lines
+pub fn print_report(checks: &[Check]) {
+    let width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    for c in checks {
+        println!("[{}] {:<width$}  {}", c.status, c.name, c.detail, width = width);
+    }
+}
+
+fn tool_check(name: &str, missing: Status, why: &str) -> Check {
+    match which::which(name) {
+        Ok(path) => Check::new(name, Status::Pass, path.display().to_string()),
+        Err(_) => Check::new(name, missing, format!("not found on PATH ({})", why)),
+    }
+}
+
+/// qvm home must be a writable directory, or creatable under a writable parent
+fn home_check() -> Check {
+    let home = match qvm_home() {
+        Ok(home) => home,
+        Err(e) => return Check::new("qvm home", Status::Fail, e.to_string()),
+    };
+    let (target, note) = if home.is_dir() {
+        (home.as_path(), "")
+    } else {
+        match home.parent() {
+            Some(parent) => (parent, " (will be created)"),
+            None => (home.as_path(), ""),
+        }
+    };
+    if writable(target) {
+        Check::new("qvm home", Status::Pass, format!("{}{}", home.display(), note))
+    } else {
+        Check::new(
+            "qvm home",
+            Status::Fail,
+            format!("{} is not writable", target.display()),
+        )
+    }
+}
+
+fn writable(path: &Path) -> bool {
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(c_path.as_ptr(), libc::W_OK) == 0 }
+}
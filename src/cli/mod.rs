@@ -1,4 +1,9 @@
 //! CLI interface for QVM
 
 pub mod commands;
-pub mod completions;
\ No newline at end of file
+pub mod completions;
+pub mod doctor;
+pub mod output;
+pub mod service;
+pub mod version;
+pub mod which;
\ No newline at end of file
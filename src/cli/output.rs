@@ -0,0 +1,74 @@
+//! Command results for people and for scripts (`--output text|json`)
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// How a command reports its result on stdout
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// The result of one command: lines of text for a terminal, and the same facts as a
+/// flat JSON object (always with an `action` key) for scripts
+#[derive(Debug, Clone)]
+pub struct Report {
+    fields: Map<String, Value>,
+    lines: Vec<String>,
+}
+
+impl Report {
+    /// Start a report for `action` (the subcommand name)
+    pub fn new(action: &str) -> Self {
+        let mut fields = Map::new();
+        fields.insert("action".to_string(), Value::from(action));
+        Self { fields, lines: Vec::new() }
+    }
+
+    /// Add a JSON field
+    pub fn field(mut self, key: &str, value: impl Serialize) -> Self {
+        self.fields.insert(key.to_string(), to_value(value));
+        self
+    }
+
+    /// Add every field of a struct that serializes to a JSON object
+    pub fn fields(mut self, value: impl Serialize) -> Self {
+        if let Value::Object(map) = to_value(value) {
+            self.fields.extend(map);
+        }
+        self
+    }
+
+    /// Add a line of text output
+    pub fn line(mut self, text: impl Into<String>) -> Self {
+        self.lines.push(text.into());
+        self
+    }
+
+    /// The JSON form of the report
+    pub fn to_json(&self) -> Value {
+        Value::Object(self.fields.clone())
+    }
+
+    /// Print the report to stdout in `format`
+    pub fn print(&self, format: OutputFormat) {
+        match format {
+            OutputFormat::Text => {
+                for line in &self.lines {
+                    println!("{}", line);
+                }
+            }
+            OutputFormat::Json => {
+                println!("{}", serde_json::to_string_pretty(&self.fields).unwrap_or_default())
+            }
+        }
+    }
+}
+
+// Only plain data (strings, numbers, paths, derived structs) is reported, which always serializes
+fn to_value(value: impl Serialize) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
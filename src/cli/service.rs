@@ -0,0 +1,210 @@
+//! `qvm install-service`: a systemd user unit (Linux) or launchd agent (macOS) that
+//! starts a VM at login and stops it on logout or shutdown
+
+use crate::utils::paths::{find_vm_dir, pid_path, qvm_home};
+use crate::utils::system::shell_quote;
+use crate::{QvmError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Which service manager to generate a definition for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceKind {
+    Systemd,
+    Launchd,
+}
+
+impl ServiceKind {
+    /// The service manager of this host
+    pub fn host() -> Self {
+        if cfg!(target_os = "macos") {
+            ServiceKind::Launchd
+        } else {
+            ServiceKind::Systemd
+        }
+    }
+
+    /// Parse `systemd` or `launchd`
+    pub fn parse(kind: &str) -> Result<Self> {
+        match kind {
+            "systemd" => Ok(ServiceKind::Systemd),
+            "launchd" => Ok(ServiceKind::Launchd),
+            other => Err(QvmError::Invalid(format!(
+                "Unknown service kind '{}' (systemd or launchd)",
+                other
+            ))),
+        }
+    }
+}
+
+/// What a service definition runs: the qvm binary, the VM and where it lives
+#[derive(Debug, Clone)]
+pub struct ServiceSpec {
+    pub name: String,
+    pub qvm: PathBuf,
+    pub home: PathBuf,
+    pub vm_dir: PathBuf,
+}
+
+impl ServiceSpec {
+    /// The spec for VM `name`, run by the qvm binary executing now
+    pub fn for_vm(name: &str) -> Result<Self> {
+        Ok(ServiceSpec {
+            name: name.to_string(),
+            qvm: std::env::current_exe()?,
+            home: qvm_home()?,
+            vm_dir: find_vm_dir(name)?,
+        })
+    }
+}
+
+/// A systemd user unit: `qvm start --daemon` forks off qemu, whose pidfile systemd tracks
+pub fn systemd_unit(spec: &ServiceSpec) -> String {
+    let qvm = systemd_quote(&spec.qvm.display().to_string());
+    let name = systemd_quote(&spec.name);
+    format!(
+        "[Unit]\n\
+         Description=qvm VM {name_raw}\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=forking\n\
+         Environment={home}\n\
+         PIDFile={pid}\n\
+         ExecStart={qvm} start {name} --daemon\n\
+         ExecStop={qvm} stop {name}\n\
+         TimeoutStopSec=45\n\
+         \n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        name_raw = spec.name,
+        home = systemd_quote(&format!("QVM_HOME={}", spec.home.display())),
+        pid = pid_path(&spec.vm_dir).display().to_string().replace('%', "%%"),
+    )
+}
+
+/// A launchd agent. launchd has no stop command, so a shell keeps the job alive after
+/// `qvm start --daemon` and runs `qvm stop` when launchd sends it SIGTERM.
+pub fn launchd_plist(spec: &ServiceSpec) -> String {
+    let qvm = shell_quote(&spec.qvm.display().to_string());
+    let name = shell_quote(&spec.name);
+    let script = format!(
+        "stop() {{ {qvm} stop {name}; exit 0; }}; trap stop TERM; \
+         {qvm} start {name} --daemon || exit 1; \
+         while :; do sleep 86400 & wait $!; done"
+    );
+    let log = spec.vm_dir.join("service.log");
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>/bin/sh</string>
+        <string>-c</string>
+        <string>{script}</string>
+    </array>
+    <key>EnvironmentVariables</key>
+    <dict>
+        <key>QVM_HOME</key>
+        <string>{home}</string>
+    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>ExitTimeOut</key>
+    <integer>45</integer>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+        label = xml_escape(&launchd_label(&spec.name)),
+        script = xml_escape(&script),
+        home = xml_escape(&spec.home.display().to_string()),
+        log = xml_escape(&log.display().to_string()),
+    )
+}
+
+/// The service definition of `kind` for `spec`
+pub fn render(kind: ServiceKind, spec: &ServiceSpec) -> String {
+    match kind {
+        ServiceKind::Systemd => systemd_unit(spec),
+        ServiceKind::Launchd => launchd_plist(spec),
+    }
+}
+
+/// Where the definition for VM `name` is installed
+pub fn service_file(kind: ServiceKind, name: &str) -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or(QvmError::HomeNotFound)?;
+    Ok(match kind {
+        ServiceKind::Systemd => home
+            .join(".config/systemd/user")
+            .join(format!("qvm-{}.service", name)),
+        ServiceKind::Launchd => home
+            .join("Library/LaunchAgents")
+            .join(format!("{}.plist", launchd_label(name))),
+    })
+}
+
+/// The command that enables an installed definition
+pub fn enable_command(kind: ServiceKind, file: &Path) -> String {
+    match kind {
+        ServiceKind::Systemd => format!(
+            "systemctl --user daemon-reload && systemctl --user enable --now {}",
+            shell_quote(&file.file_name().unwrap_or_default().to_string_lossy())
+        ),
+        ServiceKind::Launchd => format!(
+            "launchctl bootstrap gui/$(id -u) {}",
+            shell_quote(&file.display().to_string())
+        ),
+    }
+}
+
+/// Print the service definition for VM `name`, or with `install` write it to the service
+/// manager's directory and print how to enable it
+pub fn install_service(name: &str, kind: ServiceKind, install: bool) -> Result<()> {
+    let spec = ServiceSpec::for_vm(name)?;
+    let text = render(kind, &spec);
+    if !install {
+        print!("{}", text);
+        return Ok(());
+    }
+
+    let file = service_file(kind, name)?;
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&file, text)?;
+    println!("Wrote {}", file.display());
+    println!("Enable it with: {}", enable_command(kind, &file));
+    if kind == ServiceKind::Systemd {
+        println!("To start it at boot without logging in: loginctl enable-linger $USER");
+    }
+    Ok(())
+}
+
+fn launchd_label(name: &str) -> String {
+    format!("local.qvm.{}", name)
+}
+
+/// Quote a word for a systemd command line or assignment (`%` starts a specifier)
+fn systemd_quote(word: &str) -> String {
+    let word = word.replace('%', "%%");
+    if word.chars().any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';')) {
+        format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        word
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,115 @@
+//! `qvm version`: the qvm build and, with `--full`, the qemu, firmware and accelerator it
+//! finds on this host, for bug reports
+
+use crate::cli::output::Report;
+use crate::utils::system::{detect_accel, pick_qemu_bin, qemu_version};
+use crate::vm::firmware::locate_firmware_from_qemu;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// The crate version with the commit it was built from, when known: `0.1.0 (1a2b3c4d5e6f)`
+pub const VERSION: &str = env!("QVM_VERSION");
+
+/// The commit qvm was built from, if it was built from a git checkout
+pub const GIT_HASH: Option<&str> = option_env!("QVM_GIT_HASH");
+
+/// The qemu and UEFI firmware found for one guest architecture
+#[derive(Serialize, Debug, Clone)]
+pub struct ArchInfo {
+    pub arch: String,
+    pub qemu_bin: Option<PathBuf>,
+    pub qemu_version: Option<String>,
+    pub firmware: Option<PathBuf>,
+    /// Why the binary, its version or the firmware could not be found
+    pub error: Option<String>,
+}
+
+/// What `qvm version` reports; the host fields are only filled in for `--full`
+#[derive(Serialize, Debug, Clone)]
+pub struct VersionInfo {
+    pub version: String,
+    pub git_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_os: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_arch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accelerator: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub qemu: Vec<ArchInfo>,
+}
+
+/// Gather the version, and with `full` the qemu binaries, firmware and accelerator
+pub fn collect(full: bool) -> VersionInfo {
+    let mut info = VersionInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_hash: GIT_HASH.map(str::to_string),
+        host_os: None,
+        host_arch: None,
+        accelerator: None,
+        qemu: Vec::new(),
+    };
+    if full {
+        let host_arch = std::env::consts::ARCH;
+        info.host_os = Some(std::env::consts::OS.to_string());
+        info.host_arch = Some(host_arch.to_string());
+        info.accelerator = Some(detect_accel(host_arch));
+        info.qemu = ["aarch64", "x86_64"].into_iter().map(arch_info).collect();
+    }
+    info
+}
+
+fn arch_info(arch: &str) -> ArchInfo {
+    let mut info = ArchInfo {
+        arch: arch.to_string(),
+        qemu_bin: None,
+        qemu_version: None,
+        firmware: None,
+        error: None,
+    };
+    let bin = match pick_qemu_bin(arch) {
+        Ok(bin) => bin,
+        Err(e) => {
+            info.error = Some(e.to_string());
+            return info;
+        }
+    };
+    match qemu_version(&bin) {
+        Ok(version) => info.qemu_version = Some(version.to_string()),
+        Err(e) => info.error = Some(e.to_string()),
+    }
+    match locate_firmware_from_qemu(&bin, arch, false) {
+        Ok((code, _)) => info.firmware = Some(code),
+        Err(e) => {
+            info.error.get_or_insert(e.to_string());
+        }
+    }
+    info.qemu_bin = Some(bin);
+    info
+}
+
+/// The `qvm version` report: one line for qvm, then one per host detail and guest arch
+pub fn report(info: &VersionInfo) -> Report {
+    let mut report = Report::new("version").fields(info).line(format!("qvm {}", VERSION));
+    if let (Some(os), Some(arch), Some(accel)) = (&info.host_os, &info.host_arch, &info.accelerator) {
+        report = report.line(format!("host: {} {}, accelerator {}", os, arch, accel));
+    }
+    for q in &info.qemu {
+        let qemu = match (&q.qemu_bin, &q.qemu_version) {
+            (Some(bin), Some(version)) => format!("{} (QEMU {})", bin.display(), version),
+            (Some(bin), None) => format!("{} (unknown version)", bin.display()),
+            (None, _) => "not found".to_string(),
+        };
+        report = report.line(format!("qemu-system-{}: {}", q.arch, qemu));
+        if q.qemu_bin.is_some() {
+            report = report.line(match &q.firmware {
+                Some(code) => format!("  firmware: {}", code.display()),
+                None => "  firmware: not found".to_string(),
+            });
+        }
+        if let Some(e) = &q.error {
+            report = report.line(format!("  error: {}", e));
+        }
+    }
+    report
+}
@@ -0,0 +1,153 @@
+//! `qvm which`: the qemu binary and firmware a VM resolves to, and everywhere qvm looked
+
+use crate::cli::output::Report;
+use crate::config::schema::VmConfig;
+use crate::utils::paths::resolve_under_root;
+use crate::utils::system::{pick_vm_qemu_bin, qemu_bin_candidates, qemu_version, QEMU_BIN_ENV};
+use crate::vm::firmware::{firmware_dir_override, firmware_pairs, firmware_search_dirs, locate_firmware_from_qemu};
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// A path looked at during detection and whether it was there
+#[derive(Serialize, Debug, Clone)]
+pub struct Probe {
+    pub path: PathBuf,
+    pub found: bool,
+}
+
+/// How the qemu binary and firmware of one VM are resolved on this host
+#[derive(Serialize, Debug, Clone)]
+pub struct Resolution {
+    pub name: String,
+    pub arch: String,
+    /// Where the binary comes from: `$QVM_QEMU_BIN`, the VM's pinned `paths.qemu_bin`, or
+    /// detection
+    pub qemu_source: String,
+    pub qemu_searched: Vec<Probe>,
+    pub qemu_bin: Option<PathBuf>,
+    pub qemu_version: Option<String>,
+    /// Why no usable binary (or version) was found
+    pub qemu_error: Option<String>,
+    /// What detection would pick now, from the resolved binary
+    pub firmware_code: Option<PathBuf>,
+    pub firmware_vars: Option<PathBuf>,
+    pub firmware_error: Option<String>,
+    /// The code/vars file name pairs looked for, most preferred first
+    pub firmware_names: Vec<String>,
+    pub firmware_searched: Vec<Probe>,
+    /// What vm.json records (and `start` uses while the code file exists)
+    pub configured_code: Probe,
+    pub configured_vars: Probe,
+}
+
+/// Resolve the qemu binary and firmware of the VM of `cfg` the way `create` and `start` do,
+/// recording each candidate path
+pub fn resolve(cfg: &VmConfig) -> Resolution {
+    let arch = &cfg.meta.arch;
+    let probe = |path: PathBuf| Probe { found: path.is_file(), path };
+
+    let env_bin = std::env::var_os(QEMU_BIN_ENV).filter(|b| !b.is_empty());
+    let (qemu_source, qemu_searched) = match (&env_bin, &cfg.paths.qemu_bin) {
+        (Some(bin), _) => (format!("${} (--qemu-bin)", QEMU_BIN_ENV), vec![probe(PathBuf::from(bin))]),
+        (None, Some(pinned)) => ("paths.qemu_bin (pinned)".to_string(), vec![probe(pinned.clone())]),
+        (None, None) => (
+            "detected".to_string(),
+            qemu_bin_candidates(arch).unwrap_or_default().into_iter().map(probe).collect(),
+        ),
+    };
+
+    let mut res = Resolution {
+        name: cfg.meta.name.clone(),
+        arch: arch.clone(),
+        qemu_source,
+        qemu_searched,
+        qemu_bin: None,
+        qemu_version: None,
+        qemu_error: None,
+        firmware_code: None,
+        firmware_vars: None,
+        firmware_error: None,
+        firmware_names: firmware_pairs(arch, cfg.firmware.secure_boot)
+            .map(|pairs| pairs.iter().map(|(code, vars)| format!("{} + {}", code, vars)).collect())
+            .unwrap_or_default(),
+        firmware_searched: Vec::new(),
+        configured_code: probe(resolve_under_root(&cfg.paths.root, &cfg.firmware.code)),
+        configured_vars: probe(resolve_under_root(&cfg.paths.root, &cfg.firmware.vars_template)),
+    };
+
+    let bin = match pick_vm_qemu_bin(cfg) {
+        Ok(bin) => bin,
+        Err(e) => {
+            res.qemu_error = Some(e.to_string());
+            return res;
+        }
+    };
+    match qemu_version(&bin) {
+        Ok(version) => res.qemu_version = Some(version.to_string()),
+        Err(e) => res.qemu_error = Some(e.to_string()),
+    }
+    res.firmware_searched = firmware_search_dirs(firmware_dir_override().as_deref(), &bin, arch)
+        .into_iter()
+        .map(|dir| Probe { found: dir.is_dir(), path: dir })
+        .collect();
+    match locate_firmware_from_qemu(&bin, arch, cfg.firmware.secure_boot) {
+        Ok((code, vars)) => {
+            res.firmware_code = Some(code);
+            res.firmware_vars = Some(vars);
+        }
+        Err(e) => res.firmware_error = Some(e.to_string()),
+    }
+    res.qemu_bin = Some(bin);
+    res
+}
+
+/// The `qvm which` report: the resolved paths first, then the searches behind them
+pub fn report(res: &Resolution) -> Report {
+    let mut report = Report::new("which")
+        .fields(res)
+        .line(format!("VM '{}' ({})", res.name, res.arch));
+
+    report = match (&res.qemu_bin, &res.qemu_version) {
+        (Some(bin), Some(version)) => {
+            report.line(format!("qemu: {} (QEMU {}, {})", bin.display(), version, res.qemu_source))
+        }
+        (Some(bin), None) => report.line(format!("qemu: {} ({})", bin.display(), res.qemu_source)),
+        (None, _) => report.line(format!("qemu: not found ({})", res.qemu_source)),
+    };
+    if let Some(e) = &res.qemu_error {
+        report = report.line(format!("  error: {}", e));
+    }
+    report = report.line("  searched:");
+    for p in &res.qemu_searched {
+        report = report.line(probe_line(p, "missing"));
+    }
+
+    report = match (&res.firmware_code, &res.firmware_vars) {
+        (Some(code), Some(vars)) => report
+            .line(format!("firmware: {}", code.display()))
+            .line(format!("  vars template: {}", vars.display())),
+        _ => report.line("firmware: not found"),
+    };
+    if let Some(e) = &res.firmware_error {
+        report = report.line(format!("  error: {}", e));
+    }
+    report = report.line(format!("  looked for: {}", res.firmware_names.join(", ")));
+    if !res.firmware_searched.is_empty() {
+        report = report.line("  searched:");
+        for p in &res.firmware_searched {
+            report = report.line(probe_line(p, "no such directory"));
+        }
+    }
+    report
+        .line("vm.json firmware:")
+        .line(probe_line(&res.configured_code, "missing; start re-detects it"))
+        .line(probe_line(&res.configured_vars, "missing"))
+}
+
+fn probe_line(p: &Probe, missing: &str) -> String {
+    if p.found {
+        format!("    {}", p.path.display())
+    } else {
+        format!("    {} ({})", p.path.display(), missing)
+    }
+}
@@ -0,0 +1,351 @@
+//! Best-effort libvirt `<domain>` XML for a VM configuration
+
+use crate::config::schema::{Display, VmConfig};
+use crate::utils::paths::resolve_under_root;
+use crate::vm::launch::PASSWORD_ENV_PREFIX;
+use std::fmt::Write;
+
+/// Map a VM configuration to a libvirt domain definition (`virsh define`).
+///
+/// Covers memory, CPU model and topology, UEFI loader and NVRAM, disks, the attached ISO
+/// and cloud-init seed, the NICs, graphics, TPM, sound, USB devices and shared folders. Things libvirt has
+/// no portable equivalent for are approximated: NVMe and IDE disks become SATA, vmnet
+/// shared networking uses the `default` network, and the Cocoa display becomes VNC.
+pub fn domain_xml(cfg: &VmConfig) -> String {
+    let root = cfg.paths.root.as_path();
+    let hw = &cfg.hardware;
+    let mut x = String::new();
+
+    let domain_type = match hw.accel.as_str() {
+        "kvm" => "kvm",
+        "hvf" => "hvf",
+        _ => "qemu",
+    };
+    let (machine, machine_opts) = hw.machine.split_once(',').unwrap_or((&hw.machine, ""));
+    let gic = machine_opts
+        .split(',')
+        .find_map(|opt| opt.strip_prefix("gic-version="));
+
+    line(&mut x, 0, &format!("<domain type=\"{}\">", domain_type));
+    line(&mut x, 1, &format!("<name>{}</name>", esc(&cfg.meta.name)));
+    line(&mut x, 1, &format!("<uuid>{}</uuid>", esc(&cfg.meta.uuid)));
+    line(&mut x, 1, &format!("<memory unit=\"MiB\">{}</memory>", hw.mem_mb));
+    line(&mut x, 1, &format!("<vcpu>{}</vcpu>", cfg.vcpu_total()));
+    if !cfg.shares.is_empty() {
+        // virtiofs needs guest RAM that virtiofsd can map
+        line(&mut x, 1, "<memoryBacking>");
+        line(&mut x, 2, "<source type=\"memfd\"/>");
+        line(&mut x, 2, "<access mode=\"shared\"/>");
+        line(&mut x, 1, "</memoryBacking>");
+    }
+
+    line(&mut x, 1, "<os>");
+    line(
+        &mut x,
+        2,
+        &format!(
+            "<type arch=\"{}\" machine=\"{}\">hvm</type>",
+            esc(&cfg.meta.arch),
+            esc(machine)
+        ),
+    );
+    let secure = if cfg.firmware.secure_boot { " secure=\"yes\"" } else { "" };
+    line(
+        &mut x,
+        2,
+        &format!(
+            "<loader readonly=\"yes\"{} type=\"pflash\">{}</loader>",
+            secure,
+            esc(&resolve_under_root(root, &cfg.firmware.code).display().to_string())
+        ),
+    );
+    line(
+        &mut x,
+        2,
+        &format!(
+            "<nvram template=\"{}\">{}</nvram>",
+            esc(&resolve_under_root(root, &cfg.firmware.vars_template).display().to_string()),
+            esc(&resolve_under_root(root, &cfg.paths.efi_vars).display().to_string())
+        ),
+    );
+    for (tag, path) in [("kernel", &cfg.boot.kernel), ("initrd", &cfg.boot.initrd)] {
+        if let Some(p) = path {
+            let p = resolve_under_root(root, p).display().to_string();
+            line(&mut x, 2, &format!("<{tag}>{}</{tag}>", esc(&p)));
+        }
+    }
+    if let Some(cmdline) = &cfg.boot.cmdline {
+        line(&mut x, 2, &format!("<cmdline>{}</cmdline>", esc(cmdline)));
+    }
+    line(&mut x, 1, "</os>");
+
+    line(&mut x, 1, "<features>");
+    line(&mut x, 2, "<acpi/>");
+    if cfg.meta.arch == "x86_64" {
+        line(&mut x, 2, "<apic/>");
+        if cfg.firmware.secure_boot {
+            line(&mut x, 2, "<smm state=\"on\"/>");
+        }
+    }
+    if let Some(version) = gic {
+        line(&mut x, 2, &format!("<gic version=\"{}\"/>", esc(version)));
+    }
+    line(&mut x, 1, "</features>");
+
+    // CPU flags have no direct libvirt spelling and are dropped
+    let model = hw.cpu_model.split(',').next().unwrap_or_default();
+    if model == "host" {
+        line(&mut x, 1, "<cpu mode=\"host-passthrough\">");
+    } else if model == "max" {
+        line(&mut x, 1, "<cpu mode=\"maximum\">");
+    } else {
+        line(&mut x, 1, "<cpu mode=\"custom\">");
+        line(&mut x, 2, &format!("<model fallback=\"allow\">{}</model>", esc(model)));
+    }
+    line(
+        &mut x,
+        2,
+        &format!(
+            "<topology sockets=\"{}\" cores=\"{}\" threads=\"{}\"/>",
+            hw.sockets, hw.cores, hw.threads
+        ),
+    );
+    line(&mut x, 1, "</cpu>");
+    line(&mut x, 1, &format!("<clock offset=\"{}\"/>", esc(&hw.rtc)));
+
+    line(&mut x, 1, "<devices>");
+
+    // Per-device boot order as `qvm start` passes it (libvirt counts from 1, qemu from 0);
+    // libvirt, like qemu, refuses it together with <os><boot dev>
+    let iso = cfg.paths.iso.as_ref();
+    let boot_order = |letter| cfg.boot.bootindex(letter, iso.is_some()).map(|i| i + 1);
+    let (mut vd, mut sd) = (0u8, 0u8);
+    let mut cdroms = Vec::new();
+    if let Some(iso) = iso {
+        cdroms.push((resolve_under_root(root, iso), boot_order('d')));
+    }
+    if let Some(ci) = &cfg.cloud_init {
+        cdroms.push((resolve_under_root(root, &ci.seed_iso), None));
+    }
+    for (path, boot) in &cdroms {
+        line(&mut x, 2, "<disk type=\"file\" device=\"cdrom\">");
+        line(&mut x, 3, "<driver name=\"qemu\" type=\"raw\"/>");
+        line(&mut x, 3, &format!("<source file=\"{}\"/>", esc(&path.display().to_string())));
+        line(&mut x, 3, &format!("<target dev=\"sd{}\" bus=\"scsi\"/>", drive_letter(sd)));
+        line(&mut x, 3, "<readonly/>");
+        if let Some(order) = boot {
+            line(&mut x, 3, &format!("<boot order=\"{}\"/>", order));
+        }
+        line(&mut x, 2, "</disk>");
+        sd += 1;
+    }
+
+    for (i, disk) in cfg.paths.disks.iter().enumerate() {
+        let target = if disk.interface == "virtio" {
+            vd += 1;
+            format!("<target dev=\"vd{}\" bus=\"virtio\"/>", drive_letter(vd - 1))
+        } else {
+            sd += 1;
+            format!("<target dev=\"sd{}\" bus=\"sata\"/>", drive_letter(sd - 1))
+        };
+        line(&mut x, 2, "<disk type=\"file\" device=\"disk\">");
+        line(&mut x, 3, &format!("<driver name=\"qemu\" type=\"{}\"/>", esc(&disk.format)));
+        line(
+            &mut x,
+            3,
+            &format!(
+                "<source file=\"{}\"/>",
+                esc(&resolve_under_root(root, &disk.path).display().to_string())
+            ),
+        );
+        line(&mut x, 3, &target);
+        if let Some(order) = boot_order('c').filter(|_| i == 0) {
+            line(&mut x, 3, &format!("<boot order=\"{}\"/>", order));
+        }
+        line(&mut x, 2, "</disk>");
+    }
+    if !cdroms.is_empty() {
+        line(&mut x, 2, "<controller type=\"scsi\" model=\"virtio-scsi\"/>");
+    }
+
+    for (i, nic) in cfg.network.interfaces.iter().enumerate() {
+        match nic.mode.as_str() {
+            "vmnet-bridged" => {
+                line(&mut x, 2, "<interface type=\"bridge\">");
+                line(&mut x, 3, &format!("<source bridge=\"{}\"/>", esc(&nic.bridge_if)));
+            }
+            "user" => line(&mut x, 2, "<interface type=\"user\">"),
+            _ => {
+                line(&mut x, 2, "<interface type=\"network\">");
+                line(&mut x, 3, "<source network=\"default\"/>");
+            }
+        }
+        line(&mut x, 3, &format!("<mac address=\"{}\"/>", esc(&nic.mac)));
+        line(&mut x, 3, "<model type=\"virtio\"/>");
+        if let Some(order) = ['n', 'o', 'p'].get(i).and_then(|&letter| boot_order(letter)) {
+            line(&mut x, 3, &format!("<boot order=\"{}\"/>", order));
+        }
+        line(&mut x, 2, "</interface>");
+    }
+
+    graphics(&mut x, &cfg.display, root);
+
+    line(&mut x, 2, "<serial type=\"pty\"/>");
+    line(&mut x, 2, "<console type=\"pty\"/>");
+
+    if cfg.tpm.enabled {
+        let model = if cfg.meta.arch == "x86_64" { "tpm-crb" } else { "tpm-tis" };
+        line(&mut x, 2, &format!("<tpm model=\"{}\">", model));
+        line(&mut x, 3, "<backend type=\"emulator\" version=\"2.0\"/>");
+        line(&mut x, 2, "</tpm>");
+    }
+
+    for dev in &cfg.usb {
+        match (dev.kind.as_str(), &dev.id) {
+            ("tablet", _) => line(&mut x, 2, "<input type=\"tablet\" bus=\"usb\"/>"),
+            ("keyboard", _) => line(&mut x, 2, "<input type=\"keyboard\" bus=\"usb\"/>"),
+            (_, Some(id)) => {
+                let (vendor, product) = id.split_once(':').unwrap_or((id, ""));
+                line(&mut x, 2, "<hostdev mode=\"subsystem\" type=\"usb\">");
+                line(&mut x, 3, "<source>");
+                line(&mut x, 4, &format!("<vendor id=\"0x{}\"/>", esc(vendor)));
+                line(&mut x, 4, &format!("<product id=\"0x{}\"/>", esc(product)));
+                line(&mut x, 3, "</source>");
+                line(&mut x, 2, "</hostdev>");
+            }
+            _ => {}
+        }
+    }
+    if !cfg.usb.is_empty() {
+        line(&mut x, 2, "<controller type=\"usb\" model=\"qemu-xhci\"/>");
+    }
+
+    if cfg.audio.backend != "none" {
+        let model = if cfg.audio.model == "usb-audio" { "usb" } else { "ich6" };
+        let backend = if cfg.audio.backend == "pa" { "pulseaudio" } else { cfg.audio.backend.as_str() };
+        line(&mut x, 2, &format!("<sound model=\"{}\"/>", model));
+        line(&mut x, 2, &format!("<audio id=\"1\" type=\"{}\"/>", esc(backend)));
+    }
+
+    for share in &cfg.shares {
+        line(&mut x, 2, "<filesystem type=\"mount\" accessmode=\"passthrough\">");
+        line(&mut x, 3, "<driver type=\"virtiofs\"/>");
+        line(&mut x, 3, &format!("<source dir=\"{}\"/>", esc(&share.path.display().to_string())));
+        line(&mut x, 3, &format!("<target dir=\"{}\"/>", esc(&share.tag)));
+        if share.readonly {
+            line(&mut x, 3, "<readonly/>");
+        }
+        line(&mut x, 2, "</filesystem>");
+    }
+
+    line(&mut x, 1, "</devices>");
+    line(&mut x, 0, "</domain>");
+    x
+}
+
+/// Graphics and video devices for the display mode
+fn graphics(x: &mut String, display: &Display, root: &std::path::Path) {
+    // Only literal passwords can be carried over; `env:` references stay with qvm
+    let passwd = |password: &Option<String>| match password {
+        Some(p) if !p.starts_with(PASSWORD_ENV_PREFIX) => format!(" passwd=\"{}\"", esc(p)),
+        _ => String::new(),
+    };
+
+    match display.mode.as_str() {
+        "headless" => {
+            line(x, 2, "<video>");
+            line(x, 3, "<model type=\"none\"/>");
+            line(x, 2, "</video>");
+            return;
+        }
+        "vnc" if display.vnc.use_unix => {
+            let sock = resolve_under_root(root, &display.vnc.sock);
+            line(
+                x,
+                2,
+                &format!(
+                    "<graphics type=\"vnc\" socket=\"{}\"{}/>",
+                    esc(&sock.display().to_string()),
+                    passwd(&display.vnc.password)
+                ),
+            );
+        }
+        "vnc" => line(
+            x,
+            2,
+            &format!(
+                "<graphics type=\"vnc\" port=\"{}\" listen=\"{}\"{}/>",
+                5900 + u32::from(display.vnc.display),
+                esc(&display.vnc.host),
+                passwd(&display.vnc.password)
+            ),
+        ),
+        "spice" => {
+            let spice = &display.spice;
+            if spice.use_unix {
+                line(x, 2, &format!("<graphics type=\"spice\"{}>", passwd(&spice.password)));
+                let sock = resolve_under_root(root, &spice.sock);
+                line(
+                    x,
+                    3,
+                    &format!("<listen type=\"socket\" socket=\"{}\"/>", esc(&sock.display().to_string())),
+                );
+                line(x, 2, "</graphics>");
+            } else {
+                line(
+                    x,
+                    2,
+                    &format!(
+                        "<graphics type=\"spice\" port=\"{}\" listen=\"{}\"{}/>",
+                        spice.port,
+                        esc(&spice.addr),
+                        passwd(&spice.password)
+                    ),
+                );
+            }
+        }
+        // Cocoa is a local qemu window; libvirt needs a remote display for virt-manager
+        _ => line(x, 2, "<graphics type=\"vnc\" autoport=\"yes\" listen=\"127.0.0.1\"/>"),
+    }
+    let gpu = &display.gpu;
+    let heads = gpu.max_outputs.map(|n| format!(" heads=\"{}\"", n)).unwrap_or_default();
+    line(x, 2, "<video>");
+    if gpu.gl {
+        line(x, 3, &format!("<model type=\"virtio\"{}>", heads));
+        line(x, 4, "<acceleration accel3d=\"yes\"/>");
+        line(x, 3, "</model>");
+    } else {
+        line(x, 3, &format!("<model type=\"virtio\"{}/>", heads));
+    }
+    line(x, 2, "</video>");
+}
+
+/// Append one line at the given nesting depth
+fn line(x: &mut String, depth: usize, text: &str) {
+    let _ = writeln!(x, "{}{}", "  ".repeat(depth), text);
+}
+
+/// Device name suffix: a..z, then aa, ab, ...
+fn drive_letter(index: u8) -> String {
+    if index < 26 {
+        ((b'a' + index) as char).to_string()
+    } else {
+        format!("{}{}", drive_letter(index / 26 - 1), drive_letter(index % 26))
+    }
+}
+
+/// Escape text for XML character data and attribute values
+fn esc(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
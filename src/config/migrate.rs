@@ -0,0 +1,107 @@
+//! vm.json schema versioning and migration
+
+use crate::config::schema::VmConfig;
+use crate::utils::system::random_mac;
+use crate::{QvmError, Result};
+use serde_json::Value;
+
+/// Schema version written by this build of qvm
+pub const CURRENT_VERSION: u32 = 4;
+
+/// A migration upgrades a config `Value` from version N to N+1 in place
+type Migration = fn(&mut Value) -> Result<()>;
+
+/// Registered migrations; `MIGRATIONS[i]` upgrades version `i + 1` to `i + 2`
+const MIGRATIONS: &[Migration] = &[v1_to_v2, v2_to_v3, v3_to_v4];
+
+/// Read `meta.version` from a raw config, treating a missing field as version 1
+pub fn version_of(value: &Value) -> u32 {
+    value
+        .get("meta")
+        .and_then(|m| m.get("version"))
+        .and_then(Value::as_u64)
+        .map(|v| v as u32)
+        .unwrap_or(1)
+}
+
+/// Bring a raw vm.json value up to `CURRENT_VERSION` and deserialize it
+pub fn migrate(mut value: Value) -> Result<VmConfig> {
+    let version = version_of(&value);
+    if version == 0 || version > CURRENT_VERSION {
+        return Err(QvmError::UnsupportedConfigVersion {
+            found: version,
+            supported: CURRENT_VERSION,
+        });
+    }
+
+    for migration in &MIGRATIONS[(version - 1) as usize..] {
+        migration(&mut value)?;
+    }
+
+    if let Some(meta) = value.get_mut("meta").and_then(Value::as_object_mut) {
+        meta.insert("version".into(), CURRENT_VERSION.into());
+    }
+
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Fetch a mutable object section of the config, erroring if it is absent
+fn section<'a>(value: &'a mut Value, key: &str) -> Result<&'a mut serde_json::Map<String, Value>> {
+    value
+        .get_mut(key)
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| QvmError::Invalid(format!("vm.json is missing the '{}' section", key)))
+}
+
+/// v1 → v2: `paths.disk` (single path) becomes `paths.disks` (list of disk entries)
+fn v1_to_v2(value: &mut Value) -> Result<()> {
+    let paths = section(value, "paths")?;
+    if let Some(disk) = paths.remove("disk") {
+        if !paths.contains_key("disks") {
+            paths.insert(
+                "disks".into(),
+                serde_json::json!([{ "path": disk, "format": "qcow2", "interface": "virtio" }]),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// v2 → v3: the fixed `network.forwards.{ssh,meye}` ports become a list of forward entries.
+/// A port of 0 meant "disabled"; `ssh` forwards to guest port 22, `meye` to the same port.
+fn v2_to_v3(value: &mut Value) -> Result<()> {
+    let network = section(value, "network")?;
+    if let Some(Value::Object(old)) = network.get("forwards") {
+        let port = |key: &str| old.get(key).and_then(Value::as_u64).filter(|p| *p != 0);
+        let mut list = Vec::new();
+        if let Some(host) = port("ssh") {
+            list.push(serde_json::json!({ "protocol": "tcp", "host_port": host, "guest_port": 22 }));
+        }
+        if let Some(host) = port("meye") {
+            list.push(serde_json::json!({ "protocol": "tcp", "host_port": host, "guest_port": host }));
+        }
+        network.insert("forwards".into(), Value::Array(list));
+    }
+    Ok(())
+}
+
+/// v3 → v4: the single NIC (`network.mode`, `bridge_if` and `forwards`, with
+/// `hardware.mac`, or a new random one if it had none) becomes the one entry of
+/// `network.interfaces`; `ssh_user` stays put
+fn v3_to_v4(value: &mut Value) -> Result<()> {
+    let mac = section(value, "hardware")?
+        .remove("mac")
+        .unwrap_or_else(|| Value::String(random_mac()));
+    let network = section(value, "network")?;
+    if !network.contains_key("interfaces") {
+        let mut nic = serde_json::Map::new();
+        for key in ["mode", "bridge_if", "forwards"] {
+            if let Some(v) = network.remove(key) {
+                nic.insert(key.into(), v);
+            }
+        }
+        nic.insert("mac".into(), mac);
+        network.insert("interfaces".into(), Value::Array(vec![Value::Object(nic)]));
+    }
+    Ok(())
+}
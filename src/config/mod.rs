@@ -1,3 +1,8 @@
 //! Configuration management for QVM
 
-pub mod schema;
\ No newline at end of file
+pub mod libvirt;
+pub mod migrate;
+pub mod profile;
+pub mod schema;
+pub mod set;
+pub mod validate;
\ No newline at end of file
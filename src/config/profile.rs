@@ -0,0 +1,93 @@
+//! Create profiles: reusable defaults for `qvm create --profile`
+
+use crate::utils::paths::qvm_home;
+use crate::vm::creator::CreateParams;
+use crate::{QvmError, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+/// Defaults for `qvm create`, loaded from `$QVM_HOME/profiles/<name>.json`.
+/// Every field is optional; flags given on the command line win over them.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub arch: Option<String>,
+    pub cpu_model: Option<String>,
+    pub smp: Option<u32>,
+    pub sockets: Option<u32>,
+    pub cores: Option<u32>,
+    pub threads: Option<u32>,
+    pub mem: Option<u32>,
+    pub rtc: Option<String>,
+    pub accel: Option<String>,
+    pub net_mode: Option<String>,
+    pub bridge_if: Option<String>,
+    pub display_mode: Option<String>,
+    pub disk_size: Option<String>,
+    pub disk_format: Option<String>,
+    pub tpm: Option<bool>,
+    pub secure_boot: Option<bool>,
+}
+
+/// Directory holding the profiles
+pub fn profiles_dir() -> Result<PathBuf> {
+    Ok(qvm_home()?.join("profiles"))
+}
+
+/// Load a profile by name
+pub fn load_profile(name: &str) -> Result<Profile> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(QvmError::Invalid(format!("Invalid profile name '{}'", name)));
+    }
+    let path = profiles_dir()?.join(format!("{}.json", name));
+    let text = fs::read_to_string(&path).map_err(|e| {
+        QvmError::Invalid(format!("Cannot read profile '{}' ({}): {}", name, path.display(), e))
+    })?;
+    serde_json::from_str(&text).map_err(|e| {
+        QvmError::Invalid(format!("Invalid profile {}: {}", path.display(), e))
+    })
+}
+
+impl Profile {
+    /// Fill in every parameter the user did not pass explicitly; `explicit` reports
+    /// whether a flag (by its `CreateParams` field name) was given on the command line
+    pub fn apply(&self, params: &mut CreateParams, explicit: impl Fn(&str) -> bool) {
+        fill(&mut params.arch, &self.arch, explicit("arch"));
+        fill(&mut params.cpu_model, &self.cpu_model, explicit("cpu_model"));
+        fill(&mut params.mem, &self.mem, explicit("mem"));
+        fill(&mut params.rtc, &self.rtc, explicit("rtc"));
+        fill(&mut params.net_mode, &self.net_mode, explicit("net_mode"));
+        fill(&mut params.bridge_if, &self.bridge_if, explicit("bridge_if"));
+        fill(&mut params.display_mode, &self.display_mode, explicit("display_mode"));
+        fill(&mut params.disk_format, &self.disk_format, explicit("disk_format"));
+        fill(&mut params.tpm, &self.tpm, explicit("tpm"));
+        fill(&mut params.secure_boot, &self.secure_boot, explicit("secure_boot"));
+        if !explicit("accel") && self.accel.is_some() {
+            params.accel = self.accel.clone();
+        }
+        if !explicit("disk_size") && self.disk_size.is_some() {
+            params.disk_size = self.disk_size.clone();
+        }
+
+        // The vCPU count is one setting: any topology flag replaces the profile's entirely
+        let topology = ["smp", "sockets", "cores", "threads"];
+        if !topology.iter().any(|f| explicit(f)) {
+            if self.smp.is_some() {
+                params.smp = self.smp;
+            }
+            if self.sockets.is_some() || self.cores.is_some() || self.threads.is_some() {
+                params.sockets = self.sockets;
+                params.cores = self.cores;
+                params.threads = self.threads;
+            }
+        }
+    }
+}
+
+/// Replace `slot` with the profile's value unless the flag was given
+fn fill<T: Clone>(slot: &mut T, value: &Option<T>, given: bool) {
+    if let (Some(v), false) = (value, given) {
+        *slot = v.clone();
+    }
+}
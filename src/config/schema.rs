@@ -1,17 +1,70 @@
 //! VM configuration schema definitions
 
+use crate::config::migrate::CURRENT_VERSION;
+use crate::utils::paths::qvm_home;
+use crate::utils::system::{now_utc, random_mac};
+use crate::{QvmError, Result};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::path::PathBuf;
 
 /// VM configuration schema
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct VmConfig {
     pub meta: Meta,
     pub paths: Paths,
     pub hardware: Hardware,
     pub firmware: Firmware,
+    #[serde(default)]
+    pub boot: Boot,
     pub network: Network,
     pub display: Display,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cloud_init: Option<CloudInit>,
+    #[serde(default)]
+    pub memory: Memory,
+    #[serde(default)]
+    pub tpm: Tpm,
+    #[serde(default)]
+    pub audio: Audio,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub usb: Vec<UsbDevice>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shares: Vec<Share>,
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+    /// Appended verbatim to the qemu argv; not validated
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_args: Vec<String>,
+}
+
+impl VmConfig {
+    /// Total vCPUs of the configured topology (sockets × cores × threads, saturating)
+    pub fn vcpu_total(&self) -> u32 {
+        let hw = &self.hardware;
+        hw.sockets.saturating_mul(hw.cores).saturating_mul(hw.threads)
+    }
+
+    /// Host TCP ports the VM is set up to listen on: its VNC and SPICE ports (whichever
+    /// display mode is active) and its TCP forwards
+    pub fn tcp_ports(&self) -> Vec<u16> {
+        let mut ports = Vec::new();
+        if !self.display.vnc.use_unix {
+            ports.push(self.display.vnc.port());
+        }
+        if !self.display.spice.use_unix {
+            ports.push(self.display.spice.port);
+        }
+        ports.extend(
+            self.network
+                .interfaces
+                .iter()
+                .flat_map(|nic| &nic.forwards)
+                .filter(|f| f.protocol == "tcp")
+                .map(|f| f.host_port),
+        );
+        ports
+    }
 }
 
 /// VM metadata
@@ -22,14 +75,102 @@ pub struct Meta {
     pub name: String,
     pub arch: String,
     pub uuid: String,
+    /// Free-form notes about the VM
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// Labels for grouping VMs (`qvm list --tag`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Started by `qvm start --all` (`qvm enable` / `qvm disable`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub autostart: bool,
+}
+
+impl Default for Meta {
+    fn default() -> Self {
+        Meta {
+            version: CURRENT_VERSION,
+            generated: String::new(),
+            name: String::new(),
+            arch: "aarch64".to_string(),
+            uuid: String::new(),
+            description: String::new(),
+            tags: Vec::new(),
+            autostart: false,
+        }
+    }
 }
 
 /// VM file paths
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Paths {
     pub root: PathBuf,
-    pub disk: PathBuf,     // may be relative to root
+    pub disks: Vec<Disk>,  // first entry is the boot disk
     pub efi_vars: PathBuf, // may be relative to root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iso: Option<PathBuf>, // default install medium; may be relative to root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qemu_bin: Option<PathBuf>, // qemu-system-* this VM always runs with (create --pin-qemu)
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Paths {
+            root: PathBuf::new(),
+            disks: vec![Disk::default()],
+            efi_vars: PathBuf::from("efi_vars.fd"),
+            iso: None,
+            qemu_bin: None,
+        }
+    }
+}
+
+/// VM disk entry
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Disk {
+    pub path: PathBuf, // may be relative to root
+    #[serde(default = "default_disk_format")]
+    pub format: String, // qcow2 | raw
+    #[serde(default = "default_disk_interface")]
+    pub interface: String, // virtio | nvme | ide
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<String>, // none | writeback | writethrough (unset: qemu's writeback)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aio: Option<String>, // threads | native | io_uring (unset: qemu's threads)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub readonly: bool,
+    /// The golden image this qcow2 disk is an overlay of (shared with other VMs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backing: Option<PathBuf>,
+}
+
+impl Disk {
+    /// A qcow2 disk on the virtio bus
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Disk {
+            path: path.into(),
+            format: default_disk_format(),
+            interface: default_disk_interface(),
+            cache: None,
+            aio: None,
+            readonly: false,
+            backing: None,
+        }
+    }
+}
+
+impl Default for Disk {
+    fn default() -> Self {
+        Disk::new("disk.qcow2")
+    }
+}
+
+fn default_disk_format() -> String {
+    "qcow2".to_string()
+}
+
+fn default_disk_interface() -> String {
+    "virtio".to_string()
 }
 
 /// VM hardware configuration
@@ -42,29 +183,237 @@ pub struct Hardware {
     pub mem_mb: u32,
     pub machine: String,
     pub accel: String,
-    pub mac: String,
+    /// What the guest's real-time clock starts from: `utc`, or `localtime` (Windows)
+    #[serde(default = "default_rtc")]
+    pub rtc: String,
+}
+
+fn default_rtc() -> String {
+    "utc".to_string()
+}
+
+impl Default for Hardware {
+    fn default() -> Self {
+        Hardware {
+            cpu_model: "host".to_string(),
+            sockets: 1,
+            cores: 4,
+            threads: 1,
+            mem_mb: 4096,
+            machine: "virt,gic-version=3".to_string(),
+            accel: "tcg".to_string(),
+            rtc: default_rtc(),
+        }
+    }
 }
 
 /// VM firmware configuration
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Firmware {
-    pub code: PathBuf,          // absolute path to firmware code
-    pub vars_template: PathBuf, // absolute path to firmware vars template
+    pub code: PathBuf,          // firmware code (relative paths are under the VM root)
+    pub vars_template: PathBuf, // firmware vars template (likewise)
+    /// A secure boot build (x86_64 also needs SMM, enabled at launch)
+    #[serde(default)]
+    pub secure_boot: bool,
 }
 
-/// VM network configuration
+/// Direct kernel boot and the firmware boot order; everything is optional
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Boot {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<PathBuf>, // may be relative to root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initrd: Option<PathBuf>, // may be relative to root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmdline: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<String>, // qemu drive letters, e.g. "dc" (CD-ROM, then disk)
+}
+
+impl Boot {
+    /// The bootindex of the device a boot order letter names (`c` the first disk, `d` the
+    /// install CD-ROM, `n`-`p` the first three NICs): its position in `order`. qemu rejects
+    /// `-boot order` alongside bootindex, so the order is only ever passed this way. Without
+    /// an order an attached ISO (`has_iso`) boots first, then the disk.
+    pub fn bootindex(&self, letter: char, has_iso: bool) -> Option<u32> {
+        match &self.order {
+            Some(order) => order.chars().position(|c| c == letter).map(|i| i as u32),
+            None => match letter {
+                'd' if has_iso => Some(0),
+                'c' if has_iso => Some(1),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// VM network configuration: the guest NICs, in PCI order
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Network {
+    #[serde(default)]
+    pub interfaces: Vec<NetworkInterface>,
+    /// Default login for `qvm ssh` (ssh's own default when unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_user: Option<String>,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Network {
+            interfaces: vec![NetworkInterface::default()],
+            ssh_user: None,
+        }
+    }
+}
+
+impl Network {
+    /// Index of the NIC `qvm forward add` uses by default: the first user-mode one, else
+    /// the first NIC
+    pub fn default_forward_nic(&self) -> Option<usize> {
+        let user = self.interfaces.iter().position(|nic| nic.mode == "user");
+        user.or((!self.interfaces.is_empty()).then_some(0))
+    }
+}
+
+/// One guest NIC and how it reaches the host
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NetworkInterface {
     pub mode: String,      // vmnet-shared | vmnet-bridged | user
     pub bridge_if: String, // for vmnet-bridged
-    pub forwards: Forwards,
+    #[serde(default)]
+    pub mac: String,
+    #[serde(default)]
+    pub forwards: Vec<Forward>, // user mode only
+}
+
+impl Default for NetworkInterface {
+    fn default() -> Self {
+        NetworkInterface {
+            mode: "vmnet-shared".to_string(),
+            bridge_if: "en0".to_string(),
+            mac: String::new(),
+            forwards: Vec::new(),
+        }
+    }
+}
+
+/// A user-mode port forward from the host to the guest
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Forward {
+    pub protocol: String, // tcp | udp
+    pub host_port: u16,
+    pub guest_port: u16,
 }
 
-/// Port forwarding configuration
+impl Default for Forward {
+    fn default() -> Self {
+        Forward {
+            protocol: "tcp".to_string(),
+            host_port: 0,
+            guest_port: 0,
+        }
+    }
+}
+
+impl Forward {
+    /// The rule in QEMU `hostfwd` syntax, e.g. `tcp::2222-:22`
+    pub fn hostfwd(&self) -> String {
+        format!("{}::{}-:{}", self.protocol, self.host_port, self.guest_port)
+    }
+}
+
+/// cloud-init NoCloud seed attached as a CD-ROM on every start
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
-pub struct Forwards {
-    pub ssh: u16,
-    pub meye: u16,
+pub struct CloudInit {
+    pub seed_iso: PathBuf, // may be relative to root
+}
+
+/// A host directory shared into the guest (virtiofs, or 9p without virtiofsd)
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Share {
+    pub path: PathBuf, // absolute host directory
+    pub tag: String,   // mount tag seen by the guest
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+/// Scripts run after lifecycle events (see `vm::hooks`); relative paths are under root
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hooks {
+    /// Run once the VM is up (after `--wait-for-ssh`); if it fails, the VM is stopped again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_start: Option<PathBuf>,
+    /// Run once `qvm stop` has stopped the VM
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_stop: Option<PathBuf>,
+}
+
+impl Hooks {
+    /// No hook is set
+    pub fn is_empty(&self) -> bool {
+        self.on_start.is_none() && self.on_stop.is_none()
+    }
+}
+
+/// How guest RAM is backed on the host
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Memory {
+    #[serde(default)]
+    pub prealloc: bool, // allocate all guest RAM up front
+    #[serde(default)]
+    pub share: bool, // map guest RAM shared (vhost-user devices; always on for virtiofs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hugepages: Option<PathBuf>, // hugetlbfs mount backing guest RAM (Linux), e.g. /dev/hugepages
+}
+
+/// TPM 2.0 emulation (swtpm) configuration
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tpm {
+    pub enabled: bool,
+    pub sock: PathBuf, // swtpm control socket; may be relative to root
+}
+
+impl Default for Tpm {
+    fn default() -> Self {
+        Tpm {
+            enabled: false,
+            sock: PathBuf::from("swtpm.sock"),
+        }
+    }
+}
+
+/// Guest sound card and the host audio backend it plays through
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Audio {
+    pub backend: String, // none | coreaudio | pa | pipewire | alsa | sdl
+    pub model: String,   // intel-hda | usb-audio
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Audio {
+            backend: "none".to_string(),
+            model: "intel-hda".to_string(),
+        }
+    }
+}
+
+/// A USB device on the VM's xHCI controller
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsbDevice {
+    pub kind: String, // tablet | keyboard | host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>, // vendor:product in hex, for host passthrough
+}
+
+impl fmt::Display for UsbDevice {
+    /// The spec `qvm usb add/remove` accepts: `tablet`, `keyboard` or `vendor:product`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.id {
+            Some(id) => write!(f, "{}", id),
+            None => write!(f, "{}", self.kind),
+        }
+    }
 }
 
 /// VM display configuration
@@ -73,8 +422,100 @@ pub struct Display {
     pub mode: String, // cocoa | vnc | spice | headless
     pub vnc: Vnc,
     pub spice: Spice,
+    #[serde(default)]
+    pub gpu: Gpu,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display {
+            mode: "cocoa".to_string(),
+            vnc: Vnc::default(),
+            spice: Spice::default(),
+            gpu: Gpu::default(),
+        }
+    }
 }
 
+impl Display {
+    /// The TCP port the display is served on, None for a local window, no display or a
+    /// UNIX socket
+    pub fn tcp_port(&self) -> Option<u16> {
+        match self.mode.as_str() {
+            "vnc" if !self.vnc.use_unix => Some(self.vnc.port()),
+            "spice" if !self.spice.use_unix => Some(self.spice.port),
+            _ => None,
+        }
+    }
+}
+
+/// The virtio GPU of a graphical display (a headless VM has none)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Gpu {
+    #[serde(default = "default_gpu_model")]
+    pub model: String, // virtio-gpu-pci | virtio-vga (x86_64 only; also a boot-time VGA)
+    /// Host memory for GPU resources in MiB (qemu's `max_hostmem`; unset: qemu's 256)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vram_mb: Option<u32>,
+    /// Displays (heads) the guest sees (unset: qemu's 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_outputs: Option<u32>,
+    /// Preferred resolution `WIDTHxHEIGHT` offered to the guest (unset: qemu's 1280x800)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<String>,
+    /// virgl 3D acceleration: the model's `-gl` variant, rendered by an `egl-headless` display
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub gl: bool,
+}
+
+impl Default for Gpu {
+    fn default() -> Self {
+        Gpu {
+            model: default_gpu_model(),
+            vram_mb: None,
+            max_outputs: None,
+            resolution: None,
+            gl: false,
+        }
+    }
+}
+
+impl Gpu {
+    /// The qemu device: `model`, or its 3D variant with `gl`
+    pub fn device(&self) -> String {
+        match (self.model.as_str(), self.gl) {
+            ("virtio-gpu-pci", true) => "virtio-gpu-gl-pci".to_string(),
+            (model, true) => format!("{}-gl", model),
+            (model, false) => model.to_string(),
+        }
+    }
+
+    /// `resolution` as width and height, None if unset
+    pub fn resolution_size(&self) -> Result<Option<(u32, u32)>> {
+        let Some(res) = &self.resolution else {
+            return Ok(None);
+        };
+        let size = res
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+            .filter(|&(w, h)| w > 0 && h > 0);
+        match size {
+            Some(size) => Ok(Some(size)),
+            None => Err(QvmError::Invalid(format!(
+                "Invalid GPU resolution '{}' (expected WIDTHxHEIGHT, e.g. 1920x1080)",
+                res
+            ))),
+        }
+    }
+}
+
+fn default_gpu_model() -> String {
+    "virtio-gpu-pci".to_string()
+}
+
+/// VNC display N listens on TCP port 5900 + N
+pub const VNC_BASE_PORT: u16 = 5900;
+
 /// VNC configuration
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Vnc {
@@ -82,6 +523,30 @@ pub struct Vnc {
     pub host: String,
     pub display: u8,
     pub sock: PathBuf, // may be relative to root
+    /// Set over QMP after launch: `env:NAME` reads `$NAME` at start, anything else is literal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<DisplayTls>,
+}
+
+impl Default for Vnc {
+    fn default() -> Self {
+        Vnc {
+            use_unix: false,
+            host: "127.0.0.1".to_string(),
+            display: 1,
+            sock: PathBuf::from("vnc.sock"),
+            password: None,
+            tls: None,
+        }
+    }
+}
+
+impl Vnc {
+    pub fn port(&self) -> u16 {
+        VNC_BASE_PORT + u16::from(self.display)
+    }
 }
 
 /// SPICE configuration
@@ -92,4 +557,149 @@ pub struct Spice {
     pub port: u16,
     pub disable_ticketing: bool,
     pub sock: PathBuf, // may be relative to root
-}
\ No newline at end of file
+    /// Enables ticketing (overriding `disable_ticketing`); same format as `Vnc::password`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Serve SPICE only on a TLS port (the `port` field)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<DisplayTls>,
+}
+
+impl Default for Spice {
+    fn default() -> Self {
+        Spice {
+            use_unix: false,
+            addr: "127.0.0.1".to_string(),
+            port: 5930,
+            disable_ticketing: true,
+            sock: PathBuf::from("spice.sock"),
+            password: None,
+            tls: None,
+        }
+    }
+}
+
+/// x509 credentials encrypting a VNC or SPICE display
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DisplayTls {
+    pub cert_dir: PathBuf, // ca-cert.pem, server-cert.pem, server-key.pem; may be relative to root
+    #[serde(default)]
+    pub verify_peer: bool, // require a client certificate signed by the CA (VNC only)
+}
+
+/// Builds a `VmConfig` from `qvm create`'s defaults, e.g.
+/// `VmConfigBuilder::new("name", "aarch64").mem_mb(8192).build()`.
+/// The UUID, MACs and timestamp are fresh and the root is `$QVM_HOME/<name>.qvm` unless set.
+#[derive(Debug, Clone)]
+pub struct VmConfigBuilder {
+    cfg: VmConfig,
+}
+
+impl VmConfigBuilder {
+    /// Start from the defaults for a VM called `name` with guest architecture `arch`
+    pub fn new(name: &str, arch: &str) -> Self {
+        let mut cfg = VmConfig::default();
+        cfg.meta.name = name.to_string();
+        cfg.meta.arch = arch.to_string();
+        cfg.meta.uuid = uuid::Uuid::new_v4().to_string();
+        cfg.meta.generated = now_utc();
+        cfg.paths.root = qvm_home()
+            .map(|home| home.join(format!("{}.qvm", name)))
+            .unwrap_or_else(|_| PathBuf::from(format!("{}.qvm", name)));
+        if arch != "aarch64" {
+            cfg.hardware.machine = "q35".to_string();
+        }
+        Self { cfg }
+    }
+
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.cfg.paths.root = root.into();
+        self
+    }
+
+    pub fn uuid(mut self, uuid: &str) -> Self {
+        self.cfg.meta.uuid = uuid.to_string();
+        self
+    }
+
+    /// MAC address of the first NIC
+    pub fn mac(mut self, mac: &str) -> Self {
+        if let Some(nic) = self.cfg.network.interfaces.first_mut() {
+            nic.mac = mac.to_string();
+        }
+        self
+    }
+
+    pub fn cpu_model(mut self, model: &str) -> Self {
+        self.cfg.hardware.cpu_model = model.to_string();
+        self
+    }
+
+    /// CPU topology; the vCPU count is `sockets * cores * threads`
+    pub fn topology(mut self, sockets: u32, cores: u32, threads: u32) -> Self {
+        self.cfg.hardware.sockets = sockets;
+        self.cfg.hardware.cores = cores;
+        self.cfg.hardware.threads = threads;
+        self
+    }
+
+    pub fn mem_mb(mut self, mem_mb: u32) -> Self {
+        self.cfg.hardware.mem_mb = mem_mb;
+        self
+    }
+
+    pub fn machine(mut self, machine: &str) -> Self {
+        self.cfg.hardware.machine = machine.to_string();
+        self
+    }
+
+    pub fn accel(mut self, accel: &str) -> Self {
+        self.cfg.hardware.accel = accel.to_string();
+        self
+    }
+
+    pub fn firmware(mut self, code: impl Into<PathBuf>, vars_template: impl Into<PathBuf>) -> Self {
+        self.cfg.firmware = Firmware {
+            code: code.into(),
+            vars_template: vars_template.into(),
+            ..Firmware::default()
+        };
+        self
+    }
+
+    /// Replace the disk list (the first disk boots)
+    pub fn disks(mut self, disks: Vec<Disk>) -> Self {
+        self.cfg.paths.disks = disks;
+        self
+    }
+
+    /// Replace the NICs; those without a MAC get a random one at `build`
+    pub fn network(mut self, network: Network) -> Self {
+        self.cfg.network = network;
+        self
+    }
+
+    pub fn display(mut self, display: Display) -> Self {
+        self.cfg.display = display;
+        self
+    }
+
+    pub fn tpm(mut self, enabled: bool) -> Self {
+        self.cfg.tpm.enabled = enabled;
+        self
+    }
+
+    pub fn audio(mut self, audio: Audio) -> Self {
+        self.cfg.audio = audio;
+        self
+    }
+
+    pub fn build(mut self) -> VmConfig {
+        for nic in &mut self.cfg.network.interfaces {
+            if nic.mac.is_empty() {
+                nic.mac = random_mac();
+            }
+        }
+        self.cfg
+    }
+}
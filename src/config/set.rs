@@ -0,0 +1,165 @@
+//! `qvm set`: change one vm.json field by its dotted path (`hardware.mem_mb`)
+
+use crate::config::schema::VmConfig;
+use crate::config::validate::{check_config, validate_cpu_model, validate_hardware, validate_memory, CHOICES};
+use crate::{QvmError, Result};
+use serde_json::{Map, Value};
+
+/// Keys with their own command or owned by qvm itself, and why they are refused
+const PROTECTED_KEYS: &[(&str, &str)] = &[
+    ("meta.name", "rename the VM with 'qvm rename'"),
+    ("meta.uuid", "the UUID identifies the VM and is generated by qvm"),
+    ("meta.version", "the schema version is managed by qvm"),
+    ("meta.generated", "the creation time is recorded by qvm"),
+    ("paths.root", "the root is wherever the VM directory lives"),
+];
+
+/// The result of `set_key`: the updated configuration, the previous value at the key
+/// (`Null` if it was unset) and any validation warnings
+#[derive(Debug)]
+pub struct SetOutcome {
+    pub config: VmConfig,
+    pub old: Value,
+    pub warnings: Vec<String>,
+}
+
+/// Set the field at dotted `key` (array elements by index, e.g. `paths.disks.0.format`)
+/// to `raw` and return the validated result. `raw` is read as the type of the current
+/// value: a number, `true`/`false`, or a plain string; unset fields, arrays and objects
+/// take JSON, and `null` clears an optional field. The change is round-tripped through
+/// `VmConfig`, so keys the schema does not know and values of the wrong type are errors.
+pub fn set_key(cfg: &VmConfig, key: &str, raw: &str) -> Result<SetOutcome> {
+    if let Some((_, why)) = PROTECTED_KEYS.iter().find(|(k, _)| *k == key) {
+        return Err(QvmError::Invalid(format!("'{}' cannot be set: {}", key, why)));
+    }
+    let segments: Vec<&str> = key.split('.').collect();
+    if segments.iter().any(|s| s.is_empty()) {
+        return Err(QvmError::Invalid(format!("Invalid key '{}'", key)));
+    }
+
+    let mut root = serde_json::to_value(cfg)?;
+    let (last, parents) = segments.split_last().expect("split always yields a segment");
+    let mut node = &mut root;
+    for (i, seg) in parents.iter().enumerate() {
+        // A section vm.json leaves out while it is empty (hooks) is filled in on the way;
+        // one the schema does not have is caught after the round trip below
+        if let Value::Object(map) = node {
+            map.entry(seg.to_string()).or_insert_with(|| Value::Object(Map::new()));
+        }
+        node = child_mut(node, seg).ok_or_else(|| unknown_key(&segments[..=i].join(".")))?;
+    }
+
+    let current = match node {
+        Value::Object(map) => map.get(*last).cloned(),
+        Value::Array(items) => Some(index(items, last).ok_or_else(|| unknown_key(key))?.clone()),
+        _ => return Err(unknown_key(key)),
+    };
+    let value = parse_value(raw, current.as_ref(), key)?;
+    check_choice(&segments, &value)?;
+    if value.is_null() && current.is_none() {
+        return Err(QvmError::Invalid(format!("'{}' is not set", key)));
+    }
+    match node {
+        Value::Object(map) => {
+            map.insert(last.to_string(), value.clone());
+        }
+        Value::Array(items) => {
+            let i = last.parse::<usize>().expect("checked by index()");
+            items[i] = value.clone();
+        }
+        _ => unreachable!("checked above"),
+    }
+
+    let mut config: VmConfig = serde_json::from_value(root).map_err(|e| {
+        QvmError::Invalid(format!("Invalid value '{}' for '{}': {}", raw, key, e))
+    })?;
+    // A key serde ignored is one the schema does not have
+    if !value.is_null() && lookup(&serde_json::to_value(&config)?, &segments).is_none() {
+        return Err(unknown_key(key));
+    }
+    // An unknown model already saved is left alone; start only warns about it
+    if config.hardware.cpu_model != cfg.hardware.cpu_model {
+        validate_cpu_model(&config.meta.arch, &config.hardware.cpu_model)?;
+    }
+    // Only report problems this change introduced
+    let before = validate_hardware(&cfg.hardware).unwrap_or_default();
+    let mut warnings = check_config(&mut config)?;
+    // Free huge pages are the host's state (and a running VM holds its own), so they are
+    // only checked for a change to the memory itself
+    if config.memory != cfg.memory || config.hardware.mem_mb != cfg.hardware.mem_mb {
+        validate_memory(&config.memory, config.hardware.mem_mb)?;
+    }
+    warnings.retain(|w| !before.contains(w));
+
+    Ok(SetOutcome {
+        config,
+        old: current.unwrap_or(Value::Null),
+        warnings,
+    })
+}
+
+/// Read `raw` as the type of `current`
+fn parse_value(raw: &str, current: Option<&Value>, key: &str) -> Result<Value> {
+    let invalid = |what: &str| QvmError::Invalid(format!("'{}' expects {}, not '{}'", key, what, raw));
+    match current {
+        _ if raw == "null" => Ok(Value::Null),
+        Some(Value::String(_)) => Ok(Value::String(raw.to_string())),
+        Some(Value::Bool(_)) => raw.parse::<bool>().map(Value::Bool).map_err(|_| invalid("true or false")),
+        Some(Value::Number(_)) => serde_json::from_str::<serde_json::Number>(raw)
+            .map(Value::Number)
+            .map_err(|_| invalid("a number")),
+        // Unset optional fields, arrays and objects: JSON, or else a bare string
+        _ => Ok(serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))),
+    }
+}
+
+/// Refuse a value outside the field's fixed set of values, if it has one
+fn check_choice(segments: &[&str], value: &Value) -> Result<()> {
+    let pattern: Vec<&str> = segments
+        .iter()
+        .map(|s| if s.parse::<usize>().is_ok() { "*" } else { s })
+        .collect();
+    let pattern = pattern.join(".");
+    let Some((_, choices)) = CHOICES.iter().find(|(k, _)| *k == pattern) else {
+        return Ok(());
+    };
+    match value.as_str() {
+        Some(v) if choices.contains(&v) => Ok(()),
+        _ => Err(QvmError::Invalid(format!(
+            "'{}' must be one of {}, not {}",
+            segments.join("."),
+            choices.join(", "),
+            value
+        ))),
+    }
+}
+
+fn child_mut<'a>(node: &'a mut Value, seg: &str) -> Option<&'a mut Value> {
+    match node {
+        Value::Object(map) => map.get_mut(seg),
+        Value::Array(items) => {
+            let i = seg.parse::<usize>().ok()?;
+            items.get_mut(i)
+        }
+        _ => None,
+    }
+}
+
+fn index<'a>(items: &'a [Value], seg: &str) -> Option<&'a Value> {
+    items.get(seg.parse::<usize>().ok()?)
+}
+
+fn lookup<'a>(mut node: &'a Value, segments: &[&str]) -> Option<&'a Value> {
+    for seg in segments {
+        node = match node {
+            Value::Object(map) => map.get(*seg)?,
+            Value::Array(items) => index(items, seg)?,
+            _ => return None,
+        };
+    }
+    Some(node)
+}
+
+fn unknown_key(key: &str) -> QvmError {
+    QvmError::Invalid(format!("Unknown key '{}' (see 'qvm info' for the keys of vm.json)", key))
+}
@@ -0,0 +1,543 @@
+//! Sanity checks for hand-edited or user-supplied configuration values
+
+use crate::config::schema::{Boot, Disk, Display, Hardware, Memory, Network, VmConfig};
+use crate::utils::paths::resolve_under_root;
+use crate::utils::system::{host_memory_mb, normalize_mac, QemuVersion};
+use crate::{QvmError, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// String fields of vm.json with a fixed set of values, by dotted key (`*` matches any
+/// array index)
+pub const CHOICES: &[(&str, &[&str])] = &[
+    ("meta.arch", &["aarch64", "x86_64"]),
+    ("hardware.accel", &["kvm", "hvf", "tcg"]),
+    ("hardware.rtc", &["utc", "localtime"]),
+    ("network.interfaces.*.mode", &["vmnet-shared", "vmnet-bridged", "user"]),
+    ("network.interfaces.*.forwards.*.protocol", &["tcp", "udp"]),
+    ("display.mode", &["cocoa", "vnc", "spice", "headless"]),
+    ("display.gpu.model", &["virtio-gpu-pci", "virtio-vga"]),
+    ("audio.backend", &["none", "coreaudio", "pa", "pipewire", "alsa", "sdl"]),
+    ("audio.model", &["intel-hda", "usb-audio"]),
+    ("paths.disks.*.format", &["qcow2", "raw"]),
+    ("paths.disks.*.interface", &["virtio", "nvme", "ide"]),
+    ("paths.disks.*.cache", &["none", "writeback", "writethrough"]),
+    ("paths.disks.*.aio", &["threads", "native", "io_uring"]),
+];
+
+/// Check every field listed in `CHOICES`, naming the first one with a value outside its set
+pub fn validate_choices(cfg: &VmConfig) -> Result<()> {
+    match choice_errors(cfg)?.into_iter().next() {
+        Some(e) => Err(QvmError::Invalid(format!("'{}' {}", e.key, e.message))),
+        None => Ok(()),
+    }
+}
+
+/// The checks a new or changed vm.json passes before it is saved (`qvm create`, `qvm set`,
+/// `qvm import-config`): the fixed choices, tags (normalized in place), boot, network, GPU
+/// and disks, then the hardware, whose non-fatal warnings are returned. The CPU model is
+/// `validate_cpu_model`'s, which `qvm set` only applies to a changed one; the memory
+/// backing is `validate_memory`'s, which depends on the host's state at the time.
+pub fn check_config(cfg: &mut VmConfig) -> Result<Vec<String>> {
+    validate_choices(cfg)?;
+    cfg.meta.tags = validate_tags(&cfg.meta.tags)?;
+    validate_boot(&cfg.boot)?;
+    validate_network(&cfg.network)?;
+    validate_gpu(&cfg.display, &cfg.meta.arch)?;
+    for disk in &cfg.paths.disks {
+        validate_disk(disk)?;
+    }
+    validate_hardware(&cfg.hardware)
+}
+
+/// Every field listed in `CHOICES` with a value outside its set
+pub(crate) fn choice_errors(cfg: &VmConfig) -> Result<Vec<ValidationError>> {
+    let root = serde_json::to_value(cfg)?;
+    let mut errors = Vec::new();
+    for (key, choices) in CHOICES {
+        let segments: Vec<&str> = key.split('.').collect();
+        let mut found = Vec::new();
+        values_at(&root, &segments, String::new(), &mut found);
+        for (path, value) in found {
+            match value {
+                Value::Null => {}
+                Value::String(s) if choices.contains(&s.as_str()) => {}
+                other => errors.push(ValidationError::new(
+                    path,
+                    format!("is {}, expected one of {}", other, choices.join(", ")),
+                )),
+            }
+        }
+    }
+    Ok(errors)
+}
+
+/// Collect the values under a dotted key with `*` wildcards, with their concrete keys
+fn values_at<'a>(node: &'a Value, segments: &[&str], path: String, out: &mut Vec<(String, &'a Value)>) {
+    let Some((first, rest)) = segments.split_first() else {
+        out.push((path, node));
+        return;
+    };
+    let join = |seg: &str| if path.is_empty() { seg.to_string() } else { format!("{}.{}", path, seg) };
+    match (node, *first) {
+        (Value::Array(items), "*") => {
+            for (i, item) in items.iter().enumerate() {
+                values_at(item, rest, join(&i.to_string()), out);
+            }
+        }
+        (Value::Object(map), seg) => {
+            if let Some(child) = map.get(seg) {
+                values_at(child, rest, join(seg), out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// CPU models qvm accepts per guest arch (a `-vN` version suffix is also allowed)
+const X86_64_CPU_MODELS: &[&str] = &[
+    "host", "max", "qemu64", "kvm64", "base", "Nehalem", "Westmere", "SandyBridge",
+    "IvyBridge", "Haswell", "Broadwell", "Skylake-Client", "Skylake-Server",
+    "Cascadelake-Server", "Icelake-Server", "SapphireRapids", "EPYC", "EPYC-Rome",
+    "EPYC-Milan", "EPYC-Genoa",
+];
+const AARCH64_CPU_MODELS: &[&str] = &[
+    "host", "max", "cortex-a53", "cortex-a57", "cortex-a72", "cortex-a76", "neoverse-n1",
+    "neoverse-n2", "neoverse-v1", "a64fx",
+];
+
+/// Check VM tags and return them without duplicates: each is a non-empty word of
+/// letters, digits, `-`, `_`, `.` or `:`
+pub fn validate_tags(tags: &[String]) -> Result<Vec<String>> {
+    let mut out: Vec<String> = Vec::new();
+    for tag in tags {
+        let ok = !tag.is_empty()
+            && tag.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+        if !ok {
+            return Err(QvmError::Invalid(format!(
+                "Invalid tag '{}' (use letters, digits, '-', '_', '.' or ':')",
+                tag
+            )));
+        }
+        if !out.contains(tag) {
+            out.push(tag.clone());
+        }
+    }
+    Ok(out)
+}
+
+/// Check the boot section: a boot order of qemu drive letters (`c` is the first disk, `d`
+/// the CD-ROM, `n`-`p` the first three NICs), each at most once, and no initrd or kernel
+/// command line without a kernel
+pub fn validate_boot(boot: &Boot) -> Result<()> {
+    if let Some(order) = &boot.order {
+        let mut seen = Vec::new();
+        for c in order.chars() {
+            if !matches!(c, 'c' | 'd' | 'n'..='p') || seen.contains(&c) {
+                return Err(QvmError::Invalid(format!(
+                    "Invalid boot order '{}' (expected distinct letters c (disk), d (CD-ROM) and n-p (NICs), e.g. 'dc')",
+                    order
+                )));
+            }
+            seen.push(c);
+        }
+        if seen.is_empty() {
+            return Err(QvmError::Invalid("Boot order is empty".to_string()));
+        }
+    }
+    if boot.kernel.is_none() && (boot.initrd.is_some() || boot.cmdline.is_some()) {
+        return Err(QvmError::Invalid(
+            "An initrd or kernel command line needs a kernel".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Check a `-cpu` value: a known base model for `arch`, optionally followed by
+/// comma-separated feature flags (`+feat`, `-feat` or `feat=value`)
+pub fn validate_cpu_model(arch: &str, model: &str) -> Result<()> {
+    let known = match arch {
+        "x86_64" => X86_64_CPU_MODELS,
+        "aarch64" => AARCH64_CPU_MODELS,
+        other => return Err(QvmError::UnsupportedArch(other.to_string())),
+    };
+
+    let mut parts = model.split(',');
+    let base = parts.next().unwrap_or_default();
+    let unversioned = match base.rsplit_once("-v") {
+        Some((name, v)) if !v.is_empty() && v.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => base,
+    };
+    if !known.contains(&unversioned) {
+        return Err(QvmError::Invalid(format!(
+            "Unknown {} CPU model '{}' (known: {})",
+            arch,
+            base,
+            known.join(", ")
+        )));
+    }
+
+    for flag in parts {
+        let name = flag
+            .strip_prefix(['+', '-'])
+            .or_else(|| flag.split_once('=').map(|(n, _)| n))
+            .unwrap_or_default();
+        let ok = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+        if !ok {
+            return Err(QvmError::Invalid(format!(
+                "Invalid CPU feature flag '{}' in '{}' (use +feat, -feat or feat=value)",
+                flag, model
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Pick the CPU model to store: `host` passthrough needs a hardware accelerator, so
+/// under TCG it becomes the portable `qemu64` (x86_64) or `max` (aarch64), keeping any flags
+pub fn resolve_cpu_model(arch: &str, model: &str, accel: &str) -> String {
+    let (base, flags) = match model.split_once(',') {
+        Some((base, flags)) => (base, Some(flags)),
+        None => (model, None),
+    };
+    if base != "host" || accel != "tcg" {
+        return model.to_string();
+    }
+    let fallback = if arch == "x86_64" { "qemu64" } else { "max" };
+    match flags {
+        Some(flags) => format!("{},{}", fallback, flags),
+        None => fallback.to_string(),
+    }
+}
+
+/// Memory left to the host and qemu's own overhead when checking a start against free memory
+pub const HOST_MEMORY_HEADROOM_MB: u64 = 512;
+
+/// Check a VM's memory and vCPUs against the host right now: `available_mb` of free memory
+/// and `host_cpus` logical CPUs (None when unknown). Memory that does not fit is an error
+/// unless `force`; more vCPUs than host CPUs only slows the guest, so it is a warning.
+pub fn check_host_capacity(
+    name: &str,
+    hw: &Hardware,
+    available_mb: Option<u64>,
+    host_cpus: Option<usize>,
+    force: bool,
+) -> Result<Vec<String>> {
+    let mut warnings = Vec::new();
+    if let Some(available) = available_mb {
+        let usable = available.saturating_sub(HOST_MEMORY_HEADROOM_MB);
+        if u64::from(hw.mem_mb) > usable {
+            let problem = format!(
+                "VM '{}' needs {} MB of memory but the host has {} MB available ({} MB is kept \
+                 for the host and qemu)",
+                name, hw.mem_mb, available, HOST_MEMORY_HEADROOM_MB
+            );
+            if !force {
+                return Err(QvmError::Invalid(format!(
+                    "{}; free some memory, lower hardware.mem_mb, or start anyway with --force",
+                    problem
+                )));
+            }
+            warnings.push(problem);
+        }
+    }
+
+    let vcpus = u64::from(hw.sockets) * u64::from(hw.cores) * u64::from(hw.threads);
+    if let Some(cpus) = host_cpus.filter(|&n| vcpus > n as u64) {
+        warnings.push(format!(
+            "VM '{}' has {} vCPUs but the host has {} logical CPUs; the guest will run slower",
+            name, vcpus, cpus
+        ));
+    }
+    Ok(warnings)
+}
+
+/// Display modes and accelerators that cannot work on host OS `os` (`std::env::consts::OS`):
+/// cocoa and hvf need macOS, kvm needs Linux. `display_mode` is None when no display is
+/// opened (serial console). Returns one message per problem; create warns, start refuses.
+pub fn host_incompatibilities(os: &str, display_mode: Option<&str>, accel: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    if display_mode == Some("cocoa") && os != "macos" {
+        problems.push("display 'cocoa' needs macOS; use vnc, spice or headless".to_string());
+    }
+    match accel {
+        "hvf" if os != "macos" => {
+            problems.push("accelerator 'hvf' only exists on macOS; use kvm or tcg".to_string())
+        }
+        "kvm" if os != "linux" => {
+            problems.push("accelerator 'kvm' needs Linux; use hvf on macOS, or tcg".to_string())
+        }
+        _ => {}
+    }
+    problems
+}
+
+/// Check a disk's cache and AIO modes: qemu only accepts `aio=native` with O_DIRECT I/O,
+/// i.e. `cache=none`
+pub fn validate_disk(disk: &Disk) -> Result<()> {
+    if disk.aio.as_deref() == Some("native") && disk.cache.as_deref() != Some("none") {
+        return Err(QvmError::Invalid(format!(
+            "Disk {}: aio=native requires cache=none (cache is {})",
+            disk.path.display(),
+            disk.cache.as_deref().unwrap_or("unset, i.e. writeback")
+        )));
+    }
+    Ok(())
+}
+
+/// Most displays a virtio GPU can drive (qemu's VIRTIO_GPU_MAX_SCANOUTS)
+const GPU_MAX_OUTPUTS: u32 = 16;
+
+/// Check the GPU of `display` for a guest of `arch`: its resolution and output count,
+/// virtio-vga only on x86_64, and 3D only where an OpenGL display can render it
+pub fn validate_gpu(display: &Display, arch: &str) -> Result<()> {
+    let gpu = &display.gpu;
+    gpu.resolution_size()?;
+    if gpu.max_outputs.is_some_and(|n| n == 0 || n > GPU_MAX_OUTPUTS) {
+        return Err(QvmError::Invalid(format!(
+            "display.gpu.max_outputs must be 1-{}",
+            GPU_MAX_OUTPUTS
+        )));
+    }
+    if gpu.vram_mb == Some(0) {
+        return Err(QvmError::Invalid("display.gpu.vram_mb must be at least 1".to_string()));
+    }
+    if gpu.model == "virtio-vga" && arch != "x86_64" {
+        return Err(QvmError::Invalid(format!(
+            "virtio-vga is a PC VGA device; {} guests use virtio-gpu-pci",
+            arch
+        )));
+    }
+    if gpu.gl && display.mode == "cocoa" {
+        return Err(QvmError::Invalid(
+            "display.gpu.gl needs a vnc or spice display (qemu's cocoa window has no OpenGL)".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Files qemu reads from a TLS cert directory for a server endpoint
+pub const TLS_FILES: &[&str] = &["ca-cert.pem", "server-cert.pem", "server-key.pem"];
+
+/// Check the TLS settings of the display `display.mode` selects: the cert directory holds
+/// `TLS_FILES`, and SPICE TLS is on a TCP port without client certificates
+pub fn validate_display_tls(display: &Display, root: &Path) -> Result<()> {
+    let tls = match display.mode.as_str() {
+        "vnc" => display.vnc.tls.as_ref(),
+        "spice" => {
+            let tls = display.spice.tls.as_ref();
+            if tls.is_some() && display.spice.use_unix {
+                return Err(QvmError::Invalid(
+                    "SPICE TLS needs a TCP port; it cannot be combined with a UNIX socket".to_string(),
+                ));
+            }
+            if tls.is_some_and(|t| t.verify_peer) {
+                return Err(QvmError::Invalid(
+                    "SPICE cannot verify client certificates; unset display.spice.tls.verify_peer".to_string(),
+                ));
+            }
+            tls
+        }
+        _ => None,
+    };
+    let Some(tls) = tls else {
+        return Ok(());
+    };
+    let dir = resolve_under_root(root, &tls.cert_dir);
+    let missing: Vec<&str> = TLS_FILES.iter().copied().filter(|f| !dir.join(f).is_file()).collect();
+    if !missing.is_empty() {
+        return Err(QvmError::Invalid(format!(
+            "TLS cert directory {} is missing {} (it needs {})",
+            dir.display(),
+            missing.join(", "),
+            TLS_FILES.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Check that this host can back guest RAM as `memory` asks (only hugepages depend on
+/// the host)
+pub fn validate_memory(memory: &Memory, mem_mb: u32) -> Result<()> {
+    let Some(path) = &memory.hugepages else {
+        return Ok(());
+    };
+    let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+    let mounts = fs::read_to_string("/proc/mounts").unwrap_or_default();
+    check_hugepages(std::env::consts::OS, path, mem_mb, &meminfo, &mounts)
+}
+
+/// Hugepage-backed RAM needs Linux, a hugetlbfs mount at `path` (per `mounts`, the text
+/// of /proc/mounts) and enough free huge pages for `mem_mb` (per `meminfo`, /proc/meminfo)
+pub fn check_hugepages(os: &str, path: &Path, mem_mb: u32, meminfo: &str, mounts: &str) -> Result<()> {
+    if os != "linux" {
+        return Err(QvmError::Invalid(format!(
+            "Hugepages are only supported on Linux hosts, not {}; unset memory.hugepages",
+            os
+        )));
+    }
+    let mounted = mounts.lines().any(|line| {
+        let mut fields = line.split_whitespace();
+        let mount_point = fields.nth(1);
+        let fs_type = fields.next();
+        fs_type == Some("hugetlbfs") && mount_point.map(Path::new) == Some(path)
+    });
+    if !mounted {
+        return Err(QvmError::Invalid(format!(
+            "{} is not a hugetlbfs mount (mount one with 'mount -t hugetlbfs none {}')",
+            path.display(),
+            path.display()
+        )));
+    }
+
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.split_whitespace().next()?.parse::<u64>().ok())
+    };
+    let (Some(free), Some(page_kb)) = (field("HugePages_Free:"), field("Hugepagesize:")) else {
+        return Err(QvmError::Invalid("This kernel reports no huge pages".to_string()));
+    };
+    let needed = (u64::from(mem_mb) * 1024).div_ceil(page_kb.max(1));
+    if free < needed {
+        return Err(QvmError::Invalid(format!(
+            "Not enough free huge pages: {} MB of guest RAM needs {} pages of {} kB, {} are free \
+             (reserve more by raising the vm.nr_hugepages sysctl)",
+            mem_mb, needed, page_kb, free
+        )));
+    }
+    Ok(())
+}
+
+/// Check the accelerator, memory and topology; returns non-fatal warnings for the caller.
+/// The CPU model is checked separately by `validate_cpu_model`.
+pub fn validate_hardware(hw: &Hardware) -> Result<Vec<String>> {
+    let mut warnings = Vec::new();
+    if !["kvm", "hvf", "tcg"].contains(&hw.accel.as_str()) {
+        return Err(QvmError::Invalid(format!(
+            "Unsupported accelerator '{}' (expected kvm, hvf or tcg)",
+            hw.accel
+        )));
+    }
+
+    if hw.mem_mb == 0 {
+        return Err(QvmError::Invalid("Memory must be at least 1 MB".to_string()));
+    }
+    if let Some(host_mb) = host_memory_mb() {
+        if u64::from(hw.mem_mb) > host_mb {
+            warnings.push(format!(
+                "VM memory ({} MB) exceeds host RAM ({} MB); the guest will swap heavily",
+                hw.mem_mb, host_mb
+            ));
+        }
+    }
+
+    validate_topology(hw)?;
+    Ok(warnings)
+}
+
+/// Check that every NIC has a valid MAC, no two NICs share one, and no host port is
+/// forwarded twice across the user-mode NICs
+pub fn validate_network(net: &Network) -> Result<()> {
+    let mut macs = Vec::new();
+    let mut ports = Vec::new();
+    for (i, nic) in net.interfaces.iter().enumerate() {
+        let mac = normalize_mac(&nic.mac)
+            .map_err(|e| QvmError::Invalid(format!("NIC {}: {}", i, e)))?;
+        if macs.contains(&mac) {
+            return Err(QvmError::Invalid(format!("NIC {}: MAC {} is used by another NIC", i, mac)));
+        }
+        macs.push(mac);
+        for f in &nic.forwards {
+            if ports.contains(&(&f.protocol, f.host_port)) {
+                return Err(QvmError::Invalid(format!(
+                    "NIC {}: host port {}/{} is forwarded more than once",
+                    i, f.host_port, f.protocol
+                )));
+            }
+            ports.push((&f.protocol, f.host_port));
+        }
+    }
+    Ok(())
+}
+
+/// Check that sockets, cores and threads are each at least 1 and their product fits
+pub fn validate_topology(hw: &Hardware) -> Result<()> {
+    if hw.sockets == 0 || hw.cores == 0 || hw.threads == 0 {
+        return Err(QvmError::Invalid(format!(
+            "Invalid CPU topology sockets={},cores={},threads={}: each must be at least 1",
+            hw.sockets, hw.cores, hw.threads
+        )));
+    }
+    if hw.sockets.checked_mul(hw.cores).and_then(|n| n.checked_mul(hw.threads)).is_none() {
+        return Err(QvmError::Invalid("CPU topology is too large".to_string()));
+    }
+    Ok(())
+}
+
+/// First qemu release with vmnet networking (macOS vmnet.framework netdevs)
+const QEMU_VMNET: QemuVersion = QemuVersion::new(7, 1, 0);
+/// First qemu release with `aio=io_uring`
+const QEMU_IO_URING: QemuVersion = QemuVersion::new(5, 0, 0);
+
+/// Settings of `cfg` that qemu `version` does not support yet, by vm.json key
+pub fn qemu_feature_problems(cfg: &VmConfig, version: QemuVersion) -> Vec<ValidationError> {
+    let mut problems = Vec::new();
+    let mut need = |key: String, what: &str, required: QemuVersion| {
+        if version < required {
+            let message = format!("{} needs QEMU {} or newer (found {})", what, required, version);
+            problems.push(ValidationError::new(key, message));
+        }
+    };
+
+    let machine = cfg.hardware.machine.split(',').next().unwrap_or_default();
+    if let Some(required) = machine_type_version(machine) {
+        need("hardware.machine".into(), &format!("machine type '{}'", machine), required);
+    }
+    for (i, nic) in cfg.network.interfaces.iter().enumerate() {
+        if nic.mode.starts_with("vmnet-") {
+            need(format!("network.interfaces.{}.mode", i), &nic.mode, QEMU_VMNET);
+        }
+    }
+    for (i, disk) in cfg.paths.disks.iter().enumerate() {
+        if disk.aio.as_deref() == Some("io_uring") {
+            need(format!("paths.disks.{}.aio", i), "aio=io_uring", QEMU_IO_URING);
+        }
+    }
+    problems
+}
+
+/// The release a versioned machine type first appeared in (`pc-q35-8.2`, `virt-9.0`)
+pub fn machine_type_version(machine: &str) -> Option<QemuVersion> {
+    let (_, version) = machine.rsplit_once('-')?;
+    let (major, minor) = version.split_once('.')?;
+    Some(QemuVersion::new(major.parse().ok()?, minor.parse().ok()?, 0))
+}
+
+/// One problem found by `VmConfig::validate`: the vm.json key it concerns and what is wrong
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub key: String,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub(crate) fn new(key: impl Into<String>, message: impl Into<String>) -> Self {
+        ValidationError {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
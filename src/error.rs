@@ -0,0 +1,103 @@
+//! Error types for QVM
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Errors produced by QVM operations
+#[derive(Debug)]
+pub enum QvmError {
+    /// No `<name>.qvm` directory exists in qvm home
+    VmNotFound { name: String, home: PathBuf },
+    /// A VM with the requested name already exists
+    VmExists(String),
+    /// The operation requires the VM to be stopped
+    VmRunning { name: String, action: &'static str },
+    /// Another qvm invocation holds the VM's lock
+    VmBusy(String),
+    /// The operation requires the VM to be running
+    VmNotRunning(String),
+    /// No usable qemu-system-* binary for the given architecture
+    QemuNotFound(String),
+    /// UEFI firmware could not be located or is missing
+    FirmwareNotFound(String),
+    /// A disk image referenced by the configuration is missing
+    DiskNotFound(PathBuf),
+    /// The guest architecture is not supported
+    UnsupportedArch(String),
+    /// The user's home directory could not be determined
+    HomeNotFound,
+    /// An argument or configuration value is invalid
+    Invalid(String),
+    /// A QMP exchange with a running VM failed
+    Qmp(String),
+    /// An external command or process operation failed
+    CommandFailed(String),
+    /// vm.json was written by a newer qvm with an unknown schema version
+    UnsupportedConfigVersion { found: u32, supported: u32 },
+    /// vm.json could not be parsed or serialized
+    ConfigParse(serde_json::Error),
+    /// Filesystem or process I/O failure
+    Io(io::Error),
+}
+
+impl fmt::Display for QvmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QvmError::VmNotFound { name, home } if !home.exists() => write!(
+                f,
+                "VM '{}' not found: {} does not exist yet ('qvm create' makes it)",
+                name,
+                home.display()
+            ),
+            QvmError::VmNotFound { name, home } => {
+                write!(f, "VM '{}' not found in {}", name, home.display())
+            }
+            QvmError::VmExists(name) => write!(f, "VM '{}' already exists", name),
+            QvmError::VmRunning { name, action } => write!(
+                f,
+                "Cannot {} VM '{}': VM is currently running. Stop it first with 'qvm stop {}'",
+                action, name, name
+            ),
+            QvmError::VmBusy(name) => write!(f, "operation already in progress for VM '{}'", name),
+            QvmError::VmNotRunning(name) => write!(f, "VM '{}' is not running", name),
+            QvmError::QemuNotFound(arch) => write!(f, "qemu-system-{} not found (Nix)", arch),
+            QvmError::FirmwareNotFound(detail) => write!(f, "UEFI firmware not found: {}", detail),
+            QvmError::DiskNotFound(path) => write!(f, "Disk image not found: {}", path.display()),
+            QvmError::UnsupportedArch(arch) => write!(f, "Unsupported arch '{}'", arch),
+            QvmError::HomeNotFound => write!(f, "no home directory found"),
+            QvmError::Invalid(msg) => write!(f, "{}", msg),
+            QvmError::Qmp(msg) => write!(f, "QMP: {}", msg),
+            QvmError::CommandFailed(msg) => write!(f, "{}", msg),
+            QvmError::UnsupportedConfigVersion { found, supported } => write!(
+                f,
+                "vm.json has schema version {} but this qvm supports up to version {}; upgrade qvm",
+                found, supported
+            ),
+            QvmError::ConfigParse(e) => write!(f, "Invalid VM configuration: {}", e),
+            QvmError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for QvmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QvmError::ConfigParse(e) => Some(e),
+            QvmError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for QvmError {
+    fn from(e: io::Error) -> Self {
+        QvmError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for QvmError {
+    fn from(e: serde_json::Error) -> Self {
+        QvmError::ConfigParse(e)
+    }
+}
@@ -3,6 +3,7 @@
 //! A modern, efficient library for managing QEMU virtual machines.
 
 pub mod cli;
+pub mod error;
 pub mod vm;
 pub mod config;
 pub mod utils;
@@ -11,9 +12,10 @@ pub mod utils;
 pub use config::schema::VmConfig;
 pub use vm::manager::VmManager;
 pub use cli::commands::Cli;
+pub use error::QvmError;
 
-/// Library error type
-pub type Result<T> = anyhow::Result<T>;
+/// Library result type
+pub type Result<T> = std::result::Result<T, QvmError>;
 
 #[cfg(test)]
 mod tests {
@@ -26,11 +28,6 @@ mod tests {
     use std::fs::File;
     use std::path::PathBuf;
 
-    // Helper function that was removed from main
-    fn _cpu_total(s: u32, c: u32, t: u32) -> u32 {
-        s.saturating_mul(c).saturating_mul(t)
-    }
-
     #[test]
     fn test_now_utc() {
         let timestamp = now_utc();
@@ -53,6 +50,30 @@ mod tests {
         assert_eq!(resolve_under_root(&root, &rel_path), root.join("relative/path"));
     }
 
+    #[test]
+    fn test_expand_path() {
+        use utils::paths::expand_path;
+
+        let home = dirs::home_dir().unwrap();
+        let expand = |p: &str| expand_path(&PathBuf::from(p)).unwrap();
+        assert_eq!(expand("~"), home);
+        assert_eq!(expand("~/sub"), home.join("sub"));
+        let home_var = PathBuf::from(std::env::var("HOME").unwrap());
+        assert_eq!(expand("$HOME/sub"), home_var.join("sub"));
+        assert_eq!(expand("${HOME}/sub"), home_var.join("sub"));
+
+        // Absolute stays absolute, relative stays relative (resolved under root later)
+        assert_eq!(expand("/abs/disk.qcow2"), PathBuf::from("/abs/disk.qcow2"));
+        assert_eq!(expand("disk.qcow2"), PathBuf::from("disk.qcow2"));
+        assert!(expand("$HOME/disk.qcow2").is_absolute());
+        // Only a leading ~ is the home directory; a lone $ or $1 is literal
+        assert_eq!(expand("a/~/b$"), PathBuf::from("a/~/b$"));
+        assert_eq!(expand("x$1"), PathBuf::from("x$1"));
+
+        let err = expand_path(&PathBuf::from("$QVM_TEST_SURELY_UNSET_VAR/x")).unwrap_err();
+        assert!(err.to_string().contains("$QVM_TEST_SURELY_UNSET_VAR is not set"));
+    }
+
     #[test]
     fn test_conf_path() {
         let root = PathBuf::from("/tmp/test");
@@ -61,9 +82,17 @@ mod tests {
 
     #[test]
     fn test_cpu_total() {
-        assert_eq!(_cpu_total(2, 4, 2), 16);
-        assert_eq!(_cpu_total(1, 8, 1), 8);
-        assert_eq!(_cpu_total(0, 4, 2), 0);
+        let mut cfg = launch_config();
+        let mut total = |s, c, t| {
+            cfg.hardware.sockets = s;
+            cfg.hardware.cores = c;
+            cfg.hardware.threads = t;
+            cfg.vcpu_total()
+        };
+        assert_eq!(total(2, 4, 2), 16);
+        assert_eq!(total(1, 8, 1), 8);
+        assert_eq!(total(0, 4, 2), 0);
+        assert_eq!(total(u32::MAX, 2, 2), u32::MAX);
     }
 
     #[test]
@@ -72,11 +101,707 @@ mod tests {
         assert!(home.to_string_lossy().contains("qvm"));
     }
 
+    #[test]
+    fn test_validate_vm_name() {
+        use utils::paths::validate_vm_name;
+
+        assert!(validate_vm_name("my-vm").is_ok());
+        assert!(validate_vm_name("ubuntu_24.04").is_ok());
+        assert!(validate_vm_name("").is_err());
+        assert!(validate_vm_name(".hidden").is_err());
+        assert!(validate_vm_name("-flag").is_err());
+        assert!(validate_vm_name("a/b").is_err());
+        assert!(validate_vm_name("has space").is_err());
+        assert!(validate_vm_name(&"x".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn test_normalize_mac() {
+        use utils::system::{normalize_mac, random_mac};
+
+        assert_eq!(normalize_mac("52:54:00:AB:cd:EF").unwrap(), "52:54:00:ab:cd:ef");
+        assert!(normalize_mac("52:54:00:12:34").is_err());
+        assert!(normalize_mac("52-54-00-12-34-56").is_err());
+        assert!(normalize_mac("52:54:00:12:34:5g").is_err());
+        assert!(normalize_mac("52:54:00:1:234:56").is_err());
+        // Multicast and broadcast
+        assert!(normalize_mac("01:00:5e:00:00:01").is_err());
+        assert!(normalize_mac("ff:ff:ff:ff:ff:ff").is_err());
+        assert!(normalize_mac(&random_mac()).is_ok());
+    }
+
+    #[test]
+    fn test_generate_mac() {
+        use utils::system::{generate_mac, normalize_mac, MacStrategy, DEFAULT_MAC_PREFIX};
+
+        let web = generate_mac(MacStrategy::Deterministic, DEFAULT_MAC_PREFIX, "web").unwrap();
+        assert!(web.starts_with("52:54:00:"));
+        assert_eq!(normalize_mac(&web).unwrap(), web);
+        assert_eq!(generate_mac(MacStrategy::Deterministic, DEFAULT_MAC_PREFIX, "web").unwrap(), web);
+        assert_ne!(generate_mac(MacStrategy::Deterministic, DEFAULT_MAC_PREFIX, "db").unwrap(), web);
+        // Pinned: the hash must not change between releases
+        assert_eq!(web, "52:54:00:93:59:19");
+
+        let custom = generate_mac(MacStrategy::Deterministic, "02:AB:cd", "web").unwrap();
+        assert_eq!(custom, format!("02:ab:cd{}", &web[8..]));
+        let random = generate_mac(MacStrategy::Random, "02:00:00", "web").unwrap();
+        assert!(random.starts_with("02:00:00:") && normalize_mac(&random).is_ok());
+
+        for bad in ["52:54", "52:54:00:00", "01:00:5e", "zz:00:00", ""] {
+            let err = generate_mac(MacStrategy::Random, bad, "web").unwrap_err().to_string();
+            assert!(err.contains("Invalid MAC prefix"), "{bad}: {err}");
+        }
+        assert_eq!(MacStrategy::parse("deterministic").unwrap(), MacStrategy::Deterministic);
+        assert!(MacStrategy::parse("sequential").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        use utils::size::{format_size, parse_disk_size, parse_size};
+
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("64G").unwrap(), 64 << 30);
+        assert_eq!(parse_size("64g").unwrap(), 64 << 30);
+        assert_eq!(parse_size("512MiB").unwrap(), 512 << 20);
+        assert_eq!(parse_size("10GB").unwrap(), 10_000_000_000);
+        assert_eq!(parse_size("2T").unwrap(), 2 << 40);
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("").is_err());
+        assert!(parse_size("-5G").is_err());
+        assert!(parse_size("1.5G").is_err());
+        assert!(parse_size("99999999999T").is_err());
+
+        assert!(parse_disk_size("512K").is_err());
+        assert!(parse_disk_size("1M").is_ok());
+
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(64 << 30), "64.0 GiB");
+        assert_eq!(format_size(1536 << 20), "1.5 GiB");
+    }
+
+    #[test]
+    fn test_vm_name_globs() {
+        use utils::paths::{glob_match, is_glob};
+
+        assert!(is_glob("test-*"));
+        assert!(is_glob("vm?"));
+        assert!(is_glob("vm[12]"));
+        assert!(!is_glob("test-vm"));
+
+        assert!(glob_match("test-*", "test-a"));
+        assert!(glob_match("test-*", "test-"));
+        assert!(!glob_match("test-*", "my-test-a"));
+        assert!(glob_match("vm?", "vm1"));
+        assert!(!glob_match("vm?", "vm10"));
+        assert!(glob_match("vm[12]", "vm2"));
+        assert!(!glob_match("vm[12]", "vm3"));
+    }
+
+    #[test]
+    fn test_screendump_to_png() {
+        use vm::screenshot::{encode_png, parse_ppm};
+
+        let ppm = b"P6\n# qemu screendump\n2 1\n255\n\xff\x00\x00\x00\x00\xff";
+        let (width, height, pixels) = parse_ppm(ppm).unwrap();
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(pixels, [255, 0, 0, 0, 0, 255]);
+        assert!(parse_ppm(b"P3\n2 1\n255\n").is_err());
+        assert!(parse_ppm(b"P6\n2 2\n255\n\x00\x00\x00").is_err());
+
+        // Decodes back to the same 8-bit RGB image
+        let png = encode_png(width, height, pixels).unwrap();
+        let mut reader = png::Decoder::new(std::io::Cursor::new(png)).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size().unwrap()];
+        let frame = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!((frame.width, frame.height), (2, 1));
+        assert_eq!((frame.color_type, frame.bit_depth), (png::ColorType::Rgb, png::BitDepth::Eight));
+        assert_eq!(&decoded[..frame.buffer_size()], pixels);
+
+        // Fewer pixels than the header promises
+        assert!(encode_png(2, 2, pixels).is_err());
+    }
+
+    #[test]
+    fn test_parse_key_combo() {
+        use vm::keys::{parse_key_combo, send_key_arguments};
+
+        assert_eq!(parse_key_combo("ctrl-alt-delete").unwrap(), vec!["ctrl", "alt", "delete"]);
+        assert_eq!(parse_key_combo("Enter").unwrap(), vec!["ret"]);
+        assert_eq!(parse_key_combo("shift-a").unwrap(), vec!["shift", "a"]);
+        assert_eq!(parse_key_combo("alt-F4").unwrap(), vec!["alt", "f4"]);
+        assert_eq!(parse_key_combo("7").unwrap(), vec!["7"]);
+        assert!(parse_key_combo("ctrl-").is_err());
+        assert!(parse_key_combo("hyper").is_err());
+        assert!(parse_key_combo("ab").is_err());
+
+        let args = send_key_arguments(&parse_key_combo("ctrl-c").unwrap(), 100);
+        assert_eq!(
+            args,
+            serde_json::json!({
+                "keys": [{"type": "qcode", "data": "ctrl"}, {"type": "qcode", "data": "c"}],
+                "hold-time": 100
+            })
+        );
+    }
+
+    #[test]
+    fn test_atomic_config_write() {
+        use config::schema::VmConfigBuilder;
+        use std::io::Write;
+        use utils::system::write_atomic;
+        use vm::config::load_conf_from_dir;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = VmConfigBuilder::new("atomic-vm", "aarch64").root(dir.path()).mem_mb(2048).build();
+        save_conf(&cfg).unwrap();
+        let good = std::fs::read_to_string(conf_path(dir.path())).unwrap();
+
+        // A write that dies halfway (as a crash or full disk would) leaves the old file in place
+        let result = write_atomic(&conf_path(dir.path()), |f| {
+            f.write_all(&good.as_bytes()[..good.len() / 2])?;
+            Err(QvmError::Io(std::io::Error::other("disk full")))
+        });
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(conf_path(dir.path())).unwrap(), good);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1, "temp file left behind");
+        assert_eq!(load_conf_from_dir(dir.path()).unwrap().hardware.mem_mb, 2048);
+
+        let mut bigger = cfg.clone();
+        bigger.hardware.mem_mb = 4096;
+        save_conf(&bigger).unwrap();
+        assert_eq!(load_conf_from_dir(dir.path()).unwrap().hardware.mem_mb, 4096);
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        use std::io::{Seek, SeekFrom, Write};
+        use utils::size::disk_usage;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("sub/data"), vec![1u8; 256 << 10]).unwrap();
+        // A 1 GiB sparse file holding a single byte
+        let mut sparse = File::create(dir.path().join("sparse.img")).unwrap();
+        sparse.seek(SeekFrom::Start((1 << 30) - 1)).unwrap();
+        sparse.write_all(&[0]).unwrap();
+
+        let total = disk_usage(dir.path());
+        assert!(total >= 256 << 10);
+        assert!(total < 64 << 20, "sparse file counted at its apparent size");
+        assert!(disk_usage(&dir.path().join("sub")) >= 256 << 10);
+        assert_eq!(disk_usage(&dir.path().join("missing")), 0);
+    }
+
+    #[test]
+    fn test_vmnet_entitlement_check() {
+        use std::path::Path;
+        use vm::network::{check_vmnet_access, grants_vmnet, vmnet_remedy};
+
+        let xml = "<?xml version=\"1.0\"?><plist version=\"1.0\"><dict>\n\
+                   \t<key>com.apple.security.hypervisor</key>\n\t<true/>\n\
+                   \t<key>com.apple.vm.networking</key>\n\t<true/>\n</dict></plist>";
+        assert!(grants_vmnet(xml));
+        assert!(grants_vmnet("<key>com.apple.vm.networking</key><true/></dict>"));
+        assert!(!grants_vmnet(&xml.replace("networking</key>\n\t<true/>", "networking</key>\n\t<false/>")));
+        assert!(!grants_vmnet("<key>com.apple.security.hypervisor</key><true/>"));
+        let listing = "[Dict]\n\t[Key] com.apple.vm.networking\n\t[Value]\n\t\t[Bool] true\n";
+        assert!(grants_vmnet(listing));
+        assert!(!grants_vmnet(&listing.replace("true", "false")));
+
+        let qemu = Path::new("/opt/homebrew/bin/qemu-system-aarch64");
+        let msg = vmnet_remedy("web", 0, "vmnet-shared", qemu, Path::new("/Users/me/qvm"));
+        assert!(msg.contains("VM 'web' uses vmnet-shared networking (network.interfaces.0)"));
+        assert!(msg.contains("sudo qvm --qvm-home /Users/me/qvm start web"));
+        assert!(msg.contains("qvm set web network.interfaces.0.mode user"));
+
+        // User-mode networking needs no privileges anywhere
+        let mut cfg = launch_config();
+        cfg.network.interfaces[0].mode = "user".into();
+        assert!(check_vmnet_access("web", &cfg.network, Path::new("/nonexistent/qemu")).is_ok());
+    }
+
+    #[test]
+    fn test_ssh_forward_lookup() {
+        use config::schema::{Forward, Network, NetworkInterface};
+        use vm::ssh::{ssh_args, ssh_host_port};
+
+        let ssh = Forward { protocol: "tcp".into(), host_port: 2222, guest_port: 22 };
+        let mut net = Network {
+            interfaces: vec![
+                NetworkInterface { forwards: vec![ssh.clone()], ..NetworkInterface::default() },
+                NetworkInterface {
+                    mode: "user".into(),
+                    forwards: vec![
+                        Forward { protocol: "udp".into(), host_port: 2200, guest_port: 22 },
+                        Forward { protocol: "tcp".into(), host_port: 8080, guest_port: 80 },
+                    ],
+                    ..NetworkInterface::default()
+                },
+            ],
+            ssh_user: None,
+        };
+        // Forwards on a vmnet NIC do nothing
+        assert_eq!(ssh_host_port(&net), None);
+        net.interfaces[1].forwards.push(ssh);
+        assert_eq!(ssh_host_port(&net), Some(2222));
+
+        assert_eq!(ssh_args(2222, None, &[]), vec!["-p", "2222", "127.0.0.1"]);
+        assert_eq!(
+            ssh_args(2222, Some("ubuntu"), &["uname".to_string(), "-a".to_string()]),
+            vec!["-p", "2222", "ubuntu@127.0.0.1", "uname", "-a"]
+        );
+    }
+
+    #[test]
+    fn test_network_args() {
+        use config::schema::{Forward, NetworkInterface};
+        use vm::network::{interface_exists, nic_args};
+
+        let mut nic = NetworkInterface {
+            mode: "user".into(),
+            bridge_if: "en0".into(),
+            mac: "52:54:00:12:34:56".into(),
+            forwards: vec![Forward { protocol: "tcp".into(), host_port: 2222, guest_port: 22 }],
+        };
+        assert_eq!(
+            nic_args(0, &nic, None).unwrap(),
+            vec![
+                "-netdev",
+                "user,id=net0,hostfwd=tcp::2222-:22",
+                "-device",
+                "virtio-net-pci,netdev=net0,mac=52:54:00:12:34:56",
+            ]
+        );
+
+        nic.mode = "vmnet-shared".into();
+        assert_eq!(nic_args(0, &nic, None).unwrap()[1], "vmnet-shared,id=net0");
+
+        nic.mode = "vmnet-bridged".into();
+        nic.bridge_if = "qvm-no-such-if0".into();
+        assert!(!interface_exists(&nic.bridge_if).unwrap());
+        assert!(nic_args(0, &nic, None).is_err());
+
+        nic.mode = "tap".into();
+        assert!(nic_args(0, &nic, None).is_err());
+    }
+
+    #[test]
+    fn test_multiple_nics() {
+        use config::schema::{Boot, Forward, Network, NetworkInterface};
+        use config::validate::validate_network;
+        use vm::network::network_args;
+
+        let mut net = Network {
+            interfaces: vec![
+                NetworkInterface { mac: "52:54:00:00:00:01".into(), ..NetworkInterface::default() },
+                NetworkInterface {
+                    mode: "user".into(),
+                    mac: "52:54:00:00:00:02".into(),
+                    forwards: vec![Forward { protocol: "tcp".into(), host_port: 2222, guest_port: 22 }],
+                    ..NetworkInterface::default()
+                },
+            ],
+            ssh_user: None,
+        };
+        assert_eq!(
+            network_args(&net, &Boot::default()).unwrap(),
+            vec![
+                "-netdev",
+                "vmnet-shared,id=net0",
+                "-device",
+                "virtio-net-pci,netdev=net0,mac=52:54:00:00:00:01",
+                "-netdev",
+                "user,id=net1,hostfwd=tcp::2222-:22",
+                "-device",
+                "virtio-net-pci,netdev=net1,mac=52:54:00:00:00:02",
+            ]
+        );
+        assert_eq!(net.default_forward_nic(), Some(1));
+        validate_network(&net).unwrap();
+
+        // MACs and forwarded host ports must be unique across NICs
+        let mut dup = net.clone();
+        dup.interfaces[1].mac = "52:54:00:00:00:01".into();
+        assert!(validate_network(&dup).unwrap_err().to_string().contains("NIC 1"));
+        let mut dup = net.clone();
+        dup.interfaces[0].forwards = dup.interfaces[1].forwards.clone();
+        assert!(validate_network(&dup).is_err());
+        let mut bad = net.clone();
+        bad.interfaces[0].mac = "ff:ff:ff:ff:ff:ff".into();
+        assert!(validate_network(&bad).is_err());
+
+        // No NICs at all: keep qemu from adding its default one
+        net.interfaces.clear();
+        assert_eq!(network_args(&net, &Boot::default()).unwrap(), vec!["-nic", "none"]);
+        assert_eq!(net.default_forward_nic(), None);
+    }
+
+    #[test]
+    fn test_archive_vm_dir_of() {
+        use vm::archive::vm_dir_of;
+
+        let ok = |list: &[&str]| vm_dir_of(&list.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        assert_eq!(ok(&["my-vm.qvm/", "my-vm.qvm/vm.json", "./my-vm.qvm/disk.qcow2"]).unwrap(), "my-vm.qvm");
+        assert!(ok(&[]).is_err());
+        assert!(ok(&["my-vm/vm.json"]).is_err());
+        assert!(ok(&["a.qvm/vm.json", "b.qvm/vm.json"]).is_err());
+        assert!(ok(&["my-vm.qvm/../../etc/passwd"]).is_err());
+        assert!(ok(&["/my-vm.qvm/vm.json"]).is_err());
+        assert!(ok(&[".hidden.qvm/vm.json"]).is_err());
+    }
+
+    #[test]
+    fn test_locate_firmware_in_override_dir() {
+        use vm::firmware::{firmware_search_dirs, locate_firmware_in};
+
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("AAVMF_CODE.fd"), b"").unwrap();
+        std::fs::write(temp_dir.path().join("AAVMF_VARS.fd"), b"").unwrap();
+        std::fs::write(temp_dir.path().join("AAVMF_CODE.ms.fd"), b"").unwrap();
+        std::fs::write(temp_dir.path().join("AAVMF_VARS.ms.fd"), b"").unwrap();
+
+        // The override directory is searched first
+        let qemu = PathBuf::from("/nonexistent/bin/qemu-system-aarch64");
+        let dirs = firmware_search_dirs(Some(temp_dir.path()), &qemu, "aarch64");
+        assert_eq!(dirs[0], temp_dir.path());
+        assert_eq!(dirs[1], PathBuf::from("/nonexistent/share/qemu"));
+        let (code, vars) = locate_firmware_in(&dirs, "aarch64", false).unwrap();
+        let (secure_code, secure_vars) = locate_firmware_in(&dirs, "aarch64", true).unwrap();
+        assert_eq!(code, temp_dir.path().join("AAVMF_CODE.fd"));
+        assert_eq!(vars, temp_dir.path().join("AAVMF_VARS.fd"));
+        assert_eq!(secure_code, temp_dir.path().join("AAVMF_CODE.ms.fd"));
+        assert_eq!(secure_vars, temp_dir.path().join("AAVMF_VARS.ms.fd"));
+    }
+
+    #[test]
+    fn test_secure_boot_firmware() {
+        use vm::firmware::firmware_pairs;
+        use vm::launch::{build_qemu_args, StartOptions};
+
+        // The secure boot lists never offer a plain build, and vice versa
+        for arch in ["aarch64", "x86_64"] {
+            let plain = firmware_pairs(arch, false).unwrap();
+            for pair in firmware_pairs(arch, true).unwrap() {
+                assert!(!plain.contains(pair));
+            }
+        }
+        assert!(firmware_pairs("riscv64", true).is_err());
+
+        let mut cfg = config::schema::VmConfigBuilder::new("sb-vm", "x86_64")
+            .root("/vms/sb-vm.qvm")
+            .firmware("/fw/OVMF_CODE.secboot.fd", "/fw/OVMF_VARS.secboot.fd")
+            .build();
+        cfg.firmware.secure_boot = true;
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        let machine = args.iter().position(|a| a == "-machine").unwrap();
+        assert_eq!(args[machine + 1], "q35,smm=on");
+        assert!(args.windows(2).any(|w| w == ["-global", "driver=cfi.pflash01,property=secure,value=on"]));
+
+        // aarch64 secure boot firmware needs no SMM
+        cfg.meta.arch = "aarch64".into();
+        cfg.hardware.machine = "virt".into();
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        assert!(args.contains(&"virt".to_string()) && !args.contains(&"-global".to_string()));
+    }
+
+    #[test]
+    fn test_share_args() {
+        use config::schema::Share;
+        use vm::launch::memory_args;
+        use vm::share::{share_args, validate_tag};
+
+        assert!(validate_tag("src").is_ok());
+        assert!(validate_tag("").is_err());
+        assert!(validate_tag("has space").is_err());
+        assert!(validate_tag(&"t".repeat(37)).is_err());
+
+        let root = PathBuf::from("/vms/a.qvm");
+        let shares = vec![Share { path: PathBuf::from("/home/me/src"), tag: "src".into(), readonly: true }];
+        assert!(share_args(&[], &root, true).is_empty());
+
+        // virtiofs needs guest RAM in shared memory
+        let mem = memory_args(&Default::default(), 4096, true);
+        assert!(mem.contains(&"memory-backend-memfd,id=mem,size=4096M,share=on".to_string()));
+        let fs = share_args(&shares, &root, true);
+        assert!(fs.contains(&"socket,id=fs0,path=/vms/a.qvm/virtiofs-src.sock".to_string()));
+        assert!(fs.contains(&"vhost-user-fs-pci,chardev=fs0,tag=src".to_string()));
+
+        let p9 = share_args(&shares, &root, false);
+        assert_eq!(
+            p9,
+            vec![
+                "-virtfs",
+                "local,path=/home/me/src,mount_tag=src,security_model=mapped-xattr,id=fs0,readonly=on",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_log_last_lines() {
+        use vm::logs::last_lines;
+
+        let text = "one\ntwo\nthree\n";
+        assert_eq!(last_lines(text, 2), "two\nthree\n");
+        assert_eq!(last_lines(text, 3), text);
+        assert_eq!(last_lines(text, 10), text);
+        assert_eq!(last_lines(text, 0), "");
+        assert_eq!(last_lines("a\nb", 1), "b");
+        assert_eq!(last_lines("", 5), "");
+    }
+
+    #[test]
+    fn test_stale_pid_detection() {
+        use utils::system::{is_qemu_for, running_vm_pid};
+
+        let uuid = "0b7c2a4e-1111-2222-3333-444455556666";
+        let argv = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert!(is_qemu_for(&argv(&["/nix/store/x/bin/qemu-system-aarch64", "-uuid", uuid]), Some(uuid), None));
+        assert!(!is_qemu_for(&argv(&["qemu-system-aarch64", "-uuid", "other"]), Some(uuid), None));
+        assert!(!is_qemu_for(&argv(&["/usr/bin/sleep", "-uuid", uuid]), Some(uuid), None));
+        assert!(is_qemu_for(&argv(&["qemu-system-x86_64"]), None, None));
+        // A VM pinned to a binary named otherwise (a distro's qemu-kvm) is recognized by it
+        let pinned = std::path::Path::new("/usr/libexec/qemu-kvm");
+        assert!(is_qemu_for(&argv(&["/usr/libexec/qemu-kvm", "-uuid", uuid]), Some(uuid), Some(pinned)));
+        assert!(!is_qemu_for(&argv(&["/usr/libexec/qemu-kvm", "-uuid", uuid]), Some(uuid), None));
+
+        // A live PID that is not qemu (this test process) counts as a recycled, stale PID
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("vm.pid");
+        std::fs::write(&pid_file, std::process::id().to_string()).unwrap();
+        assert_eq!(running_vm_pid(dir.path()), None);
+        assert!(!pid_file.exists());
+    }
+
+    #[test]
+    fn test_report_json() {
+        use cli::output::Report;
+        use serde_json::json;
+
+        #[derive(serde::Serialize)]
+        struct Row {
+            running: bool,
+        }
+
+        let report = Report::new("stop")
+            .field("name", "vm1")
+            .fields(Row { running: false })
+            .line("Stopped VM 'vm1'");
+        assert_eq!(
+            report.to_json(),
+            json!({"action": "stop", "name": "vm1", "running": false})
+        );
+    }
+
+    #[test]
+    fn test_log_level_from_flags() {
+        use tracing_subscriber::filter::LevelFilter;
+        use utils::log::level_from_flags;
+
+        assert_eq!(level_from_flags(0, false), LevelFilter::INFO);
+        assert_eq!(level_from_flags(0, true), LevelFilter::ERROR);
+        assert_eq!(level_from_flags(1, false), LevelFilter::DEBUG);
+        assert_eq!(level_from_flags(3, false), LevelFilter::TRACE);
+        // Warnings still show when notes do
+        assert!(LevelFilter::WARN < LevelFilter::INFO);
+    }
+
+    #[test]
+    fn test_vm_lock_is_exclusive() {
+        use vm::lock::VmLock;
+
+        let dir = tempfile::tempdir().unwrap();
+        let held = VmLock::acquire("busy", dir.path()).unwrap();
+        let err = VmLock::acquire("busy", dir.path()).unwrap_err();
+        assert_eq!(err.to_string(), "operation already in progress for VM 'busy'");
+
+        drop(held);
+        assert!(VmLock::acquire("busy", dir.path()).is_ok());
+    }
+
+    #[test]
+    fn test_validate_hardware() {
+        use config::schema::Hardware;
+        use config::validate::validate_hardware;
+
+        let hw = Hardware {
+            cpu_model: "host".to_string(),
+            sockets: 1,
+            cores: 4,
+            threads: 1,
+            mem_mb: 1024,
+            machine: "virt".to_string(),
+            accel: "hvf".to_string(),
+            rtc: "utc".to_string(),
+        };
+        assert!(validate_hardware(&hw).unwrap().is_empty());
+        assert!(validate_hardware(&Hardware { mem_mb: 0, ..hw.clone() }).is_err());
+        assert!(validate_hardware(&Hardware { cores: 0, ..hw.clone() }).is_err());
+        assert!(validate_hardware(&Hardware { sockets: u32::MAX, cores: 2, ..hw.clone() }).is_err());
+        // More than any real host has: accepted with a warning
+        assert_eq!(validate_hardware(&Hardware { mem_mb: u32::MAX, ..hw }).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_cpu_models() {
+        use config::validate::{resolve_cpu_model, validate_cpu_model};
+
+        assert!(validate_cpu_model("x86_64", "host").is_ok());
+        assert!(validate_cpu_model("x86_64", "qemu64,+ssse3,-avx,level=13").is_ok());
+        assert!(validate_cpu_model("x86_64", "Skylake-Client-v2").is_ok());
+        assert!(validate_cpu_model("aarch64", "cortex-a72,pauth=off").is_ok());
+        assert!(validate_cpu_model("aarch64", "qemu64").is_err());
+        assert!(validate_cpu_model("x86_64", "pentium9").is_err());
+        assert!(validate_cpu_model("x86_64", "qemu64,+").is_err());
+        assert!(validate_cpu_model("x86_64", "qemu64,ssse3 ").is_err());
+
+        assert_eq!(resolve_cpu_model("x86_64", "host", "kvm"), "host");
+        assert_eq!(resolve_cpu_model("x86_64", "host", "hvf"), "host");
+        assert_eq!(resolve_cpu_model("x86_64", "host,+avx2", "tcg"), "qemu64,+avx2");
+        assert_eq!(resolve_cpu_model("aarch64", "host", "tcg"), "max");
+        assert_eq!(resolve_cpu_model("x86_64", "max", "tcg"), "max");
+
+        // A config built or edited without going through create still launches under TCG
+        use config::schema::VmConfigBuilder;
+        use vm::launch::{build_qemu_args, StartOptions};
+        let cfg = VmConfigBuilder::new("emulated", "aarch64").root("/tmp/emulated").build();
+        assert_eq!((cfg.hardware.cpu_model.as_str(), cfg.hardware.accel.as_str()), ("host", "tcg"));
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        assert!(args.windows(2).any(|w| w == ["-cpu", "max"]), "{:?}", args);
+    }
+
+    #[test]
+    fn test_set_config_key() {
+        use config::schema::VmConfigBuilder;
+        use config::set::set_key;
+
+        let cfg = VmConfigBuilder::new("set-vm", "x86_64").mem_mb(2048).build();
+
+        let out = set_key(&cfg, "hardware.mem_mb", "1024").unwrap();
+        assert_eq!(out.config.hardware.mem_mb, 1024);
+        assert_eq!(out.old, serde_json::json!(2048));
+        assert!(set_key(&cfg, "hardware.mem_mb", "lots").is_err());
+        assert!(set_key(&cfg, "hardware.mem_mb", "-1").is_err());
+
+        // Array elements by index, optional fields set from plain strings and cleared with null
+        let out = set_key(&cfg, "paths.disks.0.format", "raw").unwrap();
+        assert_eq!(out.config.paths.disks[0].format, "raw");
+        let out = set_key(&cfg, "paths.iso", "/isos/install.iso").unwrap();
+        assert_eq!(out.config.paths.iso, Some(PathBuf::from("/isos/install.iso")));
+        let cleared = set_key(&out.config, "paths.iso", "null").unwrap();
+        assert_eq!(cleared.config.paths.iso, None);
+        assert!(set_key(&cfg, "paths.iso", "null").is_err());
+
+        assert!(set_key(&cfg, "hardware.bogus", "1").is_err());
+        assert!(set_key(&cfg, "paths.disks.5.format", "raw").is_err());
+        assert!(set_key(&cfg, "display.mode", "sdl").is_err());
+        assert!(set_key(&cfg, "meta.uuid", "x").is_err());
+        assert!(set_key(&cfg, "meta.name", "other").is_err());
+        assert!(set_key(&cfg, "meta.tags", r#"["two words"]"#).is_err());
+    }
+
+    #[test]
+    fn test_validate_tags() {
+        use config::validate::validate_tags;
+
+        let tags: Vec<String> = ["web", "env:prod", "web", "v1.2"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(validate_tags(&tags).unwrap(), vec!["web", "env:prod", "v1.2"]);
+        assert!(validate_tags(&["".to_string()]).is_err());
+        assert!(validate_tags(&["two words".to_string()]).is_err());
+        assert!(validate_tags(&["a,b".to_string()]).is_err());
+
+        // Older vm.json files have neither field, and empty ones are not written back
+        let meta: config::schema::Meta =
+            serde_json::from_str(r#"{"version":2,"generated":"","name":"a","arch":"aarch64","uuid":"u"}"#).unwrap();
+        assert!(meta.description.is_empty() && meta.tags.is_empty());
+        let json = serde_json::to_string(&meta).unwrap();
+        assert!(!json.contains("description") && !json.contains("tags"));
+    }
+
+    #[test]
+    fn test_wait_for_port() {
+        use std::io::Write;
+        use std::net::TcpListener;
+        use std::time::Duration;
+        use utils::net::wait_for_port;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            // First connection: accepted but closed silently, like a forward with no sshd behind it
+            drop(listener.accept().unwrap());
+            let (mut conn, _) = listener.accept().unwrap();
+            conn.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").unwrap();
+        });
+        let banner = wait_for_port(addr, "SSH-", Duration::from_secs(10), || true).unwrap();
+        assert!(banner.starts_with("SSH-2.0"));
+        server.join().unwrap();
+
+        // Nothing listens any more: a dead process ends the wait before the timeout
+        let err = wait_for_port(addr, "SSH-", Duration::from_secs(60), || false).unwrap_err();
+        assert!(err.to_string().contains("Gave up"));
+    }
+
+    #[test]
+    fn test_host_incompatibilities() {
+        use config::validate::host_incompatibilities;
+
+        assert!(host_incompatibilities("macos", Some("cocoa"), "hvf").is_empty());
+        assert!(host_incompatibilities("linux", Some("vnc"), "kvm").is_empty());
+        assert!(host_incompatibilities("linux", None, "tcg").is_empty());
+        assert!(host_incompatibilities("freebsd", Some("headless"), "tcg").is_empty());
+
+        assert_eq!(host_incompatibilities("linux", Some("cocoa"), "hvf").len(), 2);
+        assert_eq!(host_incompatibilities("macos", Some("cocoa"), "kvm").len(), 1);
+        // No display is opened with a serial console, so cocoa does not matter
+        assert!(host_incompatibilities("linux", None, "kvm").is_empty());
+    }
+
+    #[test]
+    fn test_detect_accel() {
+        use utils::system::detect_accel;
+
+        let foreign = if std::env::consts::ARCH == "aarch64" { "x86_64" } else { "aarch64" };
+        assert_eq!(detect_accel(foreign), "tcg");
+        assert!(["kvm", "hvf", "tcg"].contains(&detect_accel(std::env::consts::ARCH).as_str()));
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        use utils::system::shell_quote;
+
+        assert_eq!(shell_quote("-m"), "-m");
+        assert_eq!(shell_quote("if=pflash,unit=0,file=/a/b.fd"), "if=pflash,unit=0,file=/a/b.fd");
+        assert_eq!(shell_quote("my disk.qcow2"), "'my disk.qcow2'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_delete_confirmation() {
+        use utils::confirm::{is_yes, AssumeYes, Confirmer, ScriptedConfirmer};
+
+        assert!(is_yes("y\n") && is_yes(" YES ") && !is_yes("") && !is_yes("no"));
+        assert!(AssumeYes.confirm("Delete? [y/N]: ").unwrap());
+
+        // A clone kept here sees the prompts the one handed out was asked, from any thread
+        let answers = ScriptedConfirmer::new(&[false, true]);
+        let handed_out = answers.clone();
+        let asked = std::thread::spawn(move || {
+            (0..3).map(|i| handed_out.confirm(&format!("Delete {}? [y/N]: ", i)).unwrap()).collect::<Vec<_>>()
+        });
+        assert_eq!(asked.join().unwrap(), [false, true, false]);
+        assert_eq!(answers.prompts(), ["Delete 0? [y/N]: ", "Delete 1? [y/N]: ", "Delete 2? [y/N]: "]);
+
+        // A manager can be shared across threads by a program embedding qvm
+        fn shareable<T: Send + Sync>(_: &T) {}
+        shareable(&VmManager::with_confirmer(Box::new(answers)));
+    }
+
     #[test]
     fn test_find_vm_dir_nonexistent() {
         let result = find_vm_dir("nonexistent-vm-test-12345");
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("not found"));
+        assert!(matches!(result, Err(QvmError::VmNotFound { .. })));
     }
 
     #[test]
@@ -93,64 +818,81 @@ mod tests {
 
         // Test unsupported architecture
         let result = pick_qemu_bin("unsupported");
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("Unsupported arch"));
+        assert!(matches!(result, Err(QvmError::UnsupportedArch(ref a)) if a == "unsupported"));
     }
 
     #[test]
-    fn test_vm_config_serialization() {
-        use config::schema::*;
-
-        let config = VmConfig {
-            meta: Meta {
-                version: 1,
-                generated: now_utc(),
-                name: "test-vm".to_string(),
-                arch: "aarch64".to_string(),
-                uuid: "test-uuid".to_string(),
-            },
-            paths: Paths {
-                root: PathBuf::from("/tmp/test"),
-                disk: PathBuf::from("disk.qcow2"),
-                efi_vars: PathBuf::from("efi_vars.fd"),
-            },
-            hardware: Hardware {
-                cpu_model: "host".to_string(),
-                sockets: 1,
-                cores: 4,
-                threads: 1,
-                mem_mb: 4096,
-                machine: "virt".to_string(),
-                accel: "hvf".to_string(),
-                mac: "52:54:00:12:34:56".to_string(),
-            },
-            firmware: Firmware {
-                code: PathBuf::from("/path/to/code.fd"),
-                vars_template: PathBuf::from("/path/to/vars.fd"),
-            },
-            network: Network {
-                mode: "vmnet-shared".to_string(),
-                bridge_if: "en0".to_string(),
-                forwards: Forwards::default(),
-            },
-            display: Display {
-                mode: "cocoa".to_string(),
-                vnc: Vnc {
-                    use_unix: false,
-                    host: "127.0.0.1".to_string(),
-                    display: 1,
-                    sock: PathBuf::from("vnc.sock"),
-                },
-                spice: Spice {
-                    use_unix: false,
-                    addr: "127.0.0.1".to_string(),
-                    port: 5930,
-                    disable_ticketing: true,
-                    sock: PathBuf::from("spice.sock"),
-                },
-            },
+    fn test_pick_vm_qemu_bin_pinned() {
+        use config::schema::VmConfigBuilder;
+        use std::os::unix::fs::PermissionsExt;
+        use utils::system::pick_vm_qemu_bin;
+
+        let dir = tempfile::tempdir().unwrap();
+        let pinned = dir.path().join("qemu-system-x86_64");
+        std::fs::write(&pinned, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&pinned, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let mut cfg = VmConfigBuilder::new("pinned-vm", "x86_64").build();
+        cfg.paths.qemu_bin = Some(pinned.clone());
+        let err = pick_vm_qemu_bin(&cfg).unwrap_err().to_string();
+        assert!(err.contains("paths.qemu_bin of VM 'pinned-vm'"), "{err}");
+        assert!(err.contains("not an executable file"), "{err}");
+
+        std::fs::set_permissions(&pinned, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(pick_vm_qemu_bin(&cfg).unwrap(), pinned);
+
+        // Unpinned VMs fall back to detection
+        cfg.paths.qemu_bin = None;
+        cfg.meta.arch = "unsupported".to_string();
+        assert!(matches!(pick_vm_qemu_bin(&cfg), Err(QvmError::UnsupportedArch(_))));
+    }
+
+    #[test]
+    fn test_service_definitions() {
+        use cli::service::{enable_command, launchd_plist, systemd_unit, ServiceKind, ServiceSpec};
+        use std::path::{Path, PathBuf};
+
+        let spec = ServiceSpec {
+            name: "web".to_string(),
+            qvm: PathBuf::from("/opt/my tools/qvm"),
+            home: PathBuf::from("/home/u/100% vms"),
+            vm_dir: PathBuf::from("/home/u/100% vms/web.qvm"),
         };
 
+        let unit = systemd_unit(&spec);
+        assert!(unit.contains("Type=forking\n"));
+        assert!(unit.contains("Environment=\"QVM_HOME=/home/u/100%% vms\"\n"), "{unit}");
+        assert!(unit.contains("PIDFile=/home/u/100%% vms/web.qvm/vm.pid\n"), "{unit}");
+        assert!(unit.contains("ExecStart=\"/opt/my tools/qvm\" start web --daemon\n"), "{unit}");
+        assert!(unit.contains("ExecStop=\"/opt/my tools/qvm\" stop web\n"), "{unit}");
+        assert!(unit.contains("WantedBy=default.target\n"));
+
+        let plist = launchd_plist(&spec);
+        assert!(plist.contains("<string>local.qvm.web</string>"));
+        assert!(plist.contains("trap stop TERM; '/opt/my tools/qvm' start web --daemon || exit 1;"), "{plist}");
+        assert!(plist.contains("sleep 86400 &amp; wait"));
+        assert!(plist.contains("<string>/home/u/100% vms</string>"));
+
+        let file = Path::new("/home/u/.config/systemd/user/qvm-web.service");
+        assert_eq!(
+            enable_command(ServiceKind::Systemd, file),
+            "systemctl --user daemon-reload && systemctl --user enable --now qvm-web.service"
+        );
+    }
+
+    #[test]
+    fn test_vm_config_serialization() {
+        use config::schema::VmConfigBuilder;
+
+        let config = VmConfigBuilder::new("test-vm", "aarch64")
+            .root("/tmp/test")
+            .uuid("test-uuid")
+            .mac("52:54:00:12:34:56")
+            .machine("virt")
+            .accel("hvf")
+            .firmware("/path/to/code.fd", "/path/to/vars.fd")
+            .build();
+
         // Test serialization
         let json = serde_json::to_string(&config).unwrap();
         assert!(json.contains("test-vm"));
@@ -162,62 +904,59 @@ mod tests {
         assert_eq!(parsed.meta.arch, "aarch64");
     }
 
+    #[test]
+    fn test_vm_config_builder() {
+        use config::schema::{Network, NetworkInterface, VmConfigBuilder};
+        use utils::system::normalize_mac;
+
+        let two_nics = Network {
+            interfaces: vec![
+                NetworkInterface { mode: "user".into(), ..NetworkInterface::default() },
+                NetworkInterface::default(),
+            ],
+            ssh_user: None,
+        };
+
+        let cfg = VmConfigBuilder::new("web", "x86_64")
+            .mem_mb(8192)
+            .topology(2, 2, 2)
+            .network(two_nics)
+            .build();
+        assert_eq!(cfg.meta.name, "web");
+        assert_eq!(cfg.meta.version, config::migrate::CURRENT_VERSION);
+        assert!(cfg.paths.root.ends_with("web.qvm"));
+        assert_eq!(cfg.hardware.machine, "q35");
+        assert_eq!(cfg.hardware.mem_mb, 8192);
+        assert_eq!(cfg.vcpu_total(), 8);
+        assert_eq!(cfg.network.interfaces[0].mode, "user");
+        assert_eq!(cfg.paths.disks[0].path, PathBuf::from("disk.qcow2"));
+        assert_eq!(cfg.display.spice.port, 5930);
+        // Every NIC gets its own MAC
+        let macs: Vec<_> = cfg.network.interfaces.iter().map(|nic| normalize_mac(&nic.mac).unwrap()).collect();
+        assert_eq!(macs.len(), 2);
+        assert_ne!(macs[0], macs[1]);
+        assert!(!cfg.meta.uuid.is_empty());
+
+        let arm = VmConfigBuilder::new("arm", "aarch64").build();
+        assert_eq!(arm.hardware.machine, "virt,gic-version=3");
+        assert_ne!(arm.meta.uuid, cfg.meta.uuid);
+    }
+
     #[test]
     fn test_save_and_load_conf() {
-        use config::schema::*;
+        use config::schema::VmConfigBuilder;
 
         let temp_dir = TempDir::new().unwrap();
         let root = temp_dir.path().to_path_buf();
 
-        let config = VmConfig {
-            meta: Meta {
-                version: 1,
-                generated: now_utc(),
-                name: "test-vm".to_string(),
-                arch: "aarch64".to_string(),
-                uuid: "test-uuid".to_string(),
-            },
-            paths: Paths {
-                root: root.clone(),
-                disk: PathBuf::from("disk.qcow2"),
-                efi_vars: PathBuf::from("efi_vars.fd"),
-            },
-            hardware: Hardware {
-                cpu_model: "host".to_string(),
-                sockets: 1,
-                cores: 4,
-                threads: 1,
-                mem_mb: 4096,
-                machine: "virt".to_string(),
-                accel: "hvf".to_string(),
-                mac: "52:54:00:12:34:56".to_string(),
-            },
-            firmware: Firmware {
-                code: PathBuf::from("/path/to/code.fd"),
-                vars_template: PathBuf::from("/path/to/vars.fd"),
-            },
-            network: Network {
-                mode: "vmnet-shared".to_string(),
-                bridge_if: "en0".to_string(),
-                forwards: Forwards::default(),
-            },
-            display: Display {
-                mode: "cocoa".to_string(),
-                vnc: Vnc {
-                    use_unix: false,
-                    host: "127.0.0.1".to_string(),
-                    display: 1,
-                    sock: PathBuf::from("vnc.sock"),
-                },
-                spice: Spice {
-                    use_unix: false,
-                    addr: "127.0.0.1".to_string(),
-                    port: 5930,
-                    disable_ticketing: true,
-                    sock: PathBuf::from("spice.sock"),
-                },
-            },
-        };
+        let config = VmConfigBuilder::new("test-vm", "aarch64")
+            .root(root.clone())
+            .uuid("test-uuid")
+            .mac("52:54:00:12:34:56")
+            .machine("virt")
+            .accel("hvf")
+            .firmware("/path/to/code.fd", "/path/to/vars.fd")
+            .build();
 
         // Save config
         save_conf(&config).unwrap();
@@ -235,5 +974,936 @@ mod tests {
         assert_eq!(loaded_config.meta.name, "test-vm");
         assert_eq!(loaded_config.meta.arch, "aarch64");
         assert_eq!(loaded_config.hardware.mem_mb, 4096);
+        assert_eq!(loaded_config.paths.disks.len(), 1);
+    }
+
+    fn v1_config_json() -> serde_json::Value {
+        serde_json::json!({
+            "meta": {"version": 1, "generated": "2024-01-01T00:00:00Z", "name": "old-vm",
+                     "arch": "aarch64", "uuid": "test-uuid"},
+            "paths": {"root": "/tmp/test", "disk": "disk.qcow2", "efi_vars": "efi_vars.fd"},
+            "hardware": {"cpu_model": "host", "sockets": 1, "cores": 4, "threads": 1,
+                         "mem_mb": 4096, "machine": "virt", "accel": "hvf",
+                         "mac": "52:54:00:12:34:56"},
+            "firmware": {"code": "/path/to/code.fd", "vars_template": "/path/to/vars.fd"},
+            "network": {"mode": "vmnet-shared", "bridge_if": "en0",
+                        "forwards": {"ssh": 0, "meye": 0}},
+            "display": {"mode": "cocoa",
+                        "vnc": {"use_unix": false, "host": "127.0.0.1", "display": 1, "sock": "vnc.sock"},
+                        "spice": {"use_unix": false, "addr": "127.0.0.1", "port": 5930,
+                                  "disable_ticketing": true, "sock": "spice.sock"}},
+            "some_future_field": true
+        })
+    }
+
+    #[test]
+    fn test_migrate_v1_single_disk_to_list() {
+        use config::migrate::{migrate, CURRENT_VERSION};
+
+        let cfg = migrate(v1_config_json()).unwrap();
+        assert_eq!(cfg.meta.version, CURRENT_VERSION);
+        assert_eq!(cfg.paths.disks.len(), 1);
+        assert_eq!(cfg.paths.disks[0].path, PathBuf::from("disk.qcow2"));
+        assert_eq!(cfg.paths.disks[0].format, "qcow2");
+        assert_eq!(cfg.paths.disks[0].interface, "virtio");
+
+        // The legacy field is never written back
+        let out = serde_json::to_string(&cfg).unwrap();
+        assert!(!out.contains("\"disk\""));
+    }
+
+    #[test]
+    fn test_migrate_v2_forwards_to_list() {
+        use config::migrate::migrate;
+        use config::schema::Forward;
+
+        let mut value = v1_config_json();
+        value["meta"]["version"] = 2.into();
+        value["paths"]["disks"] = serde_json::json!([{"path": "disk.qcow2"}]);
+        value["network"]["forwards"] = serde_json::json!({"ssh": 2222, "meye": 0});
+
+        let cfg = migrate(value).unwrap();
+        let forwards = &cfg.network.interfaces[0].forwards;
+        assert_eq!(forwards, &vec![Forward { protocol: "tcp".into(), host_port: 2222, guest_port: 22 }]);
+        assert_eq!(forwards[0].hostfwd(), "tcp::2222-:22");
+
+        // All-zero legacy forwards migrate to an empty list
+        let cfg = migrate(v1_config_json()).unwrap();
+        assert!(cfg.network.interfaces[0].forwards.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_v3_single_nic_to_list() {
+        use config::migrate::migrate;
+        use config::schema::NetworkInterface;
+
+        let mut value = v1_config_json();
+        value["meta"]["version"] = 3.into();
+        value["paths"]["disks"] = serde_json::json!([{"path": "disk.qcow2"}]);
+        value["network"] = serde_json::json!({"mode": "user", "bridge_if": "en1", "ssh_user": "ubuntu",
+            "forwards": [{"protocol": "tcp", "host_port": 2222, "guest_port": 22}]});
+
+        let cfg = migrate(value).unwrap();
+        assert_eq!(cfg.network.interfaces.len(), 1);
+        let nic: &NetworkInterface = &cfg.network.interfaces[0];
+        assert_eq!((nic.mode.as_str(), nic.bridge_if.as_str()), ("user", "en1"));
+        assert_eq!(nic.mac, "52:54:00:12:34:56");
+        assert_eq!(nic.forwards[0].host_port, 2222);
+        assert_eq!(cfg.network.ssh_user.as_deref(), Some("ubuntu"));
+
+        let out = serde_json::to_value(&cfg).unwrap();
+        assert!(out["hardware"].get("mac").is_none());
+        assert!(out["network"].get("mode").is_none());
+
+        // A config without a MAC gets one that validates
+        let mut value = v1_config_json();
+        value["meta"]["version"] = 3.into();
+        value["paths"]["disks"] = serde_json::json!([{"path": "disk.qcow2"}]);
+        value["network"] = serde_json::json!({"mode": "user", "bridge_if": "", "forwards": []});
+        value["hardware"].as_object_mut().unwrap().remove("mac");
+        let cfg = migrate(value).unwrap();
+        assert!(cfg.network.interfaces[0].mac.starts_with("52:54:00:"));
+        config::validate::validate_network(&cfg.network).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_rejects_future_version() {
+        use config::migrate::{migrate, CURRENT_VERSION};
+
+        let mut value = v1_config_json();
+        value["meta"]["version"] = (CURRENT_VERSION + 1).into();
+        let err = migrate(value).unwrap_err();
+        assert!(matches!(err, QvmError::UnsupportedConfigVersion { .. }));
+        assert!(err.to_string().contains("upgrade qvm"));
+    }
+
+    #[test]
+    fn test_load_conf_from_dir_upgrades_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut value = v1_config_json();
+        value["paths"]["root"] = temp_dir.path().to_string_lossy().into();
+        std::fs::write(conf_path(temp_dir.path()), value.to_string()).unwrap();
+
+        let cfg = vm::config::load_conf_from_dir(temp_dir.path()).unwrap();
+        assert_eq!(cfg.paths.disks.len(), 1);
+
+        let saved: serde_json::Value =
+            serde_json::from_reader(File::open(conf_path(temp_dir.path())).unwrap()).unwrap();
+        assert_eq!(saved["meta"]["version"], config::migrate::CURRENT_VERSION);
+        assert!(saved["paths"].get("disks").is_some());
+    }
+
+    #[test]
+    fn test_load_conf_rejects_unknown_choices() {
+        use config::validate::validate_choices;
+
+        let mut cfg = launch_config();
+        assert!(validate_choices(&cfg).is_ok());
+        cfg.paths.disks[0].interface = "scsi".to_string();
+        let err = validate_choices(&cfg).unwrap_err().to_string();
+        assert_eq!(err, "'paths.disks.0.interface' is \"scsi\", expected one of virtio, nvme, ide");
+
+        // A typo in a hand-edited vm.json fails the load, naming the file and the field
+        let temp_dir = TempDir::new().unwrap();
+        let mut value = v1_config_json();
+        value["paths"]["root"] = temp_dir.path().to_string_lossy().into();
+        value["display"]["mode"] = "vnc ".into();
+        std::fs::write(conf_path(temp_dir.path()), value.to_string()).unwrap();
+        let err = vm::config::load_conf_from_dir(temp_dir.path()).unwrap_err().to_string();
+        assert!(err.contains("vm.json: 'display.mode' is \"vnc \", expected one of cocoa, vnc, spice, headless"), "{err}");
+
+        value["display"]["mode"] = "vnc".into();
+        value["meta"]["arch"] = "x86".into();
+        std::fs::write(conf_path(temp_dir.path()), value.to_string()).unwrap();
+        let err = vm::config::load_conf_from_dir(temp_dir.path()).unwrap_err().to_string();
+        assert!(err.contains("'meta.arch' is \"x86\", expected one of aarch64, x86_64"), "{err}");
+    }
+
+    /// A migrated v1 config: aarch64, cocoa, vmnet-shared, one virtio disk under /tmp/test
+    fn launch_config() -> VmConfig {
+        config::migrate::migrate(v1_config_json()).unwrap()
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        use config::schema::Memory;
+        use config::validate::check_hugepages;
+        use std::path::Path;
+        use vm::launch::memory_args;
+
+        assert!(memory_args(&Memory::default(), 2048, false).is_empty());
+        let prealloc = Memory { prealloc: true, ..Memory::default() };
+        assert_eq!(memory_args(&prealloc, 2048, false), ["-mem-prealloc"]);
+        let huge = Memory {
+            prealloc: true,
+            share: false,
+            hugepages: Some(PathBuf::from("/dev/hugepages")),
+        };
+        assert_eq!(
+            memory_args(&huge, 2048, true),
+            [
+                "-object",
+                "memory-backend-file,id=mem,size=2048M,mem-path=/dev/hugepages,share=on,prealloc=on",
+                "-numa",
+                "node,memdev=mem",
+            ]
+        );
+
+        let path = Path::new("/dev/hugepages");
+        let mounts = "proc /proc proc rw 0 0\nhugetlbfs /dev/hugepages hugetlbfs rw,pagesize=2M 0 0\n";
+        let meminfo = "MemTotal: 16000000 kB\nHugePages_Total: 1024\nHugePages_Free: 1024\nHugepagesize: 2048 kB\n";
+        assert!(check_hugepages("linux", path, 2048, meminfo, mounts).is_ok());
+        let err = check_hugepages("linux", path, 4096, meminfo, mounts).unwrap_err().to_string();
+        assert!(err.contains("needs 2048 pages of 2048 kB, 1024 are free"), "{err}");
+        let err = check_hugepages("linux", Path::new("/mnt/huge"), 2048, meminfo, mounts).unwrap_err();
+        assert!(err.to_string().contains("/mnt/huge is not a hugetlbfs mount"));
+        assert!(check_hugepages("linux", path, 2048, "MemTotal: 1 kB\n", mounts).is_err());
+        let err = check_hugepages("macos", path, 2048, meminfo, mounts).unwrap_err();
+        assert!(err.to_string().contains("only supported on Linux"));
+
+        // qvm set only checks the host's huge pages when the memory itself changes
+        let mut cfg = launch_config();
+        cfg.memory.hugepages = Some(PathBuf::from("/nonexistent/hugepages"));
+        assert!(config::set::set_key(&cfg, "meta.description", "x").is_ok());
+        assert!(config::set::set_key(&cfg, "hardware.mem_mb", "1024").is_err());
+    }
+
+    #[test]
+    fn test_build_qemu_args_extra_args_last() {
+        use vm::launch::{build_qemu_args, StartOptions};
+
+        let mut cfg = launch_config();
+        cfg.hardware.machine = "virt,highmem=off".to_string();
+        cfg.extra_args = vec!["-rtc".to_string(), "base=utc".to_string()];
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        let machine = args.iter().position(|a| a == "-machine").unwrap();
+        assert_eq!(args[machine + 1], "virt,highmem=off");
+        assert_eq!(args[args.len() - 2..], ["-rtc", "base=utc"]);
+    }
+
+    #[test]
+    fn test_build_qemu_args_direct_kernel_boot() {
+        use config::schema::Boot;
+        use config::validate::validate_boot;
+        use vm::launch::{build_qemu_args, StartOptions};
+
+        let mut cfg = launch_config();
+        cfg.boot = Boot {
+            kernel: Some(PathBuf::from("vmlinuz")),
+            initrd: Some(PathBuf::from("/boot/initrd.img")),
+            cmdline: Some("console=ttyAMA0 root=/dev/vda2".to_string()),
+            order: Some("dc".to_string()),
+        };
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        let joined = args.join(" ");
+        assert!(joined.contains("-kernel /tmp/test/vmlinuz -initrd /boot/initrd.img"));
+        let append = args.iter().position(|a| a == "-append").unwrap();
+        assert_eq!(args[append + 1], "console=ttyAMA0 root=/dev/vda2");
+
+        // qemu refuses -boot order next to bootindex, so the order becomes bootindex
+        assert!(!args.iter().any(|a| a == "-boot"));
+        assert!(joined.contains("-device virtio-blk-pci,drive=disk0,bootindex=1 "));
+        assert!(joined.contains("mac=52:54:00:12:34:56 "));
+        cfg.boot.order = Some("ndc".to_string());
+        let opts = StartOptions { iso: Some(PathBuf::from("install.iso")), ..StartOptions::default() };
+        let joined = build_qemu_args(&cfg, &opts).unwrap().join(" ");
+        assert!(joined.contains("mac=52:54:00:12:34:56,bootindex=0 "));
+        assert!(joined.contains("scsi-cd,drive=cd0,bus=scsi0.0,bootindex=1 "));
+        assert!(joined.contains("virtio-blk-pci,drive=disk0,bootindex=2 "));
+
+        // Nothing is emitted for an empty boot section
+        let args = build_qemu_args(&launch_config(), &StartOptions::default()).unwrap();
+        assert!(!args.iter().any(|a| a == "-kernel" || a == "-boot"));
+
+        for order in ["", "cc", "dz", "C", "a"] {
+            let boot = Boot { order: Some(order.to_string()), ..Boot::default() };
+            assert!(validate_boot(&boot).is_err(), "order '{}' accepted", order);
+        }
+        let boot = Boot { cmdline: Some("quiet".to_string()), ..Boot::default() };
+        assert!(validate_boot(&boot).is_err());
+    }
+
+    #[test]
+    fn test_relative_firmware_paths() {
+        use vm::firmware::refresh_firmware;
+        use vm::launch::{build_qemu_args, StartOptions};
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("fw")).unwrap();
+        std::fs::write(dir.path().join("fw/code.fd"), b"").unwrap();
+
+        let mut cfg = launch_config();
+        cfg.paths.root = dir.path().to_path_buf();
+        cfg.firmware.code = PathBuf::from("fw/code.fd");
+        cfg.firmware.vars_template = PathBuf::from("fw/vars.fd");
+
+        // Bundled firmware that exists is kept as is
+        assert!(!refresh_firmware(&mut cfg, dir.path()).unwrap());
+        assert_eq!(cfg.firmware.code, PathBuf::from("fw/code.fd"));
+
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        let code = format!(
+            "if=pflash,format=raw,unit=0,readonly=on,file={}",
+            dir.path().join("fw/code.fd").display()
+        );
+        assert!(args.contains(&code));
+    }
+
+    #[test]
+    fn test_disk_cache_aio_readonly() {
+        use config::schema::Disk;
+        use config::validate::validate_disk;
+        use vm::launch::{build_qemu_args, StartOptions};
+
+        let mut cfg = launch_config();
+        cfg.paths.disks[0] = Disk {
+            cache: Some("none".into()),
+            aio: Some("native".into()),
+            readonly: true,
+            ..Disk::new("disk.qcow2")
+        };
+        assert!(validate_disk(&cfg.paths.disks[0]).is_ok());
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        assert!(args.contains(
+            &"file=/tmp/test/disk.qcow2,if=none,id=disk0,format=qcow2,cache=none,aio=native,readonly=on".to_string()
+        ));
+
+        // qemu refuses native AIO through the page cache
+        for cache in [None, Some("writeback"), Some("writethrough")] {
+            let disk = Disk { cache: cache.map(String::from), aio: Some("native".into()), ..Disk::default() };
+            let err = validate_disk(&disk).unwrap_err().to_string();
+            assert!(err.contains("aio=native requires cache=none"), "{err}");
+        }
+        let disk = Disk { aio: Some("io_uring".into()), ..Disk::default() };
+        assert!(validate_disk(&disk).is_ok());
+    }
+
+    #[test]
+    fn test_build_qemu_args_aarch64_cocoa() {
+        use vm::launch::{build_qemu_args, StartOptions};
+
+        let args = build_qemu_args(&launch_config(), &StartOptions::default()).unwrap();
+        let expected = [
+            "-name", "old-vm",
+            "-uuid", "test-uuid",
+            "-qmp", "unix:/tmp/test/qmp.sock,server=on,wait=off",
+            "-monitor", "unix:/tmp/test/monitor.sock,server=on,wait=off",
+            "-pidfile", "/tmp/test/vm.pid",
+            "-machine", "virt",
+            "-accel", "hvf",
+            "-cpu", "host",
+            "-smp", "cpus=4,sockets=1,cores=4,threads=1",
+            "-m", "4096",
+            "-rtc", "base=utc",
+            "-drive", "if=pflash,format=raw,unit=0,readonly=on,file=/path/to/code.fd",
+            "-drive", "if=pflash,format=raw,unit=1,file=/tmp/test/efi_vars.fd",
+            "-drive", "file=/tmp/test/disk.qcow2,if=none,id=disk0,format=qcow2",
+            "-device", "virtio-blk-pci,drive=disk0",
+            "-netdev", "vmnet-shared,id=net0",
+            "-device", "virtio-net-pci,netdev=net0,mac=52:54:00:12:34:56",
+            "-chardev", "socket,id=serial0,path=/tmp/test/console.sock,server=on,wait=off",
+            "-serial", "chardev:serial0",
+            "-device", "virtio-gpu-pci",
+            "-display", "cocoa",
+        ];
+        assert_eq!(args, expected);
+    }
+
+    #[test]
+    fn test_build_qemu_args_x86_64_vnc_unix() {
+        use vm::launch::{build_qemu_args, StartOptions};
+
+        let mut cfg = launch_config();
+        cfg.meta.arch = "x86_64".into();
+        cfg.hardware.machine = "q35".into();
+        cfg.hardware.accel = "kvm".into();
+        cfg.hardware.cpu_model = "qemu64".into();
+        cfg.display.mode = "vnc".into();
+        cfg.display.vnc.use_unix = true;
+        cfg.paths.iso = Some(PathBuf::from("/isos/install.iso"));
+
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        let joined = args.join(" ");
+        assert!(joined.contains("-machine q35 -accel kvm -cpu qemu64"));
+        // The ISO boots ahead of the disk
+        assert!(joined.contains("-device virtio-blk-pci,drive=disk0,bootindex=1"));
+        assert!(joined.contains(
+            "-drive file=/isos/install.iso,if=none,id=cd0,media=cdrom,readonly=on,format=raw \
+             -device scsi-cd,drive=cd0,bus=scsi0.0,bootindex=0"
+        ));
+        assert!(args.ends_with(&[
+            "-device".to_string(),
+            "virtio-gpu-pci".to_string(),
+            "-display".to_string(),
+            "none".to_string(),
+            "-vnc".to_string(),
+            "unix:/tmp/test/vnc.sock".to_string(),
+        ]));
+    }
+
+    #[test]
+    fn test_display_passwords_stay_off_the_command_line() {
+        use vm::launch::{build_qemu_args, display_password, resolve_password, StartOptions};
+
+        let mut cfg = launch_config();
+        cfg.display.mode = "vnc".into();
+        cfg.display.vnc.password = Some("env:QVM_TEST_VNC_PASSWORD".into());
+        let opts = StartOptions::default();
+
+        let args = build_qemu_args(&cfg, &opts).unwrap();
+        assert!(args.contains(&"127.0.0.1:1,password=on".to_string()));
+        assert!(!args.iter().any(|a| a.contains("QVM_TEST_VNC_PASSWORD")));
+        assert_eq!(display_password(&cfg, &opts), Some(("vnc", "env:QVM_TEST_VNC_PASSWORD")));
+
+        // A SPICE password turns ticketing back on
+        cfg.display.mode = "spice".into();
+        cfg.display.spice.password = Some("hunter2".into());
+        let args = build_qemu_args(&cfg, &opts).unwrap();
+        assert!(args.contains(&"port=5930,addr=127.0.0.1".to_string()));
+        assert_eq!(display_password(&cfg, &opts), Some(("spice", "hunter2")));
+
+        // Only the display actually in use is protected
+        let cocoa = StartOptions {
+            display: Some("cocoa".into()),
+            ..StartOptions::default()
+        };
+        assert_eq!(display_password(&cfg, &cocoa), None);
+
+        assert_eq!(resolve_password("hunter2").unwrap(), "hunter2");
+        assert!(resolve_password("env:QVM_TEST_UNSET_PASSWORD").is_err());
+    }
+
+    #[test]
+    fn test_qemu_img_supported_formats() {
+        use vm::disk::{is_block_device, parse_supported_formats};
+
+        let help = "qemu-img version 8.2.2\nUsage: qemu-img [standard options] command [command options]\n\n\
+                    Supported formats: blkdebug luks nbd parallels qcow qcow2 qed raw vdi vhdx vmdk vpc\n";
+        let formats = parse_supported_formats(help).unwrap();
+        assert!(formats.contains(&"raw".to_string()) && formats.contains(&"qcow2".to_string()));
+        assert_eq!(formats.len(), 12);
+        assert_eq!(parse_supported_formats("Usage: qemu-img\n"), None);
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        assert!(!is_block_device(file.path()));
+        assert!(!is_block_device(std::path::Path::new("/nonexistent/disk")));
+    }
+
+    #[test]
+    fn test_all_vms_reports_broken_entries() {
+        use config::schema::VmConfig;
+        use utils::paths::find_vm_dirs_in;
+        use vm::config::all_vms_in;
+
+        let home = tempfile::tempdir().unwrap();
+        for name in ["beta", "alpha"] {
+            let dir = home.path().join(format!("{}.qvm", name));
+            std::fs::create_dir(&dir).unwrap();
+            let mut cfg = VmConfig::default();
+            cfg.meta.name = name.into();
+            cfg.paths.root = dir.clone();
+            std::fs::write(dir.join("vm.json"), serde_json::to_string(&cfg).unwrap()).unwrap();
+        }
+        std::fs::create_dir(home.path().join("corrupt.qvm")).unwrap();
+        std::fs::write(home.path().join("corrupt.qvm/vm.json"), "{ not json").unwrap();
+        // Neither of these is a VM
+        std::fs::create_dir(home.path().join("images")).unwrap();
+        std::fs::write(home.path().join("stray.qvm"), "").unwrap();
+
+        let dirs = find_vm_dirs_in(home.path()).unwrap();
+        assert_eq!(
+            dirs,
+            ["alpha", "beta", "corrupt"].map(|n| home.path().join(format!("{}.qvm", n)))
+        );
+
+        let vms = all_vms_in(home.path()).unwrap();
+        let names: Vec<&str> = vms.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["alpha", "beta", "corrupt"]);
+        assert_eq!(vms[0].config.as_ref().unwrap().meta.name, "alpha");
+        assert_eq!(vms[1].config.as_ref().unwrap().meta.name, "beta");
+        assert!(vms[2].config.is_err());
+
+        assert!(all_vms_in(&home.path().join("missing")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_qemu_version_gates() {
+        use config::validate::{machine_type_version, qemu_feature_problems};
+        use utils::system::{parse_qemu_version, QemuVersion};
+
+        let out = "QEMU emulator version 8.2.2 (Debian 1:8.2.2+ds-0ubuntu1.4)\nCopyright (c) 2003-2023 Fabrice Bellard";
+        assert_eq!(parse_qemu_version(out), Some(QemuVersion::new(8, 2, 2)));
+        assert_eq!(parse_qemu_version("QEMU emulator version 9.0\n"), Some(QemuVersion::new(9, 0, 0)));
+        assert_eq!(parse_qemu_version("not qemu"), None);
+        assert!(QemuVersion::new(7, 1, 0) > QemuVersion::new(7, 0, 9));
+        assert_eq!(QemuVersion::new(10, 0, 1).to_string(), "10.0.1");
+
+        assert_eq!(machine_type_version("pc-q35-8.2"), Some(QemuVersion::new(8, 2, 0)));
+        assert_eq!(machine_type_version("virt-9.0"), Some(QemuVersion::new(9, 0, 0)));
+        assert_eq!(machine_type_version("virt"), None);
+        assert_eq!(machine_type_version("pc-i440fx"), None);
+
+        let mut cfg = launch_config();
+        cfg.hardware.machine = "virt-8.2,gic-version=3".into();
+        cfg.paths.disks[0].aio = Some("io_uring".into());
+        assert!(qemu_feature_problems(&cfg, QemuVersion::new(9, 0, 0)).is_empty());
+        let keys: Vec<String> = qemu_feature_problems(&cfg, QemuVersion::new(4, 2, 1))
+            .into_iter()
+            .map(|p| p.key)
+            .collect();
+        assert_eq!(keys, vec!["hardware.machine", "network.interfaces.0.mode", "paths.disks.0.aio"]);
+        let problems = qemu_feature_problems(&cfg, QemuVersion::new(7, 0, 0));
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[1].message, "vmnet-shared needs QEMU 7.1.0 or newer (found 7.0.0)");
+    }
+
+    #[test]
+    fn test_check_host_capacity() {
+        use config::schema::Hardware;
+        use config::validate::check_host_capacity;
+        use utils::system::{host_available_memory_mb, host_memory_mb};
+
+        let available = host_available_memory_mb().unwrap();
+        assert!(available > 0 && available <= host_memory_mb().unwrap());
+
+        let hw = Hardware { mem_mb: 4096, cores: 4, ..Hardware::default() };
+        let err = check_host_capacity("vm", &hw, Some(4096), Some(8), false).unwrap_err().to_string();
+        assert!(err.contains("needs 4096 MB of memory but the host has 4096 MB available"), "{err}");
+        assert!(err.contains("--force"), "{err}");
+        let warnings = check_host_capacity("vm", &hw, Some(4096), Some(8), true).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(check_host_capacity("vm", &hw, Some(8192), Some(8), false).unwrap().is_empty());
+        // Unknown host figures are not held against the VM
+        assert!(check_host_capacity("vm", &hw, None, None, false).unwrap().is_empty());
+
+        let warnings = check_host_capacity("vm", &hw, None, Some(2), false).unwrap();
+        assert!(warnings[0].contains("has 4 vCPUs but the host has 2 logical CPUs"), "{warnings:?}");
+    }
+
+    #[test]
+    fn test_display_tls() {
+        use config::schema::DisplayTls;
+        use config::validate::{validate_display_tls, TLS_FILES};
+        use vm::launch::{build_qemu_args, StartOptions};
+
+        let root = tempfile::tempdir().unwrap();
+        let mut cfg = launch_config();
+        cfg.display.mode = "vnc".into();
+        cfg.display.vnc.tls = Some(DisplayTls { cert_dir: "pki".into(), verify_peer: true });
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        assert!(args.contains(
+            &"tls-creds-x509,id=vnc-tls,dir=/tmp/test/pki,endpoint=server,verify-peer=on".to_string()
+        ));
+        assert!(args.contains(&"127.0.0.1:1,tls-creds=vnc-tls".to_string()));
+
+        // The certificates must be in place before launch
+        let err = validate_display_tls(&cfg.display, root.path()).unwrap_err().to_string();
+        assert!(err.contains("is missing ca-cert.pem, server-cert.pem, server-key.pem"), "{err}");
+        std::fs::create_dir(root.path().join("pki")).unwrap();
+        for file in TLS_FILES {
+            std::fs::write(root.path().join("pki").join(file), "").unwrap();
+        }
+        assert!(validate_display_tls(&cfg.display, root.path()).is_ok());
+
+        // SPICE moves to a TLS-only port and reads the directory itself
+        cfg.display.mode = "spice".into();
+        cfg.display.spice.tls = Some(DisplayTls { cert_dir: "/etc/pki/qemu".into(), verify_peer: false });
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        assert!(args.iter().any(|a| a.starts_with("tls-port=5930,addr=127.0.0.1,x509-dir=/etc/pki/qemu")));
+        assert!(!args.iter().any(|a| a.contains("tls-creds")));
+
+        cfg.display.spice.tls = Some(DisplayTls { cert_dir: "pki".into(), verify_peer: true });
+        assert!(validate_display_tls(&cfg.display, root.path()).is_err());
+        cfg.display.spice.tls.as_mut().unwrap().verify_peer = false;
+        cfg.display.spice.use_unix = true;
+        assert!(validate_display_tls(&cfg.display, root.path()).is_err());
+
+        // TLS settings of a display that is not in use are ignored
+        cfg.display.mode = "headless".into();
+        assert!(validate_display_tls(&cfg.display, root.path()).is_ok());
+    }
+
+    #[test]
+    fn test_build_qemu_args_audio() {
+        use config::schema::Audio;
+        use vm::launch::{build_qemu_args, StartOptions};
+        use vm::usb::parse_usb_device;
+
+        let mut cfg = launch_config();
+        cfg.audio = Audio {
+            backend: "coreaudio".into(),
+            model: "intel-hda".into(),
+        };
+        let joined = build_qemu_args(&cfg, &StartOptions::default()).unwrap().join(" ");
+        assert!(joined.contains(
+            "-audiodev coreaudio,id=audio0 -device intel-hda -device hda-duplex,audiodev=audio0"
+        ));
+
+        cfg.audio.model = "usb-audio".into();
+        let joined = build_qemu_args(&cfg, &StartOptions::default()).unwrap().join(" ");
+        assert!(joined.contains("-device qemu-xhci,id=usb -device usb-audio,audiodev=audio0,bus=usb.0"));
+
+        // With USB devices configured, the sound card shares their controller
+        cfg.usb = vec![parse_usb_device("tablet").unwrap()];
+        let joined = build_qemu_args(&cfg, &StartOptions::default()).unwrap().join(" ");
+        assert_eq!(joined.matches("qemu-xhci").count(), 1, "{}", joined);
+        assert!(joined.contains("-device usb-tablet,bus=usb.0 -audiodev coreaudio,id=audio0"));
+        assert!(joined.contains("-device usb-audio,audiodev=audio0,bus=usb.0"));
+
+        cfg.audio.backend = "oss".into();
+        assert!(build_qemu_args(&cfg, &StartOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_tpm_args() {
+        use config::schema::Tpm;
+        use std::path::Path;
+        use vm::tpm::tpm_args;
+
+        let tpm = Tpm { enabled: true, ..Tpm::default() };
+        let root = Path::new("/vms/win.qvm");
+        let args = tpm_args(&tpm, "x86_64", root);
+        assert_eq!(
+            args,
+            [
+                "-chardev", "socket,id=chrtpm,path=/vms/win.qvm/swtpm.sock",
+                "-tpmdev", "emulator,id=tpm0,chardev=chrtpm",
+                "-device", "tpm-crb,tpmdev=tpm0",
+            ]
+        );
+        assert_eq!(tpm_args(&tpm, "aarch64", root)[5], "tpm-tis-device,tpmdev=tpm0");
+
+        let elsewhere = Tpm { sock: PathBuf::from("/run/tpm.sock"), ..tpm };
+        assert_eq!(tpm_args(&elsewhere, "x86_64", root)[1], "socket,id=chrtpm,path=/run/tpm.sock");
+    }
+
+    #[test]
+    fn test_usb_devices() {
+        use vm::usb::{parse_usb_device, usb_args};
+
+        let tablet = parse_usb_device("tablet").unwrap();
+        assert_eq!(tablet.kind, "tablet");
+        let host = parse_usb_device("046D:C52B").unwrap();
+        assert_eq!(host.kind, "host");
+        assert_eq!(host.to_string(), "046d:c52b");
+        assert!(parse_usb_device("mouse").is_err());
+        assert!(parse_usb_device("46d:c52b").is_err());
+        assert!(parse_usb_device("046d:zzzz").is_err());
+
+        assert!(usb_args(&[]).unwrap().is_empty());
+        assert_eq!(
+            usb_args(&[tablet, host]).unwrap(),
+            [
+                "-device", "qemu-xhci,id=usb",
+                "-device", "usb-tablet,bus=usb.0",
+                "-device", "usb-host,vendorid=0x046d,productid=0xc52b,bus=usb.0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_libvirt_domain_xml() {
+        use config::libvirt::domain_xml;
+
+        let mut cfg = launch_config();
+        cfg.meta.name = "a&b".into();
+        cfg.hardware.machine = "virt,gic-version=3".into();
+        cfg.paths.iso = Some(PathBuf::from("install.iso"));
+        cfg.display.mode = "vnc".into();
+
+        let xml = domain_xml(&cfg);
+        assert!(xml.starts_with("<domain type=\"hvf\">\n"));
+        assert!(xml.ends_with("</domain>\n"));
+        assert!(xml.contains("<name>a&amp;b</name>"));
+        assert!(xml.contains("<memory unit=\"MiB\">4096</memory>"));
+        assert!(xml.contains("<type arch=\"aarch64\" machine=\"virt\">hvm</type>"));
+        assert!(xml.contains("<gic version=\"3\"/>"));
+        assert!(xml.contains("<cpu mode=\"host-passthrough\">"));
+        assert!(xml.contains("<topology sockets=\"1\" cores=\"4\" threads=\"1\"/>"));
+        assert!(xml.contains("<clock offset=\"utc\"/>"));
+        assert!(xml.contains("<nvram template=\"/path/to/vars.fd\">/tmp/test/efi_vars.fd</nvram>"));
+        assert!(xml.contains("<source file=\"/tmp/test/install.iso\"/>"));
+        assert!(xml.contains("<source file=\"/tmp/test/disk.qcow2\"/>\n      <target dev=\"vda\" bus=\"virtio\"/>\n      <boot order=\"2\"/>"));
+        assert!(xml.contains("<mac address=\"52:54:00:12:34:56\"/>"));
+        assert!(xml.contains("<graphics type=\"vnc\" port=\"5901\" listen=\"127.0.0.1\"/>"));
+
+        // A boot order is per device, as libvirt allows no <os><boot dev> next to it
+        cfg.boot.order = Some("nc".into());
+        let xml = domain_xml(&cfg);
+        assert!(!xml.contains("<boot dev="));
+        assert!(xml.contains("<target dev=\"vda\" bus=\"virtio\"/>\n      <boot order=\"2\"/>"));
+        assert!(xml.contains("<model type=\"virtio\"/>\n      <boot order=\"1\"/>"));
+        assert!(!xml.contains("<readonly/>\n      <boot"));
+    }
+
+    #[test]
+    fn test_build_qemu_args_headless_serial() {
+        use vm::launch::{build_qemu_args, StartOptions};
+
+        let mut cfg = launch_config();
+        cfg.display.mode = "headless".into();
+        let opts = StartOptions {
+            serial_console: true,
+            ..StartOptions::default()
+        };
+
+        let args = build_qemu_args(&cfg, &opts).unwrap();
+        assert!(args.ends_with(&[
+            "-display".to_string(),
+            "none".to_string(),
+            "-serial".to_string(),
+            "mon:stdio".to_string(),
+        ]));
+        // No console socket or GPU when the console is on stdio
+        assert!(!args.iter().any(|a| a.contains("console.sock") || a == "virtio-gpu-pci"));
+
+        // A display override must still be a known mode
+        let opts = StartOptions {
+            display: Some("sdl".into()),
+            ..StartOptions::default()
+        };
+        assert!(build_qemu_args(&cfg, &opts).is_err());
+    }
+
+    #[test]
+    fn test_disk_info_parsing() {
+        let json = r#"{
+            "virtual-size": 68719476736,
+            "filename": "disk.qcow2",
+            "cluster-size": 65536,
+            "format": "qcow2",
+            "actual-size": 200704,
+            "dirty-flag": false
+        }"#;
+        let info: vm::disk::DiskInfo = serde_json::from_str(json).unwrap();
+        assert_eq!(info.virtual_size, 64 * 1024 * 1024 * 1024);
+        assert_eq!(info.actual_size, 200704);
+        assert_eq!(info.format, "qcow2");
+    }
+
+    #[test]
+    fn test_parse_check() {
+        use vm::disk::parse_check;
+
+        let clean = br#"{
+            "image-end-offset": 262144,
+            "total-clusters": 16384,
+            "check-errors": 0,
+            "filename": "disk.qcow2",
+            "format": "qcow2"
+        }"#;
+        let result = parse_check(clean).unwrap();
+        assert_eq!(result, vm::disk::CheckResult::default());
+        assert!(!result.needs_repair());
+
+        let damaged = br#"{
+            "image-end-offset": 262144,
+            "total-clusters": 16384,
+            "check-errors": 0,
+            "leaks": 12,
+            "corruptions": 2,
+            "filename": "disk.qcow2",
+            "format": "qcow2"
+        }"#;
+        let result = parse_check(damaged).unwrap();
+        assert_eq!((result.corruptions, result.leaks), (2, 12));
+        assert!(result.needs_repair());
+
+        // After -r all the remaining counts are what is left, the fixed ones what was done
+        let repaired = br#"{"check-errors": 0, "leaks-fixed": 12, "corruptions-fixed": 2, "format": "qcow2"}"#;
+        let result = parse_check(repaired).unwrap();
+        assert_eq!((result.corruptions_fixed, result.leaks_fixed), (2, 12));
+        assert!(!result.needs_repair());
+
+        assert!(parse_check(b"qemu-img: Could not open").is_err());
+    }
+
+    #[test]
+    fn test_backup_manifest() {
+        use chrono::TimeZone;
+        use std::path::Path;
+        use vm::backup::{backup_dir_name, job_status, overlay_path, BackupManifest, BackupRecord};
+
+        let time = chrono::Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        assert_eq!(backup_dir_name("web", time), "web-20240102T030405Z");
+        assert_eq!(
+            overlay_path(Path::new("/vms/web.qvm/disk.qcow2")),
+            PathBuf::from("/vms/web.qvm/disk.qcow2.backup-overlay.qcow2")
+        );
+
+        let jobs = serde_json::json!([
+            {"device": "backup-disk0", "type": "commit", "ready": false},
+            {"device": "backup-disk1", "type": "commit", "ready": true}
+        ]);
+        assert_eq!(job_status(&jobs, "backup-disk0"), Some(false));
+        assert_eq!(job_status(&jobs, "backup-disk1"), Some(true));
+        assert_eq!(job_status(&jobs, "backup-disk2"), None);
+
+        // A directory without a manifest has no backups; saved ones are read back in order
+        let dest = TempDir::new().unwrap();
+        let mut manifest = BackupManifest::load(dest.path()).unwrap();
+        assert!(manifest.backups.is_empty());
+        for (vm, dir) in [("web", "web-1"), ("db", "db-1"), ("web", "web-2")] {
+            manifest.backups.push(BackupRecord {
+                vm: vm.into(),
+                uuid: String::new(),
+                created: String::new(),
+                dir: dir.into(),
+                live: false,
+                disks: Vec::new(),
+                efi_vars: None,
+            });
+        }
+        manifest.save(dest.path()).unwrap();
+        let loaded = BackupManifest::load(dest.path()).unwrap();
+        assert_eq!(loaded, manifest);
+        let dirs: Vec<_> = loaded.for_vm("web").map(|b| b.dir.clone()).collect();
+        assert_eq!(dirs, vec![PathBuf::from("web-2"), PathBuf::from("web-1")]);
+    }
+
+    #[test]
+    fn test_parse_snapshot_list() {
+        use vm::snapshot::parse_snapshot_list;
+
+        // qemu-img >= 8 (with unit and ICOUNT column)
+        let new = "Snapshot list:\n\
+ID        TAG               VM SIZE                DATE     VM CLOCK     ICOUNT\n\
+1         clean                 0 B 2024-05-01 10:20:30 00:00:00.000          0\n\
+2         booted          1.2 MiB 2024-05-02 11:00:00 00:01:02.345          0\n";
+        let snaps = parse_snapshot_list(new);
+        assert_eq!(snaps.len(), 2);
+        assert_eq!(snaps[0].tag, "clean");
+        assert_eq!(snaps[0].vm_size, "0 B");
+        assert_eq!(snaps[1].vm_size, "1.2 MiB");
+        assert_eq!(snaps[1].date, "2024-05-02 11:00:00");
+        assert_eq!(snaps[1].clock, "00:01:02.345");
+
+        // Older qemu-img without unit or ICOUNT
+        let old = "ID        TAG                 VM SIZE                DATE       VM CLOCK\n\
+1         base                      0 2023-01-01 00:00:00   00:00:00.000\n";
+        let snaps = parse_snapshot_list(old);
+        assert_eq!(snaps.len(), 1);
+        assert_eq!(snaps[0].id, "1");
+        assert_eq!(snaps[0].vm_size, "0");
+
+        assert!(parse_snapshot_list("").is_empty());
+    }
+
+    #[test]
+    fn test_process_usage() {
+        use std::time::Duration;
+        use utils::system::{process_usage, ProcessUsage};
+
+        let usage = process_usage(std::process::id() as i32).unwrap();
+        assert!(usage.rss_bytes > 0);
+        assert!(process_usage(-1).is_none());
+
+        let before = ProcessUsage { cpu_time: Duration::from_secs(10), rss_bytes: 0 };
+        let after = ProcessUsage { cpu_time: Duration::from_secs(13), rss_bytes: 0 };
+        assert_eq!(after.cpu_percent(&before, Duration::from_secs(2)), 150.0);
+        assert_eq!(after.cpu_percent(&before, Duration::ZERO), 0.0);
+    }
+
+    #[test]
+    fn test_process_runs() {
+        use utils::system::process_runs;
+
+        let pid = std::process::id() as i32;
+        let exe = std::env::current_exe().unwrap();
+        let name = exe.file_name().unwrap().to_str().unwrap();
+        assert!(process_runs(pid, name));
+        assert!(!process_runs(pid, "swtpm"));
+        assert!(!process_runs(-1, name));
+    }
+
+    #[test]
+    fn test_rtc_setting() {
+        use config::set::set_key;
+        use vm::launch::{build_qemu_args, StartOptions};
+
+        // Configs written before the field existed keep qemu's default
+        let cfg = launch_config();
+        assert_eq!(cfg.hardware.rtc, "utc");
+
+        let cfg = set_key(&cfg, "hardware.rtc", "localtime").unwrap().config;
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        let rtc = args.iter().position(|a| a == "-rtc").unwrap();
+        assert_eq!(args[rtc + 1], "base=localtime");
+        assert!(set_key(&cfg, "hardware.rtc", "gmt").is_err());
+    }
+
+    #[test]
+    fn test_gpu_settings() {
+        use config::set::set_key;
+        use vm::launch::{build_qemu_args, StartOptions};
+
+        let device_after = |args: &[String], display: &str| {
+            let i = args.iter().position(|a| a == "-display").unwrap();
+            assert_eq!(args[i + 1], display);
+            args[i - 1].clone()
+        };
+
+        // Older configs keep the plain virtio-gpu-pci
+        let mut cfg = launch_config();
+        cfg.display.mode = "vnc".into();
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        assert_eq!(device_after(&args, "none"), "virtio-gpu-pci");
+
+        for (key, value) in [
+            ("display.gpu.resolution", "1920x1080"),
+            ("display.gpu.max_outputs", "2"),
+            ("display.gpu.vram_mb", "512"),
+        ] {
+            cfg = set_key(&cfg, key, value).unwrap().config;
+        }
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        assert_eq!(
+            device_after(&args, "none"),
+            "virtio-gpu-pci,max_outputs=2,xres=1920,yres=1080,max_hostmem=512M"
+        );
+
+        cfg.meta.arch = "x86_64".into();
+        cfg = set_key(&cfg, "display.gpu.model", "virtio-vga").unwrap().config;
+        cfg = set_key(&cfg, "display.gpu.gl", "true").unwrap().config;
+        let args = build_qemu_args(&cfg, &StartOptions::default()).unwrap();
+        assert!(device_after(&args, "egl-headless").starts_with("virtio-vga-gl,"));
+        assert!(config::libvirt::domain_xml(&cfg).contains("<acceleration accel3d=\"yes\"/>"));
+
+        assert!(set_key(&cfg, "display.gpu.resolution", "1920").is_err());
+        assert!(set_key(&cfg, "display.gpu.max_outputs", "17").is_err());
+        assert!(set_key(&cfg, "display.gpu.model", "qxl").is_err());
+        assert!(set_key(&cfg, "display.mode", "cocoa").is_err());
+        cfg.meta.arch = "aarch64".into();
+        assert!(cfg.validate().unwrap_err().iter().any(|e| e.message.contains("virtio-vga is a PC VGA")));
+    }
+
+    #[test]
+    fn test_free_port_and_tcp_ports() {
+        use config::schema::Forward;
+        use std::net::TcpListener;
+        use utils::net::free_port;
+
+        // A port something listens on is skipped like a taken one
+        let listener = TcpListener::bind(("0.0.0.0", 0)).unwrap();
+        let busy = listener.local_addr().unwrap().port();
+        assert_ne!(free_port(busy..=busy.saturating_add(20), &[]).unwrap(), busy);
+        assert!(free_port(busy..=busy, &[]).is_err());
+        let port = free_port(busy..=busy.saturating_add(20), &[]).unwrap();
+        assert!(free_port(port..=port, &[port]).is_err());
+
+        let mut cfg = launch_config();
+        cfg.display.vnc.display = 3;
+        cfg.network.interfaces[0].forwards = vec![
+            Forward { protocol: "tcp".into(), host_port: 2222, guest_port: 22 },
+            Forward { protocol: "udp".into(), host_port: 5353, guest_port: 53 },
+        ];
+        assert_eq!(cfg.tcp_ports(), vec![5903, 5930, 2222]);
+        cfg.display.spice.use_unix = true;
+        assert_eq!(cfg.tcp_ports(), vec![5903, 2222]);
+
+        cfg.display.mode = "vnc".into();
+        assert_eq!(cfg.display.tcp_port(), Some(5903));
+        cfg.display.mode = "spice".into();
+        assert_eq!(cfg.display.tcp_port(), None);
     }
-}
\ No newline at end of file
+}
@@ -1,13 +1,40 @@
 use anyhow::Result;
-use clap::{Parser, CommandFactory};
-use qvm::cli::commands::{Cli, Cmd};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches};
+use qvm::cli::commands::{Cli, Cmd, ForwardCmd, NicCmd, ShareCmd, SnapshotCmd, UsbCmd};
+use qvm::cli::doctor;
+use qvm::cli::service::{self, ServiceKind};
+use qvm::cli::version;
+use qvm::cli::which;
+use qvm::cli::output::{OutputFormat, Report};
 use qvm::cli::completions::{print_completions, install_fish_completions, generate_man_page};
+use qvm::config::profile::load_profile;
+use qvm::config::schema::{Forward, NetworkInterface};
+use qvm::utils::confirm::AssumeYes;
+use qvm::utils::log;
+use qvm::utils::size::format_size;
+use qvm::utils::paths::{is_glob, qvm_home, QVM_HOME_ENV};
+use qvm::utils::system::{read_secret, shell_quote, MacStrategy, QEMU_BIN_ENV};
 use qvm::vm::creator::{VmCreator, CreateParams};
-use qvm::vm::manager::VmManager;
+use qvm::vm::cloud_init::SeedSources;
+use qvm::vm::launch::{StartOptions, PASSWORD_ENV_PREFIX};
+use qvm::vm::manager::{format_duration, AddDiskParams, SetDisplayParams, Started, VmManager, VmPath};
+use qvm::vm::ssh::ssh_host_port;
+use std::time::Duration;
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let vm_manager = VmManager::new();
+    // Keep the raw matches: create profiles need to know which flags were actually given
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // Library path helpers read the home from the environment; the flag wins over it
+    log::init(log::level_from_flags(cli.verbose, cli.quiet));
+    if let Some(home) = &cli.qvm_home {
+        std::env::set_var(QVM_HOME_ENV, home);
+    }
+    if let Some(bin) = &cli.qemu_bin {
+        std::env::set_var(QEMU_BIN_ENV, bin);
+    }
+    let vm_manager = if cli.yes { VmManager::with_confirmer(Box::new(AssumeYes)) } else { VmManager::new() };
 
     match cli.cmd {
         Cmd::Create {
@@ -19,11 +46,29 @@ fn main() -> Result<()> {
             cores,
             threads,
             mem,
+            rtc,
+            mem_prealloc,
+            hugepages,
+            accel,
+            machine,
             net_mode,
             bridge_if,
+            ssh_user,
+            ssh_forward,
+            ssh_port,
+            description,
+            tags,
+            mac,
+            mac_strategy,
+            mac_prefix,
             display_mode,
             disk,
             disk_size,
+            disk_format,
+            disk_cache,
+            disk_aio,
+            disk_readonly,
+            backing,
             vnc_host,
             vnc_display,
             vnc_sock,
@@ -33,8 +78,25 @@ fn main() -> Result<()> {
             spice_sock,
             spice_unix,
             spice_disable_ticketing,
+            tpm,
+            audio,
+            audio_model,
+            firmware_code,
+            firmware_vars,
+            secure_boot,
+            pin_qemu,
+            extra_args,
+            kernel,
+            initrd,
+            append,
+            boot_order,
+            from_iso,
+            dry_run,
+            start,
+            on_create,
+            profile,
         } => {
-            let params = CreateParams {
+            let mut params = CreateParams {
                 name,
                 arch,
                 cpu_model,
@@ -43,11 +105,22 @@ fn main() -> Result<()> {
                 cores,
                 threads,
                 mem,
+                rtc,
+                mem_prealloc,
+                hugepages,
                 net_mode,
                 bridge_if,
+                ssh_user,
+                ssh_forward,
+                ssh_port,
                 display_mode,
                 disk,
                 disk_size,
+                disk_format,
+                disk_cache,
+                disk_aio,
+                disk_readonly,
+                backing,
                 vnc_host,
                 vnc_display,
                 vnc_sock,
@@ -57,24 +130,653 @@ fn main() -> Result<()> {
                 spice_sock,
                 spice_unix,
                 spice_disable_ticketing,
+                tpm,
+                audio,
+                audio_model,
+                firmware_code,
+                firmware_vars,
+                secure_boot,
+                pin_qemu,
+                kernel,
+                initrd,
+                append,
+                boot_order,
+                iso: from_iso,
+                mac,
+                mac_strategy: MacStrategy::parse(&mac_strategy)?,
+                mac_prefix,
+                accel,
+                machine,
+                extra_args,
+                on_create,
+                description,
+                tags,
+            };
+            if let Some(profile) = profile {
+                let given = matches.subcommand_matches("create");
+                load_profile(&profile)?.apply(&mut params, |id| {
+                    given.and_then(|m| m.value_source(id)) == Some(ValueSource::CommandLine)
+                });
+            }
+            if dry_run {
+                let cfg = VmCreator::build_config(&params)?;
+                println!("{}", serde_json::to_string_pretty(&cfg)?);
+            } else {
+                let cfg = VmCreator::create_vm(params)?;
+                let name = cfg.meta.name.clone();
+                let mut report = Report::new("create")
+                    .field("name", &name)
+                    .field("root", &cfg.paths.root)
+                    .line(format!("Created VM '{}' at {}", name, cfg.paths.root.display()));
+                if let Some(port) = cfg.display.tcp_port() {
+                    report = report
+                        .field("display_port", port)
+                        .line(format!("{} display on port {}", cfg.display.mode, port));
+                }
+                if let Some(port) = ssh_host_port(&cfg.network) {
+                    report = report
+                        .field("ssh_port", port)
+                        .line(format!("SSH forwarded from host port {}", port));
+                }
+                if let Some(iso) = &cfg.paths.iso {
+                    report = report.field("iso", iso).line(format!(
+                        "Booting from {} until it is detached with 'qvm set-iso {} --remove'",
+                        iso.display(),
+                        name
+                    ));
+                }
+                report.print(cli.output);
+                if start {
+                    let started = vm_manager.start_vm(&name, &StartOptions::default())?;
+                    started_report("start", &name, &started).print(cli.output);
+                }
+            }
+        }
+
+        Cmd::Start {
+            all: true,
+            display,
+            wait_for_ssh,
+            ssh_timeout,
+            force,
+            ..
+        } => {
+            let opts = StartOptions {
+                display,
+                wait_for_ssh: wait_for_ssh.then(|| Duration::from_secs(ssh_timeout)),
+                force,
+                ..StartOptions::default()
+            };
+            let summary = vm_manager.start_autostart_vms(&opts)?;
+            let mut report = Report::new("start").fields(&summary);
+            for name in &summary.started {
+                report = report.line(format!("Started VM '{}' in the background", name));
+            }
+            for name in &summary.already_running {
+                report = report.line(format!("VM '{}' is already running", name));
+            }
+            for failure in &summary.failed {
+                report = report.line(format!("Failed to start VM '{}': {}", failure.name, failure.error));
+            }
+            if summary.started.is_empty() && summary.already_running.is_empty() && summary.failed.is_empty() {
+                report = report.line("No VMs have autostart enabled (see 'qvm enable')");
+            }
+            report.print(cli.output);
+            if !summary.failed.is_empty() {
+                anyhow::bail!("{} autostart VM(s) failed to start", summary.failed.len());
+            }
+        }
+
+        Cmd::Start {
+            name,
+            iso,
+            display,
+            console,
+            daemon,
+            wait_for_ssh,
+            ssh_timeout,
+            force,
+            ..
+        } => {
+            let name = name.expect("clap requires a name without --all");
+            let opts = StartOptions {
+                iso,
+                display,
+                serial_console: console == "serial",
+                daemon,
+                wait_for_ssh: wait_for_ssh.then(|| Duration::from_secs(ssh_timeout)),
+                force,
+                ..StartOptions::default()
+            };
+            let started = vm_manager.start_vm(&name, &opts)?;
+            started_report("start", &name, &started).print(cli.output);
+        }
+
+        Cmd::Enable { name } => {
+            vm_manager.set_autostart(&name, true)?;
+        }
+
+        Cmd::Disable { name } => {
+            vm_manager.set_autostart(&name, false)?;
+        }
+
+        Cmd::Stop { name, timeout, force } => {
+            let stopped = vm_manager.stop_vm(&name, Duration::from_secs(timeout), force)?;
+            let text = if stopped {
+                format!("Stopped VM '{}'", name)
+            } else {
+                format!("VM '{}' is not running", name)
+            };
+            Report::new("stop")
+                .field("name", &name)
+                .field("was_running", stopped)
+                .line(text)
+                .print(cli.output);
+        }
+
+        Cmd::Restart { name, timeout, daemon, force } => {
+            let opts = StartOptions {
+                daemon,
+                force,
+                ..StartOptions::default()
+            };
+            let started = vm_manager.restart_vm(&name, Duration::from_secs(timeout), &opts)?;
+            started_report("restart", &name, &started).print(cli.output);
+        }
+
+        Cmd::List { tags } => {
+            let vms = vm_manager.list_vms(&tags)?;
+            let mut report = Report::new("list").field("vms", &vms);
+            if vms.is_empty() && !tags.is_empty() {
+                report = report.line(format!("No VMs tagged {}", tags.join(", ")));
+            } else if vms.is_empty() {
+                let home = qvm_home()?;
+                report = report.line(if home.exists() {
+                    format!("No VMs in {}", home.display())
+                } else {
+                    format!("No VMs yet ({} is created with the first VM)", home.display())
+                });
+            } else {
+                report = report.line(format!(
+                    "{:<20} {:<8} {:<8} {:>4} {:>8}  {:<10} {:>5}  TAGS",
+                    "NAME", "STATE", "ARCH", "CPUS", "MEM", "DISPLAY", "SSH"
+                ));
+                for vm in &vms {
+                    let state = if vm.running { "running" } else { "stopped" };
+                    let display = match vm.display_port {
+                        Some(port) => format!("{}:{}", vm.display, port),
+                        None => vm.display.clone(),
+                    };
+                    let ssh = vm.ssh_port.map_or("-".to_string(), |p| p.to_string());
+                    let line = format!(
+                        "{:<20} {:<8} {:<8} {:>4} {:>6}MB  {:<10} {:>5}  {}",
+                        vm.name, state, vm.arch, vm.cpus, vm.mem_mb, display, ssh, vm.tags.join(",")
+                    );
+                    report = report.line(line.trim_end());
+                }
+            }
+            report.print(cli.output);
+        }
+
+        Cmd::Ps { watch, interval } => {
+            let interval = Duration::from_secs(interval.max(1));
+            loop {
+                // A snapshot still needs a short sample to measure CPU use
+                let vms = vm_manager.ps(if watch { interval } else { Duration::from_millis(500) })?;
+                let mut report = Report::new("ps").field("vms", &vms);
+                if vms.is_empty() {
+                    report = report.line("No running VMs");
+                } else {
+                    report = report.line(format!(
+                        "{:<20} {:>8} {:>6} {:>10}  {:<12} DISPLAY",
+                        "NAME", "PID", "CPU%", "RSS", "UPTIME"
+                    ));
+                    for vm in &vms {
+                        report = report.line(format!(
+                            "{:<20} {:>8} {:>6} {:>10}  {:<12} {}",
+                            vm.name,
+                            vm.pid,
+                            vm.cpu_percent.map_or("-".to_string(), |p| format!("{:.1}", p)),
+                            vm.rss_bytes.map_or("-".to_string(), format_size),
+                            vm.uptime_secs.map_or("-".to_string(), format_duration),
+                            vm.display
+                        ));
+                    }
+                }
+                match (watch, cli.output) {
+                    // Redraw in place, like top
+                    (true, OutputFormat::Text) => {
+                        print!("\x1b[H\x1b[2J");
+                        report.print(cli.output);
+                    }
+                    // One snapshot per line (NDJSON), so a script can read the stream line by line
+                    (true, OutputFormat::Json) => println!("{}", report.to_json()),
+                    (false, _) => report.print(cli.output),
+                }
+                if !watch {
+                    break;
+                }
+                if vms.is_empty() {
+                    std::thread::sleep(interval);
+                }
+            }
+        }
+
+        Cmd::Status { name, json } => {
+            let status = vm_manager.status(&name)?;
+            let format = if json { OutputFormat::Json } else { cli.output };
+            Report::new("status")
+                .fields(&status)
+                .line(status.to_string())
+                .print(format);
+        }
+
+        Cmd::Home => {
+            println!("{}", std::path::absolute(qvm_home()?)?.display());
+        }
+
+        Cmd::ConfigPath { name, root, disk } => {
+            let what = match (root, disk) {
+                (true, _) => VmPath::Root,
+                (false, Some(index)) => VmPath::Disk(index),
+                (false, None) => VmPath::Config,
             };
-            VmCreator::create_vm(params)?;
+            println!("{}", vm_manager.vm_path(&name, what)?.display());
+        }
+
+        Cmd::Validate { name } => {
+            let problems = vm_manager.validate_vm(&name)?;
+            let mut report = Report::new("validate").field("name", &name).field("problems", &problems);
+            if problems.is_empty() {
+                report = report.line(format!("VM '{}' is ready to start", name));
+            } else {
+                report = report.line(format!("VM '{}' would not start:", name));
+                for problem in &problems {
+                    report = report.line(format!("  - {}", problem));
+                }
+            }
+            report.print(cli.output);
+            if !problems.is_empty() {
+                anyhow::bail!("{} problem(s) found in VM '{}'", problems.len(), name);
+            }
+        }
+
+        Cmd::Info { name, command } => {
+            if command {
+                let argv = vm_manager.qemu_command(&name, &StartOptions::default())?;
+                let quoted: Vec<String> = argv.iter().map(|a| shell_quote(a)).collect();
+                println!("{}", quoted.join(" "));
+            } else {
+                println!("{}", serde_json::to_string_pretty(&vm_manager.config(&name)?)?);
+            }
+        }
+
+        Cmd::Which { name } => {
+            which::report(&which::resolve(&vm_manager.config(&name)?)).print(cli.output);
+        }
+
+        Cmd::Set { name, key, value } => {
+            vm_manager.set_config_key(&name, &key, &value)?;
+        }
+
+        Cmd::Rename { old, new } => {
+            vm_manager.rename_vm(&old, &new)?;
         }
 
-        Cmd::Start { name, .. } => {
-            vm_manager.start_vm(&name)?;
+        Cmd::AddDisk {
+            name,
+            size,
+            format,
+            interface,
+            file,
+            existing,
+            cache,
+            aio,
+            readonly,
+        } => {
+            let params = AddDiskParams {
+                size,
+                format,
+                interface,
+                file,
+                existing,
+                cache,
+                aio,
+                readonly,
+            };
+            vm_manager.add_disk(&name, params)?;
         }
 
-        Cmd::Stop { name } => {
-            vm_manager.stop_vm(&name)?;
+        Cmd::Check { name, repair } => {
+            let disks = vm_manager.check_vm(&name, repair)?;
+            let mut report = Report::new("check").field("name", &name).field("disks", &disks);
+            let mut unrepaired = 0;
+            for d in &disks {
+                let disk = format!("Disk {} ({})", d.index, d.path.display());
+                let Some(r) = &d.result else {
+                    report = report.line(format!("{}: {} images have no consistency checks", disk, d.format));
+                    continue;
+                };
+                if r.corruptions_fixed > 0 || r.leaks_fixed > 0 {
+                    report = report.line(format!(
+                        "{}: repaired {} corruption(s) and {} leaked cluster(s)",
+                        disk, r.corruptions_fixed, r.leaks_fixed
+                    ));
+                }
+                if r.needs_repair() {
+                    unrepaired += 1;
+                    report = report.line(format!(
+                        "{}: {} corruption(s), {} leaked cluster(s); repair needed",
+                        disk, r.corruptions, r.leaks
+                    ));
+                } else if r.corruptions_fixed == 0 && r.leaks_fixed == 0 {
+                    report = report.line(format!("{}: no errors", disk));
+                }
+                if r.check_errors > 0 {
+                    report = report.line(format!("  {} error(s) kept the check from completing", r.check_errors));
+                }
+            }
+            report.print(cli.output);
+            if unrepaired > 0 {
+                let hint = if repair { String::new() } else { format!(" (run 'qvm check {} --repair')", name) };
+                anyhow::bail!("{} disk(s) of VM '{}' need repair{}", unrepaired, name, hint);
+            }
         }
 
-        Cmd::Delete { name, force } => {
-            vm_manager.delete_vm(&name, force)?;
+        Cmd::ResizeDisk { name, size, disk, shrink, force } => {
+            vm_manager.resize_disk(&name, disk, &size, shrink, force)?;
         }
 
-        Cmd::SetDisplay { name, mode, .. } => {
-            println!("Setting display for VM '{}' to '{}' (not implemented in this example)", name, mode);
+        Cmd::Compact { name, disk } => {
+            let disks = vm_manager.compact_vm(&name, disk)?;
+            let mut report = Report::new("compact").field("name", &name).field("disks", &disks);
+            for d in &disks {
+                report = report.line(format!(
+                    "Compacted {}: {} -> {}",
+                    d.path.display(),
+                    format_size(d.before),
+                    format_size(d.after)
+                ));
+            }
+            report.print(cli.output);
+        }
+
+        Cmd::CloudInit {
+            name,
+            user_data,
+            meta_data,
+            network_config,
+            remove,
+        } => {
+            let sources = match (&user_data, remove) {
+                (Some(user_data), false) => Some(SeedSources {
+                    user_data,
+                    meta_data: meta_data.as_deref(),
+                    network_config: network_config.as_deref(),
+                }),
+                _ => None,
+            };
+            vm_manager.set_cloud_init(&name, sources)?;
+        }
+
+        Cmd::SetIso { name, iso, .. } => {
+            vm_manager.set_iso(&name, iso)?;
+        }
+
+        Cmd::Screenshot { name, out } => {
+            vm_manager.screenshot(&name, &out)?;
+        }
+
+        Cmd::Sendkey { name, keys, hold_ms } => {
+            vm_manager.sendkey(&name, &keys, hold_ms)?;
+        }
+
+        Cmd::Pause { name } => {
+            vm_manager.pause_vm(&name)?;
+        }
+
+        Cmd::Resume { name } => {
+            vm_manager.resume_vm(&name)?;
+        }
+
+        Cmd::Snapshot { action } => match action {
+            SnapshotCmd::Create { vm, tag } => vm_manager.snapshot_create(&vm, &tag)?,
+            SnapshotCmd::List { vm } => {
+                let snapshots = vm_manager.snapshot_list(&vm)?;
+                if snapshots.is_empty() {
+                    println!("No snapshots for VM '{}'", vm);
+                } else {
+                    println!("{:<4} {:<20} {:>12}  {:<19}  VM CLOCK", "ID", "TAG", "VM SIZE", "DATE");
+                    for s in snapshots {
+                        println!(
+                            "{:<4} {:<20} {:>12}  {:<19}  {}",
+                            s.id, s.tag, s.vm_size, s.date, s.clock
+                        );
+                    }
+                }
+            }
+            SnapshotCmd::Restore { vm, tag } => vm_manager.snapshot_restore(&vm, &tag)?,
+        },
+
+        Cmd::ExportLibvirt { name, file } => {
+            let xml = vm_manager.libvirt_xml(&name)?;
+            match file {
+                Some(path) => {
+                    std::fs::write(&path, xml)?;
+                    println!("Wrote libvirt domain for VM '{}' to {}", name, path.display());
+                }
+                None => print!("{}", xml),
+            }
+        }
+
+        Cmd::Backup { name, dest } => {
+            let record = vm_manager.backup_vm(&name, &dest)?;
+            let how = if record.live { " (live, through temporary overlays)" } else { "" };
+            Report::new("backup")
+                .fields(&record)
+                .line(format!("Backed up VM '{}' to {}{}", name, dest.join(&record.dir).display(), how))
+                .print(cli.output);
+        }
+
+        Cmd::Export { name, file } => vm_manager.export_vm(&name, &file)?,
+
+        Cmd::ImportConfig { file, name } => {
+            let json = if file.as_os_str() == "-" {
+                std::io::read_to_string(std::io::stdin())?
+            } else {
+                std::fs::read_to_string(&file)
+                    .map_err(|e| anyhow::anyhow!("cannot read {}: {}", file.display(), e))?
+            };
+            let cfg = VmCreator::create_from_json(&json, name.as_deref())?;
+            Report::new("import-config")
+                .field("name", &cfg.meta.name)
+                .field("root", &cfg.paths.root)
+                .line(format!("Created VM '{}' at {}", cfg.meta.name, cfg.paths.root.display()))
+                .print(cli.output);
+        }
+
+        Cmd::Import { file, name } => vm_manager.import_vm(&file, name.as_deref())?,
+
+        Cmd::Share { action } => match action {
+            ShareCmd::Add { vm, path, tag, readonly } => {
+                vm_manager.share_add(&vm, &path, tag.as_deref(), readonly)?
+            }
+            ShareCmd::Remove { vm, tag } => vm_manager.share_remove(&vm, &tag)?,
+            ShareCmd::List { vm } => {
+                let shares = vm_manager.share_list(&vm)?;
+                if shares.is_empty() {
+                    println!("No shared directories for VM '{}'", vm);
+                } else {
+                    println!("{:<20} {:<4} PATH", "TAG", "MODE");
+                    for s in shares {
+                        let mode = if s.readonly { "ro" } else { "rw" };
+                        println!("{:<20} {:<4} {}", s.tag, mode, s.path.display());
+                    }
+                }
+            }
+        },
+
+        Cmd::Forward { action } => match action {
+            ForwardCmd::Add { vm, host_port, guest_port, protocol, nic } => {
+                vm_manager.forward_add(&vm, Forward { protocol, host_port, guest_port }, nic)?
+            }
+            ForwardCmd::Remove { vm, host_port, protocol } => {
+                vm_manager.forward_remove(&vm, &protocol, host_port)?
+            }
+            ForwardCmd::List { vm } => {
+                let forwards = vm_manager.forward_list(&vm)?;
+                if forwards.is_empty() {
+                    println!("No port forwards for VM '{}'", vm);
+                } else {
+                    println!("{:<4} {:<6} {:>9} {:>10}", "NIC", "PROTO", "HOST PORT", "GUEST PORT");
+                    for (nic, f) in forwards {
+                        println!("{:<4} {:<6} {:>9} {:>10}", nic, f.protocol, f.host_port, f.guest_port);
+                    }
+                }
+            }
+        },
+
+        Cmd::Nic { action } => match action {
+            NicCmd::Add { vm, mode, bridge_if, mac } => {
+                let nic = NetworkInterface { mode, bridge_if, mac: mac.unwrap_or_default(), forwards: Vec::new() };
+                vm_manager.nic_add(&vm, nic)?;
+            }
+            NicCmd::Remove { vm, index } => vm_manager.nic_remove(&vm, index)?,
+            NicCmd::List { vm } => {
+                let nics = vm_manager.nic_list(&vm)?;
+                if nics.is_empty() {
+                    println!("No network interfaces for VM '{}'", vm);
+                } else {
+                    println!("{:<4} {:<14} {:<18} {:>8}", "NIC", "MODE", "MAC", "FORWARDS");
+                    for (i, nic) in nics.iter().enumerate() {
+                        let mode = match nic.mode.as_str() {
+                            "vmnet-bridged" => format!("bridged:{}", nic.bridge_if),
+                            mode => mode.to_string(),
+                        };
+                        println!("{:<4} {:<14} {:<18} {:>8}", i, mode, nic.mac, nic.forwards.len());
+                    }
+                }
+            }
+        },
+
+        Cmd::Usb { action } => match action {
+            UsbCmd::Add { vm, device } => vm_manager.usb_add(&vm, &device)?,
+            UsbCmd::Remove { vm, device } => vm_manager.usb_remove(&vm, &device)?,
+            UsbCmd::List { vm } => {
+                let devices = vm_manager.usb_list(&vm)?;
+                if devices.is_empty() {
+                    println!("No USB devices for VM '{}'", vm);
+                } else {
+                    println!("{:<8} DEVICE", "KIND");
+                    for d in devices {
+                        println!("{:<8} {}", d.kind, d);
+                    }
+                }
+            }
+        },
+
+        Cmd::Console { name } => vm_manager.console(&name)?,
+
+        Cmd::Monitor { name } => vm_manager.monitor(&name)?,
+
+        Cmd::Ssh { name, user, args } => vm_manager.ssh(&name, user.as_deref(), &args)?,
+
+        Cmd::Logs { name, follow, lines } => vm_manager.logs(&name, lines, follow)?,
+
+        Cmd::Qmp { name, command } => {
+            let cmd: serde_json::Value = serde_json::from_str(&command)
+                .map_err(|e| anyhow::anyhow!("Invalid QMP command JSON: {}", e))?;
+            let resp = vm_manager.qmp(&name)?.send(&cmd)?;
+            println!("{}", serde_json::to_string_pretty(&resp)?);
+        }
+
+        Cmd::Delete { names, all, force } if all || names.len() > 1 || names.iter().any(|n| is_glob(n)) => {
+            let summary = vm_manager.delete_vms(&names, all, force)?;
+            Report::new("delete")
+                .fields(&summary)
+                .line(format!(
+                    "Deleted {} VM(s), skipped {}",
+                    summary.deleted.len(),
+                    summary.skipped.len()
+                ))
+                .print(cli.output);
+        }
+
+        Cmd::Delete { names, force, .. } => {
+            let name = &names[0];
+            let deleted = vm_manager.delete_vm(name, force)?;
+            let text = if deleted {
+                format!("Successfully deleted VM '{}'", name)
+            } else {
+                "Deletion cancelled.".to_string()
+            };
+            Report::new("delete")
+                .field("name", name)
+                .field("deleted", deleted)
+                .line(text)
+                .print(cli.output);
+        }
+
+        Cmd::SetDisplay {
+            name,
+            mode,
+            vnc_unix,
+            vnc_host,
+            vnc_display,
+            vnc_sock,
+            spice_unix,
+            spice_addr,
+            spice_port,
+            spice_sock,
+            spice_disable_ticketing,
+            tls_cert_dir,
+            tls_verify_peer,
+            no_tls,
+        } => {
+            let params = SetDisplayParams {
+                mode,
+                vnc_unix,
+                vnc_host,
+                vnc_display,
+                vnc_sock,
+                spice_unix,
+                spice_addr,
+                spice_port,
+                spice_sock,
+                spice_disable_ticketing,
+                tls_cert_dir,
+                tls_verify_peer,
+                no_tls,
+            };
+            vm_manager.set_display(&name, params)?;
+        }
+
+        Cmd::SetAudio { name, backend, model } => {
+            vm_manager.set_audio(&name, &backend, model.as_deref())?;
+        }
+
+        Cmd::SetPassword { name, env, clear } => {
+            let spec = if clear {
+                None
+            } else if let Some(var) = env {
+                Some(format!("{}{}", PASSWORD_ENV_PREFIX, var))
+            } else {
+                Some(read_secret("Password: ")?)
+            };
+            vm_manager.set_password(&name, spec)?;
+        }
+
+        Cmd::Doctor => {
+            let checks = doctor::run_checks();
+            doctor::print_report(&checks);
+            if !doctor::all_passed(&checks) {
+                eprintln!("Some required components are missing (see FAIL above)");
+                std::process::exit(1);
+            }
+        }
+
+        Cmd::Version { full } => {
+            version::report(&version::collect(full)).print(cli.output);
         }
 
         Cmd::Completions { shell } => {
@@ -82,6 +784,14 @@ fn main() -> Result<()> {
             print_completions(shell, &mut cmd);
         }
 
+        Cmd::InstallService { name, install, kind } => {
+            let kind = match kind {
+                Some(kind) => ServiceKind::parse(&kind)?,
+                None => ServiceKind::host(),
+            };
+            service::install_service(&name, kind, install)?;
+        }
+
         Cmd::InstallFish => {
             install_fish_completions()?;
         }
@@ -92,4 +802,26 @@ fn main() -> Result<()> {
     }
 
     Ok(())
+}
+
+/// The result of `start`/`restart`: a background VM's PID, or how a foreground qemu exited
+fn started_report(action: &str, name: &str, started: &Started) -> Report {
+    let report = Report::new(action).field("name", name).field("pid", started.pid);
+    match started.exit {
+        None => {
+            let report = report
+                .field("daemon", true)
+                .line(format!("Started VM '{}' in the background (pid {})", name, started.pid));
+            match started.ssh_port {
+                Some(port) => report
+                    .field("ssh_port", port)
+                    .line(format!("SSH is up on port {} ('qvm ssh {}')", port, name)),
+                None => report,
+            }
+        }
+        Some(status) => report
+            .field("daemon", false)
+            .field("exit_code", status.code())
+            .line(format!("VM '{}' exited ({})", name, status)),
+    }
 }
\ No newline at end of file
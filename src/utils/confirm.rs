@@ -0,0 +1,73 @@
+//! Yes/no confirmation before destructive actions, answered on the terminal, by `--yes`,
+//! or by a script in tests
+
+use crate::Result;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+/// Answers the questions qvm asks before it destroys something
+pub trait Confirmer {
+    /// Ask `prompt` (ending in `[y/N]: `); true to go ahead
+    fn confirm(&self, prompt: &str) -> Result<bool>;
+}
+
+/// Asks on stderr (stdout carries the command's report, JSON with `--output json`) and
+/// reads the answer from stdin; anything but y/yes is a no, as is the end of input
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdinConfirmer;
+
+impl Confirmer for StdinConfirmer {
+    fn confirm(&self, prompt: &str) -> Result<bool> {
+        eprint!("{}", prompt);
+        io::stderr().flush()?;
+        let mut input = String::new();
+        io::stdin().lock().read_line(&mut input)?;
+        Ok(is_yes(&input))
+    }
+}
+
+/// `--yes`: every question is answered yes, and the answer printed after the prompt on stderr
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AssumeYes;
+
+impl Confirmer for AssumeYes {
+    fn confirm(&self, prompt: &str) -> Result<bool> {
+        eprintln!("{}y (--yes)", prompt);
+        Ok(true)
+    }
+}
+
+/// Gives fixed answers in order (no once they run out) and records each prompt; clones
+/// share both, so a copy kept by a test sees what the one handed out was asked
+#[derive(Debug, Default, Clone)]
+pub struct ScriptedConfirmer {
+    answers: Arc<Mutex<VecDeque<bool>>>,
+    prompts: Arc<Mutex<Vec<String>>>,
+}
+
+impl ScriptedConfirmer {
+    pub fn new(answers: &[bool]) -> Self {
+        Self {
+            answers: Arc::new(Mutex::new(answers.iter().copied().collect())),
+            prompts: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// The prompts asked so far
+    pub fn prompts(&self) -> Vec<String> {
+        self.prompts.lock().unwrap().clone()
+    }
+}
+
+impl Confirmer for ScriptedConfirmer {
+    fn confirm(&self, prompt: &str) -> Result<bool> {
+        self.prompts.lock().unwrap().push(prompt.to_string());
+        Ok(self.answers.lock().unwrap().pop_front().unwrap_or(false))
+    }
+}
+
+/// Whether an answer typed at a `[y/N]` prompt is a yes
+pub fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
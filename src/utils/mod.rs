@@ -1,4 +1,8 @@
 //! Utility functions for QVM
 
+pub mod confirm;
+pub mod log;
+pub mod net;
 pub mod paths;
+pub mod size;
 pub mod system;
\ No newline at end of file
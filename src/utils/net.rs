@@ -0,0 +1,77 @@
+//! Network helpers
+
+use crate::{QvmError, Result};
+use tracing::trace;
+use std::io::Read;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::ops::RangeInclusive;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long one connection attempt may take to connect or to send its first bytes
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Poll `addr` with TCP connects until the server sends data starting with `banner`
+/// (any data when empty) and return that first chunk as text. A user-mode forward
+/// accepts connections before the guest listens, so a bare connect proves nothing.
+/// Gives up when `timeout` elapses or `alive` returns false (e.g. qemu exited).
+pub fn wait_for_port(
+    addr: SocketAddr,
+    banner: &str,
+    timeout: Duration,
+    mut alive: impl FnMut() -> bool,
+) -> Result<String> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match read_banner(addr) {
+            Ok(text) if text.starts_with(banner) => return Ok(text),
+            Ok(text) => trace!("{}: unexpected banner {:?}", addr, text),
+            Err(e) => trace!("{}: {}", addr, e),
+        }
+        if !alive() {
+            return Err(QvmError::CommandFailed(format!("Gave up waiting for {}", addr)));
+        }
+        if Instant::now() >= deadline {
+            return Err(QvmError::CommandFailed(format!(
+                "Timed out after {}s waiting for {}",
+                timeout.as_secs(),
+                addr
+            )));
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+/// Whether TCP `port` is free on this host, on loopback and on all addresses
+pub fn port_free(port: u16) -> bool {
+    [Ipv4Addr::LOCALHOST, Ipv4Addr::UNSPECIFIED]
+        .iter()
+        .all(|ip| TcpListener::bind((*ip, port)).is_ok())
+}
+
+/// The lowest TCP port in `range` that is neither in `taken` nor in use on this host.
+/// `taken` holds ports reserved by other VMs that need not be listening right now.
+pub fn free_port(range: RangeInclusive<u16>, taken: &[u16]) -> Result<u16> {
+    range
+        .clone()
+        .find(|port| !taken.contains(port) && port_free(*port))
+        .ok_or_else(|| {
+            QvmError::Invalid(format!(
+                "No free TCP port between {} and {}",
+                range.start(),
+                range.end()
+            ))
+        })
+}
+
+/// Connect once and read whatever the server sends first
+fn read_banner(addr: SocketAddr) -> std::io::Result<String> {
+    let mut stream = TcpStream::connect_timeout(&addr, ATTEMPT_TIMEOUT)?;
+    stream.set_read_timeout(Some(ATTEMPT_TIMEOUT))?;
+    let mut buf = [0u8; 256];
+    let n = stream.read(&mut buf)?;
+    if n == 0 {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(String::from_utf8_lossy(&buf[..n]).into_owned())
+}
@@ -1,13 +1,70 @@
 //! Path utility functions
 
-use anyhow::{anyhow, Result};
+use crate::{QvmError, Result};
+use tracing::trace;
+use std::ffi::CString;
+use std::fs;
 use std::path::{Path, PathBuf};
 
-/// Get the qvm_home directory path
+/// Environment variable overriding the qvm home directory
+pub const QVM_HOME_ENV: &str = "QVM_HOME";
+
+/// Get the qvm_home directory path: `$QVM_HOME`, or `~/qvm` when unset
 pub fn qvm_home() -> Result<PathBuf> {
-    Ok(dirs::home_dir()
-        .ok_or_else(|| anyhow!("no home directory found"))?
-        .join("qvm"))
+    if let Some(dir) = std::env::var_os(QVM_HOME_ENV).filter(|d| !d.is_empty()) {
+        trace!("qvm home from ${}: {}", QVM_HOME_ENV, PathBuf::from(&dir).display());
+        return Ok(PathBuf::from(dir));
+    }
+    Ok(dirs::home_dir().ok_or(QvmError::HomeNotFound)?.join("qvm"))
+}
+
+/// Expand a leading `~` to the home directory and `$VAR` or `${VAR}` to the variable's
+/// value, as a shell would for an unquoted path; an unset variable is an error. The
+/// result is relative only if the expanded text is, so it can still go through
+/// `resolve_under_root`.
+pub fn expand_path(p: &Path) -> Result<PathBuf> {
+    // Non-UTF-8 paths cannot hold anything to expand that we could read
+    let Some(text) = p.to_str() else {
+        return Ok(p.to_path_buf());
+    };
+    let mut out = String::new();
+    let mut rest = text;
+    if rest == "~" || rest.starts_with("~/") {
+        out.push_str(&dirs::home_dir().ok_or(QvmError::HomeNotFound)?.to_string_lossy());
+        rest = &rest[1..];
+    }
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let (var, len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        if var.is_empty() || var.starts_with(|c: char| c.is_ascii_digit()) {
+            // Not a variable reference (`$`, `$1`, `${`): kept as written
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        let value = std::env::var(var).map_err(|_| {
+            QvmError::Invalid(format!("{}: environment variable ${} is not set", text, var))
+        })?;
+        out.push_str(&value);
+        rest = &after[len..];
+    }
+    out.push_str(rest);
+    if out != text {
+        trace!("expanded {} to {}", text, out);
+    }
+    Ok(PathBuf::from(out))
 }
 
 /// Resolve path under root directory
@@ -24,14 +81,129 @@ pub fn conf_path(root: &Path) -> PathBuf {
     root.join("vm.json")
 }
 
+/// Get VM PID file path
+pub fn pid_path(root: &Path) -> PathBuf {
+    root.join("vm.pid")
+}
+
+/// Get VM log file path (qemu stdout/stderr in daemon mode)
+pub fn log_path(root: &Path) -> PathBuf {
+    root.join("vm.log")
+}
+
+/// Get VM hook output log path
+pub fn hooks_log_path(root: &Path) -> PathBuf {
+    root.join("hooks.log")
+}
+
+/// Get VM lock file path (held while an operation mutates the VM)
+pub fn lock_path(root: &Path) -> PathBuf {
+    root.join("vm.lock")
+}
+
+/// Get VM serial console socket path
+pub fn console_path(root: &Path) -> PathBuf {
+    root.join("console.sock")
+}
+
+/// Get VM HMP (human monitor) socket path
+pub fn monitor_path(root: &Path) -> PathBuf {
+    root.join("monitor.sock")
+}
+
+/// Get VM QMP socket path
+pub fn qmp_path(root: &Path) -> PathBuf {
+    root.join("qmp.sock")
+}
+
+/// Validate a VM name: 1-64 characters of [A-Za-z0-9._-], not starting with '.' or '-'
+pub fn validate_vm_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > 64 {
+        return Err(QvmError::Invalid(format!(
+            "Invalid VM name '{}': must be 1-64 characters",
+            name
+        )));
+    }
+    if name.starts_with('.') || name.starts_with('-') {
+        return Err(QvmError::Invalid(format!(
+            "Invalid VM name '{}': must not start with '.' or '-'",
+            name
+        )));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')))
+    {
+        return Err(QvmError::Invalid(format!(
+            "Invalid VM name '{}': character '{}' is not allowed (use letters, digits, '.', '_' or '-')",
+            name, c
+        )));
+    }
+    Ok(())
+}
+
+/// Names of all VMs (`<name>.qvm` directories) in qvm home, sorted
+pub fn vm_names() -> Result<Vec<String>> {
+    Ok(find_all_vm_dirs()?.iter().filter_map(|dir| vm_name_of(dir)).collect())
+}
+
+/// All VM directories (`<name>.qvm`) in qvm home, sorted by VM name
+pub fn find_all_vm_dirs() -> Result<Vec<PathBuf>> {
+    find_vm_dirs_in(&qvm_home()?)
+}
+
+/// All VM directories in `home`, sorted by VM name. A missing `home` (nothing created
+/// yet) has none; one that cannot be read is an error rather than looking empty.
+pub fn find_vm_dirs_in(home: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(home) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            trace!("qvm home {} does not exist yet", home.display());
+            return Ok(Vec::new());
+        }
+        Err(e) => {
+            return Err(QvmError::Invalid(format!("cannot read qvm home {}: {}", home.display(), e)));
+        }
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_dir() && vm_name_of(p).is_some())
+        .collect();
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// The VM name of a `<name>.qvm` directory
+pub fn vm_name_of(dir: &Path) -> Option<String> {
+    dir.file_name()?.to_str()?.strip_suffix(".qvm").map(String::from)
+}
+
+/// Whether a VM name argument is a shell-style pattern (`*`, `?` or `[...]`) rather than a name
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Match a VM name against a shell-style pattern with fnmatch(3)
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (Ok(pattern), Ok(name)) = (CString::new(pattern), CString::new(name)) else {
+        return false;
+    };
+    unsafe { libc::fnmatch(pattern.as_ptr(), name.as_ptr(), 0) == 0 }
+}
+
 /// Find VM directory by name
 pub fn find_vm_dir(name: &str) -> Result<PathBuf> {
     let qvm_home = qvm_home()?;
     let vm_dir = qvm_home.join(format!("{}.qvm", name));
+    trace!("VM '{}' resolves to {}", name, vm_dir.display());
 
     if !vm_dir.exists() {
-        return Err(anyhow!("VM '{}' not found in {}", name, qvm_home.display()));
+        return Err(QvmError::VmNotFound {
+            name: name.to_string(),
+            home: qvm_home,
+        });
     }
 
     Ok(vm_dir)
-}
\ No newline at end of file
+}
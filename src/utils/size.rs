@@ -0,0 +1,91 @@
+//! Size string parsing and formatting
+
+use crate::{QvmError, Result};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const KIB: u64 = 1024;
+
+/// Smallest disk image qvm will create
+pub const MIN_DISK_SIZE: u64 = 1024 * 1024;
+
+/// Parse a size such as `64G`, `512MiB`, `10GB` or `1048576` into bytes.
+///
+/// `K`/`M`/`G`/`T` (optionally `KiB`, `MiB`, …) are binary multiples, as in qemu-img;
+/// `KB`/`MB`/`GB`/`TB` are decimal. A bare number is a byte count.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let invalid = |why: &str| QvmError::Invalid(format!("Invalid size '{}': {}", s, why));
+
+    let s_trim = s.trim();
+    let split = s_trim
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(s_trim.len());
+    let (digits, suffix) = s_trim.split_at(split);
+    if digits.is_empty() {
+        return Err(invalid("expected a number, e.g. 64G"));
+    }
+    let value: u64 = digits.parse().map_err(|_| invalid("number is too large"))?;
+
+    let multiplier = match suffix.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KIB" => KIB,
+        "M" | "MIB" => KIB.pow(2),
+        "G" | "GIB" => KIB.pow(3),
+        "T" | "TIB" => KIB.pow(4),
+        "KB" => 1000,
+        "MB" => 1000u64.pow(2),
+        "GB" => 1000u64.pow(3),
+        "TB" => 1000u64.pow(4),
+        _ => return Err(invalid("unknown suffix (use K, M, G, T or KB, MB, GB, TB)")),
+    };
+
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| invalid("size is too large"))
+}
+
+/// Parse a disk size and enforce `MIN_DISK_SIZE`
+pub fn parse_disk_size(s: &str) -> Result<u64> {
+    let bytes = parse_size(s)?;
+    if bytes < MIN_DISK_SIZE {
+        return Err(QvmError::Invalid(format!(
+            "Disk size '{}' is too small (minimum {})",
+            s,
+            format_size(MIN_DISK_SIZE)
+        )));
+    }
+    Ok(bytes)
+}
+
+/// Space a file or directory tree occupies on disk, counting allocated blocks (so sparse
+/// images count only what they use). Symlinks are not followed; unreadable entries count as 0.
+pub fn disk_usage(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    let own = meta.blocks() * 512;
+    if !meta.is_dir() {
+        return own;
+    }
+    let children = fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|e| disk_usage(&e.path())).sum())
+        .unwrap_or(0);
+    own + children
+}
+
+/// Format a byte count with binary units, e.g. `64.0 GiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= KIB as f64 && unit < UNITS.len() - 1 {
+        value /= KIB as f64;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -1,9 +1,16 @@
 //! System utility functions
 
-use anyhow::{anyhow, Result};
+use crate::config::schema::VmConfig;
+use crate::{QvmError, Result};
+use tracing::{debug, trace};
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// Get current UTC timestamp in RFC3339 format
 pub fn now_utc() -> String {
@@ -15,46 +22,495 @@ pub fn is_vm_running(name: &str) -> Result<bool> {
     use crate::utils::paths::find_vm_dir;
 
     let vm_dir = find_vm_dir(name)?;
-    let pid_file = vm_dir.join("vm.pid");
-
-    if !pid_file.exists() {
-        return Ok(false);
-    }
-
-    // Read PID and check if process exists
-    match fs::read_to_string(&pid_file) {
-        Ok(pid_str) => {
-            if let Ok(pid) = pid_str.trim().parse::<u32>() {
-                // Check if process exists (cross-platform way)
-                #[cfg(unix)]
-                {
-                    let output = Command::new("ps")
-                        .args(["-p", &pid.to_string()])
-                        .output()?;
-                    return Ok(output.status.success());
-                }
-
-                #[cfg(windows)]
-                {
-                    let output = Command::new("tasklist")
-                        .args(["/FI", &format!("PID eq {}", pid)])
-                        .output()?;
-                    return Ok(output.status.success() &&
-                             String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()));
-                }
+    Ok(running_vm_pid(&vm_dir).is_some())
+}
+
+/// PID of the qemu process recorded in the VM's pidfile, if it is still that VM's qemu.
+///
+/// A dead PID, or one the OS has since recycled for an unrelated process, leaves a stale
+/// pidfile behind; it is removed so later checks start clean.
+pub fn running_vm_pid(vm_dir: &Path) -> Option<i32> {
+    use crate::utils::paths::pid_path;
+    use crate::vm::config::load_conf_from_dir;
+
+    let pid_file = pid_path(vm_dir);
+    let pid = match read_pid_file(&pid_file) {
+        Some(pid) => pid,
+        // qemu truncates its -pidfile before writing the PID; wait out that moment
+        None if pid_file.exists() => settled_pid_file(&pid_file)?,
+        None => return None,
+    };
+    let cfg = load_conf_from_dir(vm_dir).ok();
+    let uuid = cfg.as_ref().map(|cfg| cfg.meta.uuid.as_str());
+    let pinned = cfg.as_ref().and_then(|cfg| cfg.paths.qemu_bin.as_deref());
+
+    let ours = process_alive(pid)
+        && process_cmdline(pid).is_some_and(|argv| is_qemu_for(&argv, uuid, pinned));
+    if ours {
+        Some(pid)
+    } else {
+        debug!("removing stale pidfile {} (pid {} is not this VM's qemu)", pid_file.display(), pid);
+        let _ = fs::remove_file(&pid_file);
+        None
+    }
+}
+
+/// Whether an argv is a `qemu-system-*`, `$QVM_QEMU_BIN` or `pinned` (the VM's
+/// `paths.qemu_bin`) invocation for the VM with `uuid` (any qemu process when the UUID is
+/// unknown)
+pub fn is_qemu_for(argv: &[String], uuid: Option<&str>, pinned: Option<&Path>) -> bool {
+    let override_bin = std::env::var_os(QEMU_BIN_ENV).map(PathBuf::from);
+    let names: Vec<&std::ffi::OsStr> = [override_bin.as_deref(), pinned]
+        .into_iter()
+        .flatten()
+        .filter_map(Path::file_name)
+        .collect();
+    let is_qemu = argv.iter().any(|arg| {
+        Path::new(arg)
+            .file_name()
+            .is_some_and(|f| f.to_string_lossy().starts_with("qemu-system-") || names.contains(&f))
+    });
+    let uuid_matches = match uuid {
+        Some(uuid) => argv.windows(2).any(|w| w[0] == "-uuid" && w[1] == uuid),
+        None => true,
+    };
+    is_qemu && uuid_matches
+}
+
+/// Command line of a running process
+#[cfg(target_os = "linux")]
+pub fn process_cmdline(pid: i32) -> Option<Vec<String>> {
+    let raw = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    Some(
+        raw.split(|&b| b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect(),
+    )
+}
+
+/// Command line of a running process (word-split from `ps`, which has no argv boundaries)
+#[cfg(not(target_os = "linux"))]
+pub fn process_cmdline(pid: i32) -> Option<Vec<String>> {
+    let output = std::process::Command::new("ps")
+        .args(["-o", "command=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// CPU time used so far and resident memory of a process
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProcessUsage {
+    pub cpu_time: Duration,
+    pub rss_bytes: u64,
+}
+
+impl ProcessUsage {
+    /// CPU use between an `earlier` sample and this one, `elapsed` apart, in percent of one
+    /// core (a VM busy on 4 vCPUs shows about 400)
+    pub fn cpu_percent(&self, earlier: &ProcessUsage, elapsed: Duration) -> f64 {
+        if elapsed.is_zero() {
+            return 0.0;
+        }
+        self.cpu_time.saturating_sub(earlier.cpu_time).as_secs_f64() / elapsed.as_secs_f64() * 100.0
+    }
+}
+
+/// CPU time and resident memory of a running process
+pub fn process_usage(pid: i32) -> Option<ProcessUsage> {
+    let pid = sysinfo::Pid::from(usize::try_from(pid).ok()?);
+    let mut sys = sysinfo::System::new();
+    sys.refresh_processes_specifics(
+        sysinfo::ProcessesToUpdate::Some(&[pid]),
+        false,
+        sysinfo::ProcessRefreshKind::nothing().with_cpu().with_memory(),
+    );
+    let process = sys.process(pid)?;
+    Some(ProcessUsage {
+        cpu_time: Duration::from_millis(process.accumulated_cpu_time()),
+        rss_bytes: process.memory(),
+    })
+}
+
+/// Replace `path` atomically: `write` fills a temp file in the same directory, which is
+/// fsynced and renamed over `path`. If `write` fails (or we crash) the old file is untouched
+/// and the temp file is removed (or left as a hidden `.<name>.tmp*` on a crash).
+pub fn write_atomic(path: &Path, write: impl FnOnce(&mut fs::File) -> Result<()>) -> Result<()> {
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut tmp = tempfile::Builder::new()
+        .prefix(&format!(".{}.tmp", name))
+        .tempfile_in(dir)?;
+    write(tmp.as_file_mut())?;
+    tmp.as_file().sync_all()?;
+    tmp.persist(path).map_err(|e| QvmError::Io(e.error))?;
+    // Make the rename itself durable
+    if let Ok(d) = fs::File::open(dir) {
+        let _ = d.sync_all();
+    }
+    Ok(())
+}
+
+/// How long an empty or partly written pidfile gets to be completed
+const PIDFILE_SETTLE: Duration = Duration::from_millis(500);
+
+/// Re-read a pidfile that exists without holding a PID until it does, for up to
+/// `PIDFILE_SETTLE`; None if it stays malformed or disappears
+fn settled_pid_file(pid_file: &Path) -> Option<i32> {
+    let deadline = Instant::now() + PIDFILE_SETTLE;
+    while Instant::now() < deadline && pid_file.exists() {
+        thread::sleep(Duration::from_millis(20));
+        if let Some(pid) = read_pid_file(pid_file) {
+            return Some(pid);
+        }
+    }
+    None
+}
+
+/// Read a PID from a pidfile, returning None if it is missing or malformed
+pub fn read_pid_file(pid_file: &Path) -> Option<i32> {
+    fs::read_to_string(pid_file).ok()?.trim().parse().ok()
+}
+
+/// Check whether a process with the given PID is still alive
+pub fn process_alive(pid: i32) -> bool {
+    // Signal 0 performs the permission/existence check without delivering anything
+    let alive = unsafe { libc::kill(pid, 0) } == 0
+        || io_errno() == libc::EPERM;
+
+    // An exited but unreaped child still answers kill(0); treat zombies as dead
+    #[cfg(target_os = "linux")]
+    if alive {
+        if let Ok(stat) = fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            if let Some(state) = stat.rsplit(')').next().and_then(|s| s.split_whitespace().next()) {
+                return state != "Z";
             }
         }
-        Err(_) => {
-            // If we can't read the PID file, assume it's stale
-            let _ = fs::remove_file(&pid_file);
+    }
+
+    alive
+}
+
+/// Whether `pid` is alive and running `program`: the file name of its argv[0], or of
+/// argv[1] for a script run through its interpreter. A PID recycled for an unrelated
+/// process since its pidfile was written is not.
+pub fn process_runs(pid: i32, program: &str) -> bool {
+    process_alive(pid)
+        && process_cmdline(pid).is_some_and(|argv| {
+            argv.iter().take(2).any(|arg| Path::new(arg).file_name().is_some_and(|f| f == program))
+        })
+}
+
+/// Send a signal to a process
+pub fn signal_process(pid: i32, signal: i32) -> Result<()> {
+    if unsafe { libc::kill(pid, signal) } != 0 {
+        let err = std::io::Error::last_os_error();
+        // The process exiting between our check and the signal is not an error
+        if err.raw_os_error() != Some(libc::ESRCH) {
+            return Err(QvmError::Io(err));
+        }
+    }
+    Ok(())
+}
+
+/// Poll until the process exits or the timeout elapses; returns true if it exited
+pub fn wait_for_exit(pid: i32, timeout: Duration) -> bool {
+    wait_while(timeout, || process_alive(pid))
+}
+
+/// Poll `alive` until it turns false or the timeout elapses; returns true if it did
+pub fn wait_while(timeout: Duration, mut alive: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    while alive() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    true
+}
+
+/// Validate a MAC address (`xx:xx:xx:xx:xx:xx`) and normalize it to lowercase
+pub fn normalize_mac(mac: &str) -> Result<String> {
+    let invalid = || {
+        QvmError::Invalid(format!(
+            "Invalid MAC address '{}': expected six hex octets like 52:54:00:12:34:56",
+            mac
+        ))
+    };
+
+    let octets: Vec<&str> = mac.split(':').collect();
+    if octets.len() != 6 {
+        return Err(invalid());
+    }
+    let mut bytes = [0u8; 6];
+    for (b, o) in bytes.iter_mut().zip(&octets) {
+        if o.len() != 2 {
+            return Err(invalid());
+        }
+        *b = u8::from_str_radix(o, 16).map_err(|_| invalid())?;
+    }
+
+    // The I/G bit marks group addresses (multicast and broadcast); a NIC must be unicast
+    if bytes[0] & 0x01 != 0 {
+        return Err(QvmError::Invalid(format!(
+            "Invalid MAC address '{}': multicast/broadcast addresses cannot be assigned to a guest",
+            mac
+        )));
+    }
+
+    Ok(mac.to_ascii_lowercase())
+}
+
+/// QEMU's locally used prefix for guest MACs
+pub const DEFAULT_MAC_PREFIX: &str = "52:54:00";
+
+/// How `generate_mac` picks the three octets after the prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MacStrategy {
+    #[default]
+    Random,
+    /// A hash of the seed (the VM name), so a recreated VM gets the same MAC
+    Deterministic,
+}
+
+impl MacStrategy {
+    /// Parse `random` or `deterministic`
+    pub fn parse(strategy: &str) -> Result<Self> {
+        match strategy {
+            "random" => Ok(MacStrategy::Random),
+            "deterministic" => Ok(MacStrategy::Deterministic),
+            other => Err(QvmError::Invalid(format!(
+                "Unknown MAC strategy '{}' (random or deterministic)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Generate a MAC from a three-octet `prefix` (OUI) such as `52:54:00`, followed by
+/// random octets or, with `MacStrategy::Deterministic`, octets hashed from `seed`
+pub fn generate_mac(strategy: MacStrategy, prefix: &str, seed: &str) -> Result<String> {
+    let invalid = || {
+        QvmError::Invalid(format!(
+            "Invalid MAC prefix '{}': expected three hex octets of a unicast address, like {}",
+            prefix, DEFAULT_MAC_PREFIX
+        ))
+    };
+    if prefix.split(':').count() != 3 {
+        return Err(invalid());
+    }
+    let prefix = normalize_mac(&format!("{}:00:00:00", prefix)).map_err(|_| invalid())?;
+
+    let tail: [u8; 3] = match strategy {
+        MacStrategy::Random => rand::random(),
+        MacStrategy::Deterministic => {
+            // FNV-1a: stable across Rust releases and platforms, unlike std's hashers
+            let hash = seed.bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+                (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+            });
+            let folded = hash ^ (hash >> 24) ^ (hash >> 48);
+            [(folded >> 16) as u8, (folded >> 8) as u8, folded as u8]
         }
+    };
+    Ok(format!("{}:{:02x}:{:02x}:{:02x}", &prefix[..8], tail[0], tail[1], tail[2]))
+}
+
+/// Generate a random MAC in QEMU's locally used 52:54:00 prefix
+pub fn random_mac() -> String {
+    generate_mac(MacStrategy::Random, DEFAULT_MAC_PREFIX, "").expect("the default prefix is valid")
+}
+
+/// Pick the best accelerator for a guest arch on this host: `kvm` (Linux, /dev/kvm) or
+/// `hvf` (macOS, Hypervisor.framework) when the guest matches the host CPU, else `tcg`
+pub fn detect_accel(arch: &str) -> String {
+    if arch != std::env::consts::ARCH {
+        return "tcg".to_string();
+    }
+    if cfg!(target_os = "linux") && Path::new("/dev/kvm").exists() {
+        return "kvm".to_string();
+    }
+    if cfg!(target_os = "macos") && hvf_supported() {
+        return "hvf".to_string();
+    }
+    "tcg".to_string()
+}
+
+#[cfg(target_os = "macos")]
+fn hvf_supported() -> bool {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>();
+    let rc = unsafe {
+        libc::sysctlbyname(
+            c"kern.hv_support".as_ptr(),
+            &mut value as *mut _ as *mut libc::c_void,
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    rc == 0 && value == 1
+}
+
+#[cfg(not(target_os = "macos"))]
+fn hvf_supported() -> bool {
+    false
+}
+
+/// Prompt on stderr and read one line from stdin, without echo when stdin is a terminal
+pub fn read_secret(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+
+    let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+    let tty = unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } == 0;
+    if tty {
+        let mut quiet = saved;
+        quiet.c_lflag &= !libc::ECHO;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &quiet) };
+    }
+    let mut line = String::new();
+    let read = io::stdin().read_line(&mut line);
+    if tty {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved) };
+        eprintln!();
+    }
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Quote a word for POSIX sh, leaving plain words untouched
+pub fn shell_quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// Total physical memory of the host in MB, if the platform reports it
+pub fn host_memory_mb() -> Option<u64> {
+    let bytes = host_memory().total_memory();
+    (bytes > 0).then_some(bytes / (1024 * 1024))
+}
+
+/// The host's RAM figures, freshly read
+fn host_memory() -> sysinfo::System {
+    let mut sys = sysinfo::System::new();
+    sys.refresh_memory_specifics(sysinfo::MemoryRefreshKind::nothing().with_ram());
+    sys
+}
+
+/// Memory the host could give a new process right now in MB (free plus reclaimable),
+/// if the platform reports it
+pub fn host_available_memory_mb() -> Option<u64> {
+    let bytes = host_memory().available_memory();
+    (bytes > 0).then_some(bytes / (1024 * 1024))
+}
+
+/// Logical CPUs this process may run on, if known
+pub fn host_cpu_count() -> Option<usize> {
+    thread::available_parallelism().ok().map(|n| n.get())
+}
+
+fn io_errno() -> i32 {
+    std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+/// A qemu release, as reported by `qemu-system-* -version`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct QemuVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub micro: u32,
+}
+
+impl QemuVersion {
+    pub const fn new(major: u32, minor: u32, micro: u32) -> Self {
+        QemuVersion { major, minor, micro }
     }
+}
 
-    Ok(false)
+impl fmt::Display for QemuVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.micro)
+    }
+}
+
+/// Run `bin -version` and parse the release it reports
+pub fn qemu_version(bin: &Path) -> Result<QemuVersion> {
+    let output = Command::new(bin).arg("-version").output()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    parse_qemu_version(&text).ok_or_else(|| {
+        QvmError::CommandFailed(format!(
+            "cannot tell the version of {} from '{}'",
+            bin.display(),
+            text.lines().next().unwrap_or_default().trim()
+        ))
+    })
+}
+
+/// Parse `QEMU emulator version 8.2.2 (Debian 1:8.2.2+ds-0ubuntu1)`; a missing micro
+/// number (`9.0`) counts as 0
+pub fn parse_qemu_version(text: &str) -> Option<QemuVersion> {
+    let rest = text.lines().find_map(|l| l.split_once("version ").map(|(_, v)| v))?;
+    let word = rest.split(|c: char| !(c.is_ascii_digit() || c == '.')).next()?;
+    let mut parts = word.split('.').map(|n| n.parse::<u32>().ok());
+    let major = parts.next()??;
+    let minor = parts.next()??;
+    let micro = parts.next().unwrap_or(Some(0))?;
+    Some(QemuVersion::new(major, minor, micro))
 }
 
-/// Pick qemu-system-* path, Nix-aware
+/// Environment variable naming the qemu-system-* binary to use for every arch
+pub const QEMU_BIN_ENV: &str = "QVM_QEMU_BIN";
+
+/// Pick the qemu for a VM: an explicit `$QVM_QEMU_BIN` override, else the binary the VM
+/// pins (`paths.qemu_bin`), else detection as in `pick_qemu_bin`
+pub fn pick_vm_qemu_bin(cfg: &VmConfig) -> Result<PathBuf> {
+    match &cfg.paths.qemu_bin {
+        Some(pinned) if std::env::var_os(QEMU_BIN_ENV).is_none_or(|b| b.is_empty()) => {
+            checked_qemu_bin(pinned, &format!("paths.qemu_bin of VM '{}'", cfg.meta.name))
+        }
+        _ => pick_qemu_bin(&cfg.meta.arch),
+    }
+}
+
+/// Pick qemu-system-* path: `$QVM_QEMU_BIN` (or `--qemu-bin`) if set, else Nix-aware detection
 pub fn pick_qemu_bin(arch: &str) -> Result<PathBuf> {
+    if let Some(bin) = std::env::var_os(QEMU_BIN_ENV).filter(|b| !b.is_empty()) {
+        return checked_qemu_bin(Path::new(&bin), &format!("${} (--qemu-bin)", QEMU_BIN_ENV));
+    }
+
+    for p in qemu_bin_candidates(arch)? {
+        if p.is_file() {
+            debug!("using {}", p.display());
+            return Ok(p);
+        }
+        trace!("no qemu at {}", p.display());
+    }
+    Err(QvmError::QemuNotFound(arch.to_string()))
+}
+
+/// The paths `pick_qemu_bin` tries for `arch` without `$QVM_QEMU_BIN`, in order: the
+/// NixOS system profile, then `qemu-system-<arch>` as found in PATH
+pub fn qemu_bin_candidates(arch: &str) -> Result<Vec<PathBuf>> {
     let candidates: &[&str] = match arch {
         "aarch64" => &[
             "/run/current-system/sw/bin/qemu-system-aarch64",
@@ -64,18 +520,32 @@ pub fn pick_qemu_bin(arch: &str) -> Result<PathBuf> {
             "/run/current-system/sw/bin/qemu-system-x86_64",
             "qemu-system-x86_64",
         ],
-        other => return Err(anyhow!("Unsupported arch '{}'", other)),
+        other => return Err(QvmError::UnsupportedArch(other.to_string())),
     };
+    Ok(candidates
+        .iter()
+        .map(|c| {
+            if c.starts_with('/') {
+                PathBuf::from(c)
+            } else {
+                which::which(c).unwrap_or_else(|_| PathBuf::from(c))
+            }
+        })
+        .collect())
+}
 
-    for c in candidates {
-        let p = if c.starts_with('/') {
-            PathBuf::from(c)
-        } else {
-            which::which(c).unwrap_or_else(|_| PathBuf::from(c))
-        };
-        if p.is_file() {
-            return Ok(p);
-        }
+/// Resolve `bin` (a path, or a name looked up in PATH) and check it is an executable file;
+/// `source` names where it came from for the error
+fn checked_qemu_bin(bin: &Path, source: &str) -> Result<PathBuf> {
+    let p = which::which(bin).unwrap_or_else(|_| bin.to_path_buf());
+    let executable = fs::metadata(&p).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0);
+    if !executable {
+        return Err(QvmError::Invalid(format!(
+            "{} is set to {}, which is not an executable file",
+            source,
+            p.display()
+        )));
     }
-    Err(anyhow!("qemu-system-{} not found (Nix)", arch))
-}
\ No newline at end of file
+    debug!("using {} (from {})", p.display(), source);
+    Ok(p)
+}
@@ -0,0 +1,84 @@
+//! VM export/import as tar archives (via the system `tar`)
+
+use crate::utils::paths::validate_vm_name;
+use crate::{QvmError, Result};
+use std::path::{Component, Path};
+use std::process::Command;
+
+/// Runtime files that must not travel with an exported VM
+const EXCLUDES: &[&str] = &["vm.pid", "vm.log", "swtpm.pid", "vm.lock", "*.sock"];
+
+/// Pack `<home>/<dir_name>` into a tar archive at `file`
+pub fn pack(home: &Path, dir_name: &str, file: &Path) -> Result<()> {
+    // tar resolves -f relative to its own cwd; keep the caller's meaning
+    let file = std::env::current_dir()?.join(file);
+    let mut cmd = Command::new("tar");
+    cmd.arg("-cf").arg(&file);
+    for pattern in EXCLUDES {
+        cmd.arg(format!("--exclude={}", pattern));
+    }
+    cmd.arg("-C").arg(home).arg(dir_name);
+    run_tar(cmd, "create", &file).map(|_| ())
+}
+
+/// List the member paths of an archive
+pub fn entries(file: &Path) -> Result<Vec<String>> {
+    let mut cmd = Command::new("tar");
+    cmd.arg("-tf").arg(file);
+    let out = run_tar(cmd, "read", file)?;
+    Ok(out.lines().filter(|l| !l.is_empty()).map(String::from).collect())
+}
+
+/// Extract an archive into `dest`
+pub fn unpack(file: &Path, dest: &Path) -> Result<()> {
+    let mut cmd = Command::new("tar");
+    cmd.arg("-xf").arg(file).arg("--no-same-owner").arg("-C").arg(dest);
+    run_tar(cmd, "extract", file).map(|_| ())
+}
+
+/// Check that every member lives under one `<name>.qvm/` directory and return that directory
+pub fn vm_dir_of(entries: &[String]) -> Result<String> {
+    let invalid = |why: String| QvmError::Invalid(format!("Not a qvm export archive: {}", why));
+
+    let mut dir: Option<&str> = None;
+    for entry in entries {
+        let path = Path::new(entry);
+        let mut components = path.components().filter(|c| !matches!(c, Component::CurDir));
+        let top = match components.next() {
+            Some(Component::Normal(top)) => top.to_str().unwrap_or_default(),
+            _ => return Err(invalid(format!("unexpected member '{}'", entry))),
+        };
+        if components.any(|c| !matches!(c, Component::Normal(_))) {
+            return Err(invalid(format!("unsafe member path '{}'", entry)));
+        }
+        match dir {
+            None => dir = Some(top),
+            Some(d) if d == top => {}
+            Some(d) => {
+                return Err(invalid(format!("members under both '{}' and '{}'", d, top)));
+            }
+        }
+    }
+
+    let dir = dir.ok_or_else(|| invalid("archive is empty".into()))?;
+    let name = dir
+        .strip_suffix(".qvm")
+        .ok_or_else(|| invalid(format!("top-level directory '{}' is not a .qvm directory", dir)))?;
+    validate_vm_name(name)?;
+    Ok(dir.to_string())
+}
+
+fn run_tar(mut cmd: Command, action: &str, file: &Path) -> Result<String> {
+    let output = cmd.output().map_err(|e| {
+        QvmError::CommandFailed(format!("failed to run tar (is it installed?): {}", e))
+    })?;
+    if !output.status.success() {
+        return Err(QvmError::CommandFailed(format!(
+            "tar failed to {} archive {}: {}",
+            action,
+            file.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,141 @@
+//! `qvm backup`: timestamped copies of a VM's disks and vm.json, recorded in a manifest
+//! in the destination directory
+
+use crate::utils::system::write_atomic;
+use crate::vm::qmp::QmpClient;
+use crate::{QvmError, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// The manifest listing every backup in a destination directory
+pub const MANIFEST_FILE: &str = "backups.json";
+
+/// How often to poll a block job while it runs
+const JOB_POLL: Duration = Duration::from_millis(200);
+
+/// The backups in one destination directory, oldest first
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BackupManifest {
+    #[serde(default)]
+    pub backups: Vec<BackupRecord>,
+}
+
+/// One backup: a directory holding vm.json, the EFI vars and a qcow2 copy of each disk
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackupRecord {
+    pub vm: String,
+    pub uuid: String,
+    pub created: String,
+    /// Directory of the backup, relative to the manifest
+    pub dir: PathBuf,
+    /// Whether the VM was running (its disks were read through an external snapshot)
+    pub live: bool,
+    /// The disks in `paths.disks` order
+    pub disks: Vec<BackupDisk>,
+    /// Copy of the EFI variable store, if the VM had one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub efi_vars: Option<PathBuf>,
+}
+
+/// A backed-up disk: where it came from and its file in the backup directory
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackupDisk {
+    pub source: PathBuf,
+    pub file: PathBuf,
+}
+
+impl BackupManifest {
+    /// Read the manifest of `dest`; a directory without one has no backups yet
+    pub fn load(dest: &Path) -> Result<Self> {
+        let path = dest.join(MANIFEST_FILE);
+        match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| {
+                QvmError::Invalid(format!("{}: {}", path.display(), e))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replace the manifest of `dest`
+    pub fn save(&self, dest: &Path) -> Result<()> {
+        write_atomic(&dest.join(MANIFEST_FILE), |f| {
+            let mut w = BufWriter::new(f);
+            serde_json::to_writer_pretty(&mut w, self)?;
+            w.flush()?;
+            Ok(())
+        })
+    }
+
+    /// The backups of the VM named `vm`, newest first
+    pub fn for_vm<'a>(&'a self, vm: &'a str) -> impl Iterator<Item = &'a BackupRecord> {
+        self.backups.iter().rev().filter(move |b| b.vm == vm)
+    }
+}
+
+/// Directory name of a backup of `vm` taken at `time`, e.g. `web-20240101T120000Z`
+pub fn backup_dir_name(vm: &str, time: DateTime<Utc>) -> String {
+    format!("{}-{}", vm, time.format("%Y%m%dT%H%M%SZ"))
+}
+
+/// File name of disk `index` within a backup
+pub fn backup_disk_file(index: usize) -> PathBuf {
+    PathBuf::from(format!("disk{}.qcow2", index))
+}
+
+/// The temporary overlay a running VM writes to while `disk` is being copied
+pub fn overlay_path(disk: &Path) -> PathBuf {
+    let name = disk.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    disk.with_file_name(format!("{}.backup-overlay.qcow2", name))
+}
+
+/// Redirect the guest's writes to drive `device` into a new qcow2 `overlay`, leaving the
+/// image underneath unchanged until `commit_overlay`
+pub fn freeze_drive(qmp: &mut QmpClient, device: &str, overlay: &Path) -> Result<()> {
+    qmp.execute(
+        "blockdev-snapshot-sync",
+        Some(json!({
+            "device": device,
+            "snapshot-file": overlay.display().to_string(),
+            "format": "qcow2",
+        })),
+    )?;
+    Ok(())
+}
+
+/// Merge the overlay of drive `device` back into its image and switch the guest back to
+/// it: an active `block-commit`, completed once it has caught up with the guest
+pub fn commit_overlay(qmp: &mut QmpClient, device: &str) -> Result<()> {
+    let job = format!("backup-{}", device);
+    qmp.execute("block-commit", Some(json!({ "device": device, "job-id": job })))?;
+    loop {
+        match job_status(&qmp.execute("query-block-jobs", None)?, &job) {
+            Some(true) => break,
+            Some(false) => thread::sleep(JOB_POLL),
+            None => {
+                return Err(QvmError::Qmp(format!(
+                    "committing the backup overlay of {} ended before it was ready",
+                    device
+                )))
+            }
+        }
+    }
+    qmp.execute("block-job-complete", Some(json!({ "device": job })))?;
+    while job_status(&qmp.execute("query-block-jobs", None)?, &job).is_some() {
+        thread::sleep(JOB_POLL);
+    }
+    Ok(())
+}
+
+/// Whether block job `job` in a `query-block-jobs` reply is ready to complete, or None if
+/// it is not listed (finished or failed)
+pub fn job_status(jobs: &Value, job: &str) -> Option<bool> {
+    let entry = jobs.as_array()?.iter().find(|j| j["device"] == job)?;
+    Some(entry["ready"].as_bool().unwrap_or(false))
+}
@@ -0,0 +1,73 @@
+//! cloud-init NoCloud seed ISO generation
+
+use crate::{QvmError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// File name of the seed ISO inside the VM root
+pub const SEED_ISO: &str = "seed.iso";
+
+/// Source files for a NoCloud seed
+pub struct SeedSources<'a> {
+    pub user_data: &'a Path,
+    pub meta_data: Option<&'a Path>,
+    pub network_config: Option<&'a Path>,
+}
+
+/// Build a `cidata` seed ISO under `root` and return its path.
+///
+/// When no meta-data file is given, a minimal one is generated from the VM
+/// name and UUID so cloud-init treats each VM as a distinct instance.
+pub fn build_seed_iso(root: &Path, name: &str, uuid: &str, src: &SeedSources) -> Result<PathBuf> {
+    let (tool, prefix) = find_iso_tool()?;
+
+    let staging = tempfile::tempdir()?;
+    fs::copy(src.user_data, staging.path().join("user-data"))?;
+    match src.meta_data {
+        Some(meta) => {
+            fs::copy(meta, staging.path().join("meta-data"))?;
+        }
+        None => fs::write(
+            staging.path().join("meta-data"),
+            format!("instance-id: {}\nlocal-hostname: {}\n", uuid, name),
+        )?,
+    }
+    if let Some(net) = src.network_config {
+        fs::copy(net, staging.path().join("network-config"))?;
+    }
+
+    let iso = root.join(SEED_ISO);
+    let status = Command::new(&tool)
+        .args(prefix)
+        .args(["-output"])
+        .arg(&iso)
+        .args(["-volid", "cidata", "-joliet", "-rock"])
+        .arg(staging.path())
+        .status()?;
+    if !status.success() {
+        return Err(QvmError::CommandFailed(format!(
+            "{} failed to build cloud-init seed ISO",
+            tool.display()
+        )));
+    }
+    Ok(iso)
+}
+
+/// Locate an mkisofs-compatible tool, returning it with any leading arguments it needs
+pub fn find_iso_tool() -> Result<(PathBuf, &'static [&'static str])> {
+    let candidates: &[(&str, &[&str])] = &[
+        ("genisoimage", &[]),
+        ("mkisofs", &[]),
+        ("xorriso", &["-as", "mkisofs"]),
+    ];
+    for (name, prefix) in candidates {
+        if let Ok(path) = which::which(name) {
+            return Ok((path, prefix));
+        }
+    }
+    Err(QvmError::CommandFailed(
+        "No ISO tool found: install genisoimage, mkisofs or xorriso to build cloud-init seeds"
+            .to_string(),
+    ))
+}
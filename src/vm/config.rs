@@ -1,28 +1,215 @@
 //! VM configuration management
 
+use crate::config::migrate::{migrate, version_of, CURRENT_VERSION};
 use crate::config::schema::VmConfig;
-use crate::utils::paths::{conf_path, qvm_home};
-use crate::Result;
-use std::fs::File;
+use crate::config::validate::{
+    choice_errors, host_incompatibilities, qemu_feature_problems, validate_boot, validate_choices, validate_cpu_model,
+    validate_disk, validate_display_tls, validate_gpu, validate_memory, validate_network, validate_tags,
+    validate_topology, ValidationError,
+};
+use crate::utils::paths::{conf_path, find_vm_dirs_in, qvm_home, resolve_under_root, vm_name_of};
+use crate::utils::system::{pick_vm_qemu_bin, qemu_version, write_atomic};
+use crate::vm::disk::is_block_device;
+use crate::vm::firmware::refresh_firmware;
+use crate::vm::launch::PASSWORD_ENV_PREFIX;
+use crate::{QvmError, Result};
+use std::fs::{File, Permissions};
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// A VM found in qvm home: its directory and configuration, or why that failed to load
+#[derive(Debug)]
+pub struct VmEntry {
+    pub name: String,
+    pub dir: PathBuf,
+    pub config: Result<VmConfig>,
+}
 
 /// Save VM configuration to file
 pub fn save_conf(cfg: &VmConfig) -> Result<()> {
-    let f = File::create(conf_path(&cfg.paths.root))?;
-    serde_json::to_writer_pretty(f, cfg)?;
-    Ok(())
+    write_conf(&cfg.paths.root, cfg)
 }
 
 /// Load VM configuration from file
 pub fn load_conf(name: &str) -> Result<VmConfig> {
     let root = qvm_home()?.join(format!("{name}.qvm"));
-    let f = File::open(conf_path(&root))?;
-    let cfg: VmConfig = serde_json::from_reader(f)?;
+    load_conf_from_dir(&root)
+}
+
+/// Load VM configuration from directory, upgrading older schema versions in place
+pub fn load_conf_from_dir(vm_dir: &Path) -> Result<VmConfig> {
+    let (cfg, version) = read_conf(vm_dir)?;
+    if let Err(e) = validate_choices(&cfg) {
+        return Err(QvmError::Invalid(format!("{}: {}", conf_path(vm_dir).display(), e)));
+    }
+    if version < CURRENT_VERSION {
+        write_conf(vm_dir, &cfg)?;
+    }
     Ok(cfg)
 }
 
-/// Load VM configuration from directory
-pub fn load_conf_from_dir(vm_dir: &std::path::Path) -> Result<VmConfig> {
+/// Load every VM in qvm home, sorted by name. A VM whose vm.json is missing or invalid
+/// is returned with its error rather than failing the scan.
+pub fn all_vms() -> Result<Vec<VmEntry>> {
+    all_vms_in(&qvm_home()?)
+}
+
+/// Load every VM in `home`, as `all_vms` does for qvm home
+pub fn all_vms_in(home: &Path) -> Result<Vec<VmEntry>> {
+    Ok(find_vm_dirs_in(home)?
+        .into_iter()
+        .map(|dir| VmEntry {
+            name: vm_name_of(&dir).unwrap_or_default(),
+            config: load_conf_from_dir(&dir),
+            dir,
+        })
+        .collect())
+}
+
+/// Load vm.json from a directory, upgraded to the current schema in memory only and with
+/// its values unchecked (for `VmConfig::validate`, which reports every problem at once)
+pub fn load_conf_unchecked(vm_dir: &Path) -> Result<VmConfig> {
+    Ok(read_conf(vm_dir)?.0)
+}
+
+/// Parse and migrate vm.json, returning the schema version it was stored with
+fn read_conf(vm_dir: &Path) -> Result<(VmConfig, u32)> {
     let f = File::open(conf_path(vm_dir))?;
-    let cfg: VmConfig = serde_json::from_reader(f)?;
-    Ok(cfg)
-}
\ No newline at end of file
+    let value: serde_json::Value = serde_json::from_reader(f)?;
+    let version = version_of(&value);
+    Ok((migrate(value)?, version))
+}
+
+/// Write vm.json atomically, so a crash mid-write never leaves a truncated file behind. It
+/// is mode 0600 while it holds a display password in plain text, 0644 otherwise.
+fn write_conf(root: &Path, cfg: &VmConfig) -> Result<()> {
+    let secret = [&cfg.display.vnc.password, &cfg.display.spice.password]
+        .into_iter()
+        .flatten()
+        .any(|p| !p.starts_with(PASSWORD_ENV_PREFIX));
+    write_atomic(&conf_path(root), |f| {
+        f.set_permissions(Permissions::from_mode(if secret { 0o600 } else { 0o644 }))?;
+        let mut w = BufWriter::new(f);
+        serde_json::to_writer_pretty(&mut w, cfg)?;
+        w.flush()?;
+        Ok(())
+    })
+}
+
+impl VmConfig {
+    /// Everything that would keep this VM from starting on this host, all at once: values
+    /// out of range or inconsistent with each other, files it names that are missing, a
+    /// display or accelerator the host lacks, and no usable qemu or firmware. Nothing is
+    /// changed; relative paths resolve under `paths.root`.
+    pub fn validate(&self) -> std::result::Result<(), Vec<ValidationError>> {
+        let root = self.paths.root.as_path();
+        let hw = &self.hardware;
+        let mut errors = match choice_errors(self) {
+            Ok(errors) => errors,
+            Err(e) => vec![ValidationError::new("vm.json", e.to_string())],
+        };
+        let mut check = |key: &str, result: Result<()>| {
+            if let Err(e) = result {
+                errors.push(ValidationError::new(key, e.to_string()));
+            }
+        };
+
+        check("meta.tags", validate_tags(&self.meta.tags).map(|_| ()));
+        check("hardware.cpu_model", validate_cpu_model(&self.meta.arch, &hw.cpu_model));
+        if hw.mem_mb == 0 {
+            check("hardware.mem_mb", Err(QvmError::Invalid("Memory must be at least 1 MB".to_string())));
+        }
+        check("hardware", validate_topology(hw));
+        check("network", validate_network(&self.network));
+        check("memory", validate_memory(&self.memory, hw.mem_mb));
+        check("boot", validate_boot(&self.boot));
+        check("display", validate_display_tls(&self.display, root));
+        check("display.gpu", validate_gpu(&self.display, &self.meta.arch));
+
+        for problem in host_incompatibilities(std::env::consts::OS, Some(&self.display.mode), "tcg") {
+            check("display.mode", Err(QvmError::Invalid(problem)));
+        }
+        for problem in host_incompatibilities(std::env::consts::OS, None, &hw.accel) {
+            check("hardware.accel", Err(QvmError::Invalid(problem)));
+        }
+        let host_arch = std::env::consts::ARCH;
+        if matches!(hw.accel.as_str(), "kvm" | "hvf") && self.meta.arch != host_arch {
+            check(
+                "hardware.accel",
+                Err(QvmError::Invalid(format!(
+                    "accelerator '{}' only runs {} guests on this host; use tcg for {}",
+                    hw.accel, host_arch, self.meta.arch
+                ))),
+            );
+        }
+
+        if self.paths.disks.is_empty() {
+            check("paths.disks", Err(QvmError::Invalid("no disks configured".to_string())));
+        }
+        for (i, disk) in self.paths.disks.iter().enumerate() {
+            let key = format!("paths.disks.{}", i);
+            check(&key, validate_disk(disk));
+            let path = resolve_under_root(root, &disk.path);
+            if path.is_file() || is_block_device(&path) {
+                continue;
+            }
+            let missing = match path.parent() {
+                Some(dir) if !dir.is_dir() => format!("directory {} does not exist", dir.display()),
+                _ => format!("no disk image at {}", path.display()),
+            };
+            check(&key, Err(QvmError::Invalid(missing)));
+        }
+
+        let mut files = vec![
+            ("paths.iso", self.paths.iso.as_ref()),
+            ("boot.kernel", self.boot.kernel.as_ref()),
+            ("boot.initrd", self.boot.initrd.as_ref()),
+        ];
+        if let Some(ci) = &self.cloud_init {
+            files.push(("cloud_init.seed_iso", Some(&ci.seed_iso)));
+        }
+        for (key, path) in files {
+            if let Some(path) = path.map(|p| resolve_under_root(root, p)) {
+                if !path.is_file() {
+                    check(key, Err(QvmError::Invalid(format!("{} does not exist", path.display()))));
+                }
+            }
+        }
+        for (i, share) in self.shares.iter().enumerate() {
+            if !share.path.is_dir() {
+                let missing = format!("shared directory {} does not exist", share.path.display());
+                check(&format!("shares.{}.path", i), Err(QvmError::Invalid(missing)));
+            }
+        }
+
+        match pick_vm_qemu_bin(self) {
+            Ok(bin) => {
+                let version = qemu_version(&bin).ok();
+                for problem in version.map(|v| qemu_feature_problems(self, v)).unwrap_or_default() {
+                    check(&problem.key, Err(QvmError::Invalid(problem.message)));
+                }
+            }
+            Err(e) => check("paths.qemu_bin", Err(e)),
+        }
+        // A missing firmware file is re-detected at start; only report it if that fails too
+        let mut firmware = self.clone();
+        check("firmware.code", refresh_firmware(&mut firmware, root).map(|_| ()));
+        let efi_vars = resolve_under_root(root, &self.paths.efi_vars);
+        let template = resolve_under_root(root, &firmware.firmware.vars_template);
+        if !efi_vars.exists() && !template.is_file() {
+            let missing = format!(
+                "{} does not exist and neither does its template {}",
+                efi_vars.display(),
+                template.display()
+            );
+            check("paths.efi_vars", Err(QvmError::Invalid(missing)));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
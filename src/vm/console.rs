@@ -0,0 +1,105 @@
+//! Interactive client for a VM's serial console and HMP monitor sockets
+
+use crate::{QvmError, Result};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+/// Byte sent by Ctrl-] (telnet's traditional escape), which disconnects the client
+pub const ESCAPE: u8 = 0x1d;
+
+/// Puts the terminal in raw mode and restores the saved settings on drop
+struct RawTerminal {
+    fd: i32,
+    saved: libc::termios,
+}
+
+impl RawTerminal {
+    /// Switch `fd` to raw mode; returns None when it is not a terminal
+    fn enable(fd: i32) -> Option<Self> {
+        unsafe {
+            if libc::isatty(fd) != 1 {
+                return None;
+            }
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut saved) != 0 {
+                return None;
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(fd, libc::TCSANOW, &raw) != 0 {
+                return None;
+            }
+            Some(RawTerminal { fd, saved })
+        }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+/// Relay stdin/stdout to a socket (`what` names it in messages, e.g. "console")
+/// until Ctrl-] or the VM closes it
+pub fn attach(sock: &Path, what: &str) -> Result<()> {
+    let mut stream = UnixStream::connect(sock).map_err(|e| {
+        QvmError::CommandFailed(format!(
+            "Cannot connect to {} socket {}: {}",
+            what,
+            sock.display(),
+            e
+        ))
+    })?;
+
+    let stdin = io::stdin();
+    let stdin_fd = stdin.as_raw_fd();
+    let mut stdout = io::stdout();
+    let _raw = RawTerminal::enable(stdin_fd);
+
+    let mut fds = [
+        libc::pollfd { fd: stdin_fd, events: libc::POLLIN, revents: 0 },
+        libc::pollfd { fd: stream.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+    ];
+    let mut buf = [0u8; 4096];
+    loop {
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err.into());
+        }
+
+        if fds[1].revents != 0 {
+            let n = stream.read(&mut buf)?;
+            if n == 0 {
+                write!(stdout, "\r\n[{} closed]\r\n", what)?;
+                break;
+            }
+            stdout.write_all(&buf[..n])?;
+            stdout.flush()?;
+        }
+
+        if fds[0].revents != 0 {
+            let n = stdin.lock().read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            match buf[..n].iter().position(|&b| b == ESCAPE) {
+                Some(i) => {
+                    stream.write_all(&buf[..i])?;
+                    write!(stdout, "\r\n")?;
+                    break;
+                }
+                None => stream.write_all(&buf[..n])?,
+            }
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
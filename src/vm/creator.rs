@@ -1,15 +1,34 @@
 //! VM creation functionality
 
+use crate::config::migrate::{migrate, CURRENT_VERSION};
 use crate::config::schema::*;
-use crate::utils::paths::{qvm_home, resolve_under_root};
-use crate::utils::system::{now_utc, pick_qemu_bin};
-use crate::vm::config::save_conf;
-use crate::vm::firmware::{locate_firmware_from_qemu, get_default_firmware_paths};
-use crate::Result;
-use anyhow::anyhow;
+use crate::config::validate::{
+    check_config, host_incompatibilities, qemu_feature_problems, resolve_cpu_model, validate_cpu_model, validate_memory,
+};
+use crate::utils::net::free_port;
+use crate::utils::size::parse_disk_size;
+use crate::utils::paths::{expand_path, qvm_home, resolve_under_root, validate_vm_name};
+use crate::utils::system::{
+    detect_accel, generate_mac, normalize_mac, now_utc, pick_qemu_bin, qemu_version, random_mac, MacStrategy,
+};
+use crate::vm::config::{all_vms, save_conf};
+use crate::vm::disk::{create_disk, create_overlay, disk_info, is_block_device};
+use crate::vm::hooks::run_hook;
+use crate::vm::firmware::{locate_firmware_from_qemu, get_default_firmware_paths, seed_efi_vars};
+use crate::vm::lock::PortLock;
+use crate::vm::ssh::SSH_GUEST_PORT;
+use crate::{QvmError, Result};
+use tracing::{info, warn};
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+/// Ports picked for VNC displays 1 to 99 when `--vnc-display` is not given
+const VNC_PORTS: RangeInclusive<u16> = VNC_BASE_PORT + 1..=VNC_BASE_PORT + 99;
+/// Ports picked for SPICE when `--spice-port` is not given
+const SPICE_PORTS: RangeInclusive<u16> = 5930..=5999;
+/// Host ports picked for the SSH forward when `--ssh-port` is not given
+const SSH_PORTS: RangeInclusive<u16> = 2222..=2299;
 
 /// VM Creation parameters
 pub struct CreateParams {
@@ -21,61 +40,157 @@ pub struct CreateParams {
     pub cores: Option<u32>,
     pub threads: Option<u32>,
     pub mem: u32,
+    /// Guest RTC base (`utc` or `localtime`)
+    pub rtc: String,
+    /// Allocate all guest RAM when the VM starts
+    pub mem_prealloc: bool,
+    /// hugetlbfs mount to back guest RAM with (Linux)
+    pub hugepages: Option<PathBuf>,
     pub net_mode: String,
     pub bridge_if: String,
     pub display_mode: String,
     pub disk: Option<PathBuf>,
     pub disk_size: Option<String>,
+    /// Boot disk image format (`qcow2` or `raw`)
+    pub disk_format: String,
+    /// Boot disk cache mode, AIO engine and read-only flag (None: qemu's defaults)
+    pub disk_cache: Option<String>,
+    pub disk_aio: Option<String>,
+    pub disk_readonly: bool,
+    /// Golden qcow2 image the boot disk is created as an overlay of
+    pub backing: Option<PathBuf>,
     pub vnc_host: String,
-    pub vnc_display: u8,
+    /// None: the lowest display whose port is free and not used by another VM
+    pub vnc_display: Option<u8>,
     pub vnc_sock: Option<PathBuf>,
     pub vnc_unix: bool,
     pub spice_addr: String,
-    pub spice_port: u16,
+    /// None: picked like `vnc_display`
+    pub spice_port: Option<u16>,
     pub spice_sock: Option<PathBuf>,
     pub spice_unix: bool,
     pub spice_disable_ticketing: bool,
+    pub tpm: bool,
+    pub audio: String,
+    pub audio_model: String,
+    /// Firmware code/vars template overriding detection (set together)
+    pub firmware_code: Option<PathBuf>,
+    pub firmware_vars: Option<PathBuf>,
+    /// Use a secure boot firmware build (an error if none is found)
+    pub secure_boot: bool,
+    /// Record the qemu binary picked now in vm.json, so the VM keeps using it
+    pub pin_qemu: bool,
+    pub ssh_user: Option<String>,
+    /// Forward a host port to the guest's SSH port (user-mode networking); the host port
+    /// is `ssh_port`, or else picked like `vnc_display`
+    pub ssh_forward: bool,
+    pub ssh_port: Option<u16>,
+    /// Direct kernel boot (paths relative to the VM root unless absolute)
+    pub kernel: Option<PathBuf>,
+    pub initrd: Option<PathBuf>,
+    pub append: Option<String>,
+    pub boot_order: Option<String>,
+    /// Install medium kept attached (`paths.iso`) until detached; the VM boots from it
+    /// first, then from the disk
+    pub iso: Option<PathBuf>,
+    pub mac: Option<String>,
+    /// How the first NIC's MAC is generated when `mac` is not given, and its prefix (OUI)
+    pub mac_strategy: MacStrategy,
+    pub mac_prefix: String,
+    pub accel: Option<String>,
+    /// qemu `-machine` value instead of the arch default (`virt,gic-version=3` / `q35`)
+    pub machine: Option<String>,
+    /// Arguments appended verbatim to the qemu command line
+    pub extra_args: Vec<String>,
+    /// Script run once the VM is created (see `vm::hooks`)
+    pub on_create: Option<PathBuf>,
+    pub description: String,
+    pub tags: Vec<String>,
 }
 
 /// VM Creator
 pub struct VmCreator;
 
 impl VmCreator {
-    /// Create a new VM with the given parameters
-    pub fn create_vm(params: CreateParams) -> Result<()> {
-        // VM root
-        let root = qvm_home()?.join(format!("{}.qvm", params.name));
-        fs::create_dir_all(&root)?;
+    /// Create a new VM with the given parameters and return its configuration. The
+    /// `on_create` hook runs once vm.json is written; if it fails the VM is kept.
+    pub fn create_vm(params: CreateParams) -> Result<VmConfig> {
+        let on_create = params
+            .on_create
+            .as_ref()
+            .map(|script| {
+                fs::canonicalize(script).map_err(|_| {
+                    QvmError::Invalid(format!("hook script {} does not exist", script.display()))
+                })
+            })
+            .transpose()?;
+        let cfg = {
+            // Held until vm.json is saved, so the next create sees the ports picked here
+            let _lock = PortLock::acquire()?;
+            let cfg = Self::build_config(&params)?;
+            let disk_size = params.disk_size.as_deref().map(parse_disk_size).transpose()?;
+            Self::persist(&cfg, disk_size)?;
+            cfg
+        };
+        if let Some(script) = on_create {
+            run_hook("create", &script, &cfg)?;
+        }
+        Ok(cfg)
+    }
 
-        // Disk path (keep relative in JSON if user provided relative)
-        let disk_rel_or_abs = params.disk.unwrap_or_else(|| PathBuf::from("disk.qcow2"));
-        let disk_abs = resolve_under_root(&root, &disk_rel_or_abs);
+    /// Create a VM from a complete vm.json made elsewhere (`qvm import-config`), named
+    /// `name` or else by its `meta.name`. It is upgraded from older schema versions and
+    /// checked like `qvm set` checks a change, then gets a root in qvm home, a new UUID,
+    /// creation time, NIC MACs and display ports; paths under its old root are kept relative
+    /// to the new one. It is saved like `create_vm` saves a VM, and whatever would still
+    /// keep it from starting (a disk not there yet, say) is warned about.
+    pub fn create_from_json(json: &str, name: Option<&str>) -> Result<VmConfig> {
+        let value: serde_json::Value = serde_json::from_str(json)
+            .map_err(|e| QvmError::Invalid(format!("invalid VM configuration: {}", e)))?;
+        let mut cfg = migrate(value)?;
 
-        // Create disk if size requested and file not present
-        if let Some(sz) = &params.disk_size {
-            if !disk_abs.exists() {
-                let status = Command::new("qemu-img")
-                    .args(["create", "-f", "qcow2"])
-                    .arg(&disk_abs)
-                    .arg(sz)
-                    .status()?;
-                if !status.success() {
-                    return Err(anyhow!("qemu-img failed to create disk (size: {sz})"));
-                }
+        if let Some(name) = name {
+            cfg.meta.name = name.to_string();
+        }
+        validate_vm_name(&cfg.meta.name)?;
+        let root = qvm_home()?.join(format!("{}.qvm", cfg.meta.name));
+        if root.exists() {
+            return Err(QvmError::VmExists(cfg.meta.name.clone()));
+        }
+
+        cfg.meta.generated = now_utc();
+
+        validate_cpu_model(&cfg.meta.arch, &cfg.hardware.cpu_model)?;
+        for w in check_config(&mut cfg)? {
+            warn!("{w}");
+        }
+
+        {
+            // Held until vm.json is saved, so the next create sees the ports picked here
+            let _lock = PortLock::acquire()?;
+            rehome(&mut cfg, root)?;
+            Self::persist(&cfg, None)?;
+        }
+        if let Err(problems) = cfg.validate() {
+            for problem in problems {
+                warn!("VM '{}' cannot start yet: {}", cfg.meta.name, problem);
             }
-        } else if !disk_abs.exists() {
-            eprintln!(
-                "Note: no disk at {} (use --disk-size to create one)",
-                disk_abs.display()
-            );
         }
+        Ok(cfg)
+    }
 
-        // CPU model normalization (like earlier: x86_64 'host' → 'qemu64' for portability)
-        let cpu_model_final = if params.arch == "x86_64" && params.cpu_model == "host" {
-            "qemu64".to_string()
-        } else {
-            params.cpu_model.clone()
+    /// Validate the parameters and build the VM's configuration (detecting firmware and
+    /// generating a MAC and UUID) without touching the filesystem
+    pub fn build_config(params: &CreateParams) -> Result<VmConfig> {
+        validate_vm_name(&params.name)?;
+        let mac = match &params.mac {
+            Some(m) => normalize_mac(m)?,
+            None => generate_mac(params.mac_strategy, &params.mac_prefix, &params.name)?,
         };
+        if params.smp == Some(0) {
+            return Err(QvmError::Invalid("--smp must be at least 1".to_string()));
+        }
+        params.disk_size.as_deref().map(parse_disk_size).transpose()?;
 
         // Topology decision
         let (skt, cor, thr) = if params.sockets.is_some() || params.cores.is_some() || params.threads.is_some() {
@@ -85,80 +200,341 @@ impl VmCreator {
                 params.threads.unwrap_or(1),
             )
         } else if let Some(n) = params.smp {
-            (1, n.max(1), 1) // simple: cores=N
+            (1, n, 1) // simple: cores=N
         } else {
             (1, 4, 1) // defaults
         };
 
-        // Resolve qemu bin (Nix aware) and firmware from it
+        if params.machine.as_deref().is_some_and(|m| m.trim().is_empty()) {
+            return Err(QvmError::Invalid("--machine must not be empty".to_string()));
+        }
+        let accel = params
+            .accel
+            .clone()
+            .unwrap_or_else(|| detect_accel(&params.arch));
+        let hardware = Hardware {
+            cpu_model: resolve_cpu_model(&params.arch, &params.cpu_model, &accel),
+            sockets: skt,
+            cores: cor,
+            threads: thr,
+            mem_mb: params.mem,
+            machine: match &params.machine {
+                Some(machine) => machine.clone(),
+                None if params.arch == "aarch64" => "virt,gic-version=3".into(),
+                None => "q35".into(),
+            },
+            accel,
+            rtc: params.rtc.clone(),
+        };
+        validate_cpu_model(&params.arch, &hardware.cpu_model)?;
+        // Only warn: the VM may be meant for another host (export/import)
+        for problem in host_incompatibilities(std::env::consts::OS, Some(&params.display_mode), &hardware.accel) {
+            warn!("{problem} (the VM will not start on this host)");
+        }
+
+        let memory = Memory {
+            prealloc: params.mem_prealloc,
+            share: false,
+            hugepages: params.hugepages.clone(),
+        };
+        validate_memory(&memory, params.mem)?;
+
+        // The install medium is found from wherever the VM is started, so store it absolute
+        let iso = match &params.iso {
+            Some(iso) => Some(std::path::absolute(expand_path(iso)?)?),
+            None => None,
+        };
+        if let Some(iso) = iso.as_ref().filter(|iso| !iso.is_file()) {
+            return Err(QvmError::Invalid(format!("ISO {} does not exist", iso.display())));
+        }
+        let boot = Boot {
+            kernel: params.kernel.clone(),
+            initrd: params.initrd.clone(),
+            cmdline: params.append.clone(),
+            // An attached ISO already boots first (its CD-ROM gets bootindex 0)
+            order: params.boot_order.clone(),
+        };
+
+        let ssh_forward = params.ssh_forward || params.ssh_port.is_some();
+        if ssh_forward && params.net_mode != "user" {
+            return Err(QvmError::Invalid(format!(
+                "An SSH forward needs --net-mode user, not {}",
+                params.net_mode
+            )));
+        }
+        let mut taken = ports_of_other_vms(&params.name)?;
+        // A display served on a UNIX socket has no port to pick
+        let vnc_display = if params.vnc_unix {
+            params.vnc_display.unwrap_or(Vnc::default().display)
+        } else {
+            let given = params.vnc_display.map(|d| VNC_BASE_PORT + u16::from(d));
+            (pick_port("VNC", given, VNC_PORTS, &mut taken)? - VNC_BASE_PORT) as u8
+        };
+        let spice_port = if params.spice_unix {
+            params.spice_port.unwrap_or(Spice::default().port)
+        } else {
+            pick_port("SPICE", params.spice_port, SPICE_PORTS, &mut taken)?
+        };
+        let forwards = if ssh_forward {
+            vec![Forward {
+                protocol: "tcp".to_string(),
+                host_port: pick_port("SSH", params.ssh_port, SSH_PORTS, &mut taken)?,
+                guest_port: SSH_GUEST_PORT,
+            }]
+        } else {
+            Vec::new()
+        };
+
+        // VM root
+        let root = qvm_home()?.join(format!("{}.qvm", params.name));
+
+        // Disk path (keep relative in JSON if user provided relative)
+        let disk_rel_or_abs = match &params.disk {
+            Some(disk) => expand_path(disk)?,
+            None => PathBuf::from(format!("disk.{}", params.disk_format)),
+        };
+        if params.disk_size.is_some() && is_block_device(&resolve_under_root(&root, &disk_rel_or_abs)) {
+            return Err(QvmError::Invalid(format!(
+                "{} is a block device; --disk-size only creates image files, so leave it out",
+                disk_rel_or_abs.display()
+            )));
+        }
+        let backing = match &params.backing {
+            Some(golden) => {
+                if params.disk_format != "qcow2" {
+                    return Err(QvmError::Invalid(
+                        "--backing creates a qcow2 overlay; it cannot be used with --disk-format raw".to_string(),
+                    ));
+                }
+                if resolve_under_root(&root, &disk_rel_or_abs).exists() {
+                    return Err(QvmError::Invalid(format!(
+                        "{} already exists; --backing creates a new overlay there",
+                        disk_rel_or_abs.display()
+                    )));
+                }
+                Some(golden_image(&expand_path(golden)?)?)
+            }
+            None => None,
+        };
+        let boot_disk = Disk {
+            format: params.disk_format.clone(),
+            cache: params.disk_cache.clone(),
+            aio: params.disk_aio.clone(),
+            readonly: params.disk_readonly,
+            backing,
+            ..Disk::new(disk_rel_or_abs)
+        };
+
+        // Resolve qemu bin (Nix aware) and firmware from it, unless firmware was given
         let qemu_bin = pick_qemu_bin(&params.arch)?;
-        let (fw_code_path, fw_vars_tpl_path) = locate_firmware_from_qemu(&qemu_bin, &params.arch)
-            .unwrap_or_else(|e| {
-                eprintln!("Warning: {e}");
+        let (fw_code_path, fw_vars_tpl_path) = match (&params.firmware_code, &params.firmware_vars) {
+            (Some(code), Some(vars)) => {
+                let (code, vars) = (expand_path(code)?, expand_path(vars)?);
+                // Relative paths are under the VM root, which does not exist yet
+                for p in [&code, &vars] {
+                    if p.is_absolute() && !p.is_file() {
+                        return Err(QvmError::FirmwareNotFound(format!("{} does not exist", p.display())));
+                    }
+                }
+                (code, vars)
+            }
+            // Never fall back to a non-secure pair when secure boot was asked for
+            _ if params.secure_boot => locate_firmware_from_qemu(&qemu_bin, &params.arch, true)?,
+            _ => locate_firmware_from_qemu(&qemu_bin, &params.arch, false).unwrap_or_else(|e| {
+                warn!("{e}");
                 get_default_firmware_paths(&params.arch)
-            });
+            }),
+        };
 
-        let cfg = VmConfig {
+        let mut cfg = VmConfig {
             meta: Meta {
-                version: 1,
+                version: CURRENT_VERSION,
                 generated: now_utc(),
                 name: params.name.clone(),
                 arch: params.arch.clone(),
                 uuid: uuid::Uuid::new_v4().to_string(),
+                description: params.description.clone(),
+                tags: params.tags.clone(),
+                autostart: false,
             },
             paths: Paths {
                 root: root.clone(),
-                disk: disk_rel_or_abs,
+                disks: vec![boot_disk],
                 efi_vars: PathBuf::from("efi_vars.fd"),
+                iso,
+                qemu_bin: params.pin_qemu.then(|| qemu_bin.clone()),
             },
-            hardware: Hardware {
-                cpu_model: cpu_model_final,
-                sockets: skt,
-                cores: cor,
-                threads: thr,
-                mem_mb: params.mem,
-                machine: if params.arch == "aarch64" {
-                    "virt,gic-version=3".into()
-                } else {
-                    "q35".into()
-                },
-                accel: if params.arch == "aarch64" {
-                    "hvf".into()
-                } else {
-                    "kvm".into() // Assuming the rest was "kvm" for x86_64
-                },
-                mac: format!("52:54:00:{:02x}:{:02x}:{:02x}",
-                    rand::random::<u8>(), rand::random::<u8>(), rand::random::<u8>()),
-            },
+            hardware,
             firmware: Firmware {
                 code: fw_code_path,
                 vars_template: fw_vars_tpl_path,
+                secure_boot: params.secure_boot,
             },
+            boot,
             network: Network {
-                mode: params.net_mode,
-                bridge_if: params.bridge_if,
-                forwards: Forwards::default(),
+                interfaces: vec![NetworkInterface {
+                    mode: params.net_mode.clone(),
+                    bridge_if: params.bridge_if.clone(),
+                    mac,
+                    forwards,
+                }],
+                ssh_user: params.ssh_user.clone(),
             },
             display: Display {
-                mode: params.display_mode,
+                mode: params.display_mode.clone(),
                 vnc: Vnc {
                     use_unix: params.vnc_unix,
-                    host: params.vnc_host,
-                    display: params.vnc_display,
-                    sock: params.vnc_sock.unwrap_or_else(|| PathBuf::from("vnc.sock")),
+                    host: params.vnc_host.clone(),
+                    display: vnc_display,
+                    sock: params.vnc_sock.clone().unwrap_or_else(|| PathBuf::from("vnc.sock")),
+                    password: None,
+                    tls: None,
                 },
                 spice: Spice {
                     use_unix: params.spice_unix,
-                    addr: params.spice_addr,
-                    port: params.spice_port,
+                    addr: params.spice_addr.clone(),
+                    port: spice_port,
                     disable_ticketing: params.spice_disable_ticketing,
-                    sock: params.spice_sock.unwrap_or_else(|| PathBuf::from("spice.sock")),
+                    sock: params.spice_sock.clone().unwrap_or_else(|| PathBuf::from("spice.sock")),
+                    password: None,
+                    tls: None,
                 },
+                gpu: Gpu::default(),
             },
+            cloud_init: None,
+            memory,
+            tpm: Tpm {
+                enabled: params.tpm,
+                ..Tpm::default()
+            },
+            audio: Audio {
+                backend: params.audio.clone(),
+                model: params.audio_model.clone(),
+            },
+            usb: Vec::new(),
+            shares: Vec::new(),
+            hooks: Hooks::default(),
+            extra_args: params.extra_args.clone(),
         };
+        // Profiles bypass the flags' value lists, so the whole config is checked once built
+        for warning in check_config(&mut cfg)? {
+            warn!("{warning}");
+        }
+        // Only warn: qemu may be upgraded before the VM's first start
+        if let Ok(version) = qemu_version(&qemu_bin) {
+            for problem in qemu_feature_problems(&cfg, version) {
+                warn!("{problem}");
+            }
+        }
+        Ok(cfg)
+    }
 
-        save_conf(&cfg)?;
-        println!("Created VM '{}' at {}", params.name, root.display());
-        Ok(())
+    /// Write a built configuration out: the VM directory, its boot disk (an overlay of its
+    /// backing file, or a new image when `disk_size` is given, if it does not exist yet),
+    /// the seeded EFI vars and vm.json
+    fn persist(cfg: &VmConfig, disk_size: Option<u64>) -> Result<()> {
+        let root = &cfg.paths.root;
+        fs::create_dir_all(root)?;
+
+        // Create disk if size requested and file not present
+        let disk_abs = resolve_under_root(root, &cfg.paths.disks[0].path);
+        if let Some(backing) = &cfg.paths.disks[0].backing {
+            if !disk_abs.exists() {
+                create_overlay(&disk_abs, backing, disk_size)?;
+            }
+        } else if let Some(sz) = disk_size {
+            if !disk_abs.exists() {
+                create_disk(&disk_abs, &cfg.paths.disks[0].format, sz)?;
+            }
+        } else if !disk_abs.exists() {
+            info!(
+                "no disk at {} (use --disk-size to create one)",
+                disk_abs.display()
+            );
+        }
+
+        // Each VM gets its own NVRAM so UEFI boot entries persist across boots; without a
+        // template (firmware not found above) it is seeded on first start instead
+        let efi_vars = resolve_under_root(root, &cfg.paths.efi_vars);
+        let vars_template = resolve_under_root(root, &cfg.firmware.vars_template);
+        if !efi_vars.exists() && vars_template.is_file() {
+            seed_efi_vars(&vars_template, &efi_vars)?;
+        }
+
+        save_conf(cfg)
+    }
+}
+
+/// The absolute path of golden image `backing`, which must be an existing qcow2 image
+fn golden_image(backing: &Path) -> Result<PathBuf> {
+    let abs = fs::canonicalize(backing)
+        .map_err(|_| QvmError::Invalid(format!("backing file {} does not exist", backing.display())))?;
+    if !abs.is_file() {
+        return Err(QvmError::Invalid(format!("backing file {} is not a file", abs.display())));
+    }
+    let info = disk_info(&abs)?;
+    if info.format != "qcow2" {
+        return Err(QvmError::Invalid(format!(
+            "backing file {} is {}, not qcow2",
+            abs.display(),
+            info.format
+        )));
+    }
+    Ok(abs)
+}
+
+/// Move a VM made elsewhere (`qvm import-config`, `qvm import`) to `root`, keeping paths
+/// under its old root relative to the new one, and give it a new UUID, NIC MACs and
+/// display ports: one template or archive imported twice must not give two VMs one MAC
+/// or port. The caller holds `PortLock` until vm.json is saved.
+pub(crate) fn rehome(cfg: &mut VmConfig, root: PathBuf) -> Result<()> {
+    let old_root = std::mem::replace(&mut cfg.paths.root, root);
+    for path in cfg.paths.disks.iter_mut().map(|d| &mut d.path).chain([&mut cfg.paths.efi_vars]) {
+        if let Ok(rel) = path.strip_prefix(&old_root) {
+            *path = rel.to_path_buf();
+        }
     }
-}
\ No newline at end of file
+    cfg.meta.uuid = uuid::Uuid::new_v4().to_string();
+    for nic in &mut cfg.network.interfaces {
+        nic.mac = random_mac();
+    }
+
+    let others = ports_of_other_vms(&cfg.meta.name)?;
+    let mut taken = others.clone();
+    if !cfg.display.vnc.use_unix {
+        cfg.display.vnc.display = (pick_port("VNC", None, VNC_PORTS, &mut taken)? - VNC_BASE_PORT) as u8;
+    }
+    if !cfg.display.spice.use_unix {
+        cfg.display.spice.port = pick_port("SPICE", None, SPICE_PORTS, &mut taken)?;
+    }
+    for port in cfg.tcp_ports().into_iter().filter(|p| others.contains(p)) {
+        warn!("port {} is also used by another VM", port);
+    }
+    Ok(())
+}
+
+/// The TCP ports every other VM in qvm home is set up to listen on
+fn ports_of_other_vms(name: &str) -> Result<Vec<u16>> {
+    Ok(all_vms()?
+        .into_iter()
+        .filter(|vm| vm.name != name)
+        .filter_map(|vm| vm.config.ok())
+        .flat_map(|cfg| cfg.tcp_ports())
+        .collect())
+}
+
+/// `given`, or else the lowest port in `range` that is free on this host and not in
+/// `taken`; the port is added to `taken` either way
+fn pick_port(what: &str, given: Option<u16>, range: RangeInclusive<u16>, taken: &mut Vec<u16>) -> Result<u16> {
+    let port = match given {
+        Some(port) => {
+            if taken.contains(&port) {
+                warn!("{} port {} is also used by another VM", what, port);
+            }
+            port
+        }
+        None => free_port(range, taken)?,
+    };
+    taken.push(port);
+    Ok(port)
+}
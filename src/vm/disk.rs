@@ -0,0 +1,195 @@
+//! Disk image management via qemu-img
+
+use crate::utils::size::format_size;
+use crate::{QvmError, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::process::Command;
+
+/// Image details reported by `qemu-img info`
+#[derive(Deserialize, Debug, Clone)]
+pub struct DiskInfo {
+    #[serde(rename = "virtual-size")]
+    pub virtual_size: u64,
+    #[serde(rename = "actual-size", default)]
+    pub actual_size: u64,
+    pub format: String,
+}
+
+/// What `qemu-img check` found in an image (after repairing it, if asked to)
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct CheckResult {
+    /// Metadata errors that can lose guest data
+    #[serde(default)]
+    pub corruptions: u64,
+    /// Clusters allocated but unused: wasted space, harmless to the guest
+    #[serde(default)]
+    pub leaks: u64,
+    /// Errors that kept the check itself from completing
+    #[serde(default)]
+    pub check_errors: u64,
+    #[serde(default)]
+    pub corruptions_fixed: u64,
+    #[serde(default)]
+    pub leaks_fixed: u64,
+}
+
+impl CheckResult {
+    /// Whether the image still has corruptions or leaked clusters that `-r all` would fix
+    pub fn needs_repair(&self) -> bool {
+        self.corruptions > 0 || self.leaks > 0
+    }
+}
+
+/// qemu-img check's exit status for an image format without consistency checks (raw)
+const CHECK_UNSUPPORTED: i32 = 63;
+
+/// Create a new disk image of the given format and size in bytes (see `parse_disk_size`)
+pub fn create_disk(path: &Path, format: &str, size: u64) -> Result<()> {
+    if let Some(formats) = supported_formats() {
+        if !formats.iter().any(|f| f == format) {
+            return Err(QvmError::Invalid(format!(
+                "qemu-img on this host does not support the '{}' format (supported: {})",
+                format,
+                formats.join(" ")
+            )));
+        }
+    }
+    let status = Command::new("qemu-img")
+        .args(["create", "-f", format])
+        .arg(path)
+        .arg(size.to_string())
+        .status()?;
+    if !status.success() {
+        return Err(QvmError::CommandFailed(format!(
+            "qemu-img failed to create disk {} (size: {})",
+            path.display(),
+            format_size(size)
+        )));
+    }
+    Ok(())
+}
+
+/// Create a qcow2 overlay at `path` whose unwritten clusters read from the qcow2 image
+/// `backing`; `size` in bytes defaults to the backing image's
+pub fn create_overlay(path: &Path, backing: &Path, size: Option<u64>) -> Result<()> {
+    let mut cmd = Command::new("qemu-img");
+    cmd.args(["create", "-f", "qcow2", "-b"]).arg(backing).args(["-F", "qcow2"]).arg(path);
+    if let Some(size) = size {
+        cmd.arg(size.to_string());
+    }
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Err(QvmError::CommandFailed(format!(
+            "qemu-img failed to create overlay {} of {}: {}",
+            path.display(),
+            backing.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `path` is a block device (a whole disk or partition rather than an image file)
+pub fn is_block_device(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.file_type().is_block_device())
+}
+
+/// Image formats this host's qemu-img supports, or None if that cannot be determined
+pub fn supported_formats() -> Option<Vec<String>> {
+    let output = Command::new("qemu-img").arg("--help").output().ok()?;
+    parse_supported_formats(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The `Supported formats:` line of `qemu-img --help`
+pub fn parse_supported_formats(help: &str) -> Option<Vec<String>> {
+    let line = help.lines().find_map(|l| l.trim().strip_prefix("Supported formats:"))?;
+    let formats: Vec<String> = line.split_whitespace().map(String::from).collect();
+    (!formats.is_empty()).then_some(formats)
+}
+
+/// Inspect a disk image with `qemu-img info --output=json`
+pub fn disk_info(path: &Path) -> Result<DiskInfo> {
+    let output = Command::new("qemu-img")
+        .args(["info", "--output=json"])
+        .arg(path)
+        .output()?;
+    if !output.status.success() {
+        return Err(QvmError::CommandFailed(format!(
+            "qemu-img info failed for {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let info: DiskInfo = serde_json::from_slice(&output.stdout)?;
+    Ok(info)
+}
+
+/// Check the consistency of a disk image of `format` with `qemu-img check`, repairing
+/// corruptions and leaks (`-r all`) if `repair` is set. None if the format has no checks.
+pub fn check_disk(path: &Path, format: &str, repair: bool) -> Result<Option<CheckResult>> {
+    let mut cmd = Command::new("qemu-img");
+    cmd.args(["check", "--output=json", "-f", format]);
+    if repair {
+        cmd.args(["-r", "all"]);
+    }
+    let output = cmd.arg(path).output()?;
+    // Exit 2 (corruptions) and 3 (leaks) still come with a complete report
+    match output.status.code() {
+        Some(0 | 2 | 3) => Ok(Some(parse_check(&output.stdout)?)),
+        Some(CHECK_UNSUPPORTED) => Ok(None),
+        _ => Err(QvmError::CommandFailed(format!(
+            "qemu-img check failed for {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))),
+    }
+}
+
+/// Parse the `--output=json` report of `qemu-img check`
+pub fn parse_check(json: &[u8]) -> Result<CheckResult> {
+    serde_json::from_slice(json)
+        .map_err(|e| QvmError::CommandFailed(format!("cannot read the qemu-img check report: {}", e)))
+}
+
+/// Copy a disk image of `format` to a new qcow2 image at `dest`, leaving out unused
+/// clusters. `force_share` reads an image a running qemu holds open (safe only while qemu
+/// is not writing to it).
+pub fn convert_to_qcow2(src: &Path, format: &str, dest: &Path, force_share: bool) -> Result<()> {
+    let mut cmd = Command::new("qemu-img");
+    cmd.args(["convert", "-f", format, "-O", "qcow2"]);
+    if force_share {
+        cmd.arg("-U");
+    }
+    let output = cmd.arg(src).arg(dest).output()?;
+    if !output.status.success() {
+        return Err(QvmError::CommandFailed(format!(
+            "qemu-img failed to copy {} to {}: {}",
+            src.display(),
+            dest.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Resize a disk image to `new_size` bytes; qemu-img refuses to shrink unless `shrink` is set
+pub fn resize_disk(path: &Path, format: &str, new_size: u64, shrink: bool) -> Result<()> {
+    let mut cmd = Command::new("qemu-img");
+    cmd.args(["resize", "-f", format]);
+    if shrink {
+        cmd.arg("--shrink");
+    }
+    let status = cmd.arg(path).arg(new_size.to_string()).status()?;
+    if !status.success() {
+        return Err(QvmError::CommandFailed(format!(
+            "qemu-img failed to resize disk {} to {}",
+            path.display(),
+            format_size(new_size)
+        )));
+    }
+    Ok(())
+}
@@ -1,36 +1,162 @@
 //! Firmware detection and management
 
-use anyhow::{anyhow, Result};
+use crate::config::schema::{Firmware, VmConfig};
+use crate::utils::paths::resolve_under_root;
+use crate::utils::system::pick_vm_qemu_bin;
+use crate::{QvmError, Result};
+use tracing::{debug, trace};
 use std::fs;
+use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
 
-/// From qemu-system-* realpath, derive/share and find firmware in Nix paths
-pub fn locate_firmware_from_qemu(qemu_bin: &Path, arch: &str) -> Result<(PathBuf, PathBuf)> {
+/// Environment variable naming an extra directory to search first for firmware
+pub const FIRMWARE_DIR_ENV: &str = "QVM_FIRMWARE_DIR";
+
+/// Known UEFI code/vars file name pairs for `arch`, most preferred first; with
+/// `secure_boot`, only secure boot builds (vars with Microsoft keys enrolled where the
+/// distro ships them)
+pub fn firmware_pairs(arch: &str, secure_boot: bool) -> Result<&'static [(&'static str, &'static str)]> {
+    Ok(match (arch, secure_boot) {
+        ("aarch64", false) => &[
+            // qemu's own build (Nix, Homebrew, source installs)
+            ("edk2-aarch64-code.fd", "edk2-arm-vars.fd"),
+            ("edk2-aarch64-code.fd", "edk2-aarch64-vars.fd"),
+            // Debian/Ubuntu (qemu-efi-aarch64)
+            ("AAVMF_CODE.fd", "AAVMF_VARS.fd"),
+            ("AAVMF_CODE.no-secboot.fd", "AAVMF_VARS.fd"),
+            // Fedora (edk2-aarch64)
+            ("QEMU_EFI-pflash.raw", "vars-template-pflash.raw"),
+            // Arch (edk2-aarch64)
+            ("QEMU_CODE.fd", "QEMU_VARS.fd"),
+        ],
+        ("aarch64", true) => &[
+            // Debian/Ubuntu
+            ("AAVMF_CODE.ms.fd", "AAVMF_VARS.ms.fd"),
+            ("AAVMF_CODE.secboot.fd", "AAVMF_VARS.ms.fd"),
+        ],
+        ("x86_64", false) => &[
+            ("OVMF_CODE.fd", "OVMF_VARS.fd"),
+            // Debian/Ubuntu
+            ("OVMF_CODE_4M.fd", "OVMF_VARS_4M.fd"),
+            // Arch
+            ("OVMF_CODE.4m.fd", "OVMF_VARS.4m.fd"),
+            ("edk2-x86_64-code.fd", "edk2-x86_64-vars.fd"),
+            ("edk2-x86_64-code.fd", "edk2-i386-vars.fd"),
+        ],
+        ("x86_64", true) => &[
+            // Fedora
+            ("OVMF_CODE.secboot.fd", "OVMF_VARS.secboot.fd"),
+            // Debian/Ubuntu
+            ("OVMF_CODE_4M.secboot.fd", "OVMF_VARS_4M.ms.fd"),
+            ("OVMF_CODE_4M.ms.fd", "OVMF_VARS_4M.ms.fd"),
+            ("OVMF_CODE.secboot.fd", "OVMF_VARS.ms.fd"),
+            // Arch
+            ("OVMF_CODE.secboot.4m.fd", "OVMF_VARS.4m.fd"),
+            // qemu's own build
+            ("edk2-x86_64-secure-code.fd", "edk2-i386-vars.fd"),
+        ],
+        _ => return Err(QvmError::UnsupportedArch(arch.to_string())),
+    })
+}
+
+/// Find a UEFI code/vars pair for `arch` (a secure boot build if `secure_boot`), searching
+/// (in order) `$QVM_FIRMWARE_DIR`, the share directory next to the qemu binary,
+/// distro/Homebrew locations and Nix paths
+pub fn locate_firmware_from_qemu(qemu_bin: &Path, arch: &str, secure_boot: bool) -> Result<(PathBuf, PathBuf)> {
+    let dirs = firmware_search_dirs(firmware_dir_override().as_deref(), qemu_bin, arch);
+    locate_firmware_in(&dirs, arch, secure_boot)
+}
+
+/// The first of `dirs` holding a UEFI code/vars pair for `arch` (a secure boot build if
+/// `secure_boot`)
+pub fn locate_firmware_in(dirs: &[PathBuf], arch: &str, secure_boot: bool) -> Result<(PathBuf, PathBuf)> {
+    let pairs = firmware_pairs(arch, secure_boot)?;
+    for d in dirs {
+        if !d.is_dir() {
+            trace!("firmware: {} does not exist", d.display());
+            continue;
+        }
+        trace!("firmware: searching {}", d.display());
+        for (code, vars) in pairs {
+            let c = d.join(code);
+            let v = d.join(vars);
+            if c.is_file() && v.is_file() {
+                debug!("firmware for {}: code {}, vars template {}", arch, c.display(), v.display());
+                return Ok((c, v));
+            }
+        }
+    }
+    let kind = if secure_boot { "secure boot code/vars pair" } else { "code/vars pair" };
+    Err(QvmError::FirmwareNotFound(format!(
+        "no {} for {} (set ${} to its directory)",
+        kind, arch, FIRMWARE_DIR_ENV
+    )))
+}
+
+/// Point a VM at this host's firmware if its stored code file no longer exists (the VM
+/// was moved between hosts, or a Nix store path was garbage-collected). Returns whether
+/// `cfg.firmware` changed; the caller saves the config.
+pub fn refresh_firmware(cfg: &mut VmConfig, root: &Path) -> Result<bool> {
+    let code = resolve_under_root(root, &cfg.firmware.code);
+    if code.is_file() {
+        return Ok(false);
+    }
+    let (code_new, vars_template) = pick_vm_qemu_bin(cfg)
+        .and_then(|bin| locate_firmware_from_qemu(&bin, &cfg.meta.arch, cfg.firmware.secure_boot))
+        .map_err(|e| {
+            let detail = match e {
+                QvmError::FirmwareNotFound(detail) => detail,
+                e => e.to_string(),
+            };
+            QvmError::FirmwareNotFound(format!("code file {} is missing and re-detection failed: {}", code.display(), detail))
+        })?;
+    cfg.firmware = Firmware {
+        code: code_new,
+        vars_template,
+        secure_boot: cfg.firmware.secure_boot,
+    };
+    Ok(true)
+}
+
+/// The firmware directory set in `$QVM_FIRMWARE_DIR`, if any
+pub fn firmware_dir_override() -> Option<PathBuf> {
+    std::env::var_os(FIRMWARE_DIR_ENV).map(PathBuf::from)
+}
+
+/// Candidate firmware directories for this platform, most specific first: `override_dir`
+/// (see `firmware_dir_override`), then the ones found from `qemu_bin` and the host
+pub fn firmware_search_dirs(override_dir: Option<&Path>, qemu_bin: &Path, arch: &str) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = override_dir.into_iter().map(Path::to_path_buf).collect();
+
+    // .../share/qemu next to .../bin/qemu-system-* (Nix store, Homebrew, /usr/local builds)
     let bin_real = qemu_bin
         .canonicalize()
         .unwrap_or_else(|_| qemu_bin.to_path_buf());
-    let bin_str = bin_real.to_string_lossy();
+    if let Some(prefix) = bin_real.parent().and_then(Path::parent) {
+        dirs.push(prefix.join("share/qemu"));
+    }
 
-    // Try to derive .../share/qemu next to .../bin/qemu-system-*
-    let derived_share = if bin_str.contains("/bin/qemu-system-") {
-        if arch == "aarch64" {
-            PathBuf::from(bin_str.replace("/bin/qemu-system-aarch64", "/share/qemu"))
-        } else {
-            PathBuf::from(bin_str.replace("/bin/qemu-system-x86_64", "/share/qemu"))
-        }
+    if cfg!(target_os = "macos") {
+        dirs.push(PathBuf::from("/opt/homebrew/share/qemu"));
+        dirs.push(PathBuf::from("/usr/local/share/qemu"));
     } else {
-        qemu_bin
-            .parent()
-            .and_then(|p| p.parent())
-            .map(|p| p.join("share/qemu"))
-            .unwrap_or_else(|| PathBuf::from("/run/current-system/sw/share/qemu"))
-    };
+        match arch {
+            "aarch64" => {
+                dirs.push(PathBuf::from("/usr/share/AAVMF"));
+                dirs.push(PathBuf::from("/usr/share/edk2/aarch64"));
+                dirs.push(PathBuf::from("/usr/share/qemu-efi-aarch64"));
+            }
+            _ => {
+                dirs.push(PathBuf::from("/usr/share/OVMF"));
+                dirs.push(PathBuf::from("/usr/share/edk2/ovmf"));
+                dirs.push(PathBuf::from("/usr/share/edk2/x64"));
+            }
+        }
+        dirs.push(PathBuf::from("/usr/share/qemu"));
+    }
 
-    let mut dirs = vec![
-        derived_share,
-        PathBuf::from("/run/current-system/sw/share/qemu"),
-        PathBuf::from("/nix/var/nix/profiles/system/sw/share/qemu"),
-    ];
+    dirs.push(PathBuf::from("/run/current-system/sw/share/qemu"));
+    dirs.push(PathBuf::from("/nix/var/nix/profiles/system/sw/share/qemu"));
 
     // Also scan /nix/store/*-qemu-*/share/qemu
     if let Ok(iter) = fs::read_dir("/nix/store") {
@@ -47,32 +173,21 @@ pub fn locate_firmware_from_qemu(qemu_bin: &Path, arch: &str) -> Result<(PathBuf
         }
     }
 
-    let pairs: &[(&str, &str)] = match arch {
-        "aarch64" => &[
-            ("edk2-aarch64-code.fd", "edk2-arm-vars.fd"),
-            ("edk2-aarch64-code.fd", "edk2-aarch64-vars.fd"),
-        ],
-        "x86_64" => &[
-            ("OVMF_CODE.fd", "OVMF_VARS.fd"),
-            ("edk2-x86_64-code.fd", "edk2-x86_64-vars.fd"),
-            ("edk2-x86_64-code.fd", "edk2-i386-vars.fd"),
-        ],
-        _ => return Err(anyhow!("Unsupported arch: {}", arch)),
-    };
+    dirs
+}
 
-    for d in dirs {
-        if !d.is_dir() {
-            continue;
-        }
-        for (code, vars) in pairs {
-            let c = d.join(code);
-            let v = d.join(vars);
-            if c.is_file() && v.is_file() {
-                return Ok((c, v));
-            }
-        }
+/// Copy the firmware vars template to a VM's writable per-VM NVRAM file
+pub fn seed_efi_vars(template: &Path, dest: &Path) -> Result<()> {
+    if !template.is_file() {
+        return Err(QvmError::FirmwareNotFound(format!(
+            "vars template {} is missing",
+            template.display()
+        )));
     }
-    Err(anyhow!("UEFI firmware not found in Nix paths for {}", arch))
+    fs::copy(template, dest)?;
+    // Templates from the Nix store are read-only; the per-VM copy must be writable
+    fs::set_permissions(dest, fs::Permissions::from_mode(0o644))?;
+    Ok(())
 }
 
 /// Get default firmware paths for architecture
@@ -0,0 +1,63 @@
+//! Hook scripts run after a VM is created (`--on-create`), started or stopped (`hooks`)
+
+use crate::config::schema::VmConfig;
+use crate::utils::paths::{hooks_log_path, resolve_under_root};
+use crate::utils::system::now_utc;
+use crate::vm::logs::last_lines;
+use crate::{QvmError, Result};
+use tracing::debug;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Run `script` (relative to the VM root unless absolute) for the `event` (`create`,
+/// `start` or `stop`) of the VM of `cfg`, in the VM root with `QVM_NAME`, `QVM_ROOT`,
+/// `QVM_DISK` (the boot disk) and `QVM_HOOK` (the event) set. Its output is appended to
+/// hooks.log; a non-zero exit is an error carrying the end of that output.
+pub fn run_hook(event: &str, script: &Path, cfg: &VmConfig) -> Result<()> {
+    let root = &cfg.paths.root;
+    let script = resolve_under_root(root, script);
+    let disk = cfg
+        .paths
+        .disks
+        .first()
+        .map(|d| resolve_under_root(root, &d.path))
+        .unwrap_or_default();
+
+    debug!("running {} hook {}", event, script.display());
+    let output = Command::new(&script)
+        .current_dir(root)
+        .env("QVM_NAME", &cfg.meta.name)
+        .env("QVM_ROOT", root)
+        .env("QVM_DISK", disk)
+        .env("QVM_HOOK", event)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| {
+            QvmError::CommandFailed(format!("cannot run {} hook {}: {}", event, script.display(), e))
+        })?;
+
+    let mut log = OpenOptions::new().create(true).append(true).open(hooks_log_path(root))?;
+    writeln!(log, "== {} {} hook {} ({})", now_utc(), event, script.display(), output.status)?;
+    log.write_all(&output.stdout)?;
+    log.write_all(&output.stderr)?;
+
+    if !output.status.success() {
+        let text = String::from_utf8_lossy(&[output.stdout, output.stderr].concat()).into_owned();
+        let tail = last_lines(&text, 20).trim_end();
+        let mut msg = format!(
+            "{} hook {} of VM '{}' failed ({})",
+            event,
+            script.display(),
+            cfg.meta.name,
+            output.status
+        );
+        if !tail.is_empty() {
+            msg.push_str(":\n");
+            msg.push_str(tail);
+        }
+        return Err(QvmError::CommandFailed(msg));
+    }
+    Ok(())
+}
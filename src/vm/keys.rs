@@ -0,0 +1,64 @@
+//! Key combos for `qvm sendkey`, as QMP `send-key` qcodes
+
+use crate::{QvmError, Result};
+use serde_json::{json, Value};
+
+/// QMP qcodes accepted as written (letters and digits are handled separately)
+const QCODES: &[&str] = &[
+    "shift", "shift_r", "alt", "alt_r", "ctrl", "ctrl_r", "meta_l", "meta_r", "menu",
+    "esc", "tab", "ret", "spc", "backspace", "delete", "insert", "home", "end", "pgup", "pgdn",
+    "up", "down", "left", "right", "caps_lock", "num_lock", "scroll_lock", "print", "sysrq",
+    "pause", "minus", "equal", "bracket_left", "bracket_right", "semicolon", "apostrophe",
+    "grave_accent", "backslash", "comma", "dot", "slash", "less", "f1", "f2", "f3", "f4", "f5",
+    "f6", "f7", "f8", "f9", "f10", "f11", "f12", "kp_0", "kp_1", "kp_2", "kp_3", "kp_4", "kp_5",
+    "kp_6", "kp_7", "kp_8", "kp_9", "kp_add", "kp_subtract", "kp_multiply", "kp_divide",
+    "kp_decimal", "kp_enter",
+];
+
+/// Friendlier names for qcodes
+const ALIASES: &[(&str, &str)] = &[
+    ("enter", "ret"),
+    ("return", "ret"),
+    ("space", "spc"),
+    ("escape", "esc"),
+    ("del", "delete"),
+    ("ins", "insert"),
+    ("bksp", "backspace"),
+    ("pageup", "pgup"),
+    ("pagedown", "pgdn"),
+    ("control", "ctrl"),
+    ("altgr", "alt_r"),
+    ("super", "meta_l"),
+    ("win", "meta_l"),
+    ("cmd", "meta_l"),
+    ("capslock", "caps_lock"),
+    ("printscreen", "print"),
+];
+
+/// Parse a combo like `ctrl-alt-delete` (keys joined by `-`, pressed together, names as in
+/// qemu's HMP `sendkey`) into qcodes. Case does not matter; `minus` is the `-` key.
+pub fn parse_key_combo(spec: &str) -> Result<Vec<String>> {
+    let mut keys = Vec::new();
+    for name in spec.split('-') {
+        let name = name.to_ascii_lowercase();
+        let qcode = match ALIASES.iter().find(|(alias, _)| *alias == name) {
+            Some((_, qcode)) => qcode.to_string(),
+            None => name,
+        };
+        let single = qcode.len() == 1 && qcode.chars().all(|c| c.is_ascii_alphanumeric());
+        if !single && !QCODES.contains(&qcode.as_str()) {
+            return Err(QvmError::Invalid(format!(
+                "Unknown key '{}' in '{}' (e.g. a, 1, ret, spc, esc, tab, f1, ctrl, alt, shift, delete, up)",
+                qcode, spec
+            )));
+        }
+        keys.push(qcode);
+    }
+    Ok(keys)
+}
+
+/// Arguments of a QMP `send-key` command pressing `keys` together for `hold_ms`
+pub fn send_key_arguments(keys: &[String], hold_ms: u32) -> Value {
+    let keys: Vec<Value> = keys.iter().map(|k| json!({ "type": "qcode", "data": k })).collect();
+    json!({ "keys": keys, "hold-time": hold_ms })
+}
@@ -0,0 +1,412 @@
+//! Building the qemu-system-* command line for a VM
+
+use crate::config::schema::{Audio, Boot, Disk, Display, Gpu, Memory, VmConfig};
+use crate::config::validate::{resolve_cpu_model, validate_boot};
+use crate::utils::paths::{console_path, monitor_path, pid_path, qmp_path, resolve_under_root};
+use crate::vm::network::network_args;
+use crate::vm::share::share_args;
+use crate::vm::tpm::tpm_args;
+use crate::vm::usb::usb_args;
+use crate::{QvmError, Result};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Prefix marking a display password as a reference to an environment variable
+pub const PASSWORD_ENV_PREFIX: &str = "env:";
+
+/// Per-boot overrides for `start_vm`
+#[derive(Debug, Clone, Default)]
+pub struct StartOptions {
+    /// Boot from this ISO instead of the persisted `paths.iso` (relative paths resolve under root)
+    pub iso: Option<PathBuf>,
+    /// Use this display mode instead of the persisted `display.mode`
+    pub display: Option<String>,
+    /// Attach the guest serial console to this terminal instead of opening a display
+    pub serial_console: bool,
+    /// Detach from the terminal once qemu is running
+    pub daemon: bool,
+    /// After a daemon start, wait up to this long for the guest's SSH banner on the forwarded port
+    pub wait_for_ssh: Option<Duration>,
+    /// Start even when the host does not have the VM's memory available
+    pub force: bool,
+    /// Attach shares with virtiofs rather than 9p (`start_vm` sets this when virtiofsd is found)
+    pub virtiofs: bool,
+}
+
+/// Build the full qemu-system-* argument vector for a VM (everything after the binary).
+/// Pure: it only reads `cfg` and `opts`, so it can be tested and printed as a dry run.
+pub fn build_qemu_args(cfg: &VmConfig, opts: &StartOptions) -> Result<Vec<String>> {
+    let root = cfg.paths.root.as_path();
+    let hw = &cfg.hardware;
+    let efi_vars = resolve_under_root(root, &cfg.paths.efi_vars);
+
+    let mut args: Vec<String> = vec![
+        "-name".into(),
+        cfg.meta.name.clone(),
+        "-uuid".into(),
+        cfg.meta.uuid.clone(),
+        "-qmp".into(),
+        format!("unix:{},server=on,wait=off", qmp_path(root).display()),
+        "-monitor".into(),
+        format!("unix:{},server=on,wait=off", monitor_path(root).display()),
+        "-pidfile".into(),
+        pid_path(root).display().to_string(),
+        "-machine".into(),
+        machine_arg(cfg),
+        "-accel".into(),
+        hw.accel.clone(),
+        "-cpu".into(),
+        // "host" (the default) has no meaning without a hypervisor
+        resolve_cpu_model(&cfg.meta.arch, &hw.cpu_model, &hw.accel),
+        "-smp".into(),
+        format!(
+            "cpus={},sockets={},cores={},threads={}",
+            cfg.vcpu_total(),
+            hw.sockets,
+            hw.cores,
+            hw.threads
+        ),
+        "-m".into(),
+        hw.mem_mb.to_string(),
+        "-rtc".into(),
+        format!("base={}", hw.rtc),
+        "-drive".into(),
+        format!(
+            "if=pflash,format=raw,unit=0,readonly=on,file={}",
+            resolve_under_root(root, &cfg.firmware.code).display()
+        ),
+        "-drive".into(),
+        format!("if=pflash,format=raw,unit=1,file={}", efi_vars.display()),
+    ];
+    let virtiofs = opts.virtiofs && !cfg.shares.is_empty();
+    args.extend(memory_args(&cfg.memory, hw.mem_mb, virtiofs));
+    if secure_boot_smm(cfg) {
+        // Only code running in SMM may write the varstore, so the guest can't bypass secure boot
+        args.extend(["-global".into(), "driver=cfi.pflash01,property=secure,value=on".into()]);
+    }
+    args.extend(boot_args(&cfg.boot, root)?);
+
+    // An attached install medium boots first, ahead of the boot disk, unless boot.order is set
+    let iso = opts.iso.as_ref().or(cfg.paths.iso.as_ref());
+    for (i, disk) in cfg.paths.disks.iter().enumerate() {
+        let bootindex = if i == 0 { cfg.boot.bootindex('c', iso.is_some()) } else { None };
+        args.extend(disk_args(disk, i, root, bootindex)?);
+    }
+
+    // CD-ROMs hang off a virtio-scsi controller, which both q35 and virt support
+    let mut cdroms = Vec::new();
+    if let Some(iso) = iso {
+        cdroms.push(("cd0", resolve_under_root(root, iso), cfg.boot.bootindex('d', true)));
+    }
+    if let Some(ci) = &cfg.cloud_init {
+        cdroms.push(("cidata", resolve_under_root(root, &ci.seed_iso), None));
+    }
+    if !cdroms.is_empty() {
+        args.extend(["-device".into(), "virtio-scsi-pci,id=scsi0".into()]);
+    }
+    for (id, path, bootindex) in cdroms {
+        let mut device = format!("scsi-cd,drive={},bus=scsi0.0", id);
+        if let Some(idx) = bootindex {
+            device.push_str(&format!(",bootindex={}", idx));
+        }
+        args.extend([
+            "-drive".into(),
+            format!(
+                "file={},if=none,id={},media=cdrom,readonly=on,format=raw",
+                path.display(),
+                id
+            ),
+            "-device".into(),
+            device,
+        ]);
+    }
+    args.extend(network_args(&cfg.network, &cfg.boot)?);
+    args.extend(share_args(&cfg.shares, root, opts.virtiofs));
+    if cfg.tpm.enabled {
+        args.extend(tpm_args(&cfg.tpm, &cfg.meta.arch, root));
+    }
+    args.extend(usb_args(&cfg.usb)?);
+    // A USB sound card plugs into the controller the USB devices above brought, if any
+    args.extend(audio_args(&cfg.audio, !cfg.usb.is_empty())?);
+    if opts.serial_console {
+        // No display at all: the serial port and the HMP monitor are multiplexed on stdio
+        args.extend([
+            "-display".into(),
+            "none".into(),
+            "-serial".into(),
+            "mon:stdio".into(),
+        ]);
+    } else {
+        // The guest serial port stays reachable through `qvm console`
+        args.extend([
+            "-chardev".into(),
+            format!(
+                "socket,id=serial0,path={},server=on,wait=off",
+                console_path(root).display()
+            ),
+            "-serial".into(),
+            "chardev:serial0".into(),
+        ]);
+        let mut display = cfg.display.clone();
+        if let Some(mode) = &opts.display {
+            display.mode = mode.clone();
+        }
+        args.extend(display_args(&display, root)?);
+    }
+    // Escape hatch for options qvm has no setting for; last, so they can override ours
+    args.extend(cfg.extra_args.iter().cloned());
+    Ok(args)
+}
+
+/// The guest RAM backend. Hugepages (a file on hugetlbfs) and shared RAM (which virtiofs
+/// needs) are a memory backend object used as the NUMA node's memory; plain preallocated
+/// RAM only needs `-mem-prealloc`.
+pub fn memory_args(memory: &Memory, mem_mb: u32, virtiofs: bool) -> Vec<String> {
+    let on_off = |b: bool| if b { "on" } else { "off" };
+    let share = memory.share || virtiofs;
+    let mut backend = match &memory.hugepages {
+        Some(path) => format!(
+            "memory-backend-file,id=mem,size={}M,mem-path={},share={}",
+            mem_mb,
+            path.display(),
+            on_off(share)
+        ),
+        None if share => format!("memory-backend-memfd,id=mem,size={}M,share=on", mem_mb),
+        None if memory.prealloc => return vec!["-mem-prealloc".into()],
+        None => return Vec::new(),
+    };
+    if memory.prealloc {
+        backend.push_str(",prealloc=on");
+    }
+    vec!["-object".into(), backend, "-numa".into(), "node,memdev=mem".into()]
+}
+
+/// x86 secure boot firmware only runs with System Management Mode enabled
+fn secure_boot_smm(cfg: &VmConfig) -> bool {
+    cfg.firmware.secure_boot && cfg.meta.arch == "x86_64"
+}
+
+/// The `-machine` value: the configured machine, plus SMM for x86 secure boot
+fn machine_arg(cfg: &VmConfig) -> String {
+    if secure_boot_smm(cfg) {
+        format!("{},smm=on", cfg.hardware.machine)
+    } else {
+        cfg.hardware.machine.clone()
+    }
+}
+
+/// The display protocol and password spec `start_vm` sets over QMP once qemu is up,
+/// if the display this boot uses is password protected
+pub fn display_password<'a>(cfg: &'a VmConfig, opts: &StartOptions) -> Option<(&'static str, &'a str)> {
+    if opts.serial_console {
+        return None;
+    }
+    match opts.display.as_deref().unwrap_or(&cfg.display.mode) {
+        "vnc" => cfg.display.vnc.password.as_deref().map(|p| ("vnc", p)),
+        "spice" => cfg.display.spice.password.as_deref().map(|p| ("spice", p)),
+        _ => None,
+    }
+}
+
+/// Turn a stored password spec into the secret itself, reading `env:NAME` from the environment
+pub fn resolve_password(spec: &str) -> Result<String> {
+    match spec.strip_prefix(PASSWORD_ENV_PREFIX) {
+        Some(var) => std::env::var(var).map_err(|_| {
+            QvmError::Invalid(format!(
+                "display password refers to ${}, which is not set",
+                var
+            ))
+        }),
+        None => Ok(spec.to_string()),
+    }
+}
+
+/// `-kernel`/`-initrd`/`-append` for direct kernel boot; the boot order is given as the
+/// devices' bootindex instead (see `Boot::bootindex`)
+fn boot_args(boot: &Boot, root: &Path) -> Result<Vec<String>> {
+    validate_boot(boot)?;
+    let mut args = Vec::new();
+    if let Some(kernel) = &boot.kernel {
+        args.extend(["-kernel".into(), resolve_under_root(root, kernel).display().to_string()]);
+    }
+    if let Some(initrd) = &boot.initrd {
+        args.extend(["-initrd".into(), resolve_under_root(root, initrd).display().to_string()]);
+    }
+    if let Some(cmdline) = &boot.cmdline {
+        args.extend(["-append".into(), cmdline.clone()]);
+    }
+    Ok(args)
+}
+
+/// Host audio backends `-audiodev` accepts here; `none` means no sound card at all
+const AUDIO_BACKENDS: &[&str] = &["none", "coreaudio", "pa", "pipewire", "alsa", "sdl"];
+
+/// Guest sound card models
+const AUDIO_MODELS: &[&str] = &["intel-hda", "usb-audio"];
+
+/// Build the -audiodev backend and the guest sound card wired to it; `usb-audio` adds an
+/// xHCI controller unless `has_usb_controller` (`usb_args` already added one)
+fn audio_args(audio: &Audio, has_usb_controller: bool) -> Result<Vec<String>> {
+    if !AUDIO_BACKENDS.contains(&audio.backend.as_str()) {
+        return Err(QvmError::Invalid(format!(
+            "Unsupported audio backend '{}' (expected one of: {})",
+            audio.backend,
+            AUDIO_BACKENDS.join(", ")
+        )));
+    }
+    if audio.backend == "none" {
+        return Ok(Vec::new());
+    }
+
+    let mut args = vec!["-audiodev".into(), format!("{},id=audio0", audio.backend)];
+    match audio.model.as_str() {
+        "intel-hda" => args.extend([
+            "-device".into(),
+            "intel-hda".into(),
+            "-device".into(),
+            "hda-duplex,audiodev=audio0".into(),
+        ]),
+        "usb-audio" => {
+            if !has_usb_controller {
+                args.extend(["-device".into(), "qemu-xhci,id=usb".into()]);
+            }
+            args.extend(["-device".into(), "usb-audio,audiodev=audio0,bus=usb.0".into()]);
+        }
+        other => {
+            return Err(QvmError::Invalid(format!(
+                "Unsupported audio model '{}' (expected one of: {})",
+                other,
+                AUDIO_MODELS.join(", ")
+            )))
+        }
+    }
+    Ok(args)
+}
+
+/// Drive id of the disk at `index`, which QMP block commands take as `device`
+pub fn disk_id(index: usize) -> String {
+    format!("disk{}", index)
+}
+
+/// Build the -drive/-device pair attaching one disk
+fn disk_args(disk: &Disk, index: usize, root: &Path, bootindex: Option<u32>) -> Result<Vec<String>> {
+    let id = disk_id(index);
+    let mut device = match disk.interface.as_str() {
+        "virtio" => format!("virtio-blk-pci,drive={}", id),
+        "nvme" => format!("nvme,drive={},serial={}", id, id),
+        "ide" => format!("ide-hd,drive={}", id),
+        other => {
+            return Err(QvmError::Invalid(format!(
+                "Unsupported disk interface '{}'",
+                other
+            )))
+        }
+    };
+    if let Some(idx) = bootindex {
+        device.push_str(&format!(",bootindex={}", idx));
+    }
+    let mut drive = format!(
+        "file={},if=none,id={},format={}",
+        resolve_under_root(root, &disk.path).display(),
+        id,
+        disk.format
+    );
+    if let Some(cache) = &disk.cache {
+        drive.push_str(&format!(",cache={}", cache));
+    }
+    if let Some(aio) = &disk.aio {
+        drive.push_str(&format!(",aio={}", aio));
+    }
+    if disk.readonly {
+        drive.push_str(",readonly=on");
+    }
+    Ok(vec!["-drive".into(), drive, "-device".into(), device])
+}
+
+/// The `-device` value of the GPU, e.g. `virtio-gpu-pci,max_outputs=2,xres=1920,yres=1080`
+pub fn gpu_device(gpu: &Gpu) -> Result<String> {
+    let mut device = gpu.device();
+    if let Some(outputs) = gpu.max_outputs {
+        device.push_str(&format!(",max_outputs={}", outputs));
+    }
+    if let Some((width, height)) = gpu.resolution_size()? {
+        device.push_str(&format!(",xres={},yres={}", width, height));
+    }
+    if let Some(mb) = gpu.vram_mb {
+        device.push_str(&format!(",max_hostmem={}M", mb));
+    }
+    Ok(device)
+}
+
+/// Build the display-related qemu arguments for the configured display mode
+fn display_args(display: &Display, root: &Path) -> Result<Vec<String>> {
+    let gpu = ["-device".to_string(), gpu_device(&display.gpu)?];
+    // A 3D GPU renders through an offscreen OpenGL context that VNC/SPICE then serve
+    let none = if display.gpu.gl { "egl-headless" } else { "none" };
+
+    let args = match display.mode.as_str() {
+        "cocoa" => {
+            let mut a = gpu.to_vec();
+            a.extend(["-display".into(), "cocoa".into()]);
+            a
+        }
+        "vnc" => {
+            let vnc = &display.vnc;
+            let target = if vnc.use_unix {
+                format!("unix:{}", resolve_under_root(root, &vnc.sock).display())
+            } else {
+                format!("{}:{}", vnc.host, vnc.display)
+            };
+            // The password itself is sent over QMP so it never appears in the process list
+            let mut spec = if vnc.password.is_some() {
+                format!("{},password=on", target)
+            } else {
+                target
+            };
+            let mut a = gpu.to_vec();
+            if let Some(tls) = &vnc.tls {
+                a.extend([
+                    "-object".into(),
+                    format!(
+                        "tls-creds-x509,id=vnc-tls,dir={},endpoint=server,verify-peer={}",
+                        resolve_under_root(root, &tls.cert_dir).display(),
+                        if tls.verify_peer { "on" } else { "off" }
+                    ),
+                ]);
+                spec.push_str(",tls-creds=vnc-tls");
+            }
+            a.extend(["-display".into(), none.into(), "-vnc".into(), spec]);
+            a
+        }
+        "spice" => {
+            let spice = &display.spice;
+            // SPICE reads its certificates itself rather than through a tls-creds object
+            let mut spec = match &spice.tls {
+                _ if spice.use_unix => {
+                    format!("unix=on,addr={}", resolve_under_root(root, &spice.sock).display())
+                }
+                Some(tls) => format!(
+                    "tls-port={},addr={},x509-dir={}",
+                    spice.port,
+                    spice.addr,
+                    resolve_under_root(root, &tls.cert_dir).display()
+                ),
+                None => format!("port={},addr={}", spice.port, spice.addr),
+            };
+            if spice.disable_ticketing && spice.password.is_none() {
+                spec.push_str(",disable-ticketing=on");
+            }
+            let mut a = gpu.to_vec();
+            a.extend(["-display".into(), none.into(), "-spice".into(), spec]);
+            a
+        }
+        "headless" => vec!["-display".into(), "none".into()],
+        other => {
+            return Err(QvmError::Invalid(format!(
+                "Unsupported display mode '{}'",
+                other
+            )))
+        }
+    };
+    Ok(args)
+}
@@ -0,0 +1,58 @@
+//! Per-VM advisory locking for mutating operations
+
+use crate::utils::paths::{lock_path, qvm_home};
+use crate::{QvmError, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// An exclusive `flock` on `<root>/vm.lock`, released when dropped (or when the process exits)
+#[derive(Debug)]
+pub struct VmLock {
+    _file: File,
+}
+
+impl VmLock {
+    /// Take the VM's lock without blocking; fails if another qvm invocation holds it
+    pub fn acquire(name: &str, root: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(lock_path(root))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            return Err(if err.kind() == io::ErrorKind::WouldBlock {
+                QvmError::VmBusy(name.to_string())
+            } else {
+                err.into()
+            });
+        }
+        Ok(Self { _file: file })
+    }
+}
+
+/// An exclusive `flock` on `$QVM_HOME/ports.lock`, held while a new VM picks its ports
+/// and is saved, so concurrent creates never pick the same ones
+#[derive(Debug)]
+pub struct PortLock {
+    _file: File,
+}
+
+impl PortLock {
+    /// Take the lock, waiting for another qvm invocation holding it
+    pub fn acquire() -> Result<Self> {
+        let home = qvm_home()?;
+        fs::create_dir_all(&home)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(home.join("ports.lock"))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Self { _file: file })
+    }
+}
@@ -0,0 +1,86 @@
+//! Reading and following a VM's vm.log
+
+use crate::Result;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How much of a foreground qemu's stderr `tee_stderr` keeps
+const CAPTURE_LIMIT: usize = 64 * 1024;
+
+/// Return the last `n` lines of `text` (all of it when it has fewer)
+pub fn last_lines(text: &str, n: usize) -> &str {
+    if n == 0 {
+        return "";
+    }
+    // Ignore the trailing newline so it does not count as an empty last line
+    let body = text.strip_suffix('\n').unwrap_or(text);
+    match body.rmatch_indices('\n').nth(n - 1) {
+        Some((i, _)) => &text[i + 1..],
+        None => text,
+    }
+}
+
+/// Print the log (or its last `lines` lines) and return the byte offset reached
+pub fn print_log(path: &Path, lines: Option<usize>) -> Result<u64> {
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    let text = String::from_utf8_lossy(&bytes);
+    let shown = match lines {
+        Some(n) => last_lines(&text, n),
+        None => &text,
+    };
+    let mut out = io::stdout().lock();
+    out.write_all(shown.as_bytes())?;
+    out.flush()?;
+    Ok(bytes.len() as u64)
+}
+
+/// Copy a child's stderr to ours as it arrives, keeping the last bytes of it to explain
+/// a failure afterwards; the thread returns them once the child closes its end
+pub fn tee_stderr(mut from: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut kept = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = match from.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            let _ = io::stderr().write_all(&buf[..n]);
+            kept.extend_from_slice(&buf[..n]);
+            if kept.len() > CAPTURE_LIMIT {
+                kept.drain(..kept.len() - CAPTURE_LIMIT);
+            }
+        }
+        kept
+    })
+}
+
+/// Poll the log for appended bytes and print them until interrupted.
+/// A file that shrinks (truncated by the next `qvm start`) is re-read from the beginning.
+pub fn follow_log(path: &Path, mut offset: u64) -> Result<()> {
+    let mut buf = Vec::new();
+    loop {
+        if let Ok(mut f) = File::open(path) {
+            let len = f.metadata()?.len();
+            if len < offset {
+                offset = 0;
+            }
+            if len > offset {
+                f.seek(SeekFrom::Start(offset))?;
+                buf.clear();
+                f.take(len - offset).read_to_end(&mut buf)?;
+                offset += buf.len() as u64;
+                let mut out = io::stdout().lock();
+                out.write_all(&buf)?;
+                out.flush()?;
+            }
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+}
@@ -1,74 +1,2422 @@
 //! VM lifecycle management
 
-use crate::utils::paths::{find_vm_dir, resolve_under_root};
-use crate::utils::system::is_vm_running;
-use crate::vm::config::load_conf;
-use crate::Result;
-use anyhow::anyhow;
+use crate::config::libvirt;
+use crate::config::validate::{
+    check_host_capacity, host_incompatibilities, qemu_feature_problems, validate_disk, validate_display_tls, validate_gpu,
+    validate_choices, validate_cpu_model, validate_hardware, validate_memory, validate_network, ValidationError,
+};
+use crate::config::schema::{
+    CloudInit, Disk, Display, DisplayTls, Forward, NetworkInterface, Share, UsbDevice, VmConfig,
+};
+use crate::config::set::set_key;
+use crate::utils::confirm::{Confirmer, StdinConfirmer};
+use crate::utils::paths::{
+    conf_path, console_path, expand_path, find_vm_dir, glob_match, is_glob, log_path, monitor_path, pid_path, qmp_path, qvm_home, resolve_under_root,
+    validate_vm_name, vm_names,
+};
+use crate::utils::net::wait_for_port;
+use crate::utils::size::{disk_usage, format_size, parse_disk_size};
+use crate::utils::system::{
+    host_available_memory_mb, host_cpu_count, is_vm_running, normalize_mac, pick_qemu_bin, pick_vm_qemu_bin, process_alive, process_usage, qemu_version, random_mac, read_pid_file, running_vm_pid,
+    shell_quote, signal_process, wait_while,
+};
+use crate::vm::archive;
+use crate::vm::backup::{self, BackupDisk, BackupManifest, BackupRecord};
+use crate::vm::cloud_init::{build_seed_iso, SeedSources};
+use crate::vm::console;
+use crate::vm::config::{all_vms, load_conf, load_conf_from_dir, load_conf_unchecked, save_conf, VmEntry};
+use crate::vm::disk::{check_disk, convert_to_qcow2, create_disk, disk_info, is_block_device, resize_disk, CheckResult};
+use crate::vm::hooks::run_hook;
+use crate::vm::firmware::{locate_firmware_from_qemu, refresh_firmware, seed_efi_vars};
+use crate::vm::launch::{build_qemu_args, disk_id, display_password, resolve_password, StartOptions, PASSWORD_ENV_PREFIX};
+use crate::vm::keys::{parse_key_combo, send_key_arguments};
+use crate::vm::creator::rehome;
+use crate::vm::lock::{PortLock, VmLock};
+use crate::vm::logs::{follow_log, last_lines, print_log, tee_stderr};
+use crate::vm::network::{check_vmnet_access, netdev_id};
+use crate::vm::qmp::QmpClient;
+use crate::vm::screenshot;
+use crate::vm::share::{find_virtiofsd, start_virtiofsd, stop_virtiofsd, validate_tag};
+use crate::vm::snapshot::{self, Snapshot};
+use crate::vm::ssh::{exec_ssh, ssh_args, ssh_host_port, SSH_GUEST_PORT};
+use crate::vm::tpm::{start_swtpm, stop_swtpm};
+use crate::vm::usb::parse_usb_device;
+use crate::{QvmError, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+use std::fmt;
 use std::fs;
-use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How long a freshly spawned qemu gets to open its QMP socket
+const QMP_STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A qemu that exits this soon after being spawned failed to start the VM at all
+const STARTUP_GRACE: Duration = Duration::from_secs(1);
+
+/// How long qemu gets to exit on SIGTERM once the guest was not (or could not be) shut down
+const TERM_GRACE: Duration = Duration::from_secs(5);
 
 /// VM Manager for lifecycle operations
-pub struct VmManager;
+pub struct VmManager {
+    /// Asked before VMs are deleted without `force`
+    confirmer: Box<dyn Confirmer + Send + Sync>,
+}
+
+/// Parameters for attaching a disk to an existing VM
+pub struct AddDiskParams {
+    pub size: Option<String>,
+    pub format: String,
+    pub interface: String,
+    pub file: Option<PathBuf>,
+    pub existing: Option<PathBuf>,
+    /// Host cache mode (`-drive cache=`); None keeps qemu's default
+    pub cache: Option<String>,
+    /// Async I/O engine (`-drive aio=`); None keeps qemu's default
+    pub aio: Option<String>,
+    pub readonly: bool,
+}
+
+/// Display settings for `set_display`; options left unset keep their value in vm.json
+#[derive(Debug, Clone, Default)]
+pub struct SetDisplayParams {
+    pub mode: String,
+    pub vnc_unix: bool,
+    pub vnc_host: Option<String>,
+    pub vnc_display: Option<u8>,
+    pub vnc_sock: Option<PathBuf>,
+    pub spice_unix: bool,
+    pub spice_addr: Option<String>,
+    pub spice_port: Option<u16>,
+    pub spice_sock: Option<PathBuf>,
+    pub spice_disable_ticketing: Option<bool>,
+    /// Serve the VNC or SPICE display over TLS with the certificates in this directory
+    pub tls_cert_dir: Option<PathBuf>,
+    pub tls_verify_peer: bool,
+    /// Turn TLS off for the display
+    pub no_tls: bool,
+}
+
+/// What `start_vm` launched: the qemu PID and, for a foreground VM, how qemu exited
+#[derive(Debug, Clone, Copy)]
+pub struct Started {
+    pub pid: u32,
+    pub exit: Option<ExitStatus>,
+    /// Forwarded SSH port, when the start waited for the guest's sshd
+    pub ssh_port: Option<u16>,
+}
+
+/// A qemu process spawned by `VmManager::launch`
+struct Launched {
+    vm_dir: PathBuf,
+    child: Child,
+    /// The foreground qemu's stderr, as `tee_stderr` keeps it
+    stderr: Option<JoinHandle<Vec<u8>>>,
+    spawned_at: Instant,
+    ssh_port: Option<u16>,
+}
+
+/// A VM started by `VmManager::spawn_vm`, owning its qemu child process
+#[derive(Debug)]
+pub struct VmHandle {
+    name: String,
+    vm_dir: PathBuf,
+    child: Child,
+}
+
+impl VmHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// PID of the qemu process
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Whether qemu is still running (reaps it once it has exited)
+    pub fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Run QMP `command` with optional `arguments` and return its result
+    pub fn qmp(&mut self, command: &str, arguments: Option<Value>) -> Result<Value> {
+        if !self.is_alive() {
+            return Err(QvmError::VmNotRunning(self.name.clone()));
+        }
+        QmpClient::connect(&qmp_path(&self.vm_dir))?.execute(command, arguments)
+    }
+
+    /// Stop the VM as `VmManager::stop_vm` does: ACPI power-off, then SIGTERM once
+    /// `timeout` has passed
+    pub fn stop(mut self, timeout: Duration) -> Result<()> {
+        VmManager::new().stop_child(&self.name, timeout, false, Some(&mut self.child))?;
+        self.child.wait()?;
+        Ok(())
+    }
+}
+
+/// One row of `qvm list`
+#[derive(Serialize, Debug, Clone)]
+pub struct VmSummary {
+    pub name: String,
+    pub running: bool,
+    pub arch: String,
+    pub cpus: u32,
+    pub mem_mb: u32,
+    pub display: String,
+    /// TCP port of a VNC or SPICE display
+    pub display_port: Option<u16>,
+    /// Host port forwarded to the guest's SSH port
+    pub ssh_port: Option<u16>,
+    pub description: String,
+    pub tags: Vec<String>,
+    pub autostart: bool,
+}
+
+/// Outcome of a batch `qvm delete`
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DeleteSummary {
+    pub deleted: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+/// A disk image rewritten by `qvm compact`, with its allocated size before and after
+#[derive(Serialize, Debug, Clone)]
+pub struct CompactedDisk {
+    pub path: PathBuf,
+    pub before: u64,
+    pub after: u64,
+}
+
+/// A disk checked by `qvm check`; `result` is None for formats without consistency checks
+#[derive(Serialize, Debug, Clone)]
+pub struct CheckedDisk {
+    pub index: usize,
+    pub path: PathBuf,
+    pub format: String,
+    pub result: Option<CheckResult>,
+}
+
+/// Which path of a VM `vm_path` returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmPath {
+    Config,
+    Root,
+    Disk(usize),
+}
+
+/// Outcome of `qvm start --all`
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct StartAllSummary {
+    pub started: Vec<String>,
+    pub already_running: Vec<String>,
+    pub failed: Vec<StartFailure>,
+}
+
+/// An autostart VM that `qvm start --all` could not start
+#[derive(Serialize, Debug, Clone)]
+pub struct StartFailure {
+    pub name: String,
+    pub error: String,
+}
+
+/// Live state of a VM as reported by `qvm status`
+#[derive(Serialize, Debug, Clone)]
+pub struct VmStatus {
+    pub name: String,
+    pub running: bool,
+    /// "stopped", or the guest run state reported by QMP (e.g. "running", "paused")
+    pub state: String,
+    pub vcpus: u32,
+    pub mem_mb: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime_secs: Option<u64>,
+    pub disks: Vec<DiskUsage>,
+    pub display: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_port: Option<u16>,
+    pub description: String,
+    pub tags: Vec<String>,
+}
+
+/// One row of `qvm ps`: a running VM and what its qemu process uses
+#[derive(Serialize, Debug, Clone)]
+pub struct VmProcess {
+    pub name: String,
+    pub pid: i32,
+    /// CPU use over the sampling interval, in percent of one host core
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_percent: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rss_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime_secs: Option<u64>,
+    pub display: String,
+}
+
+/// A disk image path and its current size on the host
+#[derive(Serialize, Debug, Clone)]
+pub struct DiskUsage {
+    pub path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+}
+
+impl fmt::Display for VmStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.state)?;
+        if !self.description.is_empty() {
+            write!(f, "\n  Description: {}", self.description)?;
+        }
+        if !self.tags.is_empty() {
+            write!(f, "\n  Tags: {}", self.tags.join(", "))?;
+        }
+        if !self.running {
+            return Ok(());
+        }
+        writeln!(f)?;
+        if let Some(pid) = self.pid {
+            writeln!(f, "  PID: {}", pid)?;
+        }
+        writeln!(f, "  vCPUs: {}, Memory: {} MB", self.vcpus, self.mem_mb)?;
+        if let Some(secs) = self.uptime_secs {
+            writeln!(f, "  Uptime: {}", format_duration(secs))?;
+        }
+        for disk in &self.disks {
+            match disk.bytes {
+                Some(bytes) => writeln!(f, "  Disk: {} ({})", disk.path.display(), format_size(bytes))?,
+                None => writeln!(f, "  Disk: {} (missing)", disk.path.display())?,
+            }
+        }
+        if let Some(port) = self.ssh_port {
+            writeln!(f, "  SSH: port {}", port)?;
+        }
+        write!(f, "  Display: {}", self.display)
+    }
+}
 
 impl VmManager {
     /// Create a new VM manager instance
     pub fn new() -> Self {
-        Self
+        Self::with_confirmer(Box::new(StdinConfirmer))
+    }
+
+    /// A manager whose confirmation prompts `confirmer` answers (`--yes`, or tests)
+    pub fn with_confirmer(confirmer: Box<dyn Confirmer + Send + Sync>) -> Self {
+        Self { confirmer }
+    }
+
+    /// Every VM in qvm home carrying all of `tags`, sorted by name; directories without a
+    /// readable vm.json are skipped
+    pub fn list_vms(&self, tags: &[String]) -> Result<Vec<VmSummary>> {
+        let mut vms = Vec::new();
+        for VmEntry { name, dir, config } in all_vms()? {
+            match config {
+                Ok(cfg) if !tags.iter().all(|t| cfg.meta.tags.contains(t)) => {}
+                Ok(cfg) => vms.push(VmSummary {
+                    name,
+                    running: running_vm_pid(&dir).is_some(),
+                    arch: cfg.meta.arch.clone(),
+                    cpus: cfg.vcpu_total(),
+                    mem_mb: cfg.hardware.mem_mb,
+                    display: cfg.display.mode.clone(),
+                    display_port: cfg.display.tcp_port(),
+                    ssh_port: ssh_host_port(&cfg.network),
+                    description: cfg.meta.description,
+                    tags: cfg.meta.tags,
+                    autostart: cfg.meta.autostart,
+                }),
+                Err(e) => warn!("skipping {}: {}", dir.display(), e),
+            }
+        }
+        Ok(vms)
     }
 
-    /// Delete a VM by name
-    pub fn delete_vm(&self, name: &str, force: bool) -> Result<()> {
+    /// Delete every VM named in `targets` (names or shell-style patterns), or all VMs.
+    /// Running or busy VMs are skipped with a warning instead of failing the batch;
+    /// without `force` the whole batch is confirmed once.
+    pub fn delete_vms(&self, targets: &[String], all: bool, force: bool) -> Result<DeleteSummary> {
+        let existing = vm_names()?;
+        let mut names: Vec<String> = Vec::new();
+        if all {
+            names = existing.clone();
+        }
+        for target in targets {
+            let matched: Vec<&String> = if is_glob(target) {
+                existing.iter().filter(|n| glob_match(target, n)).collect()
+            } else {
+                existing.iter().filter(|n| *n == target).collect()
+            };
+            if matched.is_empty() {
+                warn!("no VM matches '{}'", target);
+            }
+            for name in matched {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+
+        let mut summary = DeleteSummary::default();
+        let mut batch = Vec::new();
+        for name in names {
+            if is_vm_running(&name)? {
+                warn!("skipping VM '{}': it is running", name);
+                summary.skipped.push(name);
+            } else {
+                batch.push(name);
+            }
+        }
+
+        if !force && !batch.is_empty() {
+            let home = qvm_home()?;
+            eprintln!("About to delete {} VM(s):", batch.len());
+            let mut total = 0;
+            for name in &batch {
+                let usage = disk_usage(&home.join(format!("{}.qvm", name)));
+                total += usage;
+                eprintln!("  {} ({})", name, format_size(usage));
+                for note in shared_disk_notes(name, &home.join(format!("{}.qvm", name))) {
+                    eprintln!("    Note: {}", note);
+                }
+            }
+            eprintln!("  Space reclaimed: {}", format_size(total));
+            eprintln!();
+            if !self.confirmer.confirm("Are you sure you want to delete these VMs? [y/N]: ")? {
+                summary.skipped.extend(batch);
+                return Ok(summary);
+            }
+        }
+
+        for name in batch {
+            // Without the listing above, the shared disk notes become warnings
+            match self.delete_one(&name, true, force) {
+                Ok(_) => summary.deleted.push(name),
+                Err(e) => {
+                    warn!("skipping VM '{}': {}", name, e);
+                    summary.skipped.push(name);
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Delete a VM by name; returns false if the user declined the confirmation prompt
+    pub fn delete_vm(&self, name: &str, force: bool) -> Result<bool> {
+        self.delete_one(name, force, true)
+    }
+
+    /// `delete_vm`; with `force` there is no listing to show the shared disk notes in, so
+    /// they are warnings, if `warn_shared` is set
+    fn delete_one(&self, name: &str, force: bool, warn_shared: bool) -> Result<bool> {
         // Check if VM exists
         let vm_dir = find_vm_dir(name)?;
+        let _lock = VmLock::acquire(name, &vm_dir)?;
 
         // Check if VM is running
         if is_vm_running(name)? {
-            return Err(anyhow!(
-                "Cannot delete VM '{}': VM is currently running. Stop it first with 'qvm stop {}'",
-                name, name
-            ));
+            return Err(QvmError::VmRunning {
+                name: name.to_string(),
+                action: "delete",
+            });
         }
 
-        // Load config to show user what will be deleted
-        let config = load_conf(name)?;
-
         if !force {
-            println!("About to delete VM '{}':", name);
-            println!("  VM Directory: {}", vm_dir.display());
-            println!("  Disk: {}", resolve_under_root(&vm_dir, &config.paths.disk).display());
-            println!("  EFI Vars: {}", resolve_under_root(&vm_dir, &config.paths.efi_vars).display());
-            println!();
-            print!("Are you sure you want to delete this VM? [y/N]: ");
-            io::stdout().flush()?;
-
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
-
-            if !matches!(input.trim().to_lowercase().as_str(), "y" | "yes") {
-                println!("Deletion cancelled.");
-                return Ok(());
+            // Load config to show user what will be deleted; like the question itself this
+            // goes to stderr, keeping stdout for the command's report
+            let config = load_conf(name)?;
+            eprintln!("About to delete VM '{}':", name);
+            eprintln!("  VM Directory: {}", vm_dir.display());
+            for disk in &config.paths.disks {
+                let path = resolve_under_root(&vm_dir, &disk.path);
+                if !path.exists() {
+                    eprintln!("  Disk: {} (missing)", path.display());
+                    continue;
+                }
+                // qemu-img reports what a sparse qcow2 really uses; fall back to allocated blocks
+                let size = match disk_info(&path) {
+                    Ok(info) => format!("{} used of {}", format_size(info.actual_size), format_size(info.virtual_size)),
+                    Err(_) => format!("{} used", format_size(disk_usage(&path))),
+                };
+                let kept = if path.starts_with(&vm_dir) { "" } else { "; outside the VM directory, kept" };
+                eprintln!("  Disk: {} ({}{})", path.display(), size, kept);
+            }
+            let efi_vars = resolve_under_root(&vm_dir, &config.paths.efi_vars);
+            eprintln!("  EFI Vars: {} ({})", efi_vars.display(), format_size(disk_usage(&efi_vars)));
+            eprintln!("  Space reclaimed: {}", format_size(disk_usage(&vm_dir)));
+            for note in shared_disk_notes(name, &vm_dir) {
+                eprintln!("  Note: {}", note);
+            }
+            eprintln!();
+            if !self.confirmer.confirm("Are you sure you want to delete this VM? [y/N]: ")? {
+                return Ok(false);
+            }
+        } else if warn_shared {
+            for note in shared_disk_notes(name, &vm_dir) {
+                warn!("{}", note);
             }
         }
 
         // Remove the entire VM directory
         fs::remove_dir_all(&vm_dir)?;
+        Ok(true)
+    }
+
+    /// Rename a stopped VM: move its directory and update vm.json
+    pub fn rename_vm(&self, old: &str, new: &str) -> Result<()> {
+        let old_dir = find_vm_dir(old)?;
+        validate_vm_name(new)?;
+        let _lock = VmLock::acquire(old, &old_dir)?;
+
+        if is_vm_running(old)? {
+            return Err(QvmError::VmRunning {
+                name: old.to_string(),
+                action: "rename",
+            });
+        }
+
+        let new_dir = qvm_home()?.join(format!("{}.qvm", new));
+        if new_dir.exists() {
+            return Err(QvmError::VmExists(new.to_string()));
+        }
+
+        fs::rename(&old_dir, &new_dir)?;
+
+        let mut cfg = load_conf_from_dir(&new_dir)?;
+        cfg.meta.name = new.to_string();
+        // Absolute disk paths that pointed inside the old directory must follow the move
+        for disk in &mut cfg.paths.disks {
+            if let Ok(rel) = disk.path.strip_prefix(&old_dir) {
+                disk.path = new_dir.join(rel);
+            }
+        }
+        cfg.paths.root = new_dir;
+        save_conf(&cfg)?;
+
+        println!("Renamed VM '{}' to '{}'", old, new);
+        Ok(())
+    }
+
+    /// Bundle a stopped VM's directory (config, disks, EFI vars) into a tar archive
+    pub fn export_vm(&self, name: &str, file: &Path) -> Result<()> {
+        let vm_dir = find_vm_dir(name)?;
+        if is_vm_running(name)? {
+            return Err(QvmError::VmRunning {
+                name: name.to_string(),
+                action: "export",
+            });
+        }
+
+        let cfg = load_conf(name)?;
+        for disk in &cfg.paths.disks {
+            let abs = resolve_under_root(&vm_dir, &disk.path);
+            if !abs.starts_with(&vm_dir) {
+                warn!(
+                    "disk {} is outside the VM directory and is not included",
+                    abs.display()
+                );
+            }
+            if let Some(backing) = &disk.backing {
+                warn!(
+                    "disk {} is an overlay of shared backing file {}, which is not included; it must exist at that path wherever the VM is imported",
+                    abs.display(),
+                    backing.display()
+                );
+            }
+        }
+
+        archive::pack(&qvm_home()?, &format!("{}.qvm", name), file)?;
+        println!("Exported VM '{}' to {}", name, file.display());
+        Ok(())
+    }
+
+    /// Copy a VM's disks (as compacted qcow2), vm.json and EFI vars into a new timestamped
+    /// directory under `dest` and record it in `dest`'s manifest. A running VM keeps
+    /// running: its writes go to temporary overlays while the disks are copied and are
+    /// committed back afterwards.
+    pub fn backup_vm(&self, name: &str, dest: &Path) -> Result<BackupRecord> {
+        let vm_dir = find_vm_dir(name)?;
+        let _lock = VmLock::acquire(name, &vm_dir)?;
+        let cfg = load_conf(name)?;
+        let live = is_vm_running(name)?;
+
+        let created = chrono::Utc::now();
+        let dir = PathBuf::from(backup::backup_dir_name(name, created));
+        let backup_dir = dest.join(&dir);
+        if backup_dir.exists() {
+            return Err(QvmError::Invalid(format!("{} already exists", backup_dir.display())));
+        }
+        fs::create_dir_all(&backup_dir)?;
+
+        let disks: Vec<BackupDisk> = cfg
+            .paths
+            .disks
+            .iter()
+            .enumerate()
+            .map(|(i, d)| BackupDisk {
+                source: resolve_under_root(&vm_dir, &d.path),
+                file: backup::backup_disk_file(i),
+            })
+            .collect();
+        let copied = if live {
+            self.copy_disks_live(name, &cfg, &disks, &backup_dir)
+        } else {
+            cfg.paths.disks.iter().zip(&disks).try_for_each(|(disk, b)| {
+                convert_to_qcow2(&b.source, &disk.format, &backup_dir.join(&b.file), false)
+            })
+        };
+        if let Err(e) = copied {
+            let _ = fs::remove_dir_all(&backup_dir);
+            return Err(e);
+        }
+
+        fs::copy(conf_path(&vm_dir), backup_dir.join("vm.json"))?;
+        let efi_vars = resolve_under_root(&vm_dir, &cfg.paths.efi_vars);
+        let efi_vars = match efi_vars.is_file() {
+            true => {
+                let file = PathBuf::from(efi_vars.file_name().unwrap_or_default());
+                fs::copy(&efi_vars, backup_dir.join(&file))?;
+                Some(file)
+            }
+            false => None,
+        };
+
+        let record = BackupRecord {
+            vm: name.to_string(),
+            uuid: cfg.meta.uuid.clone(),
+            created: created.to_rfc3339(),
+            dir,
+            live,
+            disks,
+            efi_vars,
+        };
+        let mut manifest = BackupManifest::load(dest)?;
+        manifest.backups.push(record.clone());
+        manifest.save(dest)?;
+        Ok(record)
+    }
+
+    /// Copy the disks of a running VM: freeze each writable disk behind an overlay, copy
+    /// it, then commit the overlays back whether or not the copies succeeded
+    fn copy_disks_live(&self, name: &str, cfg: &VmConfig, disks: &[BackupDisk], backup_dir: &Path) -> Result<()> {
+        let mut qmp = self.qmp(name)?;
+        let mut frozen = Vec::new();
+        let mut copied = Ok(());
+        for (i, (disk, b)) in cfg.paths.disks.iter().zip(disks).enumerate() {
+            if disk.readonly {
+                continue;
+            }
+            let overlay = backup::overlay_path(&b.source);
+            if let Err(e) = backup::freeze_drive(&mut qmp, &disk_id(i), &overlay) {
+                copied = Err(e);
+                break;
+            }
+            frozen.push((disk_id(i), overlay));
+        }
+        if copied.is_ok() {
+            copied = cfg.paths.disks.iter().zip(disks).try_for_each(|(disk, b)| {
+                convert_to_qcow2(&b.source, &disk.format, &backup_dir.join(&b.file), true)
+            });
+        }
+
+        for (device, overlay) in frozen {
+            backup::commit_overlay(&mut qmp, &device).map_err(|e| {
+                QvmError::Qmp(format!(
+                    "{}; VM '{}' still writes {} to {}: once it is stopped, merge it with \
+                     'qemu-img commit {}' or the writes made since the backup are lost",
+                    e,
+                    name,
+                    device,
+                    overlay.display(),
+                    shell_quote(&overlay.display().to_string())
+                ))
+            })?;
+            if let Err(e) = fs::remove_file(&overlay) {
+                warn!("cannot remove backup overlay {}: {}", overlay.display(), e);
+            }
+        }
+        copied
+    }
+
+    /// Unpack an exported VM into qvm home (optionally under a new name), giving it a
+    /// fresh UUID, NIC MACs and display ports and this host's firmware paths
+    pub fn import_vm(&self, file: &Path, new_name: Option<&str>) -> Result<()> {
+        let dir_name = archive::vm_dir_of(&archive::entries(file)?)?;
+        let name = match new_name {
+            Some(n) => n,
+            None => dir_name.trim_end_matches(".qvm"),
+        };
+        validate_vm_name(name)?;
+
+        let home = qvm_home()?;
+        fs::create_dir_all(&home)?;
+        let dest = home.join(format!("{}.qvm", name));
+        if dest.exists() {
+            return Err(QvmError::VmExists(name.to_string()));
+        }
+
+        // Unpack next to the destination so the final move is a same-filesystem rename
+        let staging = tempfile::Builder::new().prefix(".import-").tempdir_in(&home)?;
+        archive::unpack(file, staging.path())?;
+        let staged = staging.path().join(&dir_name);
+        let mut cfg = load_conf_from_dir(&staged)?;
+
+        cfg.meta.name = name.to_string();
+
+        // A pinned qemu from another host is unlikely to exist here
+        if let Some(pinned) = cfg.paths.qemu_bin.take_if(|bin| !bin.is_file()) {
+            warn!("qemu {} pinned by the exported VM is missing; unpinning it", pinned.display());
+        }
+
+        // Firmware outside the VM directory differs between hosts; relative firmware came along
+        if cfg.firmware.code.is_absolute() {
+            match pick_qemu_bin(&cfg.meta.arch)
+                .and_then(|bin| locate_firmware_from_qemu(&bin, &cfg.meta.arch, cfg.firmware.secure_boot))
+            {
+                Ok((code, vars_template)) => {
+                    cfg.firmware.code = code;
+                    cfg.firmware.vars_template = vars_template;
+                }
+                Err(e) => warn!(
+                    "{e}; keeping firmware path {}",
+                    cfg.firmware.code.display()
+                ),
+            }
+        }
+
+        {
+            // Held until vm.json is saved, so the next create sees the ports picked here
+            let _lock = PortLock::acquire()?;
+            rehome(&mut cfg, dest.clone())?;
+            fs::rename(&staged, &dest)?;
+            save_conf(&cfg)?;
+        }
 
-        println!("Successfully deleted VM '{}'", name);
+        println!("Imported VM '{}' from {}", name, file.display());
         Ok(())
     }
 
-    /// Start a VM (placeholder for future implementation)
-    pub fn start_vm(&self, name: &str) -> Result<()> {
-        println!("Starting VM '{}' (not implemented)", name);
+    /// Grow (or, with `shrink` and `force`, shrink) disk `index` of a stopped VM to `size`
+    pub fn resize_disk(&self, name: &str, index: usize, size: &str, shrink: bool, force: bool) -> Result<()> {
+        let vm_dir = find_vm_dir(name)?;
+        let new_size = parse_disk_size(size)?;
+        let _lock = VmLock::acquire(name, &vm_dir)?;
+
+        if is_vm_running(name)? {
+            return Err(QvmError::VmRunning {
+                name: name.to_string(),
+                action: "resize a disk of",
+            });
+        }
+
+        let cfg = load_conf(name)?;
+        let disk = cfg.paths.disks.get(index).ok_or_else(|| {
+            QvmError::Invalid(format!(
+                "VM '{}' has no disk {} (it has {})",
+                name,
+                index,
+                cfg.paths.disks.len()
+            ))
+        })?;
+        let path = resolve_under_root(&vm_dir, &disk.path);
+        if !path.is_file() {
+            return Err(QvmError::DiskNotFound(path));
+        }
+
+        let current = disk_info(&path)?.virtual_size;
+        if new_size == current {
+            println!("Disk {} is already {}", path.display(), format_size(current));
+            return Ok(());
+        }
+        let shrinking = new_size < current;
+        if shrinking && !(shrink && force) {
+            return Err(QvmError::Invalid(format!(
+                "Refusing to shrink {} from {} to {}: data past the new end is lost. \
+                 Shrink the guest filesystem first, then pass --shrink --force",
+                path.display(),
+                format_size(current),
+                format_size(new_size)
+            )));
+        }
+
+        resize_disk(&path, &disk.format, new_size, shrinking)?;
+        println!(
+            "Resized disk {} of VM '{}' from {} to {}",
+            path.display(),
+            name,
+            format_size(current),
+            format_size(new_size)
+        );
+        if !shrinking {
+            info!("grow the partition and filesystem inside the guest to use the new space");
+        }
         Ok(())
     }
 
-    /// Stop a VM (placeholder for future implementation)
-    pub fn stop_vm(&self, name: &str) -> Result<()> {
-        println!("Stopping VM '{}' (not implemented)", name);
+    /// Check every disk of a stopped VM with `qemu-img check`, repairing what it finds if
+    /// `repair` is set
+    pub fn check_vm(&self, name: &str, repair: bool) -> Result<Vec<CheckedDisk>> {
+        let vm_dir = find_vm_dir(name)?;
+        let _lock = VmLock::acquire(name, &vm_dir)?;
+        // qemu's own writes would show up as corruption, and a repair would cause it
+        if is_vm_running(name)? {
+            return Err(QvmError::VmRunning {
+                name: name.to_string(),
+                action: "check the disks of",
+            });
+        }
+
+        let cfg = load_conf(name)?;
+        let mut checked = Vec::new();
+        for (index, disk) in cfg.paths.disks.iter().enumerate() {
+            let path = resolve_under_root(&vm_dir, &disk.path);
+            if !path.is_file() && !is_block_device(&path) {
+                return Err(QvmError::DiskNotFound(path));
+            }
+            debug!("checking {}", path.display());
+            checked.push(CheckedDisk {
+                index,
+                result: check_disk(&path, &disk.format, repair)?,
+                path,
+                format: disk.format.clone(),
+            });
+        }
+        Ok(checked)
+    }
+
+    /// Rewrite the qcow2 disks of a stopped VM (or only disk `index`) with `qemu-img
+    /// convert`, which leaves out clusters the guest freed or zeroed. Disks with internal
+    /// snapshots are refused, since the copy would not keep them.
+    pub fn compact_vm(&self, name: &str, index: Option<usize>) -> Result<Vec<CompactedDisk>> {
+        let vm_dir = find_vm_dir(name)?;
+        let _lock = VmLock::acquire(name, &vm_dir)?;
+        if is_vm_running(name)? {
+            return Err(QvmError::VmRunning {
+                name: name.to_string(),
+                action: "compact the disks of",
+            });
+        }
+
+        let cfg = load_conf(name)?;
+        let disks: Vec<&Disk> = match index {
+            Some(i) => {
+                let disk = cfg.paths.disks.get(i).ok_or_else(|| {
+                    QvmError::Invalid(format!(
+                        "VM '{}' has no disk {} (it has {})",
+                        name,
+                        i,
+                        cfg.paths.disks.len()
+                    ))
+                })?;
+                if disk.format != "qcow2" {
+                    return Err(QvmError::Invalid(format!(
+                        "Disk {} of VM '{}' is {}; only qcow2 images can be compacted",
+                        i, name, disk.format
+                    )));
+                }
+                vec![disk]
+            }
+            None => cfg.paths.disks.iter().filter(|d| d.format == "qcow2").collect(),
+        };
+        if disks.is_empty() {
+            return Err(QvmError::Invalid(format!("VM '{}' has no qcow2 disks to compact", name)));
+        }
+
+        let paths: Vec<PathBuf> = disks.iter().map(|d| resolve_under_root(&vm_dir, &d.path)).collect();
+        for path in &paths {
+            if !path.is_file() {
+                return Err(QvmError::DiskNotFound(path.clone()));
+            }
+            let snapshots = snapshot::list(path)?;
+            if !snapshots.is_empty() {
+                let tags: Vec<&str> = snapshots.iter().map(|s| s.tag.as_str()).collect();
+                return Err(QvmError::Invalid(format!(
+                    "{} has internal snapshots ({}), which compacting would drop",
+                    path.display(),
+                    tags.join(", ")
+                )));
+            }
+        }
+
+        let mut compacted = Vec::new();
+        for path in paths {
+            let before = disk_info(&path)?.actual_size;
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            let tmp = path.with_file_name(format!(".{}.compact", file_name));
+            let converted = convert_to_qcow2(&path, "qcow2", &tmp, false)
+                .and_then(|()| Ok(fs::set_permissions(&tmp, fs::metadata(&path)?.permissions())?))
+                .and_then(|()| disk_info(&tmp));
+            let after = match converted {
+                Ok(info) => info.actual_size,
+                Err(e) => {
+                    let _ = fs::remove_file(&tmp);
+                    return Err(e);
+                }
+            };
+            fs::rename(&tmp, &path)?;
+            compacted.push(CompactedDisk { path, before, after });
+        }
+        info!("space the guest has freed is only reclaimed once trimmed or zeroed: run 'fstrim -av' in the guest before compacting");
+        Ok(compacted)
+    }
+
+    /// Create (or adopt) a disk image and append it to the VM's disk list
+    pub fn add_disk(&self, name: &str, params: AddDiskParams) -> Result<()> {
+        let vm_dir = find_vm_dir(name)?;
+        let size = params.size.as_deref().map(parse_disk_size).transpose()?;
+        let _lock = VmLock::acquire(name, &vm_dir)?;
+
+        if is_vm_running(name)? {
+            return Err(QvmError::VmRunning {
+                name: name.to_string(),
+                action: "add a disk to",
+            });
+        }
+
+        let mut cfg = load_conf(name)?;
+
+        let path = match params.existing.as_deref().map(expand_path).transpose()? {
+            Some(existing) => {
+                if !existing.is_file() && !is_block_device(&existing) {
+                    return Err(QvmError::DiskNotFound(existing));
+                }
+                existing.canonicalize()?
+            }
+            None => match &params.file {
+                Some(file) => expand_path(file)?,
+                None => PathBuf::from(format!("disk{}.{}", cfg.paths.disks.len(), params.format)),
+            },
+        };
+
+        let file_name = path.file_name().map(|f| f.to_os_string());
+        if cfg
+            .paths
+            .disks
+            .iter()
+            .any(|d| d.path.file_name().map(|f| f.to_os_string()) == file_name)
+        {
+            return Err(QvmError::Invalid(format!(
+                "VM '{}' already has a disk named '{}'",
+                name,
+                path.display()
+            )));
+        }
+
+        if let Some(size) = size {
+            let disk_abs = resolve_under_root(&vm_dir, &path);
+            if disk_abs.exists() {
+                return Err(QvmError::Invalid(format!(
+                    "Disk image already exists: {}",
+                    disk_abs.display()
+                )));
+            }
+            create_disk(&disk_abs, &params.format, size)?;
+        }
+
+        let disk = Disk {
+            path: path.clone(),
+            format: params.format,
+            interface: params.interface,
+            cache: params.cache,
+            aio: params.aio,
+            readonly: params.readonly,
+            backing: None,
+        };
+        validate_disk(&disk)?;
+        cfg.paths.disks.push(disk);
+        save_conf(&cfg)?;
+
+        println!("Added disk {} to VM '{}'", path.display(), name);
         Ok(())
     }
+
+    /// Build a cloud-init seed ISO for a stopped VM and attach it on every start;
+    /// `None` detaches and deletes the current seed
+    pub fn set_cloud_init(&self, name: &str, sources: Option<SeedSources>) -> Result<()> {
+        let vm_dir = find_vm_dir(name)?;
+
+        if is_vm_running(name)? {
+            return Err(QvmError::VmRunning {
+                name: name.to_string(),
+                action: "change cloud-init for",
+            });
+        }
+
+        let mut cfg = load_conf(name)?;
+
+        match sources {
+            Some(src) => {
+                let iso = build_seed_iso(&vm_dir, &cfg.meta.name, &cfg.meta.uuid, &src)?;
+                let rel = iso.strip_prefix(&vm_dir).map(Path::to_path_buf).unwrap_or(iso);
+                println!("Built cloud-init seed {} for VM '{}'", rel.display(), name);
+                cfg.cloud_init = Some(CloudInit { seed_iso: rel });
+            }
+            None => {
+                let Some(ci) = cfg.cloud_init.take() else {
+                    println!("VM '{}' has no cloud-init seed attached", name);
+                    return Ok(());
+                };
+                let _ = fs::remove_file(resolve_under_root(&vm_dir, &ci.seed_iso));
+                println!("Removed cloud-init seed from VM '{}'", name);
+            }
+        }
+
+        save_conf(&cfg)
+    }
+
+    /// Report whether a VM is running, and if so its PID, uptime, disk and display endpoint
+    pub fn status(&self, name: &str) -> Result<VmStatus> {
+        let vm_dir = find_vm_dir(name)?;
+        let cfg = load_conf(name)?;
+        let running = is_vm_running(name)?;
+
+        let pid_file = pid_path(&vm_dir);
+        let (pid, uptime_secs) = if running {
+            (read_pid_file(&pid_file), uptime_secs(&pid_file))
+        } else {
+            (None, None)
+        };
+
+        let disks = cfg
+            .paths
+            .disks
+            .iter()
+            .map(|d| {
+                let path = resolve_under_root(&vm_dir, &d.path);
+                let bytes = fs::metadata(&path).ok().map(|m| m.len());
+                DiskUsage { path, bytes }
+            })
+            .collect();
+
+        let state = if running {
+            // The process being alive does not mean the guest is executing; ask qemu.
+            // VMs started without a QMP socket are reported as plain "running".
+            self.qmp(name)
+                .and_then(|mut q| q.execute("query-status", None))
+                .ok()
+                .and_then(|s| s.get("status").and_then(|v| v.as_str()).map(String::from))
+                .unwrap_or_else(|| "running".to_string())
+        } else {
+            "stopped".to_string()
+        };
+
+        Ok(VmStatus {
+            name: name.to_string(),
+            running,
+            state,
+            vcpus: cfg.vcpu_total(),
+            mem_mb: cfg.hardware.mem_mb,
+            pid,
+            uptime_secs,
+            disks,
+            display: display_endpoint(&cfg.display, &vm_dir),
+            ssh_port: ssh_host_port(&cfg.network),
+            description: cfg.meta.description.clone(),
+            tags: cfg.meta.tags.clone(),
+        })
+    }
+
+    /// Every running VM with its qemu's CPU use, measured over `sample`, and resident
+    /// memory; VMs whose pidfile is stale are left out
+    pub fn ps(&self, sample: Duration) -> Result<Vec<VmProcess>> {
+        let mut running = Vec::new();
+        for VmEntry { name, dir, config } in all_vms()? {
+            let Some(pid) = running_vm_pid(&dir) else {
+                continue;
+            };
+            let display = match &config {
+                Ok(cfg) => display_endpoint(&cfg.display, &dir),
+                Err(_) => "?".to_string(),
+            };
+            running.push((name, dir, pid, display, process_usage(pid)));
+        }
+        if running.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // CPU use is the CPU time each qemu accumulates during the sample
+        let started = Instant::now();
+        thread::sleep(sample);
+        let elapsed = started.elapsed();
+        Ok(running
+            .into_iter()
+            .map(|(name, dir, pid, display, before)| {
+                let after = process_usage(pid);
+                VmProcess {
+                    name,
+                    pid,
+                    cpu_percent: before.zip(after).map(|(b, a)| a.cpu_percent(&b, elapsed)),
+                    rss_bytes: after.map(|a| a.rss_bytes),
+                    uptime_secs: uptime_secs(&pid_path(&dir)),
+                    display,
+                }
+            })
+            .collect())
+    }
+
+    /// Start a VM: build the qemu-system-* argv from vm.json, spawn it and record its PID.
+    ///
+    /// qemu writes its PID to `vm.pid` (`-pidfile`) and qvm records the child's PID there
+    /// until it does, so `stop_vm` always finds the real qemu process:
+    /// - daemon: qemu runs in its own process group with stdio redirected to `vm.log`,
+    ///   and this call returns as soon as it has been spawned;
+    /// - foreground: qemu shares the terminal and this call waits for it to exit,
+    ///   then removes `vm.pid`.
+    pub fn start_vm(&self, name: &str, opts: &StartOptions) -> Result<Started> {
+        let Launched { vm_dir, mut child, stderr, spawned_at, ssh_port } = self.launch(name, opts)?;
+        let pid = child.id();
+        if opts.daemon {
+            return Ok(Started { pid, exit: None, ssh_port });
+        }
+
+        info!("started VM '{}' (pid {}); waiting for it to exit", name, pid);
+        // Ctrl-C is for qemu (which shares our process group); stay alive to clean up, then
+        // hand a program embedding qvm its own SIGINT handling back
+        let prev_sigint = unsafe { libc::signal(libc::SIGINT, libc::SIG_IGN) };
+        let status = child.wait();
+        unsafe {
+            libc::signal(libc::SIGINT, prev_sigint);
+        }
+        let status = status?;
+        let stderr = stderr.and_then(|tee| tee.join().ok()).unwrap_or_default();
+        // qemu removes its -pidfile on a clean exit. A stop or restart holding the lock
+        // cleans up itself, and after a restart the pidfile and helpers belong to the new qemu.
+        if let Ok(_lock) = VmLock::acquire(name, &vm_dir) {
+            if read_pid_file(&pid_path(&vm_dir)).is_none_or(|p| p == pid as i32) {
+                let _ = fs::remove_file(pid_path(&vm_dir));
+                stop_helpers(&vm_dir);
+            }
+        }
+        if !status.success() && spawned_at.elapsed() < STARTUP_GRACE {
+            // Kept for 'qvm logs', as a daemonized qemu's output would be
+            if let Err(e) = fs::write(log_path(&vm_dir), &stderr) {
+                warn!("could not write {}: {}", log_path(&vm_dir).display(), e);
+            }
+            return Err(startup_failure(name, status, &String::from_utf8_lossy(&stderr)));
+        }
+        Ok(Started { pid, exit: Some(status), ssh_port: None })
+    }
+
+    /// Start a VM in the background, as `start --daemon`, and keep its qemu process as a
+    /// `VmHandle` for a program embedding qvm. Dropping the handle leaves the VM running.
+    ///
+    /// ```no_run
+    /// use qvm::config::schema::{Display, Network, NetworkInterface, VmConfigBuilder};
+    /// use qvm::vm::config::save_conf;
+    /// use qvm::vm::disk::create_disk;
+    /// use qvm::VmManager;
+    /// use std::time::Duration;
+    ///
+    /// // The defaults (a Cocoa window, vmnet networking) are for macOS hosts
+    /// let nic = NetworkInterface { mode: "user".into(), ..NetworkInterface::default() };
+    /// let cfg = VmConfigBuilder::new("demo", "x86_64")
+    ///     .mem_mb(1024)
+    ///     .accel("tcg")
+    ///     .display(Display { mode: "headless".into(), ..Display::default() })
+    ///     .network(Network { interfaces: vec![nic], ..Network::default() })
+    ///     .build();
+    /// std::fs::create_dir_all(&cfg.paths.root)?;
+    /// create_disk(&cfg.paths.root.join(&cfg.paths.disks[0].path), "qcow2", 8 << 30)?;
+    /// save_conf(&cfg)?;
+    ///
+    /// let mut vm = VmManager::new().spawn_vm("demo")?;
+    /// assert!(vm.is_alive());
+    /// let status = vm.qmp("query-status", None)?;
+    /// println!("{} (pid {}) is {}", vm.name(), vm.pid(), status["status"]);
+    /// vm.stop(Duration::from_secs(30))?;
+    /// # Ok::<(), qvm::QvmError>(())
+    /// ```
+    pub fn spawn_vm(&self, name: &str) -> Result<VmHandle> {
+        let opts = StartOptions { daemon: true, ..StartOptions::default() };
+        let Launched { vm_dir, child, .. } = self.launch(name, &opts)?;
+        Ok(VmHandle { name: name.to_string(), vm_dir, child })
+    }
+
+    /// Everything `start_vm` does up to a running qemu: check the VM, build its argv with
+    /// `build_qemu_args`, start the helpers and spawn qemu
+    fn launch(&self, name: &str, opts: &StartOptions) -> Result<Launched> {
+        let vm_dir = find_vm_dir(name)?;
+        let opts = &StartOptions {
+            iso: opts.iso.as_deref().map(expand_path).transpose()?,
+            ..opts.clone()
+        };
+
+        if opts.serial_console && opts.daemon {
+            return Err(QvmError::Invalid(
+                "--console serial needs a terminal and cannot be combined with --daemon".to_string(),
+            ));
+        }
+        if opts.wait_for_ssh.is_some() && !opts.daemon {
+            return Err(QvmError::Invalid(
+                "--wait-for-ssh only works together with --daemon".to_string(),
+            ));
+        }
+
+        // Held until qemu is spawned and vm.pid written; a foreground VM must stay stoppable
+        let lock = VmLock::acquire(name, &vm_dir)?;
+
+        if is_vm_running(name)? {
+            return Err(QvmError::VmRunning {
+                name: name.to_string(),
+                action: "start",
+            });
+        }
+
+        let mut cfg = load_conf(name)?;
+
+        // Verify everything qemu needs before spawning it
+        if cfg.paths.disks.is_empty() {
+            return Err(QvmError::Invalid(format!(
+                "VM '{}' has no disks configured",
+                name
+            )));
+        }
+        for d in &cfg.paths.disks {
+            let disk = resolve_under_root(&vm_dir, &d.path);
+            if !disk.is_file() && !is_block_device(&disk) {
+                return Err(QvmError::DiskNotFound(disk));
+            }
+        }
+        if let Some(iso) = opts.iso.as_ref().or(cfg.paths.iso.as_ref()) {
+            let iso = resolve_under_root(&vm_dir, iso);
+            if !iso.is_file() {
+                return Err(QvmError::Invalid(format!("ISO not found: {}", iso.display())));
+            }
+        }
+        for (what, path) in [("Kernel", &cfg.boot.kernel), ("Initrd", &cfg.boot.initrd)] {
+            if let Some(p) = path {
+                let p = resolve_under_root(&vm_dir, p);
+                if !p.is_file() {
+                    return Err(QvmError::Invalid(format!("{} not found: {}", what, p.display())));
+                }
+            }
+        }
+        if let Some(ci) = &cfg.cloud_init {
+            let seed = resolve_under_root(&vm_dir, &ci.seed_iso);
+            if !seed.is_file() {
+                return Err(QvmError::Invalid(format!(
+                    "cloud-init seed not found: {} (rebuild it with 'qvm cloud-init {}')",
+                    seed.display(),
+                    name
+                )));
+            }
+        }
+        if refresh_firmware(&mut cfg, &vm_dir)? {
+            info!(
+                "firmware of VM '{}' was missing; now using {} (saved to vm.json)",
+                name,
+                cfg.firmware.code.display()
+            );
+            save_conf(&cfg)?;
+        }
+
+        // VMs created without a usable template get their NVRAM on first boot instead
+        let efi_vars = resolve_under_root(&vm_dir, &cfg.paths.efi_vars);
+        if !efi_vars.exists() {
+            seed_efi_vars(&resolve_under_root(&vm_dir, &cfg.firmware.vars_template), &efi_vars)?;
+        }
+
+        for share in &cfg.shares {
+            if !share.path.is_dir() {
+                return Err(QvmError::Invalid(format!(
+                    "Shared directory not found: {} (remove it with 'qvm share remove {} {}')",
+                    share.path.display(),
+                    name,
+                    share.tag
+                )));
+            }
+        }
+
+        // vm.json may have been edited by hand since create
+        // qvm's list of models may lag behind the installed qemu, which has the final say
+        if let Err(e) = validate_cpu_model(&cfg.meta.arch, &cfg.hardware.cpu_model) {
+            warn!("{e}; passing it to qemu as is");
+        }
+        for warning in validate_hardware(&cfg.hardware)? {
+            warn!("{warning}");
+        }
+        validate_memory(&cfg.memory, cfg.hardware.mem_mb)?;
+        validate_network(&cfg.network)?;
+        for disk in &cfg.paths.disks {
+            validate_disk(disk)?;
+        }
+        // Huge pages come from a reserved pool that validate_memory has already checked
+        let available_mb = match cfg.memory.hugepages {
+            Some(_) => None,
+            None => host_available_memory_mb(),
+        };
+        for warning in check_host_capacity(name, &cfg.hardware, available_mb, host_cpu_count(), opts.force)? {
+            warn!("{warning}");
+        }
+        let display = if opts.serial_console {
+            None
+        } else {
+            Some(opts.display.as_deref().unwrap_or(&cfg.display.mode))
+        };
+        if let Some(mode) = display {
+            let effective = Display { mode: mode.to_string(), ..cfg.display.clone() };
+            validate_display_tls(&effective, &vm_dir)?;
+            validate_gpu(&effective, &cfg.meta.arch)?;
+        }
+        let problems = host_incompatibilities(std::env::consts::OS, display, &cfg.hardware.accel);
+        if !problems.is_empty() {
+            return Err(QvmError::Invalid(format!(
+                "Cannot start VM '{}' on this {} host:\n  - {}\nOverride the display for one boot with \
+                 'qvm start {} --display vnc', or edit display.mode / hardware.accel in {}",
+                name,
+                std::env::consts::OS,
+                problems.join("\n  - "),
+                name,
+                conf_path(&vm_dir).display()
+            )));
+        }
+
+        let ssh_port = if opts.wait_for_ssh.is_some() {
+            let port = ssh_host_port(&cfg.network);
+            Some(port.ok_or_else(|| {
+                QvmError::Invalid(format!(
+                    "--wait-for-ssh needs a user-mode TCP forward to guest port {}; add one with \
+                     'qvm forward add {} 2222 {}'",
+                    SSH_GUEST_PORT, name, SSH_GUEST_PORT
+                ))
+            })?)
+        } else {
+            None
+        };
+
+        // Resolve now so a missing $VAR fails before anything is launched
+        let password = display_password(&cfg, opts)
+            .map(|(protocol, spec)| resolve_password(spec).map(|p| (protocol, p)))
+            .transpose()?;
+
+        let qemu_bin = pick_vm_qemu_bin(&cfg)?;
+        match qemu_version(&qemu_bin) {
+            Ok(version) => {
+                let problems = qemu_feature_problems(&cfg, version);
+                if !problems.is_empty() {
+                    let problems: Vec<String> = problems.iter().map(|p| p.to_string()).collect();
+                    return Err(QvmError::Invalid(format!(
+                        "{} is QEMU {}, too old for VM '{}':\n  - {}",
+                        qemu_bin.display(),
+                        version,
+                        name,
+                        problems.join("\n  - ")
+                    )));
+                }
+            }
+            Err(e) => debug!("{e}; not checking the VM's settings against it"),
+        }
+        check_vmnet_access(name, &cfg.network, &qemu_bin)?;
+        let virtiofsd = if cfg.shares.is_empty() { None } else { find_virtiofsd() };
+        let args = build_qemu_args(
+            &cfg,
+            &StartOptions {
+                virtiofs: virtiofsd.is_some(),
+                ..opts.clone()
+            },
+        )?;
+
+        if cfg.tpm.enabled {
+            start_swtpm(&vm_dir, &cfg.tpm)?;
+        }
+        if let Some(virtiofsd) = &virtiofsd {
+            if let Err(e) = start_virtiofsd(virtiofsd, &vm_dir, &cfg.shares) {
+                stop_helpers(&vm_dir);
+                return Err(e);
+            }
+        }
+
+        debug!(
+            "spawning {} {}",
+            qemu_bin.display(),
+            args.iter().map(|a| shell_quote(a)).collect::<Vec<_>>().join(" ")
+        );
+        let mut cmd = Command::new(&qemu_bin);
+        cmd.args(&args);
+        if opts.daemon {
+            let log = fs::File::create(log_path(&vm_dir))?;
+            cmd.stdin(Stdio::null())
+                .stdout(log.try_clone()?)
+                .stderr(log)
+                // Own process group: terminal signals (Ctrl-C, hangup) must not reach qemu
+                .process_group(0);
+        } else {
+            // Still shown on the terminal, but kept to explain a qemu that dies at once
+            cmd.stderr(Stdio::piped());
+        }
+
+        let mut child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                stop_helpers(&vm_dir);
+                return Err(e.into());
+            }
+        };
+        let spawned_at = Instant::now();
+        let stderr = child.stderr.take().map(tee_stderr);
+        record_pid(&vm_dir, child.id())?;
+        drop(lock);
+
+        if opts.daemon {
+            if let Some(status) = exited_within(&mut child, STARTUP_GRACE)? {
+                let _ = fs::remove_file(pid_path(&vm_dir));
+                stop_helpers(&vm_dir);
+                let log = fs::read(log_path(&vm_dir)).unwrap_or_default();
+                return Err(startup_failure(name, status, &String::from_utf8_lossy(&log)));
+            }
+            // qemu has written its -pidfile by now; it must agree with the child we spawned
+            record_pid(&vm_dir, child.id())?;
+        }
+
+        if let Some((protocol, password)) = &password {
+            if let Err(e) = apply_display_password(&vm_dir, child.id() as i32, protocol, password) {
+                warn!(
+                    "could not set the {} password ({}); the display refuses clients until 'qvm set-password {}'",
+                    protocol, e, name
+                );
+            }
+        }
+
+        if let (Some(timeout), Some(port)) = (opts.wait_for_ssh, ssh_port) {
+            info!("waiting up to {}s for SSH on port {}", timeout.as_secs(), port);
+            let addr = SocketAddr::from(([127, 0, 0, 1], port));
+            let alive = || matches!(child.try_wait(), Ok(None));
+            if let Err(e) = wait_for_port(addr, "SSH-", timeout, alive) {
+                // A VM that never became reachable is useless to the caller; don't leave it behind.
+                // It never fully started, so like a failed on_start hook this skips on_stop.
+                self.halt(name, Duration::from_secs(10), true, Some(&mut child))?;
+                return Err(QvmError::CommandFailed(format!(
+                    "VM '{}' did not answer on its SSH port: {}; stopped it again (see 'qvm logs {}')",
+                    name, e, name
+                )));
+            }
+        }
+
+        if let Some(script) = &cfg.hooks.on_start {
+            if let Err(e) = run_hook("start", script, &cfg) {
+                // Like an unreachable VM, one whose setup failed is not left running
+                self.halt(name, Duration::from_secs(10), true, Some(&mut child))?;
+                return Err(e);
+            }
+        }
+        Ok(Launched { vm_dir, child, stderr, spawned_at, ssh_port })
+    }
+
+    /// Stop a running VM (gracefully, as `stop_vm`) and start it again from its saved config;
+    /// a stopped VM is simply started. `stop_vm` only returns once qemu has exited and
+    /// `vm.pid` is gone, so the new qemu never overlaps the old one.
+    pub fn restart_vm(&self, name: &str, timeout: Duration, opts: &StartOptions) -> Result<Started> {
+        if is_vm_running(name)? && self.stop_vm(name, timeout, false)? {
+            info!("stopped VM '{}'", name);
+        }
+        self.start_vm(name, opts)
+    }
+
+    /// Print a VM's vm.log (optionally only the last `lines`), then keep printing
+    /// appended output when `follow` is set
+    pub fn logs(&self, name: &str, lines: Option<usize>, follow: bool) -> Result<()> {
+        let vm_dir = find_vm_dir(name)?;
+        let log = log_path(&vm_dir);
+        if !log.exists() {
+            println!(
+                "No log for VM '{}' yet (qemu output is logged when started with --daemon)",
+                name
+            );
+            return Ok(());
+        }
+
+        let offset = print_log(&log, lines)?;
+        if follow {
+            follow_log(&log, offset)?;
+        }
+        Ok(())
+    }
+
+    /// Attach this terminal to a running VM's serial console (Ctrl-] detaches)
+    pub fn console(&self, name: &str) -> Result<()> {
+        let vm_dir = find_vm_dir(name)?;
+        if !is_vm_running(name)? {
+            return Err(QvmError::VmNotRunning(name.to_string()));
+        }
+        let sock = console_path(&vm_dir);
+        if !sock.exists() {
+            return Err(QvmError::Invalid(format!(
+                "VM '{}' has no console socket (it was started with --console serial or by an older qvm)",
+                name
+            )));
+        }
+
+        eprintln!("Connected to the serial console of VM '{}' (Ctrl-] to detach)", name);
+        console::attach(&sock, "console")
+    }
+
+    /// Attach this terminal to a running VM's HMP monitor (Ctrl-] detaches)
+    pub fn monitor(&self, name: &str) -> Result<()> {
+        let vm_dir = find_vm_dir(name)?;
+        if !is_vm_running(name)? {
+            return Err(QvmError::VmNotRunning(name.to_string()));
+        }
+        let sock = monitor_path(&vm_dir);
+        if !sock.exists() {
+            return Err(QvmError::Invalid(format!(
+                "VM '{}' has no monitor socket (it was started by an older qvm; restart it with 'qvm restart {}')",
+                name, name
+            )));
+        }
+
+        eprintln!(
+            "Connected to the monitor of VM '{}' (Ctrl-] to detach; 'quit' here stops the VM)",
+            name
+        );
+        console::attach(&sock, "monitor")
+    }
+
+    /// Replace qvm with an ssh session to a running VM over its forwarded SSH port
+    pub fn ssh(&self, name: &str, user: Option<&str>, extra: &[String]) -> Result<()> {
+        find_vm_dir(name)?;
+        let cfg = load_conf(name)?;
+        let port = ssh_host_port(&cfg.network).ok_or_else(|| {
+            let user_nic = cfg.network.interfaces.iter().any(|nic| nic.mode == "user");
+            let hint = if user_nic { "" } else { " (needs a user-mode NIC)" };
+            QvmError::Invalid(format!(
+                "VM '{}' has no TCP forward to guest port {}; add one with 'qvm forward add {} 2222 {}'{}",
+                name, SSH_GUEST_PORT, name, SSH_GUEST_PORT, hint
+            ))
+        })?;
+        if !is_vm_running(name)? {
+            return Err(QvmError::VmNotRunning(name.to_string()));
+        }
+
+        let args = ssh_args(port, user.or(cfg.network.ssh_user.as_deref()), extra);
+        let quoted: Vec<String> = args.iter().map(|a| shell_quote(a)).collect();
+        debug!("exec ssh {}", quoted.join(" "));
+        exec_ssh(&args)
+    }
+
+    /// Load a VM's configuration
+    pub fn config(&self, name: &str) -> Result<VmConfig> {
+        find_vm_dir(name)?;
+        load_conf(name)
+    }
+
+    /// Every problem that would keep a VM from starting (see `VmConfig::validate`); empty
+    /// when it passes. Unlike the other commands this reads a vm.json with invalid values.
+    pub fn validate_vm(&self, name: &str) -> Result<Vec<ValidationError>> {
+        let vm_dir = find_vm_dir(name)?;
+        let cfg = load_conf_unchecked(&vm_dir)?;
+        Ok(cfg.validate().err().unwrap_or_default())
+    }
+
+    /// A best-effort libvirt domain XML equivalent of the VM
+    pub fn libvirt_xml(&self, name: &str) -> Result<String> {
+        Ok(libvirt::domain_xml(&self.config(name)?))
+    }
+
+    /// Absolute path of a VM's vm.json, directory or disk `Some(index)` (`qvm config-path`)
+    pub fn vm_path(&self, name: &str, what: VmPath) -> Result<PathBuf> {
+        let vm_dir = std::path::absolute(find_vm_dir(name)?)?;
+        match what {
+            VmPath::Config => Ok(conf_path(&vm_dir)),
+            VmPath::Root => Ok(vm_dir),
+            VmPath::Disk(index) => {
+                let cfg = load_conf(name)?;
+                let disk = cfg.paths.disks.get(index).ok_or_else(|| {
+                    QvmError::Invalid(format!(
+                        "VM '{}' has no disk {} (it has {})",
+                        name,
+                        index,
+                        cfg.paths.disks.len()
+                    ))
+                })?;
+                Ok(resolve_under_root(&vm_dir, &disk.path))
+            }
+        }
+    }
+
+    /// The full qemu-system-* argv `start_vm` would run with these options, without
+    /// starting anything or checking that the referenced files exist
+    pub fn qemu_command(&self, name: &str, opts: &StartOptions) -> Result<Vec<String>> {
+        find_vm_dir(name)?;
+        let cfg = load_conf(name)?;
+        let bin = match pick_vm_qemu_bin(&cfg) {
+            Ok(bin) => bin.display().to_string(),
+            Err(QvmError::QemuNotFound(arch)) => format!("qemu-system-{}", arch),
+            Err(e) => return Err(e),
+        };
+        let opts = StartOptions {
+            virtiofs: !cfg.shares.is_empty() && find_virtiofsd().is_some(),
+            ..opts.clone()
+        };
+
+        let mut argv = vec![bin];
+        argv.extend(build_qemu_args(&cfg, &opts)?);
+        Ok(argv)
+    }
+
+    /// Open a QMP session to a running VM
+    pub fn qmp(&self, name: &str) -> Result<QmpClient> {
+        let vm_dir = find_vm_dir(name)?;
+        if !is_vm_running(name)? {
+            return Err(QvmError::VmNotRunning(name.to_string()));
+        }
+        let sock = qmp_path(&vm_dir);
+        if !sock.exists() {
+            return Err(QvmError::Qmp(format!(
+                "no QMP socket at {} (was the VM started by an older qvm?)",
+                sock.display()
+            )));
+        }
+        QmpClient::connect(&sock)
+    }
+
+    /// Pause a running VM's vCPUs via QMP `stop`
+    pub fn pause_vm(&self, name: &str) -> Result<()> {
+        self.qmp(name)?.execute("stop", None)?;
+        println!("Paused VM '{}'", name);
+        Ok(())
+    }
+
+    /// Resume a paused VM via QMP `cont`
+    pub fn resume_vm(&self, name: &str) -> Result<()> {
+        self.qmp(name)?.execute("cont", None)?;
+        println!("Resumed VM '{}'", name);
+        Ok(())
+    }
+
+    /// Save the VM's display via QMP `screendump`: as PPM, or as PNG when `out` ends in `.png`
+    pub fn screenshot(&self, name: &str, out: &Path) -> Result<()> {
+        let vm_dir = find_vm_dir(name)?;
+        let mut qmp = self.qmp(name)?;
+        // qemu writes the file itself, relative to its own working directory
+        let out = std::env::current_dir()?.join(out);
+        let png = out.extension().is_some_and(|e| e.eq_ignore_ascii_case("png"));
+
+        if png {
+            let ppm = tempfile::Builder::new().prefix(".screendump").suffix(".ppm").tempfile_in(&vm_dir)?;
+            qmp.execute("screendump", Some(json!({ "filename": ppm.path() })))?;
+            let data = fs::read(ppm.path())?;
+            let (width, height, pixels) = screenshot::parse_ppm(&data)?;
+            fs::write(&out, screenshot::encode_png(width, height, pixels)?)?;
+        } else {
+            qmp.execute("screendump", Some(json!({ "filename": out })))?;
+        }
+        println!("Saved a screenshot of VM '{}' to {}", name, out.display());
+        Ok(())
+    }
+
+    /// Press each key combo (e.g. `ctrl-alt-delete`, `ret`) in turn via QMP `send-key`
+    pub fn sendkey(&self, name: &str, combos: &[String], hold_ms: u32) -> Result<()> {
+        // Reject a typo before any key of the sequence reaches the guest
+        let parsed = combos.iter().map(|c| parse_key_combo(c)).collect::<Result<Vec<_>>>()?;
+        let mut qmp = self.qmp(name)?;
+        for keys in &parsed {
+            qmp.execute("send-key", Some(send_key_arguments(keys, hold_ms)))?;
+        }
+        println!("Sent {} to VM '{}'", combos.join(" "), name);
+        Ok(())
+    }
+
+    /// Switch the VM's display mode and update the VNC or SPICE options that were given
+    pub fn set_display(&self, name: &str, params: SetDisplayParams) -> Result<()> {
+        let vm_dir = find_vm_dir(name)?;
+        let mut cfg = load_conf(name)?;
+        let display = &mut cfg.display;
+        display.mode = params.mode.clone();
+
+        let vnc = &mut display.vnc;
+        if params.vnc_unix {
+            vnc.use_unix = true;
+        } else if params.vnc_host.is_some() || params.vnc_display.is_some() {
+            vnc.use_unix = false;
+        }
+        if let Some(host) = params.vnc_host {
+            vnc.host = host;
+        }
+        if let Some(n) = params.vnc_display {
+            vnc.display = n;
+        }
+        if let Some(sock) = params.vnc_sock {
+            vnc.sock = sock;
+        }
+
+        let spice = &mut display.spice;
+        if params.spice_unix {
+            spice.use_unix = true;
+        } else if params.spice_addr.is_some() || params.spice_port.is_some() {
+            spice.use_unix = false;
+        }
+        if let Some(addr) = params.spice_addr {
+            spice.addr = addr;
+        }
+        if let Some(port) = params.spice_port {
+            spice.port = port;
+        }
+        if let Some(sock) = params.spice_sock {
+            spice.sock = sock;
+        }
+        if let Some(disable) = params.spice_disable_ticketing {
+            spice.disable_ticketing = disable;
+        }
+
+        if params.tls_cert_dir.is_some() || params.no_tls {
+            let tls = match display.mode.as_str() {
+                "vnc" => &mut display.vnc.tls,
+                "spice" => &mut display.spice.tls,
+                other => {
+                    return Err(QvmError::Invalid(format!(
+                        "TLS applies to the vnc and spice displays, not {}",
+                        other
+                    )))
+                }
+            };
+            *tls = match params.tls_cert_dir {
+                // Relative to where the command runs, not to the VM directory
+                Some(dir) => Some(DisplayTls {
+                    cert_dir: std::path::absolute(&dir)?,
+                    verify_peer: params.tls_verify_peer,
+                }),
+                None => None,
+            };
+        }
+        validate_display_tls(&cfg.display, &vm_dir)?;
+        save_conf(&cfg)?;
+
+        let tls = match cfg.display.mode.as_str() {
+            "vnc" => cfg.display.vnc.tls.is_some(),
+            "spice" => cfg.display.spice.tls.is_some(),
+            _ => false,
+        };
+        let when = if is_vm_running(name)? { " (takes effect on next start)" } else { "" };
+        println!(
+            "VM '{}' now uses the {} display{}{}",
+            name,
+            cfg.display.mode,
+            if tls { " over TLS" } else { "" },
+            when
+        );
+        Ok(())
+    }
+
+    /// Change the VM's host audio backend and, optionally, its sound card model
+    pub fn set_audio(&self, name: &str, backend: &str, model: Option<&str>) -> Result<()> {
+        find_vm_dir(name)?;
+        let mut cfg = load_conf(name)?;
+        cfg.audio.backend = backend.to_string();
+        if let Some(model) = model {
+            cfg.audio.model = model.to_string();
+        }
+        save_conf(&cfg)?;
+
+        let when = if is_vm_running(name)? { " (takes effect on next start)" } else { "" };
+        if backend == "none" {
+            println!("Removed the sound card from VM '{}'{}", name, when);
+        } else {
+            println!(
+                "VM '{}' now plays {} audio through {}{}",
+                name, cfg.audio.model, backend, when
+            );
+        }
+        Ok(())
+    }
+
+    /// Set one vm.json field by dotted key (see `config::set::set_key`) and save it
+    pub fn set_config_key(&self, name: &str, key: &str, value: &str) -> Result<()> {
+        find_vm_dir(name)?;
+        let cfg = load_conf(name)?;
+        let outcome = set_key(&cfg, key, value)?;
+        for warning in &outcome.warnings {
+            warn!("{warning}");
+        }
+        save_conf(&outcome.config)?;
+
+        println!("Set {} of VM '{}' to {} (was {})", key, name, value, outcome.old);
+        if is_vm_running(name)? {
+            warn!("VM '{}' is running; the change takes effect after 'qvm restart {}'", name, name);
+        }
+        Ok(())
+    }
+
+    /// Mark a VM to be started (or no longer started) by `qvm start --all`
+    pub fn set_autostart(&self, name: &str, enabled: bool) -> Result<()> {
+        find_vm_dir(name)?;
+        let mut cfg = load_conf(name)?;
+        let state = if enabled { "enabled" } else { "disabled" };
+        if cfg.meta.autostart == enabled {
+            println!("Autostart is already {} for VM '{}'", state, name);
+            return Ok(());
+        }
+        cfg.meta.autostart = enabled;
+        save_conf(&cfg)?;
+        println!("Autostart {} for VM '{}'", state, name);
+        Ok(())
+    }
+
+    /// Start every VM with autostart enabled in the background. A VM that fails to start
+    /// is recorded and the rest are still started.
+    pub fn start_autostart_vms(&self, opts: &StartOptions) -> Result<StartAllSummary> {
+        let opts = StartOptions {
+            daemon: true,
+            ..opts.clone()
+        };
+        let mut summary = StartAllSummary::default();
+        for VmEntry { name, config, .. } in all_vms()? {
+            match config {
+                Ok(cfg) if cfg.meta.autostart => {}
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("skipping VM '{}': {}", name, e);
+                    continue;
+                }
+            }
+            match self.start_vm(&name, &opts) {
+                Ok(_) => summary.started.push(name),
+                Err(QvmError::VmRunning { .. }) => summary.already_running.push(name),
+                Err(e) => summary.failed.push(StartFailure {
+                    name,
+                    error: e.to_string(),
+                }),
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Set (or with `None`, clear) the password of the VM's VNC or SPICE display and,
+    /// if the VM is running, apply it immediately over QMP `set_password`
+    pub fn set_password(&self, name: &str, spec: Option<String>) -> Result<()> {
+        let vm_dir = find_vm_dir(name)?;
+        let mut cfg = load_conf(name)?;
+
+        let protocol = cfg.display.mode.clone();
+        let slot = match protocol.as_str() {
+            "vnc" => &mut cfg.display.vnc.password,
+            "spice" => &mut cfg.display.spice.password,
+            other => {
+                return Err(QvmError::Invalid(format!(
+                    "VM '{}' uses the '{}' display, which has no password; switch it to vnc or spice first",
+                    name, other
+                )))
+            }
+        };
+        if spec.as_deref() == Some("") {
+            return Err(QvmError::Invalid("password must not be empty".to_string()));
+        }
+        if spec.as_deref().is_some_and(|s| !s.starts_with(PASSWORD_ENV_PREFIX)) {
+            warn!(
+                "the password is stored in plain text in vm.json (readable only by you); \
+                 use --env to reference an environment variable instead"
+            );
+        }
+
+        // A running VM gets the new secret right away, so resolve it before saving anything
+        let live = match (&spec, running_vm_pid(&vm_dir)) {
+            (Some(spec), Some(pid)) => Some((pid, resolve_password(spec)?)),
+            _ => None,
+        };
+        let cleared = spec.is_none();
+        *slot = spec;
+        save_conf(&cfg)?;
+
+        if let Some((pid, secret)) = live {
+            apply_display_password(&vm_dir, pid, &protocol, &secret).map_err(|e| {
+                QvmError::Qmp(format!(
+                    "{} (a VM started without a password picks it up on 'qvm restart {}')",
+                    e, name
+                ))
+            })?;
+            println!("Set the {} password of running VM '{}'", protocol, name);
+        } else if cleared {
+            println!(
+                "Cleared the {} password of VM '{}' (a running VM keeps it until restarted)",
+                protocol, name
+            );
+        } else {
+            println!("Set the {} password of VM '{}'", protocol, name);
+        }
+        Ok(())
+    }
+
+    /// Persist (or with `None`, clear) the ISO attached on every start
+    pub fn set_iso(&self, name: &str, iso: Option<PathBuf>) -> Result<()> {
+        let mut cfg = load_conf(name)?;
+        let iso = iso.as_deref().map(expand_path).transpose()?;
+        match &iso {
+            Some(p) => println!("VM '{}' will boot from {} on start", name, p.display()),
+            None => println!("Detached ISO from VM '{}'", name),
+        }
+        cfg.paths.iso = iso;
+        save_conf(&cfg)
+    }
+
+    /// Take an internal snapshot: `savevm` on a running VM, `qemu-img snapshot -c` on
+    /// every qcow2 disk of a stopped one
+    pub fn snapshot_create(&self, name: &str, tag: &str) -> Result<()> {
+        if is_vm_running(name)? {
+            self.hmp_checked(name, &format!("savevm {}", tag))?;
+        } else {
+            for disk in self.qcow2_disks(name)? {
+                snapshot::create(&disk, tag)?;
+            }
+        }
+        println!("Created snapshot '{}' of VM '{}'", tag, name);
+        Ok(())
+    }
+
+    /// List internal snapshots of a VM's boot disk (or all disks when running)
+    pub fn snapshot_list(&self, name: &str) -> Result<Vec<Snapshot>> {
+        if is_vm_running(name)? {
+            let out = self.qmp(name)?.human_command("info snapshots")?;
+            Ok(snapshot::parse_snapshot_list(&out))
+        } else {
+            match self.qcow2_disks(name)?.first() {
+                Some(disk) => snapshot::list(disk),
+                None => Ok(Vec::new()),
+            }
+        }
+    }
+
+    /// Revert a VM to an internal snapshot: `loadvm` when running, `qemu-img snapshot -a`
+    /// on every qcow2 disk when stopped
+    pub fn snapshot_restore(&self, name: &str, tag: &str) -> Result<()> {
+        if is_vm_running(name)? {
+            self.hmp_checked(name, &format!("loadvm {}", tag))?;
+        } else {
+            for disk in self.qcow2_disks(name)? {
+                snapshot::apply(&disk, tag)?;
+            }
+        }
+        println!("Restored VM '{}' to snapshot '{}'", name, tag);
+        Ok(())
+    }
+
+    /// Share a host directory into the guest under a mount tag (takes effect on next start)
+    pub fn share_add(&self, name: &str, path: &Path, tag: Option<&str>, readonly: bool) -> Result<()> {
+        let mut cfg = load_conf(name)?;
+        let path = expand_path(path)?;
+        if !path.is_dir() {
+            return Err(QvmError::Invalid(format!(
+                "Not a directory: {}",
+                path.display()
+            )));
+        }
+        let path = path.canonicalize()?;
+        let tag = match tag {
+            Some(t) => t.to_string(),
+            None => path
+                .file_name()
+                .map(|f| f.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "share".to_string()),
+        };
+        validate_tag(&tag)?;
+        if cfg.shares.iter().any(|s| s.tag == tag) {
+            return Err(QvmError::Invalid(format!(
+                "VM '{}' already has a share tagged '{}'",
+                name, tag
+            )));
+        }
+
+        cfg.shares.push(Share { path: path.clone(), tag: tag.clone(), readonly });
+        save_conf(&cfg)?;
+
+        println!("Sharing {} with VM '{}' as '{}'", path.display(), name, tag);
+        if is_vm_running(name)? {
+            info!("the share is attached on the next start");
+        }
+        Ok(())
+    }
+
+    /// Stop sharing the directory with the given mount tag
+    pub fn share_remove(&self, name: &str, tag: &str) -> Result<()> {
+        let mut cfg = load_conf(name)?;
+        let before = cfg.shares.len();
+        cfg.shares.retain(|s| s.tag != tag);
+        if cfg.shares.len() == before {
+            return Err(QvmError::Invalid(format!(
+                "VM '{}' has no share tagged '{}'",
+                name, tag
+            )));
+        }
+        save_conf(&cfg)?;
+
+        println!("Removed share '{}' from VM '{}'", tag, name);
+        Ok(())
+    }
+
+    /// List a VM's shared directories
+    pub fn share_list(&self, name: &str) -> Result<Vec<Share>> {
+        Ok(load_conf(name)?.shares)
+    }
+
+    /// Attach a USB device (`tablet`, `keyboard` or host `vendor:product`) on the next start
+    pub fn usb_add(&self, name: &str, spec: &str) -> Result<()> {
+        let mut cfg = load_conf(name)?;
+        let device = parse_usb_device(spec)?;
+        if cfg.usb.contains(&device) {
+            return Err(QvmError::Invalid(format!(
+                "VM '{}' already has USB device '{}'",
+                name, device
+            )));
+        }
+        if device.kind == "host" {
+            warn!(
+                "host USB passthrough needs write access to the device node (root, or a udev rule on Linux) and takes the device away from the host while the VM runs"
+            );
+        }
+        cfg.usb.push(device.clone());
+        save_conf(&cfg)?;
+
+        println!("Added USB device '{}' to VM '{}'", device, name);
+        if is_vm_running(name)? {
+            info!("the device is attached on the next start");
+        }
+        Ok(())
+    }
+
+    /// Detach a USB device
+    pub fn usb_remove(&self, name: &str, spec: &str) -> Result<()> {
+        let mut cfg = load_conf(name)?;
+        let device = parse_usb_device(spec)?;
+        let before = cfg.usb.len();
+        cfg.usb.retain(|d| *d != device);
+        if cfg.usb.len() == before {
+            return Err(QvmError::Invalid(format!(
+                "VM '{}' has no USB device '{}'",
+                name, device
+            )));
+        }
+        save_conf(&cfg)?;
+
+        println!("Removed USB device '{}' from VM '{}'", device, name);
+        Ok(())
+    }
+
+    /// List a VM's USB devices
+    pub fn usb_list(&self, name: &str) -> Result<Vec<UsbDevice>> {
+        Ok(load_conf(name)?.usb)
+    }
+
+    /// Add a port forward to NIC `nic` (by default the first user-mode NIC); applied live
+    /// with `hostfwd_add` when the VM is running
+    pub fn forward_add(&self, name: &str, forward: Forward, nic: Option<usize>) -> Result<()> {
+        let mut cfg = load_conf(name)?;
+        if forward.host_port == 0 || forward.guest_port == 0 {
+            return Err(QvmError::Invalid("Port numbers must be between 1 and 65535".into()));
+        }
+        let index = match nic {
+            Some(i) => i,
+            None => cfg.network.default_forward_nic().ok_or_else(|| {
+                QvmError::Invalid(format!("VM '{}' has no network interfaces (add one with 'qvm nic add')", name))
+            })?,
+        };
+        let interfaces = cfg.network.interfaces.len();
+        let Some(iface) = cfg.network.interfaces.get(index) else {
+            return Err(QvmError::Invalid(format!(
+                "VM '{}' has no NIC {} ({} configured)",
+                name, index, interfaces
+            )));
+        };
+        if cfg
+            .network
+            .interfaces
+            .iter()
+            .flat_map(|nic| &nic.forwards)
+            .any(|f| f.protocol == forward.protocol && f.host_port == forward.host_port)
+        {
+            return Err(QvmError::Invalid(format!(
+                "VM '{}' already forwards {} host port {}",
+                name, forward.protocol, forward.host_port
+            )));
+        }
+
+        let mode = iface.mode.clone();
+        if mode == "user" && is_vm_running(name)? {
+            self.hmp_checked(name, &format!("hostfwd_add {} {}", netdev_id(index), forward.hostfwd()))?;
+        }
+        cfg.network.interfaces[index].forwards.push(forward.clone());
+        save_conf(&cfg)?;
+
+        println!(
+            "Forwarding {} host port {} to guest port {} on VM '{}'",
+            forward.protocol, forward.host_port, forward.guest_port, name
+        );
+        if mode != "user" {
+            info!("forwards only take effect in user network mode (NIC {}: {})", index, mode);
+        }
+        Ok(())
+    }
+
+    /// Remove the port forward on `host_port`, whichever NIC has it; removed live when the
+    /// VM is running
+    pub fn forward_remove(&self, name: &str, protocol: &str, host_port: u16) -> Result<()> {
+        let mut cfg = load_conf(name)?;
+        let (nic, index) = cfg
+            .network
+            .interfaces
+            .iter()
+            .enumerate()
+            .find_map(|(i, nic)| {
+                let pos = nic.forwards.iter().position(|f| f.protocol == protocol && f.host_port == host_port);
+                pos.map(|pos| (i, pos))
+            })
+            .ok_or_else(|| {
+                QvmError::Invalid(format!(
+                    "VM '{}' has no {} forward on host port {}",
+                    name, protocol, host_port
+                ))
+            })?;
+
+        if cfg.network.interfaces[nic].mode == "user" && is_vm_running(name)? {
+            self.hmp_checked(
+                name,
+                &format!("hostfwd_remove {} {}::{}", netdev_id(nic), protocol, host_port),
+            )?;
+        }
+        cfg.network.interfaces[nic].forwards.remove(index);
+        save_conf(&cfg)?;
+
+        println!("Removed {} forward on host port {} from VM '{}'", protocol, host_port, name);
+        Ok(())
+    }
+
+    /// List a VM's configured port forwards with the index of the NIC each belongs to
+    pub fn forward_list(&self, name: &str) -> Result<Vec<(usize, Forward)>> {
+        let cfg = load_conf(name)?;
+        let forwards = cfg.network.interfaces.into_iter().enumerate();
+        Ok(forwards.flat_map(|(i, nic)| nic.forwards.into_iter().map(move |f| (i, f))).collect())
+    }
+
+    /// Add a NIC (a random MAC unless `nic.mac` is set); it is attached on the next start
+    pub fn nic_add(&self, name: &str, mut nic: NetworkInterface) -> Result<usize> {
+        let mut cfg = load_conf(name)?;
+        nic.mac = if nic.mac.is_empty() { random_mac() } else { normalize_mac(&nic.mac)? };
+        nic.forwards.clear();
+        cfg.network.interfaces.push(nic.clone());
+        validate_choices(&cfg)?;
+        validate_network(&cfg.network)?;
+        save_conf(&cfg)?;
+
+        let index = cfg.network.interfaces.len() - 1;
+        println!("Added NIC {} ({}, {}) to VM '{}'", index, nic.mode, nic.mac, name);
+        if is_vm_running(name)? {
+            info!("the NIC is attached on the next start");
+        }
+        Ok(index)
+    }
+
+    /// Remove the NIC at `index` with its forwards; later NICs move up one. Refused while
+    /// the VM runs, since that would renumber the live netdevs.
+    pub fn nic_remove(&self, name: &str, index: usize) -> Result<()> {
+        let mut cfg = load_conf(name)?;
+        if index >= cfg.network.interfaces.len() {
+            return Err(QvmError::Invalid(format!(
+                "VM '{}' has no NIC {} ({} configured)",
+                name,
+                index,
+                cfg.network.interfaces.len()
+            )));
+        }
+        if is_vm_running(name)? {
+            return Err(QvmError::VmRunning {
+                name: name.to_string(),
+                action: "remove a NIC from",
+            });
+        }
+        let nic = cfg.network.interfaces.remove(index);
+        save_conf(&cfg)?;
+
+        println!("Removed NIC {} ({}, {}) from VM '{}'", index, nic.mode, nic.mac, name);
+        Ok(())
+    }
+
+    /// List a VM's NICs in PCI order
+    pub fn nic_list(&self, name: &str) -> Result<Vec<NetworkInterface>> {
+        Ok(load_conf(name)?.network.interfaces)
+    }
+
+    /// Run an HMP command that prints nothing on success
+    fn hmp_checked(&self, name: &str, command_line: &str) -> Result<()> {
+        let out = self.qmp(name)?.human_command(command_line)?;
+        if !out.trim().is_empty() {
+            return Err(QvmError::Qmp(format!("{}: {}", command_line, out.trim())));
+        }
+        Ok(())
+    }
+
+    /// Resolved paths of a VM's qcow2 disks (raw images cannot hold snapshots)
+    fn qcow2_disks(&self, name: &str) -> Result<Vec<PathBuf>> {
+        let vm_dir = find_vm_dir(name)?;
+        let cfg = load_conf(name)?;
+        Ok(cfg
+            .paths
+            .disks
+            .iter()
+            .filter(|d| d.format == "qcow2")
+            .map(|d| resolve_under_root(&vm_dir, &d.path))
+            .collect())
+    }
+
+    /// Stop a VM: press its ACPI power button over QMP and wait up to `timeout` for the
+    /// guest to power off, then SIGTERM qemu and finally SIGKILL it. Without QMP, SIGTERM
+    /// gets the whole `timeout`; `force` skips the guest shutdown. Returns false if the VM
+    /// was not running. The VM's `on_stop` hook runs once it has stopped.
+    pub fn stop_vm(&self, name: &str, timeout: Duration, force: bool) -> Result<bool> {
+        self.stop_child(name, timeout, force, None)
+    }
+
+    /// `stop_vm` for a VM whose qemu is `child` when this process spawned it
+    fn stop_child(&self, name: &str, timeout: Duration, force: bool, child: Option<&mut Child>) -> Result<bool> {
+        let stopped = self.halt(name, timeout, force, child)?;
+        if stopped {
+            let cfg = load_conf(name)?;
+            if let Some(script) = &cfg.hooks.on_stop {
+                run_hook("stop", script, &cfg)?;
+            }
+        }
+        Ok(stopped)
+    }
+
+    /// `stop_vm` without the hook, which runs only after the lock is released. qemu's exit
+    /// is seen through `child` when given: an unreaped child still looks alive by its PID
+    /// (on macOS, which has no /proc to tell a zombie).
+    fn halt(&self, name: &str, timeout: Duration, force: bool, mut child: Option<&mut Child>) -> Result<bool> {
+        let vm_dir = find_vm_dir(name)?;
+        let _lock = VmLock::acquire(name, &vm_dir)?;
+        let pid_file = pid_path(&vm_dir);
+
+        let pid = match running_vm_pid(&vm_dir) {
+            Some(pid) => pid,
+            None => {
+                // Missing, malformed or stale pidfile (already removed): nothing to stop
+                stop_helpers(&vm_dir);
+                return Ok(false);
+            }
+        };
+
+        let mut alive = || match child.as_mut() {
+            Some(child) => matches!(child.try_wait(), Ok(None)),
+            None => process_alive(pid),
+        };
+
+        // Ask the guest OS to shut down first; qemu exits once it has powered off
+        let powering_down = !force
+            && match request_powerdown(&vm_dir) {
+                Ok(sent) => sent,
+                Err(e) => {
+                    debug!("cannot ask VM '{}' to power off: {}", name, e);
+                    false
+                }
+            };
+        let term_wait = if powering_down {
+            info!("waiting up to {}s for VM '{}' to power off", timeout.as_secs(), name);
+            if wait_while(timeout, &mut alive) {
+                let _ = fs::remove_file(&pid_file);
+                stop_helpers(&vm_dir);
+                return Ok(true);
+            }
+            warn!(
+                "VM '{}' did not power off within {}s, terminating qemu",
+                name,
+                timeout.as_secs()
+            );
+            TERM_GRACE
+        } else if force {
+            TERM_GRACE
+        } else {
+            timeout
+        };
+
+        debug!("sending SIGTERM to qemu (pid {})", pid);
+        signal_process(pid, libc::SIGTERM)?;
+        if !wait_while(term_wait, &mut alive) {
+            warn!(
+                "VM '{}' did not exit within {}s, sending SIGKILL",
+                name,
+                term_wait.as_secs()
+            );
+            signal_process(pid, libc::SIGKILL)?;
+            if !wait_while(Duration::from_secs(5), &mut alive) {
+                return Err(QvmError::CommandFailed(format!(
+                    "Failed to stop VM '{}' (pid {})",
+                    name, pid
+                )));
+            }
+        }
+
+        let _ = fs::remove_file(&pid_file);
+        stop_helpers(&vm_dir);
+        Ok(true)
+    }
+}
+
+/// Make `vm.pid` hold the qemu child's PID. qemu writes the file itself (`-pidfile`) once
+/// it has initialized, so right after spawn it can be missing or stale; qvm fills that
+/// window, and rewrites the file should qemu's copy ever disagree.
+fn record_pid(vm_dir: &Path, pid: u32) -> Result<()> {
+    let pid_file = pid_path(vm_dir);
+    match read_pid_file(&pid_file) {
+        Some(recorded) if recorded == pid as i32 => {}
+        Some(recorded) => {
+            warn!("{} names pid {}, not qemu's pid {}; correcting it", pid_file.display(), recorded, pid);
+            fs::write(&pid_file, pid.to_string())?;
+        }
+        None => fs::write(&pid_file, pid.to_string())?,
+    }
+    Ok(())
+}
+
+/// What deleting VM `name` means for disks shared through backing files: the golden
+/// images its overlays read from are kept, and other VMs' overlays of its disks break
+fn shared_disk_notes(name: &str, vm_dir: &Path) -> Vec<String> {
+    let Ok(config) = load_conf_from_dir(vm_dir) else {
+        return Vec::new();
+    };
+    let others: Vec<(String, VmConfig)> = all_vms()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|entry| entry.name != name)
+        .filter_map(|entry| entry.config.ok().map(|cfg| (entry.name, cfg)))
+        .collect();
+
+    let mut notes = Vec::new();
+    for disk in &config.paths.disks {
+        let path = resolve_under_root(vm_dir, &disk.path);
+        if let Some(backing) = &disk.backing {
+            notes.push(format!(
+                "disk {} is an overlay of shared backing file {}, which is kept",
+                path.display(),
+                backing.display()
+            ));
+        }
+        // Backing paths are recorded canonical
+        let path = fs::canonicalize(&path).unwrap_or(path);
+        let users: Vec<&str> = others
+            .iter()
+            .filter(|(_, cfg)| cfg.paths.disks.iter().any(|d| d.backing.as_deref() == Some(path.as_path())))
+            .map(|(other, _)| other.as_str())
+            .collect();
+        if !users.is_empty() {
+            notes.push(format!(
+                "disk {} is the backing file of VM(s) {}, whose disks will no longer open",
+                path.display(),
+                users.join(", ")
+            ));
+        }
+    }
+    notes
+}
+
+/// Press the guest's ACPI power button over QMP. Returns false without pressing it when
+/// the guest cannot react (paused, or not running guest code).
+fn request_powerdown(vm_dir: &Path) -> Result<bool> {
+    let mut qmp = QmpClient::connect(&qmp_path(vm_dir))?;
+    let status = qmp.execute("query-status", None)?;
+    if status.get("status").and_then(|s| s.as_str()) != Some("running") {
+        debug!("guest is {}, not shutting it down over ACPI", status["status"]);
+        return Ok(false);
+    }
+    qmp.execute("system_powerdown", None)?;
+    Ok(true)
+}
+
+/// Poll a just-spawned child for `grace` and return its status if it exited meanwhile
+fn exited_within(child: &mut std::process::Child, grace: Duration) -> Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + grace;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// The error for a qemu that exited right after it was spawned, ending with the last lines
+/// it printed (usually the reason: a bad argument, a missing or locked file)
+fn startup_failure(name: &str, status: ExitStatus, output: &str) -> QvmError {
+    let output = last_lines(output, 20).trim_end();
+    let mut msg = format!("qemu exited right after starting VM '{}' ({})", name, status);
+    if !output.is_empty() {
+        msg.push_str(":\n");
+        msg.push_str(output);
+    }
+    QvmError::CommandFailed(msg)
+}
+
+/// Send a display password to qemu, waiting for a just-spawned process to open its QMP socket
+fn apply_display_password(root: &Path, pid: i32, protocol: &str, password: &str) -> Result<()> {
+    let sock = qmp_path(root);
+    let deadline = Instant::now() + QMP_STARTUP_TIMEOUT;
+    let mut qmp = loop {
+        match QmpClient::connect(&sock) {
+            Ok(qmp) => break qmp,
+            Err(e) if Instant::now() >= deadline || !process_alive(pid) => return Err(e),
+            Err(_) => thread::sleep(Duration::from_millis(100)),
+        }
+    };
+    qmp.execute(
+        "set_password",
+        Some(json!({ "protocol": protocol, "password": password })),
+    )?;
+    Ok(())
+}
+
+/// Stop the per-VM helper daemons (swtpm, virtiofsd) once qemu is gone
+fn stop_helpers(root: &Path) {
+    stop_swtpm(root);
+    stop_virtiofsd(root);
+}
+
+/// Describe where a VM's display can be reached
+fn display_endpoint(display: &Display, root: &Path) -> String {
+    match display.mode.as_str() {
+        "vnc" if display.vnc.use_unix => format!(
+            "vnc unix:{}",
+            resolve_under_root(root, &display.vnc.sock).display()
+        ),
+        "vnc" => format!(
+            "vnc {}:{} (port {})",
+            display.vnc.host,
+            display.vnc.display,
+            display.vnc.port()
+        ),
+        "spice" if display.spice.use_unix => format!(
+            "spice unix:{}",
+            resolve_under_root(root, &display.spice.sock).display()
+        ),
+        "spice" => format!("spice {}:{}", display.spice.addr, display.spice.port),
+        other => other.to_string(),
+    }
+}
+
+/// Seconds since a running VM's pidfile was written, which approximates its boot time
+fn uptime_secs(pid_file: &Path) -> Option<u64> {
+    fs::metadata(pid_file)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+        .map(|d| d.as_secs())
+}
+
+/// Format a number of seconds as e.g. "1h 2m 3s"
+pub fn format_duration(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if h > 0 {
+        format!("{}h {}m {}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m {}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
 }
 
 impl Default for VmManager {
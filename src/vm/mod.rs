@@ -1,6 +1,24 @@
 //! VM management functionality
 
+pub mod archive;
+pub mod backup;
+pub mod cloud_init;
 pub mod config;
+pub mod console;
+pub mod hooks;
+pub mod keys;
+pub mod launch;
+pub mod lock;
+pub mod logs;
 pub mod manager;
+pub mod network;
+pub mod qmp;
+pub mod screenshot;
+pub mod share;
+pub mod snapshot;
+pub mod ssh;
 pub mod firmware;
-pub mod creator;
\ No newline at end of file
+pub mod tpm;
+pub mod usb;
+pub mod creator;
+pub mod disk;
\ No newline at end of file
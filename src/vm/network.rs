@@ -0,0 +1,162 @@
+//! Guest networking: QEMU -netdev/-device arguments for each network mode
+
+use crate::config::schema::{Boot, Network, NetworkInterface};
+use crate::utils::paths::qvm_home;
+use crate::utils::system::shell_quote;
+use crate::{QvmError, Result};
+use tracing::debug;
+use std::ffi::CStr;
+use std::path::Path;
+use std::process::Command;
+
+/// The entitlement that lets a process other than root use vmnet.framework
+pub const VMNET_ENTITLEMENT: &str = "com.apple.vm.networking";
+
+/// Netdev id of the guest NIC at `index`, also used by hostfwd_add/hostfwd_remove
+pub fn netdev_id(index: usize) -> String {
+    format!("net{}", index)
+}
+
+/// Build the -netdev/-device pairs for every configured NIC, or `-nic none` when there
+/// are none (qemu would otherwise add a default one)
+pub fn network_args(net: &Network, boot: &Boot) -> Result<Vec<String>> {
+    if net.interfaces.is_empty() {
+        return Ok(vec!["-nic".into(), "none".into()]);
+    }
+    let mut args = Vec::new();
+    for (index, nic) in net.interfaces.iter().enumerate() {
+        // Boot order letters n-p are the first three NICs
+        let bootindex = ['n', 'o', 'p'].get(index).and_then(|&letter| boot.bootindex(letter, false));
+        args.extend(nic_args(index, nic, bootindex)?);
+    }
+    Ok(args)
+}
+
+/// Build the -netdev/-device pair for the NIC at `index`, with `bootindex` if it is in
+/// the boot order.
+///
+/// `vmnet-shared` and `vmnet-bridged` use macOS vmnet.framework; `user` is QEMU's
+/// slirp stack with one `hostfwd` rule per configured forward.
+pub fn nic_args(index: usize, nic: &NetworkInterface, bootindex: Option<u32>) -> Result<Vec<String>> {
+    let id = netdev_id(index);
+    let netdev = match nic.mode.as_str() {
+        "vmnet-shared" => format!("vmnet-shared,id={}", id),
+        "vmnet-bridged" => {
+            if !interface_exists(&nic.bridge_if)? {
+                return Err(QvmError::Invalid(format!(
+                    "Bridge interface '{}' does not exist on this host",
+                    nic.bridge_if
+                )));
+            }
+            format!("vmnet-bridged,id={},ifname={}", id, nic.bridge_if)
+        }
+        "user" => {
+            let mut netdev = format!("user,id={}", id);
+            for f in &nic.forwards {
+                netdev.push_str(&format!(",hostfwd={}", f.hostfwd()));
+            }
+            netdev
+        }
+        other => {
+            return Err(QvmError::Invalid(format!(
+                "Unsupported network mode '{}' (expected vmnet-shared, vmnet-bridged or user)",
+                other
+            )))
+        }
+    };
+
+    let mut device = format!("virtio-net-pci,netdev={},mac={}", id, nic.mac);
+    if let Some(idx) = bootindex {
+        device.push_str(&format!(",bootindex={}", idx));
+    }
+    Ok(vec!["-netdev".into(), netdev, "-device".into(), device])
+}
+
+/// On macOS, refuse to start VM `vm` when one of its NICs uses vmnet but `qemu_bin`
+/// cannot: qvm is not root and the binary lacks `VMNET_ENTITLEMENT`. qemu itself only
+/// fails with a bare "cannot create vmnet interface". When codesign cannot tell, qemu
+/// is left to try.
+pub fn check_vmnet_access(vm: &str, net: &Network, qemu_bin: &Path) -> Result<()> {
+    let Some((index, nic)) = net.interfaces.iter().enumerate().find(|(_, n)| n.mode.starts_with("vmnet-")) else {
+        return Ok(());
+    };
+    if !cfg!(target_os = "macos") || unsafe { libc::geteuid() } == 0 {
+        return Ok(());
+    }
+    match vmnet_entitled(qemu_bin) {
+        Some(false) => Err(QvmError::Invalid(vmnet_remedy(vm, index, &nic.mode, qemu_bin, &qvm_home()?))),
+        _ => Ok(()),
+    }
+}
+
+/// Whether `bin` is signed with `VMNET_ENTITLEMENT`, None if codesign cannot tell
+fn vmnet_entitled(bin: &Path) -> Option<bool> {
+    let output = match Command::new("codesign").args(["-d", "--entitlements", "-", "--xml"]).arg(bin).output() {
+        Ok(output) => output,
+        Err(e) => {
+            debug!("cannot run codesign to check {} for vmnet: {}", bin.display(), e);
+            return None;
+        }
+    };
+    if !output.status.success() {
+        // An unsigned binary has no entitlements; any other failure is inconclusive
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        debug!("codesign -d {}: {}", bin.display(), stderr.trim());
+        return stderr.contains("not signed").then_some(false);
+    }
+    Some(grants_vmnet(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Whether `codesign -d --entitlements` output (an XML plist, or the `[Key] ... [Bool]`
+/// listing of newer codesign) sets `VMNET_ENTITLEMENT` to true
+pub fn grants_vmnet(entitlements: &str) -> bool {
+    let Some(i) = entitlements.find(VMNET_ENTITLEMENT) else {
+        return false;
+    };
+    let rest = entitlements[i + VMNET_ENTITLEMENT.len()..].trim_start_matches("</key>");
+    let value = rest.split_whitespace().find(|token| *token != "[Value]" && *token != "[Bool]");
+    value.is_some_and(|v| v == "true" || v.starts_with("<true/>"))
+}
+
+/// The error for a VM whose NIC `index` (vmnet `mode`) qemu cannot open, with the ways out
+pub fn vmnet_remedy(vm: &str, index: usize, mode: &str, qemu_bin: &Path, home: &Path) -> String {
+    format!(
+        "VM '{vm}' uses {mode} networking (network.interfaces.{index}), which macOS only allows \
+         for root or a qemu signed with the {entitlement} entitlement, and {bin} is neither. Either:\n  \
+         - start it as root: sudo qvm --qvm-home {home} start {vm}\n  \
+         - switch the NIC to user-mode networking: qvm set {vm} network.interfaces.{index}.mode user\n  \
+         - run a qemu signed with {entitlement} (an ad-hoc signature cannot carry it)",
+        vm = vm,
+        mode = mode,
+        index = index,
+        entitlement = VMNET_ENTITLEMENT,
+        bin = qemu_bin.display(),
+        home = shell_quote(&home.display().to_string()),
+    )
+}
+
+/// Check whether a host network interface with this name exists (via getifaddrs)
+pub fn interface_exists(name: &str) -> Result<bool> {
+    let mut ifaddrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return Err(QvmError::Io(std::io::Error::last_os_error()));
+    }
+
+    let mut found = false;
+    let mut cur = ifaddrs;
+    while !cur.is_null() {
+        // SAFETY: getifaddrs returned a valid list which stays alive until freeifaddrs
+        let entry = unsafe { &*cur };
+        if !entry.ifa_name.is_null() {
+            let ifname = unsafe { CStr::from_ptr(entry.ifa_name) };
+            if ifname.to_bytes() == name.as_bytes() {
+                found = true;
+                break;
+            }
+        }
+        cur = entry.ifa_next;
+    }
+
+    unsafe { libc::freeifaddrs(ifaddrs) };
+    Ok(found)
+}
@@ -0,0 +1,88 @@
+//! QEMU Machine Protocol (QMP) client over the VM's unix socket
+
+use crate::{QvmError, Result};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long to wait for qemu to answer a single command
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A connected QMP session that has completed the capabilities handshake
+pub struct QmpClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl QmpClient {
+    /// Connect to a QMP socket and negotiate capabilities
+    pub fn connect(sock: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(sock).map_err(|e| {
+            QvmError::Qmp(format!("cannot connect to {}: {}", sock.display(), e))
+        })?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let writer = stream.try_clone()?;
+        let mut client = QmpClient {
+            reader: BufReader::new(stream),
+            writer,
+        };
+
+        let greeting = client.read_message()?;
+        if greeting.get("QMP").is_none() {
+            return Err(QvmError::Qmp(format!("unexpected greeting: {}", greeting)));
+        }
+        client.execute("qmp_capabilities", None)?;
+        Ok(client)
+    }
+
+    /// Send a raw command object and return the full response (`return` or `error`)
+    pub fn send(&mut self, cmd: &Value) -> Result<Value> {
+        let mut line = serde_json::to_vec(cmd)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+
+        // Asynchronous events can arrive before the reply; skip them
+        loop {
+            let msg = self.read_message()?;
+            if msg.get("return").is_some() || msg.get("error").is_some() {
+                return Ok(msg);
+            }
+        }
+    }
+
+    /// Execute a command by name, returning its `return` value or the QMP error
+    pub fn execute(&mut self, command: &str, arguments: Option<Value>) -> Result<Value> {
+        let mut cmd = json!({ "execute": command });
+        if let Some(args) = arguments {
+            cmd["arguments"] = args;
+        }
+        let mut resp = self.send(&cmd)?;
+        if let Some(err) = resp.get("error") {
+            return Err(QvmError::Qmp(format!(
+                "{} failed: {}",
+                command,
+                err.get("desc").and_then(Value::as_str).unwrap_or("unknown error")
+            )));
+        }
+        Ok(resp["return"].take())
+    }
+
+    /// Run a human monitor (HMP) command through QMP and return its text output
+    pub fn human_command(&mut self, command_line: &str) -> Result<String> {
+        let out = self.execute(
+            "human-monitor-command",
+            Some(json!({ "command-line": command_line })),
+        )?;
+        Ok(out.as_str().unwrap_or_default().to_string())
+    }
+
+    fn read_message(&mut self) -> Result<Value> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(QvmError::Qmp("connection closed by qemu".to_string()));
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
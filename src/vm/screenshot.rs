@@ -0,0 +1,57 @@
+//! `qvm screenshot`: qemu's PPM screendumps, optionally re-encoded as PNG
+
+use crate::{QvmError, Result};
+
+/// Decode a binary PPM (`P6`, 8-bit) into its width, height and RGB pixels
+pub fn parse_ppm(data: &[u8]) -> Result<(u32, u32, &[u8])> {
+    let invalid = |why: &str| QvmError::Invalid(format!("Invalid PPM screendump: {}", why));
+
+    // Header: magic, width, height, maxval, separated by whitespace and `#` comments,
+    // then exactly one whitespace byte before the pixels
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while fields.len() < 4 {
+        match data.get(pos) {
+            None => return Err(invalid("truncated header")),
+            Some(b'#') => {
+                while data.get(pos).is_some_and(|&b| b != b'\n') {
+                    pos += 1;
+                }
+            }
+            Some(b) if b.is_ascii_whitespace() => pos += 1,
+            Some(_) => {
+                let start = pos;
+                while data.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+                    pos += 1;
+                }
+                fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+            }
+        }
+    }
+    pos += 1;
+
+    if fields[0] != "P6" {
+        return Err(invalid("not a binary (P6) PPM"));
+    }
+    let num = |s: &str| s.parse::<u32>().map_err(|_| invalid("bad header number"));
+    let (width, height, maxval) = (num(&fields[1])?, num(&fields[2])?, num(&fields[3])?);
+    if maxval != 255 {
+        return Err(invalid("only 8-bit PPM is supported"));
+    }
+    let len = width as usize * height as usize * 3;
+    let pixels = data.get(pos..pos + len).ok_or_else(|| invalid("truncated pixel data"))?;
+    Ok((width, height, pixels))
+}
+
+/// Encode 8-bit RGB pixels as a PNG
+pub fn encode_png(width: u32, height: u32, rgb: &[u8]) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(rgb))
+        .map_err(|e| QvmError::Invalid(format!("Cannot encode the screendump as PNG: {}", e)))?;
+    Ok(png)
+}
@@ -0,0 +1,145 @@
+//! Host directory sharing via virtiofs (virtiofsd) or 9p
+
+use crate::config::schema::Share;
+use crate::utils::system::{process_runs, read_pid_file, signal_process, wait_for_exit};
+use crate::{QvmError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Places distros install virtiofsd outside PATH
+const VIRTIOFSD_PATHS: &[&str] = &["/usr/libexec/virtiofsd", "/usr/lib/qemu/virtiofsd"];
+
+/// Longest mount tag virtio-fs accepts
+const MAX_TAG_LEN: usize = 36;
+
+/// Check that a mount tag is 1–36 characters of [A-Za-z0-9_-]
+pub fn validate_tag(tag: &str) -> Result<()> {
+    let ok = !tag.is_empty()
+        && tag.len() <= MAX_TAG_LEN
+        && tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !ok {
+        return Err(QvmError::Invalid(format!(
+            "Invalid share tag '{}': use 1-{} characters of letters, digits, '_' or '-'",
+            tag, MAX_TAG_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Locate a virtiofsd binary; shares fall back to 9p without one
+pub fn find_virtiofsd() -> Option<PathBuf> {
+    which::which("virtiofsd").ok().or_else(|| {
+        VIRTIOFSD_PATHS
+            .iter()
+            .map(PathBuf::from)
+            .find(|p| p.is_file())
+    })
+}
+
+fn socket_path(root: &Path, tag: &str) -> PathBuf {
+    root.join(format!("virtiofs-{}.sock", tag))
+}
+
+fn pid_path(root: &Path, tag: &str) -> PathBuf {
+    root.join(format!("virtiofsd-{}.pid", tag))
+}
+
+/// Launch one virtiofsd per share and wait for their vhost-user sockets
+pub fn start_virtiofsd(virtiofsd: &Path, root: &Path, shares: &[Share]) -> Result<()> {
+    for share in shares {
+        let sock = socket_path(root, &share.tag);
+        let _ = fs::remove_file(&sock);
+
+        let mut cmd = Command::new(virtiofsd);
+        cmd.arg(format!("--socket-path={}", sock.display()))
+            .arg(format!("--shared-dir={}", share.path.display()))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        if share.readonly {
+            cmd.arg("--readonly");
+        }
+        let child = cmd.spawn()?;
+        fs::write(pid_path(root, &share.tag), child.id().to_string())?;
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !sock.exists() {
+            if Instant::now() >= deadline {
+                stop_virtiofsd(root);
+                return Err(QvmError::CommandFailed(format!(
+                    "virtiofsd did not create its socket at {}",
+                    sock.display()
+                )));
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+    Ok(())
+}
+
+/// Terminate every virtiofsd started for the VM that still runs, and remove their pidfiles and sockets
+pub fn stop_virtiofsd(root: &Path) {
+    let Ok(entries) = fs::read_dir(root) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        let Some(tag) = file_name
+            .to_str()
+            .and_then(|n| n.strip_prefix("virtiofsd-"))
+            .and_then(|n| n.strip_suffix(".pid"))
+        else {
+            continue;
+        };
+        if let Some(pid) = read_pid_file(&entry.path()) {
+            // A virtiofsd that exited with its VM can leave a pidfile naming a reused PID
+            if process_runs(pid, "virtiofsd") {
+                let _ = signal_process(pid, libc::SIGTERM);
+                if !wait_for_exit(pid, Duration::from_secs(5)) {
+                    let _ = signal_process(pid, libc::SIGKILL);
+                }
+            }
+        }
+        let _ = fs::remove_file(socket_path(root, tag));
+        let _ = fs::remove_file(entry.path());
+    }
+}
+
+/// qemu arguments exposing the shares: vhost-user-fs devices (which need guest RAM
+/// in shared memory, see `launch::memory_args`) when `virtiofs` is set, 9p `-virtfs` otherwise
+pub fn share_args(shares: &[Share], root: &Path, virtiofs: bool) -> Vec<String> {
+    let mut args: Vec<String> = Vec::new();
+    if shares.is_empty() {
+        return args;
+    }
+
+    if virtiofs {
+        for (i, share) in shares.iter().enumerate() {
+            args.extend([
+                "-chardev".into(),
+                format!("socket,id=fs{},path={}", i, socket_path(root, &share.tag).display()),
+                "-device".into(),
+                format!("vhost-user-fs-pci,chardev=fs{},tag={}", i, share.tag),
+            ]);
+        }
+    } else {
+        for (i, share) in shares.iter().enumerate() {
+            let mut virtfs = format!(
+                "local,path={},mount_tag={},security_model=mapped-xattr,id=fs{}",
+                share.path.display(),
+                share.tag,
+                i
+            );
+            if share.readonly {
+                virtfs.push_str(",readonly=on");
+            }
+            args.extend(["-virtfs".into(), virtfs]);
+        }
+    }
+    args
+}
@@ -0,0 +1,82 @@
+//! Internal qcow2 snapshots via qemu-img (stopped VMs) or the monitor (running VMs)
+
+use crate::{QvmError, Result};
+use serde::Serialize;
+use std::path::Path;
+use std::process::Command;
+
+/// One entry of a snapshot listing
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub id: String,
+    pub tag: String,
+    pub vm_size: String,
+    pub date: String,
+    pub clock: String,
+}
+
+/// Create an internal snapshot on a stopped image
+pub fn create(disk: &Path, tag: &str) -> Result<()> {
+    qemu_img_snapshot(disk, &["-c", tag]).map(|_| ())
+}
+
+/// List internal snapshots of a stopped image
+pub fn list(disk: &Path) -> Result<Vec<Snapshot>> {
+    qemu_img_snapshot(disk, &["-l"]).map(|out| parse_snapshot_list(&out))
+}
+
+/// Revert a stopped image to an internal snapshot
+pub fn apply(disk: &Path, tag: &str) -> Result<()> {
+    qemu_img_snapshot(disk, &["-a", tag]).map(|_| ())
+}
+
+fn qemu_img_snapshot(disk: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("qemu-img")
+        .arg("snapshot")
+        .args(args)
+        .arg(disk)
+        .output()?;
+    if !output.status.success() {
+        return Err(QvmError::CommandFailed(format!(
+            "qemu-img snapshot {} failed for {}: {}",
+            args.join(" "),
+            disk.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parse the table printed by `qemu-img snapshot -l` or HMP `info snapshots`.
+///
+/// Rows look like `1  tag  1.2 MiB 2024-01-01 12:00:00  00:00:01.234  0`; the
+/// size column may or may not carry a unit and the ICOUNT column is optional,
+/// so fields are located relative to the date.
+pub fn parse_snapshot_list(text: &str) -> Vec<Snapshot> {
+    text.lines()
+        .filter_map(|line| {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let date_idx = tokens.iter().position(|t| is_date(t))?;
+            if date_idx < 3 || tokens.len() < date_idx + 3 {
+                return None;
+            }
+            Some(Snapshot {
+                id: tokens[0].to_string(),
+                tag: tokens[1].to_string(),
+                vm_size: tokens[2..date_idx].join(" "),
+                date: format!("{} {}", tokens[date_idx], tokens[date_idx + 1]),
+                clock: tokens[date_idx + 2].to_string(),
+            })
+        })
+        .collect()
+}
+
+fn is_date(token: &str) -> bool {
+    let b = token.as_bytes();
+    b.len() == 10
+        && b[4] == b'-'
+        && b[7] == b'-'
+        && b.iter()
+            .enumerate()
+            .all(|(i, c)| i == 4 || i == 7 || c.is_ascii_digit())
+}
@@ -0,0 +1,39 @@
+//! `qvm ssh`: reach a guest through its user-mode SSH port forward
+
+use crate::config::schema::Network;
+use crate::{QvmError, Result};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// Guest port sshd listens on
+pub const SSH_GUEST_PORT: u16 = 22;
+
+/// Host port of the TCP forward to the guest's SSH port on a user-mode NIC, if one is
+/// configured
+pub fn ssh_host_port(network: &Network) -> Option<u16> {
+    network
+        .interfaces
+        .iter()
+        .filter(|nic| nic.mode == "user")
+        .flat_map(|nic| &nic.forwards)
+        .find(|f| f.protocol == "tcp" && f.guest_port == SSH_GUEST_PORT)
+        .map(|f| f.host_port)
+}
+
+/// Arguments for `ssh`: the forwarded port, `[user@]127.0.0.1`, then `extra` (a remote
+/// command, since ssh stops parsing options at the destination)
+pub fn ssh_args(port: u16, user: Option<&str>, extra: &[String]) -> Vec<String> {
+    let dest = match user {
+        Some(user) => format!("{}@127.0.0.1", user),
+        None => "127.0.0.1".to_string(),
+    };
+    let mut args = vec!["-p".to_string(), port.to_string(), dest];
+    args.extend(extra.iter().cloned());
+    args
+}
+
+/// Replace this process with `ssh args...`; only returns on failure
+pub fn exec_ssh(args: &[String]) -> Result<()> {
+    let err = Command::new("ssh").args(args).exec();
+    Err(QvmError::CommandFailed(format!("Failed to run ssh: {}", err)))
+}
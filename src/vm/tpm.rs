@@ -0,0 +1,99 @@
+//! TPM 2.0 emulation via swtpm
+
+use crate::config::schema::Tpm;
+use crate::utils::paths::resolve_under_root;
+use crate::utils::system::{process_runs, read_pid_file, signal_process, wait_for_exit};
+use crate::{QvmError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Directory under the VM root holding the persistent TPM state
+const STATE_DIR: &str = "tpm";
+
+/// Pidfile written by swtpm itself
+fn swtpm_pid_path(root: &Path) -> PathBuf {
+    root.join("swtpm.pid")
+}
+
+/// Launch a daemonized swtpm for the VM and wait for its control socket to appear
+pub fn start_swtpm(root: &Path, tpm: &Tpm) -> Result<()> {
+    let swtpm = which::which("swtpm").map_err(|_| {
+        QvmError::CommandFailed(
+            "TPM is enabled but 'swtpm' was not found on PATH; install swtpm or disable the TPM"
+                .to_string(),
+        )
+    })?;
+
+    let state_dir = root.join(STATE_DIR);
+    fs::create_dir_all(&state_dir)?;
+
+    let sock = resolve_under_root(root, &tpm.sock);
+    let _ = fs::remove_file(&sock);
+
+    let status = Command::new(swtpm)
+        .arg("socket")
+        .arg("--tpm2")
+        .arg("--tpmstate")
+        .arg(format!("dir={}", state_dir.display()))
+        .arg("--ctrl")
+        .arg(format!("type=unixio,path={}", sock.display()))
+        .arg("--pid")
+        .arg(format!("file={}", swtpm_pid_path(root).display()))
+        .args(["--terminate", "--daemon"])
+        .status()?;
+    if !status.success() {
+        return Err(QvmError::CommandFailed("swtpm failed to start".to_string()));
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !sock.exists() {
+        if Instant::now() >= deadline {
+            stop_swtpm(root);
+            return Err(QvmError::CommandFailed(format!(
+                "swtpm did not create its socket at {}",
+                sock.display()
+            )));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    Ok(())
+}
+
+/// Terminate the VM's swtpm process, if it is still running, and remove its pidfile
+pub fn stop_swtpm(root: &Path) {
+    let pid_file = swtpm_pid_path(root);
+    if let Some(pid) = read_pid_file(&pid_file) {
+        // With --terminate swtpm exits along with qemu, leaving its pidfile behind; by now
+        // the PID may belong to something else
+        if process_runs(pid, "swtpm") {
+            let _ = signal_process(pid, libc::SIGTERM);
+            if !wait_for_exit(pid, Duration::from_secs(5)) {
+                let _ = signal_process(pid, libc::SIGKILL);
+            }
+        }
+    }
+    let _ = fs::remove_file(pid_file);
+}
+
+/// qemu arguments wiring the swtpm socket into a TPM device for the given arch
+pub fn tpm_args(tpm: &Tpm, arch: &str, root: &Path) -> Vec<String> {
+    let device = if arch == "x86_64" {
+        "tpm-crb,tpmdev=tpm0"
+    } else {
+        "tpm-tis-device,tpmdev=tpm0"
+    };
+    vec![
+        "-chardev".into(),
+        format!(
+            "socket,id=chrtpm,path={}",
+            resolve_under_root(root, &tpm.sock).display()
+        ),
+        "-tpmdev".into(),
+        "emulator,id=tpm0,chardev=chrtpm".into(),
+        "-device".into(),
+        device.into(),
+    ]
+}
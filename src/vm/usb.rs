@@ -0,0 +1,59 @@
+//! USB controller, emulated input devices and host passthrough
+
+use crate::config::schema::UsbDevice;
+use crate::{QvmError, Result};
+
+/// Parse a device spec: `tablet`, `keyboard`, or a host device as `vendor:product` in hex
+pub fn parse_usb_device(spec: &str) -> Result<UsbDevice> {
+    match spec {
+        "tablet" | "keyboard" => Ok(UsbDevice {
+            kind: spec.to_string(),
+            id: None,
+        }),
+        _ => {
+            let invalid = || {
+                QvmError::Invalid(format!(
+                    "Invalid USB device '{}': expected tablet, keyboard or a host id like 046d:c52b",
+                    spec
+                ))
+            };
+            let (vendor, product) = spec.split_once(':').ok_or_else(invalid)?;
+            for part in [vendor, product] {
+                if part.len() != 4 || u16::from_str_radix(part, 16).is_err() {
+                    return Err(invalid());
+                }
+            }
+            Ok(UsbDevice {
+                kind: "host".to_string(),
+                id: Some(spec.to_ascii_lowercase()),
+            })
+        }
+    }
+}
+
+/// Build the xHCI controller and one `-device` per configured USB device
+pub fn usb_args(devices: &[UsbDevice]) -> Result<Vec<String>> {
+    if devices.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut args = vec!["-device".into(), "qemu-xhci,id=usb".into()];
+    for dev in devices {
+        let device = match (dev.kind.as_str(), &dev.id) {
+            ("tablet", _) => "usb-tablet,bus=usb.0".to_string(),
+            ("keyboard", _) => "usb-kbd,bus=usb.0".to_string(),
+            ("host", Some(id)) => {
+                let (vendor, product) = id.split_once(':').unwrap_or((id, ""));
+                format!("usb-host,vendorid=0x{},productid=0x{},bus=usb.0", vendor, product)
+            }
+            _ => {
+                return Err(QvmError::Invalid(format!(
+                    "Unsupported USB device '{}' in vm.json",
+                    dev
+                )))
+            }
+        };
+        args.extend(["-device".into(), device]);
+    }
+    Ok(args)
+}
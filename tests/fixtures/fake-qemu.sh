@@ -0,0 +1,45 @@
+#!/bin/sh
+# Stands in for qemu-system-* in integration tests (via QVM_QEMU_BIN): records its PID
+# and arguments in $FAKE_QEMU_LOG, then idles like a running VM until signalled (the
+# signal is logged too). It stays the process qvm spawned, with this argv, so qvm
+# recognizes it as the VM's qemu. With $FAKE_QEMU_FAIL set it prints that to stderr and
+# exits 1 at once, like a qemu rejecting its arguments. `-version` reports
+# $FAKE_QEMU_VERSION (default 9.0.0) without touching the log. Like qemu it writes its
+# PID to the -pidfile and removes that file when it exits. With $FAKE_QEMU_IGNORE_TERM set
+# it ignores SIGTERM, like a wedged qemu only SIGKILL ends.
+log() {
+    [ -n "$FAKE_QEMU_LOG" ] && echo "$1" >> "$FAKE_QEMU_LOG"
+}
+stop() {
+    kill "$child" 2>/dev/null
+    [ -n "$pidfile" ] && rm -f "$pidfile"
+    log "signal $1"
+    exit 0
+}
+
+if [ "$1" = "-version" ]; then
+    echo "QEMU emulator version ${FAKE_QEMU_VERSION:-9.0.0}"
+    exit 0
+fi
+[ -n "$FAKE_QEMU_LOG" ] && : > "$FAKE_QEMU_LOG"
+log "pid $$"
+prev=
+for arg in "$@"; do
+    log "arg $arg"
+    [ "$prev" = "-pidfile" ] && pidfile=$arg
+    prev=$arg
+done
+if [ -n "$FAKE_QEMU_FAIL" ]; then
+    echo "$FAKE_QEMU_FAIL" >&2
+    exit 1
+fi
+[ -n "$pidfile" ] && echo $$ > "$pidfile"
+if [ -n "$FAKE_QEMU_IGNORE_TERM" ]; then
+    trap '' TERM
+    while :; do sleep 1; done
+fi
+trap 'stop TERM' TERM
+trap 'stop INT' INT
+sleep 600 &
+child=$!
+wait "$child"
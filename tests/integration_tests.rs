@@ -15,7 +15,7 @@ fn test_help_command() {
 #[test]
 fn test_completions_command() {
     let mut cmd = Command::cargo_bin("qvm").unwrap();
-    cmd.args(&["completions", "bash"])
+    cmd.args(["completions", "bash"])
         .assert()
         .success()
         .stdout(predicate::str::contains("_qvm"));
@@ -33,7 +33,7 @@ fn test_man_page_command() {
 #[test]
 fn test_delete_nonexistent_vm() {
     let mut cmd = Command::cargo_bin("qvm").unwrap();
-    cmd.args(&["delete", "nonexistent-vm", "--force"])
+    cmd.args(["delete", "nonexistent-vm", "--force"])
         .assert()
         .failure()
         .stderr(predicate::str::contains("VM 'nonexistent-vm' not found"));
@@ -48,7 +48,8 @@ fn test_create_and_delete_vm() {
     // Set HOME to our temp directory
     let mut cmd = Command::cargo_bin("qvm").unwrap();
     cmd.env("HOME", temp_home.path())
-        .args(&["create", "test-vm", "--mem", "2048", "--disk-size", "10G"])
+        .env_remove("QVM_HOME")
+        .args(["create", "test-vm", "--mem", "2048", "--disk-size", "10G"])
         .assert()
         .success()
         .stdout(predicate::str::contains("Created VM 'test-vm'"));
@@ -61,7 +62,8 @@ fn test_create_and_delete_vm() {
     // Delete the VM
     let mut cmd = Command::cargo_bin("qvm").unwrap();
     cmd.env("HOME", temp_home.path())
-        .args(&["delete", "test-vm", "--force"])
+        .env_remove("QVM_HOME")
+        .args(["delete", "test-vm", "--force"])
         .assert()
         .success()
         .stdout(predicate::str::contains("Successfully deleted VM 'test-vm'"));
@@ -70,13 +72,191 @@ fn test_create_and_delete_vm() {
     assert!(!vm_dir.exists());
 }
 
+#[test]
+fn test_delete_prompt_answers() {
+    let temp_home = TempDir::new().unwrap();
+    let qvm = || {
+        let mut cmd = Command::cargo_bin("qvm").unwrap();
+        cmd.env("QVM_HOME", temp_home.path());
+        cmd
+    };
+    qvm().args(["create", "kept", "--disk-size", "1G"]).assert().success();
+    let vm_dir = temp_home.path().join("kept.qvm");
+
+    // A no, or no answer at all, keeps the VM
+    for answer in ["n\n", ""] {
+        qvm()
+            .args(["delete", "kept"])
+            .write_stdin(answer)
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("About to delete VM 'kept'"))
+            .stdout(predicate::str::contains("Deletion cancelled."));
+        assert!(vm_dir.join("vm.json").is_file());
+    }
+
+    qvm()
+        .args(["--yes", "delete", "kept"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("About to delete VM 'kept'"))
+        .stderr(predicate::str::contains("[y/N]: y (--yes)"))
+        .stdout(predicate::str::contains("Successfully deleted VM 'kept'"));
+    assert!(!vm_dir.exists());
+}
+
+#[test]
+fn test_delete_json_output_stays_parseable() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "doomed", &[]).assert().success();
+
+    // The listing and the question go to stderr, leaving only the report on stdout
+    let output = qvm_with_fake_qemu(home, &log)
+        .args(["--output", "json", "delete", "doomed"])
+        .write_stdin("y\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["deleted"], true);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[y/N]: "));
+}
+
+#[test]
+fn test_export_libvirt_to_file() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "virsh", &[]).assert().success();
+
+    for flag in ["--file", "-f"] {
+        let xml = home.join("domain.xml");
+        let _ = fs::remove_file(&xml);
+        qvm_with_fake_qemu(home, &log)
+            .args(["export-libvirt", "virsh", flag])
+            .arg(&xml)
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Wrote libvirt domain for VM 'virsh'"));
+        assert!(fs::read_to_string(&xml).unwrap().contains("<name>virsh</name>"), "{}", flag);
+    }
+
+    // --output after the subcommand is the global output format
+    qvm_with_fake_qemu(home, &log)
+        .args(["export-libvirt", "virsh", "--output", "text"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("<domain"));
+}
+
+#[test]
+fn test_create_dry_run_writes_nothing() {
+    let temp_home = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["create", "preview-vm", "--disk-size", "10G", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"name\": \"preview-vm\""))
+        .stdout(predicate::str::contains("\"mac\": \"52:54:00:"));
+
+    assert!(!temp_home.path().join("preview-vm.qvm").exists());
+}
+
+#[test]
+fn test_create_deterministic_mac() {
+    let temp_home = TempDir::new().unwrap();
+    let mac_of = |name: &str, prefix: &str| -> String {
+        let output = Command::cargo_bin("qvm")
+            .unwrap()
+            .env("QVM_HOME", temp_home.path())
+            .args(["create", name, "--dry-run", "--mac-strategy", "deterministic", "--mac-prefix", prefix])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let cfg: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        cfg["network"]["interfaces"][0]["mac"].as_str().unwrap().to_string()
+    };
+
+    let mac = mac_of("repro", "52:54:00");
+    assert_eq!(mac_of("repro", "52:54:00"), mac);
+    assert_ne!(mac_of("other", "52:54:00"), mac);
+    assert_eq!(mac_of("repro", "02:00:00"), format!("02:00:00{}", &mac[8..]));
+
+    Command::cargo_bin("qvm")
+        .unwrap()
+        .env("QVM_HOME", temp_home.path())
+        .args(["create", "repro", "--dry-run", "--mac-prefix", "01:00:5e"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid MAC prefix '01:00:5e'"));
+}
+
+#[test]
+fn test_create_machine_and_extra_args() {
+    let temp_home = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["create", "tuned-vm", "--arch", "x86_64", "--machine", "pc-i440fx-8.2", "--dry-run"])
+        .args(["--extra-arg", "-rtc", "--extra-arg", "base=utc"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"machine\": \"pc-i440fx-8.2\""))
+        .stdout(predicate::str::contains("\"extra_args\": [\n    \"-rtc\",\n    \"base=utc\"\n  ]"));
+}
+
+#[test]
+fn test_create_raw_disk() {
+    let temp_home = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["create", "raw-vm", "--disk-format", "raw", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"path\": \"disk.raw\""))
+        .stdout(predicate::str::contains("\"format\": \"raw\""));
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["create", "raw-vm", "--disk-format", "vmdk", "--dry-run"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_create_profile_defaults_yield_to_flags() {
+    let temp_home = TempDir::new().unwrap();
+    let profiles = temp_home.path().join("profiles");
+    fs::create_dir_all(&profiles).unwrap();
+    fs::write(
+        profiles.join("small.json"),
+        r#"{"mem": 1024, "cores": 2, "display_mode": "headless"}"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["create", "profiled-vm", "--profile", "small", "--mem", "3072", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"mem_mb\": 3072"))
+        .stdout(predicate::str::contains("\"cores\": 2"))
+        .stdout(predicate::str::contains("\"mode\": \"headless\""));
+}
+
 #[test]
 fn test_create_vm_with_invalid_arch() {
     let temp_home = TempDir::new().unwrap();
 
     let mut cmd = Command::cargo_bin("qvm").unwrap();
     cmd.env("HOME", temp_home.path())
-        .args(&["create", "test-vm", "--arch", "invalid-arch"])
+        .env_remove("QVM_HOME")
+        .args(["create", "test-vm", "--arch", "invalid-arch"])
         .assert()
         .failure()
         .stderr(predicate::str::contains("Unsupported arch"));
@@ -105,4 +285,1771 @@ fn test_install_fish_completions() {
     // For simplicity, just check that the program says it succeeded
     // The actual file location depends on the system configuration
     println!("Fish completion install output: {}", stdout);
-}
\ No newline at end of file
+}
+#[test]
+fn test_start_nonexistent_vm() {
+    let temp_home = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("HOME", temp_home.path())
+        .env_remove("QVM_HOME")
+        .args(["start", "nonexistent-vm"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("VM 'nonexistent-vm' not found"));
+}
+
+#[test]
+fn test_restart_nonexistent_vm() {
+    let temp_home = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["restart", "nonexistent-vm", "--daemon"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("VM 'nonexistent-vm' not found"));
+}
+
+#[test]
+fn test_delete_pattern_batch() {
+    let temp_home = TempDir::new().unwrap();
+    for name in ["test-a", "test-b", "keep"] {
+        fs::create_dir_all(temp_home.path().join(format!("{}.qvm", name))).unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["delete", "test-*", "--force"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Deleted 2 VM(s), skipped 0"));
+
+    assert!(!temp_home.path().join("test-a.qvm").exists());
+    assert!(!temp_home.path().join("test-b.qvm").exists());
+    assert!(temp_home.path().join("keep.qvm").exists());
+}
+
+#[test]
+fn test_resize_disk_nonexistent_vm() {
+    let temp_home = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["resize-disk", "nonexistent-vm", "100G"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("VM 'nonexistent-vm' not found"));
+}
+
+#[test]
+fn test_stop_vm_not_running() {
+    let temp_home = TempDir::new().unwrap();
+    let vm_dir = temp_home.path().join("qvm").join("idle-vm.qvm");
+    fs::create_dir_all(&vm_dir).unwrap();
+    // A stale pidfile pointing at a PID that cannot exist
+    fs::write(vm_dir.join("vm.pid"), "999999999").unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("HOME", temp_home.path())
+        .env_remove("QVM_HOME")
+        .args(["stop", "idle-vm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("VM 'idle-vm' is not running"));
+
+    assert!(!vm_dir.join("vm.pid").exists());
+}
+
+#[test]
+fn test_read_commands_on_missing_home() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path().join("not-yet");
+    let qvm = || {
+        let mut cmd = Command::cargo_bin("qvm").unwrap();
+        cmd.env("QVM_HOME", &home);
+        cmd
+    };
+
+    qvm()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No VMs yet"))
+        .stderr(predicate::str::is_empty());
+    let output = qvm().args(["list", "--output", "json"]).output().unwrap();
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["vms"], serde_json::json!([]));
+    qvm().arg("ps").assert().success().stdout(predicate::str::contains("No running VMs"));
+    qvm()
+        .args(["status", "ghost"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not exist yet"));
+    // Reading never creates the home; creating a VM does
+    assert!(!home.exists());
+    qvm().args(["create", "first", "--disk-size", "1G"]).assert().success();
+    assert!(home.join("first.qvm/vm.json").is_file());
+}
+
+#[test]
+fn test_list_tag_filter() {
+    let temp_home = TempDir::new().unwrap();
+    for (name, tags) in [("web-vm", ["--tag", "web"]), ("db-vm", ["--tag", "db"])] {
+        let mut cmd = Command::cargo_bin("qvm").unwrap();
+        cmd.env("QVM_HOME", temp_home.path())
+            .args(["create", name, "--disk-size", "1G", "--description", "test box"])
+            .args(tags)
+            .assert()
+            .success();
+    }
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["list", "--tag", "web"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("web-vm"))
+        .stdout(predicate::str::contains("db-vm").not());
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["status", "db-vm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Description: test box"))
+        .stdout(predicate::str::contains("Tags: db"));
+}
+
+#[test]
+fn test_json_output() {
+    let temp_home = TempDir::new().unwrap();
+    fs::create_dir_all(temp_home.path().join("idle-vm.qvm")).unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["--output", "json", "stop", "idle-vm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"action\": \"stop\""))
+        .stdout(predicate::str::contains("\"was_running\": false"));
+
+    // idle-vm has no vm.json, so it is skipped
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["list", "--output", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"action\": \"list\""))
+        .stdout(predicate::str::contains("\"vms\": []"));
+}
+
+#[test]
+fn test_status_nonexistent_vm() {
+    let temp_home = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("HOME", temp_home.path())
+        .env_remove("QVM_HOME")
+        .args(["status", "nonexistent-vm", "--json"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("VM 'nonexistent-vm' not found"));
+}
+
+#[test]
+fn test_add_disk_requires_size_or_existing() {
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.args(["add-disk", "some-vm"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--size"));
+}
+
+#[test]
+fn test_create_disk_aio_native_needs_cache_none() {
+    let temp_home = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["create", "aio-vm", "--disk-aio", "native", "--dry-run"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("aio=native requires cache=none"));
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["create", "aio-vm", "--disk-aio", "native", "--disk-cache", "none", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"aio\": \"native\""));
+}
+
+#[test]
+fn test_home_and_config_path() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let qvm = || {
+        let mut cmd = Command::cargo_bin("qvm").unwrap();
+        cmd.env("QVM_HOME", home);
+        cmd
+    };
+
+    qvm().arg("home").assert().success().stdout(format!("{}\n", home.display()));
+    qvm()
+        .args(["config-path", "missing-vm"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("VM 'missing-vm' not found"));
+
+    qvm().args(["create", "paths-vm", "--disk", "/images/boot.raw"]).assert().success();
+    let vm_dir = home.join("paths-vm.qvm");
+    qvm()
+        .args(["config-path", "paths-vm"])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", vm_dir.join("vm.json").display()));
+    qvm()
+        .args(["config-path", "paths-vm", "--root"])
+        .assert()
+        .success()
+        .stdout(format!("{}\n", vm_dir.display()));
+    qvm().args(["config-path", "paths-vm", "--disk"]).assert().success().stdout("/images/boot.raw\n");
+    qvm()
+        .args(["config-path", "paths-vm", "--disk", "1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("has no disk 1"));
+}
+
+#[test]
+fn test_create_rejects_multicast_mac() {
+    let temp_home = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("HOME", temp_home.path())
+        .env_remove("QVM_HOME")
+        .args(["create", "mac-vm", "--mac", "01:00:5e:00:00:01"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("multicast/broadcast"));
+}
+
+#[test]
+fn test_nic_add_forward_remove() {
+    let temp_home = TempDir::new().unwrap();
+    let qvm = || {
+        let mut cmd = Command::cargo_bin("qvm").unwrap();
+        cmd.env("QVM_HOME", temp_home.path());
+        cmd
+    };
+
+    qvm().args(["create", "nic-vm", "--mac", "52:54:00:00:00:01"]).assert().success();
+    qvm()
+        .args(["nic", "add", "nic-vm", "--mac", "52:54:00:00:00:01"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("used by another NIC"));
+    qvm()
+        .args(["nic", "add", "nic-vm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Added NIC 1 (user,"));
+
+    // Forwards go to the user-mode NIC by default
+    qvm().args(["forward", "add", "nic-vm", "2222", "22"]).assert().success();
+    qvm()
+        .args(["forward", "list", "nic-vm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1    tcp"));
+    qvm()
+        .args(["nic", "list", "nic-vm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("vmnet-shared   52:54:00:00:00:01"))
+        .stdout(predicate::str::contains("user"));
+
+    qvm().args(["nic", "remove", "nic-vm", "1"]).assert().success();
+    qvm()
+        .args(["nic", "remove", "nic-vm", "1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("has no NIC 1"));
+    qvm()
+        .args(["forward", "list", "nic-vm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No port forwards"));
+}
+
+#[test]
+fn test_backup_stopped_vm() {
+    let temp_home = TempDir::new().unwrap();
+    let dest = temp_home.path().join("backups");
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["create", "backup-vm", "--disk-size", "1G"])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["--output", "json", "backup", "backup-vm", dest.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dest.join("backups.json")).unwrap()).unwrap();
+    let record = &manifest["backups"][0];
+    assert_eq!(record["vm"], "backup-vm");
+    assert_eq!(record["live"], false);
+    let dir = dest.join(record["dir"].as_str().unwrap());
+    assert!(dir.join("vm.json").is_file());
+    assert!(dir.join("disk0.qcow2").is_file());
+}
+
+#[test]
+fn test_compact_disks() {
+    let temp_home = TempDir::new().unwrap();
+    let qvm = || {
+        let mut cmd = Command::cargo_bin("qvm").unwrap();
+        cmd.env("QVM_HOME", temp_home.path());
+        cmd
+    };
+
+    qvm().args(["create", "bloated", "--disk-size", "1G"]).assert().success();
+    qvm()
+        .args(["compact", "bloated"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Compacted"))
+        .stdout(predicate::str::contains("disk.qcow2"));
+    assert!(temp_home.path().join("bloated.qvm/disk.qcow2").is_file());
+    qvm()
+        .args(["compact", "bloated", "--disk", "1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("has no disk 1"));
+
+    qvm().args(["create", "flat", "--disk-size", "1G", "--disk-format", "raw"]).assert().success();
+    qvm()
+        .args(["compact", "flat"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no qcow2 disks to compact"));
+}
+
+#[test]
+fn test_check_disks() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+
+    create_fake_vm(home, &log, "checked", &["--disk-size", "1G"]).assert().success();
+    qvm_with_fake_qemu(home, &log)
+        .args(["add-disk", "checked", "--size", "1G", "--format", "raw"])
+        .assert()
+        .success();
+
+    let output = qvm_with_fake_qemu(home, &log)
+        .args(["--output", "json", "check", "checked"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["disks"][0]["result"]["corruptions"], 0);
+    assert_eq!(report["disks"][1]["format"], "raw");
+    assert!(report["disks"][1]["result"].is_null());
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["check", "checked", "--repair"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Disk 0 ("))
+        .stdout(predicate::str::contains("no errors"))
+        .stdout(predicate::str::contains("raw images have no consistency checks"));
+
+    qvm_with_fake_qemu(home, &log).args(["start", "checked", "--daemon"]).assert().success();
+    qvm_with_fake_qemu(home, &log)
+        .args(["check", "checked"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("running"));
+    qvm_with_fake_qemu(home, &log).args(["stop", "checked", "--force"]).assert().success();
+}
+
+#[test]
+fn test_create_overlay_of_golden_image() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let qvm = || {
+        let mut cmd = Command::cargo_bin("qvm").unwrap();
+        cmd.env("QVM_HOME", home);
+        cmd
+    };
+
+    qvm().args(["create", "golden", "--disk-size", "1G"]).assert().success();
+    let golden = home.join("golden.qvm/disk.qcow2");
+    qvm()
+        .args(["create", "web", "--backing", golden.to_str().unwrap()])
+        .assert()
+        .success();
+    assert!(home.join("web.qvm/disk.qcow2").is_file());
+    let cfg: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(home.join("web.qvm/vm.json")).unwrap()).unwrap();
+    assert_eq!(
+        cfg["paths"]["disks"][0]["backing"],
+        fs::canonicalize(&golden).unwrap().to_str().unwrap()
+    );
+
+    qvm()
+        .args(["create", "missing", "--backing", home.join("nope.qcow2").to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does not exist"));
+    qvm()
+        .args(["create", "flat", "--disk-format", "raw", "--backing", golden.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("qcow2 overlay"));
+
+    qvm()
+        .args(["export", "web", home.join("web.tar").to_str().unwrap()])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("which is not included"));
+    qvm()
+        .args(["delete", "golden", "--force"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("backing file of VM(s) web"));
+}
+
+#[test]
+fn test_export_nonexistent_vm() {
+    let temp_home = TempDir::new().unwrap();
+    let archive = temp_home.path().join("out.tar");
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("HOME", temp_home.path())
+        .env_remove("QVM_HOME")
+        .args(["export", "nonexistent-vm", archive.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("VM 'nonexistent-vm' not found"));
+
+    assert!(!archive.exists());
+}
+
+#[test]
+fn test_monitor_vm_not_running() {
+    let temp_home = TempDir::new().unwrap();
+    fs::create_dir_all(temp_home.path().join("idle-vm.qvm")).unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .args(["monitor", "idle-vm"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("VM 'idle-vm' is not running"));
+}
+
+#[test]
+fn test_logs_without_log_file() {
+    let temp_home = TempDir::new().unwrap();
+    fs::create_dir_all(temp_home.path().join("qvm").join("quiet-vm.qvm")).unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("HOME", temp_home.path())
+        .env_remove("QVM_HOME")
+        .args(["logs", "quiet-vm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No log for VM 'quiet-vm'"));
+}
+
+#[test]
+fn test_doctor_reports_checks() {
+    let temp_home = TempDir::new().unwrap();
+
+    // Pass or fail depends on the host; the report itself must always be printed
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("HOME", temp_home.path())
+        .env_remove("QVM_HOME")
+        .args(["doctor"])
+        .assert()
+        .stdout(predicate::str::contains("qvm home"))
+        .stdout(predicate::str::contains("qemu-img"));
+}
+
+#[test]
+fn test_qvm_home_env_replaces_home_qvm() {
+    let home = TempDir::new().unwrap();
+    let qvm_home = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("HOME", home.path())
+        .env("QVM_HOME", qvm_home.path())
+        .args(["create", "env-vm", "--disk-size", "1G"])
+        .assert()
+        .success();
+    assert!(qvm_home.path().join("env-vm.qvm/vm.json").is_file());
+    assert!(!home.path().join("qvm").exists());
+}
+
+#[test]
+fn test_qvm_home_flag_overrides_env() {
+    let env_home = TempDir::new().unwrap();
+    let flag_home = TempDir::new().unwrap();
+    fs::create_dir_all(flag_home.path().join("flag-vm.qvm")).unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", env_home.path())
+        .args([
+            "--qvm-home",
+            flag_home.path().to_str().unwrap(),
+            "logs",
+            "flag-vm",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No log for VM 'flag-vm'"));
+}
+
+/// A `qvm` command whose qemu is `tests/fixtures/fake-qemu.sh`, logging to `log`
+fn qvm_with_fake_qemu(home: &std::path::Path, log: &std::path::Path) -> Command {
+    let fake = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fake-qemu.sh");
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", home)
+        .env("QVM_QEMU_BIN", fake)
+        .env("FAKE_QEMU_LOG", log);
+    cmd
+}
+
+/// `create NAME` for the fake qemu: an x86_64 TCG guest with 256 MB of memory, no display
+/// and empty firmware files in `home`, plus the `extra` create flags. A flag in `extra`
+/// replaces the default of the same name.
+fn create_fake_vm(home: &std::path::Path, log: &std::path::Path, name: &str, extra: &[&str]) -> Command {
+    for file in ["code.fd", "vars.fd"] {
+        if !home.join(file).exists() {
+            fs::write(home.join(file), b"").unwrap();
+        }
+    }
+    let mut cmd = qvm_with_fake_qemu(home, log);
+    cmd.args(["create", name]);
+    for (flag, value) in [("--arch", "x86_64"), ("--accel", "tcg"), ("--display-mode", "headless"), ("--mem", "256")] {
+        if !extra.contains(&flag) {
+            cmd.args([flag, value]);
+        }
+    }
+    cmd.arg("--firmware-code")
+        .arg(home.join("code.fd"))
+        .arg("--firmware-vars")
+        .arg(home.join("vars.fd"))
+        .args(extra);
+    cmd
+}
+
+/// Serve QMP on `sock` for the fake qemu, which has no monitor of its own: greet each
+/// connection, answer `query-status` from a run state that `stop` and `cont` change, and
+/// answer every other command with an empty return. Returns the commands received.
+fn serve_fake_qmp(sock: &std::path::Path) -> std::sync::Arc<std::sync::Mutex<Vec<String>>> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let _ = fs::remove_file(sock);
+    let listener = std::os::unix::net::UnixListener::bind(sock).unwrap();
+    let received = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let log = received.clone();
+    std::thread::spawn(move || {
+        let mut state = "running";
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { break };
+            let mut writer = stream.try_clone().unwrap();
+            let _ = writeln!(writer, r#"{{"QMP": {{"version": {{}}, "capabilities": []}}}}"#);
+            for line in BufReader::new(stream).lines() {
+                let Ok(line) = line else { break };
+                let cmd: serde_json::Value = serde_json::from_str(&line).unwrap();
+                let name = cmd["execute"].as_str().unwrap_or_default().to_string();
+                let ret = match name.as_str() {
+                    "query-status" => serde_json::json!({ "status": state, "running": state == "running" }),
+                    "stop" | "cont" => {
+                        state = if name == "stop" { "paused" } else { "running" };
+                        serde_json::json!({})
+                    }
+                    _ => serde_json::json!({}),
+                };
+                log.lock().unwrap().push(name);
+                if writeln!(writer, "{}", serde_json::json!({ "return": ret })).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    received
+}
+
+/// The fake's log once it contains `needle` (the fake runs concurrently with qvm)
+fn wait_for_log(log: &std::path::Path, needle: &str) -> String {
+    for _ in 0..50 {
+        let text = fs::read_to_string(log).unwrap_or_default();
+        if text.contains(needle) {
+            return text;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    panic!("{:?} never appeared in {}", needle, log.display());
+}
+
+#[test]
+fn test_start_status_stop_with_fake_qemu() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+
+    create_fake_vm(home, &log, "fake-vm", &[]).assert().success();
+    let vm_dir = home.join("fake-vm.qvm");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["start", "fake-vm", "--daemon"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Started VM 'fake-vm' in the background"));
+
+    // vm.pid names the fake, which was given the VM's command line and wrote it as -pidfile
+    let pid = fs::read_to_string(vm_dir.join("vm.pid")).unwrap();
+    let logged = wait_for_log(&log, "arg -uuid\n");
+    assert!(logged.starts_with(&format!("pid {}\n", pid.trim())), "{}", logged);
+    assert!(logged.contains(&format!("arg -pidfile\narg {}\n", vm_dir.join("vm.pid").display())));
+    assert!(logged.contains("arg -name\narg fake-vm\n"));
+    assert!(logged.contains("arg -display\narg none\n"));
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["status", "fake-vm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("running"))
+        .stdout(predicate::str::contains(format!("PID: {}", pid.trim())));
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["start", "fake-vm", "--daemon"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("currently running"));
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["stop", "fake-vm", "--timeout", "5"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Stopped VM 'fake-vm'"));
+    assert!(!vm_dir.join("vm.pid").exists());
+    wait_for_log(&log, "signal TERM\n");
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["status", "fake-vm"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("stopped"));
+}
+
+#[test]
+fn test_start_reports_immediate_qemu_exit() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "crashy", &[]).assert().success();
+    let vm_dir = home.join("crashy.qvm");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+    let reason = "qemu-system-x86_64: -drive if=pflash: Could not open 'code.fd'";
+
+    // Daemon: the reason comes from the tail of vm.log
+    qvm_with_fake_qemu(home, &log)
+        .env("FAKE_QEMU_FAIL", reason)
+        .args(["start", "crashy", "--daemon"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("qemu exited right after starting VM 'crashy'"))
+        .stderr(predicate::str::contains(reason));
+    assert!(!vm_dir.join("vm.pid").exists());
+
+    // Foreground: stderr is captured and also written to vm.log
+    fs::remove_file(vm_dir.join("vm.log")).unwrap();
+    qvm_with_fake_qemu(home, &log)
+        .env("FAKE_QEMU_FAIL", reason)
+        .args(["start", "crashy"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("qemu exited right after starting VM 'crashy' (exit status: 1):"))
+        .stderr(predicate::str::contains(reason));
+    assert!(fs::read_to_string(vm_dir.join("vm.log")).unwrap().contains(reason));
+    assert!(!vm_dir.join("vm.pid").exists());
+}
+
+#[test]
+fn test_create_from_iso_and_start() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    fs::write(home.join("install.iso"), b"").unwrap();
+    let create = |name: &str| create_fake_vm(home, &log, name, &[]);
+
+    create("no-iso")
+        .args(["--from-iso", home.join("missing.iso").to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("missing.iso does not exist"));
+
+    // The start half fails at once, after qemu was given the install medium
+    create("installer")
+        .args(["--disk-size", "1G", "--from-iso", home.join("install.iso").to_str().unwrap(), "--start"])
+        .env("FAKE_QEMU_FAIL", "no display")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("Created VM 'installer'"))
+        .stdout(predicate::str::contains("qvm set-iso installer --remove"))
+        .stderr(predicate::str::contains("qemu exited right after starting VM 'installer'"));
+    let logged = fs::read_to_string(&log).unwrap();
+    assert!(logged.contains("install.iso"), "{}", logged);
+    // The medium boots first by its bootindex; a -boot order would override that
+    assert!(logged.contains("arg scsi-cd,drive=cd0,bus=scsi0.0,bootindex=0\n"), "{}", logged);
+    assert!(logged.contains("arg virtio-blk-pci,drive=disk0,bootindex=1\n"), "{}", logged);
+    assert!(!logged.contains("arg -boot\n"), "{}", logged);
+
+    let cfg: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(home.join("installer.qvm/vm.json")).unwrap()).unwrap();
+    assert_eq!(cfg["paths"]["iso"], home.join("install.iso").to_str().unwrap());
+    assert!(cfg["boot"]["order"].is_null());
+}
+
+#[test]
+fn test_start_checks_qemu_version() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "versioned", &["--machine", "pc-q35-8.2"]).assert().success();
+    fs::write(home.join("versioned.qvm/disk.qcow2"), b"").unwrap();
+
+    qvm_with_fake_qemu(home, &log)
+        .env("FAKE_QEMU_VERSION", "8.1.5")
+        .args(["start", "versioned", "--daemon"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is QEMU 8.1.5, too old for VM 'versioned'"))
+        .stderr(predicate::str::contains("machine type 'pc-q35-8.2' needs QEMU 8.2.0 or newer"));
+    assert!(!log.exists());
+
+    qvm_with_fake_qemu(home, &log)
+        .env("FAKE_QEMU_VERSION", "8.1.5")
+        .args(["validate", "versioned"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("hardware.machine"));
+}
+
+#[test]
+fn test_start_checks_host_memory() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    // 64 TB: more than any test host has free
+    create_fake_vm(home, &log, "hungry", &["--mem", "67108864"]).assert().success();
+    fs::write(home.join("hungry.qvm/disk.qcow2"), b"").unwrap();
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["start", "hungry", "--daemon"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("VM 'hungry' needs 67108864 MB of memory"))
+        .stderr(predicate::str::contains("--force"));
+    assert!(!log.exists());
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["start", "hungry", "--daemon", "--force"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Started VM 'hungry' in the background"));
+    qvm_with_fake_qemu(home, &log)
+        .args(["stop", "hungry", "--force"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Stopped VM 'hungry'"));
+    wait_for_log(&log, "signal TERM\n");
+}
+
+#[test]
+fn test_validate_lists_every_problem() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "checked", &[]).assert().success();
+    let vm_dir = home.join("checked.qvm");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["validate", "checked"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("VM 'checked' is ready to start"));
+
+    // Several hand edits gone wrong, including one that stops every other command
+    let conf = fs::read_to_string(vm_dir.join("vm.json")).unwrap();
+    let conf = conf
+        .replace("\"mode\": \"headless\"", "\"mode\": \"vnc \"")
+        .replace("\"cores\": 4", "\"cores\": 0")
+        .replace("\"path\": \"disk.qcow2\"", "\"path\": \"missing/disk.qcow2\"");
+    fs::write(vm_dir.join("vm.json"), conf).unwrap();
+    qvm_with_fake_qemu(home, &log)
+        .args(["validate", "checked"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("VM 'checked' would not start:"))
+        .stdout(predicate::str::contains("  - display.mode: is \"vnc \""))
+        .stdout(predicate::str::contains("  - hardware: Invalid CPU topology"))
+        .stdout(predicate::str::contains("  - paths.disks.0: directory"))
+        .stderr(predicate::str::contains("3 problem(s) found in VM 'checked'"));
+}
+
+#[test]
+fn test_start_all_autostart_vms() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+
+    for name in ["auto", "manual", "broken"] {
+        create_fake_vm(home, &log, name, &[]).assert().success();
+    }
+    // "broken" has no disk image, so starting it fails
+    fs::write(home.join("auto.qvm/disk.qcow2"), b"").unwrap();
+    fs::write(home.join("manual.qvm/disk.qcow2"), b"").unwrap();
+    let _ = fs::remove_file(home.join("broken.qvm/disk.qcow2"));
+
+    for name in ["auto", "broken"] {
+        qvm_with_fake_qemu(home, &log)
+            .args(["enable", name])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(format!("Autostart enabled for VM '{}'", name)));
+    }
+    let conf = fs::read_to_string(home.join("auto.qvm/vm.json")).unwrap();
+    assert!(conf.contains("\"autostart\": true"), "{}", conf);
+
+    // One failure does not keep the other VM from starting, but fails the command
+    qvm_with_fake_qemu(home, &log)
+        .args(["start", "--all"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("Started VM 'auto' in the background"))
+        .stdout(predicate::str::contains("Failed to start VM 'broken'"))
+        .stderr(predicate::str::contains("1 autostart VM(s) failed to start"));
+    assert!(home.join("auto.qvm/vm.pid").exists());
+    assert!(!home.join("manual.qvm/vm.pid").exists());
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["disable", "broken"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Autostart disabled for VM 'broken'"));
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["start", "--all"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("VM 'auto' is already running"));
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["stop", "auto", "--timeout", "5"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_install_service_writes_unit() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "svc-vm", &[]).assert().success();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", home)
+        .env("HOME", home)
+        .args(["install-service", "svc-vm", "--kind", "systemd", "--install"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("systemctl --user enable --now qvm-svc-vm.service"));
+    let unit = fs::read_to_string(home.join(".config/systemd/user/qvm-svc-vm.service")).unwrap();
+    assert!(unit.contains("start svc-vm --daemon\n"), "{}", unit);
+    assert!(unit.contains(&format!("Environment=QVM_HOME={}\n", home.display())), "{}", unit);
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", home)
+        .args(["install-service", "no-such-vm"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_qemu_bin_override_must_exist() {
+    let temp_home = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .env("QVM_QEMU_BIN", temp_home.path().join("no-such-qemu"))
+        .args(["create", "some-vm", "--dry-run"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("$QVM_QEMU_BIN (--qemu-bin) is set to"));
+}
+
+#[test]
+fn test_qemu_bin_flag_must_be_executable() {
+    let temp_home = TempDir::new().unwrap();
+    let not_executable = temp_home.path().join("qemu-system-x86_64");
+    fs::write(&not_executable, "").unwrap();
+
+    let mut cmd = Command::cargo_bin("qvm").unwrap();
+    cmd.env("QVM_HOME", temp_home.path())
+        .env_remove("QVM_QEMU_BIN")
+        .args(["create", "some-vm", "--dry-run", "--qemu-bin"])
+        .arg(&not_executable)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("which is not an executable file"));
+}
+
+#[test]
+fn test_create_picks_unique_ports() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let create = |name: &str| create_fake_vm(home, &home.join("fake-qemu.log"), name, &["--display-mode", "vnc"]);
+    let config = |name: &str| -> serde_json::Value {
+        serde_json::from_str(&fs::read_to_string(home.join(format!("{}.qvm/vm.json", name))).unwrap()).unwrap()
+    };
+
+    create("nat").arg("--ssh-forward").assert().failure().stderr(predicate::str::contains(
+        "An SSH forward needs --net-mode user",
+    ));
+
+    create("one").args(["--net-mode", "user", "--ssh-forward"]).assert().success();
+    create("two")
+        .args(["--net-mode", "user", "--ssh-forward"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("SSH forwarded from host port"));
+    create("three").args(["--net-mode", "user", "--ssh-port", "2300", "--vnc-display", "40"]).assert().success();
+
+    let (one, two, three) = (config("one"), config("two"), config("three"));
+    let vnc = |cfg: &serde_json::Value| cfg["display"]["vnc"]["display"].as_u64().unwrap();
+    let spice = |cfg: &serde_json::Value| cfg["display"]["spice"]["port"].as_u64().unwrap();
+    let ssh = |cfg: &serde_json::Value| cfg["network"]["interfaces"][0]["forwards"][0]["host_port"].as_u64().unwrap();
+    assert_ne!(vnc(&one), vnc(&two));
+    assert_ne!(spice(&one), spice(&two));
+    assert_ne!(ssh(&one), ssh(&two));
+    assert_eq!(three["network"]["interfaces"][0]["forwards"][0]["guest_port"], 22);
+    assert_eq!(ssh(&three), 2300);
+    assert_eq!(vnc(&three), 40);
+
+    qvm_with_fake_qemu(home, &home.join("fake-qemu.log"))
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("vnc:{}", 5900 + vnc(&two))))
+        .stdout(predicate::str::contains("vnc:5940    2300"));
+}
+
+#[test]
+fn test_ps_shows_running_vms() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    for name in ["up", "down"] {
+        create_fake_vm(home, &log, name, &[]).assert().success();
+        fs::write(home.join(format!("{}.qvm/disk.qcow2", name)), b"").unwrap();
+    }
+
+    qvm_with_fake_qemu(home, &log).arg("ps").assert().success().stdout("No running VMs\n");
+
+    qvm_with_fake_qemu(home, &log).args(["start", "up", "--daemon"]).assert().success();
+    // A stale pidfile is not a running VM
+    fs::write(home.join("down.qvm/vm.pid"), "999999999").unwrap();
+    let pid = fs::read_to_string(home.join("up.qvm/vm.pid")).unwrap();
+
+    let output = qvm_with_fake_qemu(home, &log).args(["--output", "json", "ps"]).output().unwrap();
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let vms = report["vms"].as_array().unwrap();
+    assert_eq!(vms.len(), 1, "{}", report);
+    assert_eq!(vms[0]["name"], "up");
+    assert_eq!(vms[0]["pid"].to_string(), pid.trim());
+    assert_eq!(vms[0]["display"], "headless");
+    assert!(vms[0]["cpu_percent"].is_number());
+    assert!(vms[0]["rss_bytes"].as_u64().unwrap() > 0);
+
+    qvm_with_fake_qemu(home, &log)
+        .arg("ps")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("CPU%"))
+        .stdout(predicate::str::contains(format!("up                   {:>8}", pid.trim())))
+        .stdout(predicate::str::contains("down").not());
+
+    // --watch with JSON output streams one report per line
+    let fake = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fake-qemu.sh");
+    let mut watch = std::process::Command::new(assert_cmd::cargo::cargo_bin("qvm"))
+        .env("QVM_HOME", home)
+        .env("QVM_QEMU_BIN", fake)
+        .args(["--output", "json", "ps", "--watch", "--interval", "1"])
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = std::io::BufRead::lines(std::io::BufReader::new(watch.stdout.take().unwrap()));
+    for _ in 0..2 {
+        let report: serde_json::Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(report["vms"][0]["name"], "up");
+    }
+    watch.kill().unwrap();
+    watch.wait().unwrap();
+
+    qvm_with_fake_qemu(home, &log).args(["stop", "up", "--force"]).assert().success();
+}
+
+#[test]
+fn test_create_start_stop_hooks() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    let script = |name: &str, body: &str| {
+        let path = home.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    };
+    let record = script("record.sh", "echo \"$QVM_HOOK $QVM_NAME $QVM_DISK\" >> \"$QVM_ROOT/events\"");
+    let broken = script("broken.sh", "echo provisioning went wrong; exit 3");
+    let create = |name: &str, hook: &std::path::Path| {
+        create_fake_vm(home, &log, name, &["--on-create", hook.to_str().unwrap()])
+    };
+
+    create("hooked", &record).assert().success();
+    let vm_dir = home.join("hooked.qvm");
+    let events = || fs::read_to_string(vm_dir.join("events")).unwrap();
+    assert_eq!(events(), format!("create hooked {}\n", vm_dir.join("disk.qcow2").display()));
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+    // Like other unset sections, no hooks means no hooks key in vm.json
+    assert!(!fs::read_to_string(vm_dir.join("vm.json")).unwrap().contains("\"hooks\""));
+
+    for key in ["hooks.on_start", "hooks.on_stop"] {
+        qvm_with_fake_qemu(home, &log)
+            .args(["set", "hooked", key, record.to_str().unwrap()])
+            .assert()
+            .success();
+    }
+    qvm_with_fake_qemu(home, &log).args(["start", "hooked", "--daemon"]).assert().success();
+    assert!(events().ends_with(&format!("start hooked {}\n", vm_dir.join("disk.qcow2").display())));
+    qvm_with_fake_qemu(home, &log).args(["stop", "hooked", "--force"]).assert().success();
+    assert!(events().contains("\nstop hooked "));
+
+    // A VM whose start hook fails is stopped again
+    qvm_with_fake_qemu(home, &log)
+        .args(["set", "hooked", "hooks.on_start", broken.to_str().unwrap()])
+        .assert()
+        .success();
+    qvm_with_fake_qemu(home, &log)
+        .args(["start", "hooked", "--daemon"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("start hook"));
+    assert!(!vm_dir.join("vm.pid").exists());
+
+    // A failing hook fails the command and its output says why; the VM is still created
+    create("failing", &broken)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("create hook"))
+        .stderr(predicate::str::contains("provisioning went wrong"));
+    assert!(home.join("failing.qvm/vm.json").is_file());
+    assert!(fs::read_to_string(home.join("failing.qvm/hooks.log")).unwrap().contains("provisioning went wrong"));
+}
+
+#[test]
+fn test_which_shows_resolved_qemu_and_firmware() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    let fw = home.join("fw");
+    fs::create_dir(&fw).unwrap();
+    fs::write(fw.join("OVMF_CODE.fd"), b"").unwrap();
+    fs::write(fw.join("OVMF_VARS.fd"), b"").unwrap();
+
+    create_fake_vm(home, &log, "probe", &[]).assert().success();
+
+    let fake = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fake-qemu.sh");
+    qvm_with_fake_qemu(home, &log)
+        .env("QVM_FIRMWARE_DIR", &fw)
+        .args(["which", "probe"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("qemu: {} (QEMU", fake.display())))
+        .stdout(predicate::str::contains("$QVM_QEMU_BIN (--qemu-bin)"))
+        .stdout(predicate::str::contains(format!("firmware: {}", fw.join("OVMF_CODE.fd").display())))
+        .stdout(predicate::str::contains("OVMF_CODE_4M.fd + OVMF_VARS_4M.fd"));
+
+    let output = qvm_with_fake_qemu(home, &log)
+        .env("QVM_FIRMWARE_DIR", home.join("nowhere"))
+        .args(["--output", "json", "which", "probe"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["action"], "which");
+    assert!(report["firmware_code"].is_null());
+    assert_eq!(report["firmware_searched"][0]["path"], home.join("nowhere").to_str().unwrap());
+    assert_eq!(report["firmware_searched"][0]["found"], false);
+    assert_eq!(report["configured_code"]["found"], true);
+}
+
+#[test]
+fn test_version_reports_qemu_and_firmware() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    let fw = home.join("fw");
+    fs::create_dir(&fw).unwrap();
+    fs::write(fw.join("OVMF_CODE.fd"), b"").unwrap();
+    fs::write(fw.join("OVMF_VARS.fd"), b"").unwrap();
+
+    qvm_with_fake_qemu(home, &log)
+        .arg("--version")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(format!("qvm {}", env!("CARGO_PKG_VERSION"))));
+
+    let fake = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fake-qemu.sh");
+    qvm_with_fake_qemu(home, &log)
+        .env("QVM_FIRMWARE_DIR", &fw)
+        .args(["version", "--full"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("qemu-system-x86_64: {} (QEMU", fake.display())))
+        .stdout(predicate::str::contains(format!("firmware: {}", fw.join("OVMF_CODE.fd").display())))
+        .stdout(predicate::str::contains("accelerator"));
+
+    let output = qvm_with_fake_qemu(home, &log)
+        .args(["--output", "json", "version", "--full"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(json["qemu"].as_array().unwrap().len(), 2);
+    assert_eq!(json["qemu"][0]["arch"], "aarch64");
+}
+
+#[test]
+fn test_import_config_from_stdin() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+
+    create_fake_vm(home, &log, "tmpl", &[]).assert().success();
+    let tmpl_root = home.join("tmpl.qvm");
+    let mut cfg: serde_json::Value =
+        serde_json::from_slice(&fs::read(tmpl_root.join("vm.json")).unwrap()).unwrap();
+    cfg["paths"]["disks"][0]["path"] = tmpl_root.join("disk.qcow2").display().to_string().into();
+    cfg["hardware"]["mem_mb"] = 512.into();
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["import-config", "-", "--name", "gen"])
+        .write_stdin(cfg.to_string())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Created VM 'gen'"));
+    let gen: serde_json::Value =
+        serde_json::from_slice(&fs::read(home.join("gen.qvm/vm.json")).unwrap()).unwrap();
+    assert_eq!(gen["meta"]["name"], "gen");
+    assert_ne!(gen["meta"]["uuid"], cfg["meta"]["uuid"]);
+    assert_eq!(gen["paths"]["root"], home.join("gen.qvm").display().to_string());
+    assert_eq!(gen["paths"]["disks"][0]["path"], "disk.qcow2");
+    assert_eq!(gen["hardware"]["mem_mb"], 512);
+    // The copy gets its own MAC and display ports
+    let nic = &gen["network"]["interfaces"][0];
+    assert_ne!(nic["mac"], cfg["network"]["interfaces"][0]["mac"]);
+    assert!(nic["mac"].as_str().unwrap().starts_with("52:54:00:"));
+    assert_ne!(gen["display"]["vnc"]["display"], cfg["display"]["vnc"]["display"]);
+    assert_ne!(gen["display"]["spice"]["port"], cfg["display"]["spice"]["port"]);
+
+    // Taken names and invalid values are refused before anything is written
+    qvm_with_fake_qemu(home, &log)
+        .args(["import-config", "-"])
+        .write_stdin(cfg.to_string())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("tmpl"));
+    cfg["hardware"]["accel"] = "warp".into();
+    qvm_with_fake_qemu(home, &log)
+        .args(["import-config", "-", "--name", "bad"])
+        .write_stdin(cfg.to_string())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("warp"));
+    assert!(!home.join("bad.qvm").exists());
+}
+
+#[test]
+fn test_import_renews_mac_and_ports() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "orig", &[]).assert().success();
+    let orig_root = home.join("orig.qvm");
+    let conf = orig_root.join("vm.json");
+    let mut cfg: serde_json::Value = serde_json::from_slice(&fs::read(&conf).unwrap()).unwrap();
+    cfg["paths"]["efi_vars"] = orig_root.join("efi_vars.fd").display().to_string().into();
+    fs::write(&conf, cfg.to_string()).unwrap();
+    fs::write(orig_root.join("disk.qcow2"), b"").unwrap();
+
+    let archive = home.join("orig.tar");
+    qvm_with_fake_qemu(home, &log)
+        .args(["export", "orig", archive.to_str().unwrap()])
+        .assert()
+        .success();
+    qvm_with_fake_qemu(home, &log)
+        .args(["import", archive.to_str().unwrap(), "--name", "copy"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Imported VM 'copy'"));
+
+    let copy: serde_json::Value =
+        serde_json::from_slice(&fs::read(home.join("copy.qvm/vm.json")).unwrap()).unwrap();
+    assert_eq!(copy["paths"]["root"], home.join("copy.qvm").display().to_string());
+    assert_eq!(copy["paths"]["efi_vars"], "efi_vars.fd");
+    assert_ne!(copy["meta"]["uuid"], cfg["meta"]["uuid"]);
+    // Both VMs stay in qvm home, so they must not share a MAC or display port
+    assert_ne!(copy["network"]["interfaces"][0]["mac"], cfg["network"]["interfaces"][0]["mac"]);
+    assert_ne!(copy["display"]["vnc"]["display"], cfg["display"]["vnc"]["display"]);
+    assert_ne!(copy["display"]["spice"]["port"], cfg["display"]["spice"]["port"]);
+}
+
+#[test]
+fn test_set_password_keeps_vm_json_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "pw", &["--display-mode", "vnc"]).assert().success();
+    let conf = home.join("pw.qvm/vm.json");
+    let mode = || fs::metadata(&conf).unwrap().permissions().mode() & 0o777;
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["set-password", "pw"])
+        .write_stdin("hunter2\n")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("plain text"));
+    assert!(fs::read_to_string(&conf).unwrap().contains("hunter2"));
+    assert_eq!(mode(), 0o600);
+
+    // Only a reference to the environment is stored then
+    qvm_with_fake_qemu(home, &log).args(["set-password", "pw", "--env", "VNC_PW"]).assert().success();
+    assert!(!fs::read_to_string(&conf).unwrap().contains("hunter2"));
+    assert_eq!(mode(), 0o644);
+}
+
+#[test]
+fn test_cloud_init_seed_attached_on_start() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    let tools = home.join("tools");
+    fs::create_dir(&tools).unwrap();
+    fs::write(home.join("user-data"), "#cloud-config\n").unwrap();
+    create_fake_vm(home, &log, "seeded", &[]).assert().success();
+    let vm_dir = home.join("seeded.qvm");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+
+    qvm_with_fake_qemu(home, &log)
+        .env("PATH", &tools)
+        .args(["cloud-init", "seeded", "--user-data", home.join("user-data").to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No ISO tool found"));
+
+    // Stands in for genisoimage: the "ISO" lists the staged files, then the meta-data
+    let tool = tools.join("genisoimage");
+    fs::write(
+        &tool,
+        "#!/bin/sh\nwhile [ $# -gt 1 ]; do [ \"$1\" = -output ] && out=$2; shift; done\n\
+         ls \"$1\" > \"$out\"; cat \"$1/meta-data\" >> \"$out\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", tools.display(), std::env::var("PATH").unwrap());
+
+    qvm_with_fake_qemu(home, &log)
+        .env("PATH", &path)
+        .args(["cloud-init", "seeded", "--user-data", home.join("user-data").to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Built cloud-init seed seed.iso for VM 'seeded'"));
+    let seed = fs::read_to_string(vm_dir.join("seed.iso")).unwrap();
+    assert!(seed.contains("meta-data\nuser-data\n"), "{}", seed);
+    assert!(seed.contains("local-hostname: seeded\n"), "{}", seed);
+    let cfg: serde_json::Value = serde_json::from_slice(&fs::read(vm_dir.join("vm.json")).unwrap()).unwrap();
+    assert_eq!(cfg["cloud_init"]["seed_iso"], "seed.iso");
+
+    qvm_with_fake_qemu(home, &log).args(["start", "seeded", "--daemon"]).assert().success();
+    let logged = wait_for_log(&log, "arg -uuid\n");
+    assert!(logged.contains(&format!("file={},if=none,id=cidata", vm_dir.join("seed.iso").display())), "{}", logged);
+    qvm_with_fake_qemu(home, &log).args(["stop", "seeded", "--force"]).assert().success();
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["cloud-init", "seeded", "--remove"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Removed cloud-init seed from VM 'seeded'"));
+    assert!(!vm_dir.join("seed.iso").exists());
+    let cfg: serde_json::Value = serde_json::from_slice(&fs::read(vm_dir.join("vm.json")).unwrap()).unwrap();
+    assert!(cfg.get("cloud_init").is_none_or(|c| c.is_null()));
+}
+
+#[test]
+fn test_tpm_runs_swtpm_alongside_qemu() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    let tools = home.join("tools");
+    fs::create_dir(&tools).unwrap();
+    create_fake_vm(home, &log, "trusted", &["--tpm"]).assert().success();
+    let vm_dir = home.join("trusted.qvm");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+
+    qvm_with_fake_qemu(home, &log)
+        .env("PATH", &tools)
+        .args(["start", "trusted", "--daemon"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("'swtpm' was not found on PATH"));
+
+    // Stands in for swtpm: daemonizes a copy of itself, writes its pidfile and creates the socket
+    let swtpm = tools.join("swtpm");
+    fs::write(
+        &swtpm,
+        "#!/bin/sh\n[ \"$1\" = idle ] && while :; do sleep 1; done\n\
+         for a; do case \"$a\" in\n\
+         type=unixio,path=*) sock=${a#*path=};;\nfile=*) pid=${a#file=};;\nesac; done\n\
+         \"$0\" idle </dev/null >/dev/null 2>&1 &\necho $! > \"$pid\"; : > \"$sock\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&swtpm, fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", tools.display(), std::env::var("PATH").unwrap());
+
+    qvm_with_fake_qemu(home, &log)
+        .env("PATH", &path)
+        .args(["start", "trusted", "--daemon"])
+        .assert()
+        .success();
+    let logged = wait_for_log(&log, "arg -uuid\n");
+    assert!(logged.contains(&format!("arg socket,id=chrtpm,path={}\n", vm_dir.join("swtpm.sock").display())));
+    assert!(logged.contains("arg tpm-crb,tpmdev=tpm0\n"), "{}", logged);
+    let swtpm_pid = fs::read_to_string(vm_dir.join("swtpm.pid")).unwrap();
+    // Gone, or a zombie its (container) init has not reaped yet
+    let stat = std::path::PathBuf::from(format!("/proc/{}/stat", swtpm_pid.trim()));
+    let alive = || fs::read_to_string(&stat).is_ok_and(|s| !s.contains(") Z "));
+
+    qvm_with_fake_qemu(home, &log).args(["stop", "trusted", "--force"]).assert().success();
+    assert!(!vm_dir.join("swtpm.pid").exists());
+    if cfg!(target_os = "linux") {
+        for _ in 0..50 {
+            if !alive() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        assert!(!alive(), "swtpm {} outlived its VM", swtpm_pid.trim());
+    }
+}
+
+#[test]
+fn test_qmp_command_on_running_vm() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "monitored", &[]).assert().success();
+    let vm_dir = home.join("monitored.qvm");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+    let query = r#"{"execute":"query-status"}"#;
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["qmp", "monitored", query])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("VM 'monitored' is not running"));
+
+    qvm_with_fake_qemu(home, &log).args(["start", "monitored", "--daemon"]).assert().success();
+    let logged = wait_for_log(&log, "arg -uuid\n");
+    let qmp_arg = format!("arg -qmp\narg unix:{},server=on,wait=off\n", vm_dir.join("qmp.sock").display());
+    assert!(logged.contains(&qmp_arg), "{}", logged);
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["qmp", "monitored", query])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no QMP socket at"));
+
+    let received = serve_fake_qmp(&vm_dir.join("qmp.sock"));
+    qvm_with_fake_qemu(home, &log)
+        .args(["qmp", "monitored", query])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"status\": \"running\""));
+    qvm_with_fake_qemu(home, &log)
+        .args(["qmp", "monitored", "{not json"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid QMP command JSON"));
+    assert_eq!(*received.lock().unwrap(), ["qmp_capabilities", "query-status"]);
+
+    qvm_with_fake_qemu(home, &log).args(["stop", "monitored", "--force"]).assert().success();
+}
+
+#[test]
+fn test_stop_escalates_to_sigterm_and_sigkill() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "wedged", &[]).assert().success();
+    let vm_dir = home.join("wedged.qvm");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+
+    qvm_with_fake_qemu(home, &log)
+        .env("FAKE_QEMU_IGNORE_TERM", "1")
+        .args(["start", "wedged", "--daemon"])
+        .assert()
+        .success();
+    wait_for_log(&log, "arg -uuid\n");
+    // The guest never acts on the ACPI power button, and qemu ignores SIGTERM
+    let received = serve_fake_qmp(&vm_dir.join("qmp.sock"));
+    qvm_with_fake_qemu(home, &log)
+        .args(["stop", "wedged", "--timeout", "1"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("VM 'wedged' did not power off within 1s, terminating qemu"))
+        .stderr(predicate::str::contains("VM 'wedged' did not exit within 5s, sending SIGKILL"));
+    assert!(received.lock().unwrap().iter().any(|c| c == "system_powerdown"));
+    assert!(!vm_dir.join("vm.pid").exists());
+    qvm_with_fake_qemu(home, &log)
+        .args(["status", "wedged"])
+        .assert()
+        .stdout(predicate::str::contains("stopped"));
+}
+
+#[test]
+fn test_pause_resume_via_qmp() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "napper", &[]).assert().success();
+    let vm_dir = home.join("napper.qvm");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["pause", "napper"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("VM 'napper' is not running"));
+
+    qvm_with_fake_qemu(home, &log).args(["start", "napper", "--daemon"]).assert().success();
+    wait_for_log(&log, "arg -uuid\n");
+    let received = serve_fake_qmp(&vm_dir.join("qmp.sock"));
+    let state = || {
+        let output = qvm_with_fake_qemu(home, &log).args(["status", "napper", "--json"]).output().unwrap();
+        let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        status["state"].as_str().unwrap().to_string()
+    };
+
+    assert_eq!(state(), "running");
+    qvm_with_fake_qemu(home, &log)
+        .args(["pause", "napper"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Paused VM 'napper'"));
+    assert_eq!(state(), "paused");
+    qvm_with_fake_qemu(home, &log)
+        .args(["resume", "napper"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Resumed VM 'napper'"));
+    assert_eq!(state(), "running");
+    assert!(received.lock().unwrap().iter().any(|c| c == "stop"));
+    assert!(received.lock().unwrap().iter().any(|c| c == "cont"));
+
+    qvm_with_fake_qemu(home, &log).args(["stop", "napper", "--force"]).assert().success();
+}
+
+#[test]
+fn test_start_iso_override_and_set_iso() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "installer", &[]).assert().success();
+    let vm_dir = home.join("installer.qvm");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+    let saved_iso = || {
+        let cfg: serde_json::Value = serde_json::from_slice(&fs::read(vm_dir.join("vm.json")).unwrap()).unwrap();
+        cfg["paths"]["iso"].clone()
+    };
+
+    // A relative medium is kept as given and resolved under the VM root on start
+    qvm_with_fake_qemu(home, &log)
+        .args(["set-iso", "installer", "install.iso"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("VM 'installer' will boot from install.iso on start"));
+    assert_eq!(saved_iso(), "install.iso");
+    qvm_with_fake_qemu(home, &log)
+        .args(["start", "installer", "--daemon"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!("ISO not found: {}", vm_dir.join("install.iso").display())));
+
+    fs::write(vm_dir.join("install.iso"), b"").unwrap();
+    qvm_with_fake_qemu(home, &log).args(["start", "installer", "--daemon"]).assert().success();
+    let logged = wait_for_log(&log, "arg -uuid\n");
+    let saved_drive = format!("arg file={},if=none,id=cd0,", vm_dir.join("install.iso").display());
+    assert!(logged.contains(&saved_drive), "{}", logged);
+    qvm_with_fake_qemu(home, &log).args(["stop", "installer", "--force"]).assert().success();
+
+    // --iso replaces the saved medium for this boot only
+    fs::write(home.join("rescue.iso"), b"").unwrap();
+    fs::remove_file(&log).unwrap();
+    qvm_with_fake_qemu(home, &log)
+        .args(["start", "installer", "--daemon", "--iso", home.join("rescue.iso").to_str().unwrap()])
+        .assert()
+        .success();
+    let logged = wait_for_log(&log, "arg -uuid\n");
+    assert!(logged.contains(&format!("arg file={},if=none,id=cd0,", home.join("rescue.iso").display())), "{}", logged);
+    assert!(!logged.contains("install.iso"), "{}", logged);
+    assert_eq!(saved_iso(), "install.iso");
+    qvm_with_fake_qemu(home, &log).args(["stop", "installer", "--force"]).assert().success();
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["set-iso", "installer", "--remove"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Detached ISO from VM 'installer'"));
+    assert!(saved_iso().is_null());
+    fs::remove_file(&log).unwrap();
+    qvm_with_fake_qemu(home, &log).args(["start", "installer", "--daemon"]).assert().success();
+    let logged = wait_for_log(&log, "arg -uuid\n");
+    assert!(!logged.contains("id=cd0"), "{}", logged);
+    qvm_with_fake_qemu(home, &log).args(["stop", "installer", "--force"]).assert().success();
+}
+
+#[test]
+fn test_start_console_serial_and_display_override() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "terminal", &[]).assert().success();
+    let vm_dir = home.join("terminal.qvm");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["start", "terminal", "--console", "serial", "--daemon"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--console serial needs a terminal and cannot be combined with --daemon"));
+    assert!(!log.exists());
+
+    // In the foreground qemu gets the serial port and monitor on stdio, and no display
+    qvm_with_fake_qemu(home, &log)
+        .env("FAKE_QEMU_FAIL", "stopped here")
+        .args(["start", "terminal", "--console", "serial"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("stopped here"));
+    let logged = fs::read_to_string(&log).unwrap();
+    assert!(logged.contains("arg -display\narg none\narg -serial\narg mon:stdio\n"), "{}", logged);
+    assert!(!logged.contains("id=serial0"), "{}", logged);
+
+    // --display applies to this boot only
+    fs::remove_file(&log).unwrap();
+    qvm_with_fake_qemu(home, &log)
+        .args(["start", "terminal", "--display", "vnc", "--daemon"])
+        .assert()
+        .success();
+    let logged = wait_for_log(&log, "arg -uuid\n");
+    assert!(logged.contains("arg -vnc\narg 127.0.0.1:1\n"), "{}", logged);
+    assert!(logged.contains("arg chardev:serial0\n"), "{}", logged);
+    let cfg: serde_json::Value = serde_json::from_slice(&fs::read(vm_dir.join("vm.json")).unwrap()).unwrap();
+    assert_eq!(cfg["display"]["mode"], "headless");
+    qvm_with_fake_qemu(home, &log).args(["stop", "terminal", "--force"]).assert().success();
+}
+
+#[test]
+fn test_efi_vars_copied_from_template() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    // Read-only, like a template in the Nix store
+    fs::write(home.join("vars.fd"), b"template").unwrap();
+    fs::set_permissions(home.join("vars.fd"), fs::Permissions::from_mode(0o444)).unwrap();
+    create_fake_vm(home, &log, "uefi", &[]).assert().success();
+    let vm_dir = home.join("uefi.qvm");
+    let efi_vars = vm_dir.join("efi_vars.fd");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+
+    assert_eq!(fs::read(&efi_vars).unwrap(), b"template");
+    assert_eq!(fs::metadata(&efi_vars).unwrap().permissions().mode() & 0o777, 0o644);
+
+    // The firmware is mapped read-only, the VM's own copy of the vars writable
+    qvm_with_fake_qemu(home, &log).args(["start", "uefi", "--daemon"]).assert().success();
+    let logged = wait_for_log(&log, "arg -uuid\n");
+    let code = format!("arg if=pflash,format=raw,unit=0,readonly=on,file={}\n", home.join("code.fd").display());
+    assert!(logged.contains(&code), "{}", logged);
+    assert!(logged.contains(&format!("arg if=pflash,format=raw,unit=1,file={}\n", efi_vars.display())), "{}", logged);
+    qvm_with_fake_qemu(home, &log).args(["stop", "uefi", "--force"]).assert().success();
+
+    // Boot entries the guest saved survive a restart; lost vars are seeded again
+    fs::write(&efi_vars, b"boot entries").unwrap();
+    qvm_with_fake_qemu(home, &log).args(["start", "uefi", "--daemon"]).assert().success();
+    qvm_with_fake_qemu(home, &log).args(["stop", "uefi", "--force"]).assert().success();
+    assert_eq!(fs::read(&efi_vars).unwrap(), b"boot entries");
+
+    fs::remove_file(&efi_vars).unwrap();
+    qvm_with_fake_qemu(home, &log).args(["start", "uefi", "--daemon"]).assert().success();
+    qvm_with_fake_qemu(home, &log).args(["stop", "uefi", "--force"]).assert().success();
+    assert_eq!(fs::read(&efi_vars).unwrap(), b"template");
+}
+
+#[test]
+fn test_console_relays_serial_socket() {
+    use std::io::{Read, Write};
+
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+    create_fake_vm(home, &log, "serial", &[]).assert().success();
+    let vm_dir = home.join("serial.qvm");
+    let sock = vm_dir.join("console.sock");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+
+    qvm_with_fake_qemu(home, &log)
+        .args(["console", "serial"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("VM 'serial' is not running"));
+
+    qvm_with_fake_qemu(home, &log).args(["start", "serial", "--daemon"]).assert().success();
+    let logged = wait_for_log(&log, "arg -uuid\n");
+    let chardev = format!("arg socket,id=serial0,path={},server=on,wait=off\n", sock.display());
+    assert!(logged.contains(&chardev), "{}", logged);
+    qvm_with_fake_qemu(home, &log)
+        .args(["console", "serial"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("VM 'serial' has no console socket"));
+
+    // Stands in for the guest: prompts, answers the user name, then hangs up; then takes
+    // one more session and returns what was typed into it
+    let listener = std::os::unix::net::UnixListener::bind(&sock).unwrap();
+    let guest = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"login: ").unwrap();
+        let mut typed = Vec::new();
+        let mut buf = [0u8; 64];
+        while !typed.ends_with(b"root\n") {
+            let n = stream.read(&mut buf).unwrap();
+            assert!(n > 0, "console closed early");
+            typed.extend_from_slice(&buf[..n]);
+        }
+        stream.write_all(b"welcome\n").unwrap();
+        drop(stream);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut typed = Vec::new();
+        stream.read_to_end(&mut typed).unwrap();
+        typed
+    });
+
+    // stdin stays open, so the session ends when the guest hangs up
+    let fake = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/fake-qemu.sh");
+    let mut console = std::process::Command::new(assert_cmd::cargo::cargo_bin("qvm"))
+        .env("QVM_HOME", home)
+        .env("QVM_QEMU_BIN", fake)
+        .args(["console", "serial"])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = console.stdin.take().unwrap();
+    stdin.write_all(b"root\n").unwrap();
+    let mut out = String::new();
+    console.stdout.take().unwrap().read_to_string(&mut out).unwrap();
+    assert!(console.wait().unwrap().success());
+    drop(stdin);
+    assert!(out.contains("login: welcome\n"), "{}", out);
+    assert!(out.contains("[console closed]"), "{}", out);
+
+    // Ctrl-] detaches; what came before it still reaches the guest
+    qvm_with_fake_qemu(home, &log)
+        .args(["console", "serial"])
+        .write_stdin("ls\x1dnot sent")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Connected to the serial console of VM 'serial' (Ctrl-] to detach)"));
+    assert_eq!(guest.join().unwrap(), b"ls");
+
+    qvm_with_fake_qemu(home, &log).args(["stop", "serial", "--force"]).assert().success();
+}
+
+#[test]
+fn test_unknown_cpu_model_only_refused_on_change() {
+    let temp_home = TempDir::new().unwrap();
+    let home = temp_home.path();
+    let log = home.join("fake-qemu.log");
+
+    create_fake_vm(home, &log, "novel", &["--cpu-model", "FutureLake"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown x86_64 CPU model 'FutureLake'"));
+    create_fake_vm(home, &log, "novel", &[]).assert().success();
+    let vm_dir = home.join("novel.qvm");
+    fs::write(vm_dir.join("disk.qcow2"), b"").unwrap();
+    qvm_with_fake_qemu(home, &log)
+        .args(["set", "novel", "hardware.cpu_model", "FutureLake"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Unknown x86_64 CPU model 'FutureLake'"));
+
+    // A model newer than qvm's list, put in vm.json by hand, is qemu's to judge
+    let json = fs::read_to_string(vm_dir.join("vm.json")).unwrap();
+    let mut cfg: serde_json::Value = serde_json::from_str(&json).unwrap();
+    cfg["hardware"]["cpu_model"] = "FutureLake".into();
+    fs::write(vm_dir.join("vm.json"), serde_json::to_string_pretty(&cfg).unwrap()).unwrap();
+    qvm_with_fake_qemu(home, &log)
+        .args(["set", "novel", "hardware.mem_mb", "512"])
+        .assert()
+        .success();
+    qvm_with_fake_qemu(home, &log)
+        .args(["start", "novel", "--daemon"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Unknown x86_64 CPU model 'FutureLake'"))
+        .stderr(predicate::str::contains("passing it to qemu as is"));
+    let logged = wait_for_log(&log, "arg -smp\n");
+    assert!(logged.contains("arg -cpu\narg FutureLake\n"), "{}", logged);
+    qvm_with_fake_qemu(home, &log).args(["stop", "novel", "--force"]).assert().success();
+}